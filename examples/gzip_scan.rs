@@ -0,0 +1,73 @@
+//! Measures how long `GzipDecompressor` takes to find the members of a multi-member gzip file on
+//! the reading thread, against how long inflating them takes on a single thread.
+//!
+//! A `PooledGzipReader` can only read as fast as its reading thread finds members, however many
+//! threads the pool has, so the ratio of the two bounds the speed-up of reading with the pool.
+//!
+//! ```text
+//! cargo run --release --example gzip_scan --features gzip_compressor -- FILE.gz
+//! ```
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, Cursor},
+    process,
+    time::Instant,
+};
+
+use pooled_writer::{gzip::GzipDecompressor, reader::Decompressor};
+
+type DynError = Box<dyn Error + 'static>;
+
+fn main() -> Result<(), DynError> {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: gzip_scan FILE.gz");
+            process::exit(1)
+        }
+    };
+
+    // Read the file into memory first, so that neither measurement includes reading it
+    let mut compressed = vec![];
+    std::io::copy(&mut BufReader::new(File::open(&path)?), &mut compressed)?;
+
+    let start = Instant::now();
+    let mut members = vec![];
+    let mut input = Cursor::new(compressed.as_slice());
+    while let Some(member) = GzipDecompressor::read_block(&mut input)? {
+        members.push(member);
+    }
+    let scan = start.elapsed();
+
+    let start = Instant::now();
+    let mut decompressor = GzipDecompressor::new();
+    let mut uncompressed = 0;
+    let mut output = vec![];
+    for member in &members {
+        output.clear();
+        decompressor.decompress(member, &mut output)?;
+        uncompressed += output.len();
+    }
+    let inflate = start.elapsed();
+
+    let mb_per_sec = |secs: f64| uncompressed as f64 / 1e6 / secs;
+    eprintln!(
+        "{} members, {} compressed bytes, {} uncompressed bytes",
+        members.len(),
+        compressed.len(),
+        uncompressed
+    );
+    eprintln!(
+        "finding members: {:.3}s ({:.1} MB/s), inflating: {:.3}s ({:.1} MB/s)",
+        scan.as_secs_f64(),
+        mb_per_sec(scan.as_secs_f64()),
+        inflate.as_secs_f64(),
+        mb_per_sec(inflate.as_secs_f64())
+    );
+    eprintln!(
+        "reading with the pool is at most {:.1}x as fast as inflating on one thread",
+        inflate.as_secs_f64() / scan.as_secs_f64()
+    );
+    Ok(())
+}
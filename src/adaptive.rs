@@ -0,0 +1,123 @@
+//! Adapting the compression level to load.
+//!
+//! When enabled via [`PoolBuilder::adaptive_compression`](crate::PoolBuilder::adaptive_compression),
+//! the pool threads watch how full the queue of blocks waiting to be compressed is as they take
+//! blocks from it.  While it stays above the high water mark the producers are outpacing the
+//! threads, so the compression level is lowered a step at a time, trading ratio for throughput;
+//! while it stays below the low water mark the threads have capacity to spare, so the level is
+//! raised again.
+use parking_lot::Mutex;
+
+/// Configuration for adaptive compression levels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveCompression {
+    /// The lowest compression level to fall back to under load.
+    pub min_level: u8,
+    /// The highest compression level, used when the pool keeps up; the pool starts at this level.
+    pub max_level: u8,
+    /// The fraction of the queue capacity above which the queue counts as full.
+    pub high_water: f64,
+    /// The fraction of the queue capacity below which the queue counts as idle.
+    pub low_water: f64,
+    /// The number of consecutive full (or idle) observations before the level is changed.
+    pub patience: usize,
+}
+
+impl AdaptiveCompression {
+    /// The default high water mark.
+    pub const DEFAULT_HIGH_WATER: f64 = 0.9;
+
+    /// The default low water mark.
+    pub const DEFAULT_LOW_WATER: f64 = 0.1;
+
+    /// The default number of consecutive observations before the level is changed.
+    pub const DEFAULT_PATIENCE: usize = 64;
+
+    /// Creates a new configuration adapting the level between `min_level` and `max_level`.
+    ///
+    /// Will panic if `min_level` exceeds `max_level`.
+    pub fn new(min_level: u8, max_level: u8) -> Self {
+        assert!(min_level <= max_level, "Minimum compression level must not exceed the maximum.");
+        Self {
+            min_level,
+            max_level,
+            high_water: Self::DEFAULT_HIGH_WATER,
+            low_water: Self::DEFAULT_LOW_WATER,
+            patience: Self::DEFAULT_PATIENCE,
+        }
+    }
+
+    /// Sets the low and high water marks, as fractions of the queue capacity.
+    ///
+    /// Will panic unless `0 <= low < high <= 1`.
+    pub fn water_marks(mut self, low: f64, high: f64) -> Self {
+        assert!(
+            0.0 <= low && low < high && high <= 1.0,
+            "Water marks must be 0 <= low < high <= 1."
+        );
+        self.low_water = low;
+        self.high_water = high;
+        self
+    }
+
+    /// Sets the number of consecutive observations before the level is changed.
+    ///
+    /// Will panic if set to 0.
+    pub fn patience(mut self, patience: usize) -> Self {
+        assert!(patience > 0, "Must wait for at least one observation.");
+        self.patience = patience;
+        self
+    }
+}
+
+#[derive(Debug)]
+struct ControllerState {
+    level: u8,
+    full: usize,
+    idle: usize,
+}
+
+/// Chooses the compression level from observations of the compressor queue, shared by the pool
+/// threads.
+#[derive(Debug)]
+pub(crate) struct LevelController {
+    config: AdaptiveCompression,
+    state: Mutex<ControllerState>,
+}
+
+impl LevelController {
+    pub(crate) fn new(config: &AdaptiveCompression) -> Self {
+        let state = ControllerState { level: config.max_level, full: 0, idle: 0 };
+        Self { config: config.clone(), state: Mutex::new(state) }
+    }
+
+    /// The compression level to use for the next block.
+    pub(crate) fn level(&self) -> u8 {
+        self.state.lock().level
+    }
+
+    /// Records that `queued` of `capacity` blocks were waiting to be compressed, and changes the
+    /// level once the queue has been full or idle for long enough.
+    pub(crate) fn observe(&self, queued: usize, capacity: usize) {
+        let fill = queued as f64 / std::cmp::max(capacity, 1) as f64;
+        let mut state = self.state.lock();
+        if fill >= self.config.high_water {
+            state.full += 1;
+            state.idle = 0;
+        } else if fill <= self.config.low_water {
+            state.idle += 1;
+            state.full = 0;
+        } else {
+            state.full = 0;
+            state.idle = 0;
+        }
+
+        if state.full >= self.config.patience {
+            state.level = std::cmp::max(state.level.saturating_sub(1), self.config.min_level);
+            state.full = 0;
+        } else if state.idle >= self.config.patience {
+            state.level = std::cmp::min(state.level.saturating_add(1), self.config.max_level);
+            state.idle = 0;
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! An [`Encoder`] that encrypts each block independently with AES-256-GCM.
+use std::fmt;
+
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use rand_core::{OsRng, RngCore};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::encoder::Encoder;
+use crate::CompressorCapabilities;
+
+/// The length of the random nonce that precedes each encrypted block.
+pub const NONCE_LEN: usize = 12;
+
+/// The length of the authentication tag that follows each encrypted block.
+pub const TAG_LEN: usize = 16;
+
+/// The errors that may be returned by the [`AesGcmEncoder`].
+#[derive(Error, Debug)]
+pub enum AesGcmError {
+    #[error("AES-GCM encryption failed")]
+    Encryption,
+}
+
+/// A 256 bit AES key, which is zeroized when dropped.
+#[derive(Clone)]
+pub struct AesGcmKey(Zeroizing<[u8; 32]>);
+
+impl From<[u8; 32]> for AesGcmKey {
+    fn from(key: [u8; 32]) -> Self {
+        Self(Zeroizing::new(key))
+    }
+}
+
+impl fmt::Debug for AesGcmKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AesGcmKey(..)")
+    }
+}
+
+/// An encoder that encrypts each block with AES-256-GCM under a 32 byte key.
+///
+/// Each block is written as a random 12 byte nonce, the ciphertext, and a 16 byte tag, so the
+/// output is a sequence of independently authenticated records.  Readers must know the block
+/// boundaries, e.g. by chaining after a compressor whose blocks are self-delimiting, or by
+/// framing the output themselves.  Since nonces are random, a single key should not be used
+/// for more than about 2^32 blocks.
+///
+/// To compress and then encrypt each block on the pool's threads, chain it after a compressor:
+///
+/// ```rust,no_run
+/// use pooled_writer::{aes::AesGcmEncoder, bgzf::BgzfCompressor, encoder::Chain};
+/// use pooled_writer::{Compressor, PoolBuilder};
+///
+/// let key = [42u8; 32];
+/// let mut builder = PoolBuilder::<_, BgzfCompressor>::new();
+/// let writer = builder.exchange_with_encoder::<Chain<BgzfCompressor, AesGcmEncoder>>(
+///     std::fs::File::create("out.gz.enc")?,
+///     (BgzfCompressor::new_compression_level(5)?, key.into()),
+/// )?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct AesGcmEncoder {
+    cipher: Aes256Gcm,
+}
+
+impl Encoder for AesGcmEncoder {
+    type Error = AesGcmError;
+    type Config = AesGcmKey;
+
+    fn capabilities() -> CompressorCapabilities {
+        CompressorCapabilities::new(Self::BLOCK_SIZE).deterministic(false)
+    }
+
+    fn new(key: Self::Config) -> Self {
+        Self { cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&*key.0)) }
+    }
+
+    fn encode(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        output.reserve(NONCE_LEN + input.len() + TAG_LEN);
+        output.extend_from_slice(&nonce);
+        let start = output.len();
+        output.extend_from_slice(input);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), b"", &mut output[start..])
+            .map_err(|_| AesGcmError::Encryption)?;
+        output.extend_from_slice(&tag);
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+//! Scaling the number of active threads to load.
+//!
+//! When enabled via [`PoolBuilder::autoscale`](crate::PoolBuilder::autoscale), the pool starts
+//! with only the minimum number of compressor threads doing work, and the threads watch how full
+//! the queue of blocks waiting to be compressed is as they take blocks from it.  While it stays
+//! above the high water mark the producers are outpacing the threads, so another thread is put
+//! to work; while it stays below the low water mark the threads have capacity to spare, so a
+//! thread is retired again.  Retired threads idle so that they can be put back to work without
+//! being spawned again.  A limit set with
+//! [`Pool::set_max_active_threads`](crate::Pool::set_max_active_threads) caps the range, so
+//! that the pool never puts more threads to work than the application allows.
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parking_lot::Mutex;
+
+/// Configuration for scaling the number of active threads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoScaling {
+    /// The fewest threads kept at work, however idle the pool; the pool starts with this many.
+    pub min_threads: usize,
+    /// The most threads put to work, however busy the pool.
+    pub max_threads: usize,
+    /// The fraction of the queue capacity above which the queue counts as full.
+    pub high_water: f64,
+    /// The fraction of the queue capacity below which the queue counts as idle.
+    pub low_water: f64,
+    /// The number of consecutive full (or idle) observations before a thread is added (or
+    /// retired).
+    pub patience: usize,
+}
+
+impl AutoScaling {
+    /// The default high water mark.
+    pub const DEFAULT_HIGH_WATER: f64 = 0.9;
+
+    /// The default low water mark.
+    pub const DEFAULT_LOW_WATER: f64 = 0.1;
+
+    /// The default number of consecutive observations before a thread is added or retired.
+    pub const DEFAULT_PATIENCE: usize = 64;
+
+    /// Creates a new configuration scaling the active threads between `min_threads` and
+    /// `max_threads`.
+    ///
+    /// Will panic if `min_threads` is 0 or exceeds `max_threads`.
+    pub fn new(min_threads: usize, max_threads: usize) -> Self {
+        assert!(min_threads > 0, "Must keep at least one thread active.");
+        assert!(min_threads <= max_threads, "Minimum threads must not exceed the maximum.");
+        Self {
+            min_threads,
+            max_threads,
+            high_water: Self::DEFAULT_HIGH_WATER,
+            low_water: Self::DEFAULT_LOW_WATER,
+            patience: Self::DEFAULT_PATIENCE,
+        }
+    }
+
+    /// Sets the low and high water marks, as fractions of the queue capacity.
+    ///
+    /// Will panic unless `0 <= low < high <= 1`.
+    pub fn water_marks(mut self, low: f64, high: f64) -> Self {
+        assert!(
+            0.0 <= low && low < high && high <= 1.0,
+            "Water marks must be 0 <= low < high <= 1."
+        );
+        self.low_water = low;
+        self.high_water = high;
+        self
+    }
+
+    /// Sets the number of consecutive observations before a thread is added or retired.
+    ///
+    /// Will panic if set to 0.
+    pub fn patience(mut self, patience: usize) -> Self {
+        assert!(patience > 0, "Must wait for at least one observation.");
+        self.patience = patience;
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+struct ScalerState {
+    full: usize,
+    idle: usize,
+}
+
+/// Chooses the number of active threads from observations of the compressor queue, shared by
+/// the pool threads.
+#[derive(Debug)]
+pub(crate) struct ThreadScaler {
+    config: AutoScaling,
+    state: Mutex<ScalerState>,
+}
+
+impl ThreadScaler {
+    /// Creates a scaler for a pool with `threads` threads that may compress, to which the
+    /// configured range is capped.
+    pub(crate) fn new(config: &AutoScaling, threads: usize) -> Self {
+        let mut config = config.clone();
+        config.max_threads = std::cmp::min(config.max_threads, threads);
+        config.min_threads = std::cmp::min(config.min_threads, config.max_threads);
+        Self { config, state: Mutex::new(ScalerState::default()) }
+    }
+
+    /// The number of threads active when the pool starts.
+    pub(crate) fn initial_threads(&self) -> usize {
+        self.config.min_threads
+    }
+
+    /// Records that `queued` of `capacity` blocks were waiting to be compressed, and adds or
+    /// retires a thread in `active` once the queue has been full or idle for long enough,
+    /// keeping it within the configured range capped at `cap` threads.  Returns true if a
+    /// thread was added.
+    pub(crate) fn observe(
+        &self,
+        queued: usize,
+        capacity: usize,
+        active: &AtomicUsize,
+        cap: usize,
+    ) -> bool {
+        let fill = queued as f64 / std::cmp::max(capacity, 1) as f64;
+        let mut state = self.state.lock();
+        if fill >= self.config.high_water {
+            state.full += 1;
+            state.idle = 0;
+        } else if fill <= self.config.low_water {
+            state.idle += 1;
+            state.full = 0;
+        } else {
+            state.full = 0;
+            state.idle = 0;
+        }
+
+        // Only observations store to `active`, and they are serialized by the state's lock
+        let max_threads = std::cmp::min(self.config.max_threads, cap);
+        let min_threads = std::cmp::min(self.config.min_threads, max_threads);
+        let mut current = active.load(Ordering::Relaxed);
+        if current > max_threads {
+            current = max_threads;
+            active.store(current, Ordering::Relaxed);
+        }
+        if state.full >= self.config.patience {
+            state.full = 0;
+            if current < max_threads {
+                active.store(current + 1, Ordering::Relaxed);
+                return true;
+            }
+        } else if state.idle >= self.config.patience {
+            state.idle = 0;
+            if current > min_threads {
+                active.store(current - 1, Ordering::Relaxed);
+            }
+        }
+        false
+    }
+}
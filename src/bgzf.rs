@@ -1,39 +1,463 @@
 ///! An implementation of [`Compressor`] for the `BGZF` format.
-use crate::Compressor;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::reader::{Decompressor, PooledReader};
+use crate::{
+    check_round_trip, BlockObserver, Compressor, CompressorCapabilities, ExtraSubfield, GzipHeader,
+    PoolBuilder, PooledWriter, Sink,
+};
+
+/// The offset of the two byte `XLEN` field within a BGZF block header.
+const XLEN_OFFSET: usize = 10;
+
+/// The offset of the two byte `BSIZE` field within a BGZF block header.
+const BSIZE_OFFSET: usize = 16;
+
+/// The length of the fixed BGZF block header, including the `BC` subfield.
+const HEADER_LEN: usize = 18;
+
+/// The maximum total size of a BGZF block, including header and footer.
+const MAX_BLOCK_LEN: usize = 64 * 1024;
+
+/// The length of the BGZF block footer, holding the CRC32 and the uncompressed size.
+const FOOTER_LEN: usize = 8;
+
+/// The length of the header of a stored (uncompressed) DEFLATE block.
+const STORED_HEADER_LEN: usize = 5;
+
+/// The largest number of bytes that fit in a BGZF block as a single stored DEFLATE block.
+const MAX_STORED_LEN: usize = MAX_BLOCK_LEN - HEADER_LEN - STORED_HEADER_LEN - FOOTER_LEN;
+
+/// Inserts `subfields` into the FEXTRA field of the BGZF block starting at `start` within
+/// `block`, after the required `BC` subfield, updating `XLEN` and `BSIZE` accordingly.
+///
+/// Returns an error if the block would exceed the 64 KiB BGZF block size limit.
+pub fn add_extra_subfields(
+    block: &mut Vec<u8>,
+    start: usize,
+    subfields: &[ExtraSubfield],
+) -> io::Result<()> {
+    if subfields.is_empty() {
+        return Ok(());
+    }
+
+    let mut extra = Vec::with_capacity(subfields.iter().map(ExtraSubfield::encoded_len).sum());
+    subfields.iter().for_each(|s| s.encode(&mut extra));
+
+    let block_len = block.len() - start + extra.len();
+    if block_len > MAX_BLOCK_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "BGZF block of {} bytes with extra subfields exceeds {}",
+                block_len, MAX_BLOCK_LEN
+            ),
+        ));
+    }
+
+    let read_u16 = |b: &[u8], at: usize| u16::from_le_bytes([b[at], b[at + 1]]) as usize;
+    let xlen = read_u16(block, start + XLEN_OFFSET) + extra.len();
+    if xlen > u16::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "BGZF extra field is too long"));
+    }
+    block[start + XLEN_OFFSET..start + XLEN_OFFSET + 2]
+        .copy_from_slice(&(xlen as u16).to_le_bytes());
+    block[start + BSIZE_OFFSET..start + BSIZE_OFFSET + 2]
+        .copy_from_slice(&((block_len - 1) as u16).to_le_bytes());
+
+    let header_end = start + HEADER_LEN;
+    block.splice(header_end..header_end, extra);
+    Ok(())
+}
+
+/// Concatenates the BGZF streams read from `inputs`, in order, into a single BGZF stream
+/// written to `output`.  Empty blocks, including the EOF marker block at the end of each input,
+/// are dropped and a single EOF marker block is written at the end of the output.
+///
+/// This supports the common map-then-merge pattern in which several writers each produce a
+/// temporary output that is then combined into one final file.  Blocks are copied without being
+/// decompressed.
+pub fn concatenate<I, R, W>(inputs: I, output: &mut W) -> io::Result<()>
+where
+    I: IntoIterator<Item = R>,
+    R: Read,
+    W: Write,
+{
+    let mut block = Vec::with_capacity(MAX_BLOCK_LEN);
+    for mut input in inputs {
+        while read_block(&mut input, &mut block)? {
+            let isize_at = block.len() - 4;
+            if block[isize_at..] != [0, 0, 0, 0] {
+                output.write_all(&block)?;
+            }
+        }
+    }
+
+    let mut eof = Vec::new();
+    bgzf::Compressor::append_eof(&mut eof);
+    output.write_all(&eof)?;
+    output.flush()
+}
+
+/// Reads the next complete BGZF block from `input` into `block`, returning false if `input` was
+/// already at end of file.
+pub(crate) fn read_block<R: Read>(input: &mut R, block: &mut Vec<u8>) -> io::Result<bool> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    block.clear();
+    block.resize(XLEN_OFFSET + 2, 0);
+    let mut read = 0;
+    while read < block.len() {
+        match input.read(&mut block[read..])? {
+            0 if read == 0 => return Ok(false),
+            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            n => read += n,
+        }
+    }
+    if block[..4] != [0x1f, 0x8b, 0x08, 0x04] {
+        return Err(invalid("not a BGZF block: bad gzip magic or missing FEXTRA"));
+    }
+
+    let xlen = u16::from_le_bytes([block[XLEN_OFFSET], block[XLEN_OFFSET + 1]]) as usize;
+    let extra_start = block.len();
+    block.resize(extra_start + xlen, 0);
+    input.read_exact(&mut block[extra_start..])?;
+
+    // Find the BC subfield holding the total block size minus one
+    let mut bsize = None;
+    let mut at = extra_start;
+    while at + 4 <= block.len() {
+        let len = u16::from_le_bytes([block[at + 2], block[at + 3]]) as usize;
+        if block[at..at + 2] == *b"BC" && len == 2 && at + 6 <= block.len() {
+            bsize = Some(u16::from_le_bytes([block[at + 4], block[at + 5]]) as usize + 1);
+        }
+        at += 4 + len;
+    }
+    let bsize = bsize.ok_or_else(|| invalid("not a BGZF block: missing BC subfield"))?;
+    if bsize < block.len() + 8 {
+        return Err(invalid("BGZF block size is smaller than its header and footer"));
+    }
+
+    let rest = block.len();
+    block.resize(bsize, 0);
+    input.read_exact(&mut block[rest..])?;
+    Ok(true)
+}
+
+/// Appends `input` to `output` as a BGZF block holding a single stored DEFLATE block, i.e.
+/// without compressing it.
+///
+/// Returns an error if `input` is too large to fit in a single BGZF block when stored.
+fn store_block(input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+    if input.len() > MAX_STORED_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "stored BGZF block of {} bytes exceeds the maximum of {}",
+                input.len(),
+                MAX_STORED_LEN
+            ),
+        ));
+    }
+
+    let bsize = (HEADER_LEN + STORED_HEADER_LEN + input.len() + FOOTER_LEN - 1) as u16;
+    output
+        .extend_from_slice(&[0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0]);
+    output.extend_from_slice(&bsize.to_le_bytes());
+
+    // BFINAL set, BTYPE 00 (stored), then LEN and its one's complement NLEN
+    let len = input.len() as u16;
+    output.push(1);
+    output.extend_from_slice(&len.to_le_bytes());
+    output.extend_from_slice(&(!len).to_le_bytes());
+    output.extend_from_slice(input);
+
+    output.extend_from_slice(&libdeflater::crc32(input).to_le_bytes());
+    output.extend_from_slice(&(input.len() as u32).to_le_bytes());
+    Ok(())
+}
+
+/// The compression level of a [`BgzfCompressor`].
+#[derive(Debug, Clone, Copy)]
+pub enum BgzfCompressionLevel {
+    /// Level 0: blocks are stored without being deflated, keeping BGZF's blocking and virtual
+    /// offsets without the CPU cost of compression.
+    Store,
+    /// Levels 1 to 12: blocks are deflated at the given level.
+    Deflate(bgzf::CompressionLevel),
+}
 
 /// A BGZF compressor.
+///
+/// Level 0 stores blocks uncompressed, see [`BgzfCompressionLevel::Store`].  Blocks hold up to
+/// [`bgzf::BGZF_BLOCK_SIZE`] bytes by default; smaller blocks, for finer grained random access,
+/// may be chosen with [`PoolBuilder::block_size`](crate::PoolBuilder::block_size).
 pub struct BgzfCompressor {
-    inner: bgzf::Compressor,
+    /// The deflating compressor, or `None` if blocks are stored.
+    inner: Option<bgzf::Compressor>,
+    /// The header fields written at the start of each block, if not the default.
+    header: Option<GzipHeader>,
+}
+
+impl BgzfCompressor {
+    /// Compresses, or stores, `input` as a single BGZF block appended to `output`.
+    fn compress_block(&mut self, input: &[u8], output: &mut Vec<u8>) -> bgzf::BgzfResult<()> {
+        let start = output.len();
+        match &mut self.inner {
+            Some(inner) => inner.compress(input, output)?,
+            None => store_block(input, output)?,
+        }
+        if let Some(header) = &self.header {
+            header.apply(output, start);
+        }
+        Ok(())
+    }
 }
 
 impl Compressor for BgzfCompressor {
     type Error = bgzf::BgzfError;
-    type CompressionLevel = bgzf::CompressionLevel;
+    type CompressionLevel = BgzfCompressionLevel;
 
     const BLOCK_SIZE: usize = bgzf::BGZF_BLOCK_SIZE;
 
+    fn capabilities() -> CompressorCapabilities {
+        CompressorCapabilities::new(Self::BLOCK_SIZE)
+            .eof_marker(true)
+            .dictionaries(false)
+            .extra_subfields(true)
+            .compression_levels(0, 12)
+            .deterministic(true)
+            .verification(true)
+            .gzip_header(true, false)
+    }
+
     fn new(compression_level: Self::CompressionLevel) -> Self {
-        Self { inner: bgzf::Compressor::new(compression_level) }
+        match compression_level {
+            BgzfCompressionLevel::Store => Self { inner: None, header: None },
+            BgzfCompressionLevel::Deflate(level) => {
+                Self { inner: Some(bgzf::Compressor::new(level)), header: None }
+            }
+        }
     }
 
     fn default_compression_level() -> Self::CompressionLevel {
-        bgzf::CompressionLevel::new(5).unwrap()
+        BgzfCompressionLevel::Deflate(bgzf::CompressionLevel::new(5).unwrap())
     }
 
     fn new_compression_level(compression_level: u8) -> Result<Self::CompressionLevel, Self::Error> {
-        bgzf::CompressionLevel::new(compression_level)
+        match compression_level {
+            0 => Ok(BgzfCompressionLevel::Store),
+            level => bgzf::CompressionLevel::new(level).map(BgzfCompressionLevel::Deflate),
+        }
+    }
+
+    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        self.compress_block(input, output)
     }
 
-    fn compress(
+    fn compress_with_extra_subfields(
         &mut self,
         input: &[u8],
         output: &mut Vec<u8>,
-        is_last: bool,
+        subfields: &[ExtraSubfield],
     ) -> Result<(), Self::Error> {
-        self.inner.compress(input, output)?;
-        if is_last {
-            bgzf::Compressor::append_eof(output);
+        let start = output.len();
+        self.compress_block(input, output)?;
+        add_extra_subfields(output, start, subfields)?;
+        Ok(())
+    }
+
+    /// Sets the `MTIME`, `XFL` and `OS` of each block, though not of the EOF marker block,
+    /// whose bytes are fixed.  BGZF does not allow a file name.
+    fn set_gzip_header(&mut self, header: &GzipHeader) {
+        self.header = Some(header.clone());
+    }
+
+    fn verify(&mut self, input: &[u8], compressed: &[u8]) -> io::Result<()> {
+        // The reader checks the CRC of each block as well as decompressing it
+        let mut decompressed = Vec::with_capacity(input.len());
+        bgzf::Reader::new(compressed).read_to_end(&mut decompressed)?;
+        check_round_trip(input, &decompressed)
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        bgzf::Compressor::append_eof(output);
+        Ok(())
+    }
+}
+
+/// A [`PooledReader`] of a BGZF stream, whose blocks are decompressed in parallel by the
+/// threads of a pool and read in order.  Create one with
+/// [`PoolBuilder::exchange_reader`](crate::PoolBuilder::exchange_reader).
+pub type PooledBgzfReader<R> = PooledReader<R, BgzfDecompressor>;
+
+/// An implementation of [`Decompressor`] for the `BGZF` format, which splits a stream into its
+/// blocks and decompresses each one independently.
+pub struct BgzfDecompressor {
+    inflater: libdeflater::Decompressor,
+}
+
+impl Decompressor for BgzfDecompressor {
+    type Error = io::Error;
+
+    fn new() -> Self {
+        Self { inflater: libdeflater::Decompressor::new() }
+    }
+
+    fn read_block<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+        let mut block = Vec::with_capacity(MAX_BLOCK_LEN);
+        Ok(read_block(reader, &mut block)?.then(|| block))
+    }
+
+    fn decompress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        let isize_at = input.len() - 4;
+        let len = u32::from_le_bytes([
+            input[isize_at],
+            input[isize_at + 1],
+            input[isize_at + 2],
+            input[isize_at + 3],
+        ]) as usize;
+        if len > MAX_BLOCK_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("BGZF block claims {} uncompressed bytes, more than allowed", len),
+            ));
+        }
+        let start = output.len();
+        output.resize(start + len, 0);
+        let n = self
+            .inflater
+            .gzip_decompress(input, &mut output[start..])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        if n != len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("BGZF block decompressed to {} bytes, expected {}", n, len),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Builds a `.gzi` index of a BGZF stream, as written by `bgzip -i`, from its compressed blocks:
+/// the compressed and uncompressed offsets of the end of each non-empty block.
+#[derive(Debug, Default)]
+struct GziIndex {
+    compressed: u64,
+    uncompressed: u64,
+    entries: Vec<(u64, u64)>,
+}
+
+impl GziIndex {
+    /// Adds the blocks in `blocks`, which hold one or more whole BGZF blocks.
+    fn add_blocks(&mut self, blocks: &[u8]) -> io::Result<()> {
+        let mut at = 0;
+        while at < blocks.len() {
+            if blocks.len() < at + HEADER_LEN {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated BGZF block"));
+            }
+            let bsize =
+                u16::from_le_bytes([blocks[at + BSIZE_OFFSET], blocks[at + BSIZE_OFFSET + 1]])
+                    as usize
+                    + 1;
+            if blocks.len() < at + bsize {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated BGZF block"));
+            }
+            let isize_at = at + bsize - 4;
+            let mut isize = [0u8; 4];
+            isize.copy_from_slice(&blocks[isize_at..isize_at + 4]);
+            let isize = u32::from_le_bytes(isize);
+            self.compressed += bsize as u64;
+            self.uncompressed += u64::from(isize);
+            if isize > 0 {
+                self.entries.push((self.compressed, self.uncompressed));
+            }
+            at += bsize;
         }
         Ok(())
     }
+
+    /// Writes the index in the `.gzi` format: the number of entries followed by each entry's
+    /// compressed and uncompressed offsets, all as little endian 64-bit integers.
+    fn write<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        output.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for (compressed, uncompressed) in &self.entries {
+            output.write_all(&compressed.to_le_bytes())?;
+            output.write_all(&uncompressed.to_le_bytes())?;
+        }
+        output.flush()
+    }
+}
+
+impl<W> PoolBuilder<W, BgzfCompressor>
+where
+    W: Write + Send + 'static,
+{
+    /// Exchanges a writer for a [`PooledWriter`] whose BGZF output is indexed as it is written,
+    /// writing a `.gzi` index, as written by `bgzip -i`, to `index` when the stream is finished.
+    pub fn exchange_with_gzi(&mut self, writer: W, mut index: W) -> PooledWriter {
+        let mut gzi = GziIndex::default();
+        let observer: BlockObserver = Box::new(move |blocks: &[u8], is_last: bool| {
+            gzi.add_blocks(blocks)?;
+            if is_last {
+                gzi.write(&mut index)?;
+                gzi = GziIndex::default();
+            }
+            Ok(())
+        });
+
+        let mut sink = Sink::new(writer, None);
+        sink.block_observer = Some(observer);
+        let block_size = self.writer_block_size();
+        self.exchange_sink::<BgzfCompressor>(sink, block_size, None)
+    }
+}
+
+impl<W> PoolBuilder<W, BgzfCompressor>
+where
+    W: Read + Write + Seek + Send + 'static,
+{
+    /// Exchanges a writer of an existing BGZF file for a [`PooledWriter`] that appends to it,
+    /// e.g. to resume a long-running job rather than rewriting its output.  BGZF blocks are
+    /// independent of one another, so no state is carried over from the existing blocks.
+    ///
+    /// If the file ends with the EOF marker block, the writer is positioned over it so that the
+    /// marker is overwritten by the appended blocks and the new marker written at the end of the
+    /// stream; otherwise it is positioned at the end of the file.  Virtual offsets, and the
+    /// limit set with [`PoolBuilder::max_output_size`], account for the existing bytes.
+    ///
+    /// Returns an error if the file is not empty and does not start with a BGZF block.
+    pub fn exchange_append(&mut self, mut writer: W) -> io::Result<PooledWriter> {
+        let len = writer.seek(SeekFrom::End(0))?;
+        let mut end = len;
+        if len > 0 {
+            let mut magic = [0u8; 4];
+            writer.seek(SeekFrom::Start(0))?;
+            writer.read_exact(&mut magic)?;
+            if magic != [0x1f, 0x8b, 0x08, 0x04] {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "cannot append to a file that is not BGZF",
+                ));
+            }
+        }
+        if len >= bgzf::BGZF_EOF.len() as u64 {
+            let mut tail = vec![0u8; bgzf::BGZF_EOF.len()];
+            writer.seek(SeekFrom::End(-(tail.len() as i64)))?;
+            writer.read_exact(&mut tail)?;
+            if tail[..] == bgzf::BGZF_EOF[..] {
+                end -= tail.len() as u64;
+            }
+        }
+        writer.seek(SeekFrom::Start(end))?;
+
+        let mut sink = Sink::new(writer, None);
+        sink.output_offset = end;
+        let block_size = self.writer_block_size();
+        let writer = self.exchange_sink::<BgzfCompressor>(sink, block_size, None);
+        if let Some(offsets) = &writer.shared.offsets {
+            offsets.start_at(end);
+        }
+        Ok(writer)
+    }
 }
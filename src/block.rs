@@ -0,0 +1,75 @@
+//! A stable framing unit for compressed blocks that leave the pool.
+//!
+//! A [`CompressedBlock`] carries a single compressed block together with enough information to
+//! place it back into its writer's stream and to check it, for applications that move blocks
+//! between processes themselves.  A writer exchanged with [`PoolBuilder::exchange_blocks`]
+//! hands each of its blocks to a callback as a [`CompressedBlock`], and blocks may also be
+//! built from the compressed and uncompressed bytes with [`CompressedBlock::new`].  With the
+//! `serde` feature enabled it may be serialized with any serde format.  Requires one of the
+//! features that use `libdeflater`, whose CRC32 is used for the checksums.
+use std::io::{self, Write};
+
+use crate::{BlockFramer, Compressor, PoolBuilder, PooledWriter, Sink, WriterMessage};
+
+/// A single compressed block of a writer's stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CompressedBlock {
+    /// The index of the writer whose stream the block belongs to.
+    pub writer_id: usize,
+    /// The position of the block within the writer's stream, starting at zero.
+    pub seq: u64,
+    /// The compressed bytes.
+    pub bytes: Vec<u8>,
+    /// The number of uncompressed bytes in the block.
+    pub uncompressed_len: usize,
+    /// The CRC32 of the uncompressed bytes, as used by gzip and BGZF.
+    pub checksum: u32,
+}
+
+impl CompressedBlock {
+    /// Creates a block from the compressed `bytes` of `uncompressed`, computing the length and
+    /// checksum of the uncompressed bytes.
+    pub fn new(writer_id: usize, seq: u64, bytes: Vec<u8>, uncompressed: &[u8]) -> Self {
+        Self {
+            writer_id,
+            seq,
+            bytes,
+            uncompressed_len: uncompressed.len(),
+            checksum: libdeflater::crc32(uncompressed),
+        }
+    }
+
+    /// Returns true if `uncompressed`, e.g. the result of decompressing the block, matches the
+    /// recorded length and checksum.
+    pub fn matches(&self, uncompressed: &[u8]) -> bool {
+        uncompressed.len() == self.uncompressed_len
+            && libdeflater::crc32(uncompressed) == self.checksum
+    }
+}
+
+impl<W, C> PoolBuilder<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    /// Creates a [`PooledWriter`] without an underlying writer whose blocks are passed to
+    /// `callback` as [`CompressedBlock`]s, on a pool thread and in order, e.g. to send them to
+    /// another process.  The `writer_id` of each block is the index of the writer in the pool,
+    /// and its `seq` the number of the block within the writer's stream.
+    pub fn exchange_blocks<F>(&mut self, mut callback: F) -> PooledWriter
+    where
+        F: FnMut(&CompressedBlock) -> io::Result<()> + Send + 'static,
+    {
+        let writer_id = self.writer_index;
+        let framer: BlockFramer = Box::new(move |message: &WriterMessage| {
+            let raw = message.raw.as_deref().unwrap_or_default();
+            let bytes = message.buffer.clone();
+            callback(&CompressedBlock::new(writer_id, message.block_number, bytes, raw))
+        });
+        let mut sink = Sink::discard();
+        sink.framer = Some(framer);
+        let block_size = self.writer_block_size();
+        self.exchange_sink::<C>(sink, block_size, None)
+    }
+}
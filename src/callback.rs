@@ -0,0 +1,50 @@
+//! A writer that hands each compressed block to a callback, for quick integrations that would
+//! otherwise need a newtype implementing [`Write`].
+use std::fmt;
+use std::io::{self, Write};
+
+/// A [`Write`] implementation that passes every buffer written to it to a callback.  When
+/// exchanged with a pool the callback is called on a pool thread with the compressed bytes of
+/// each block, in order, e.g. to push them to an existing channel.
+///
+/// See also [`PoolBuilder::exchange_callback`](crate::PoolBuilder::exchange_callback) for pools
+/// of boxed writers.
+pub struct CallbackWriter<F>
+where
+    F: FnMut(&[u8]) -> io::Result<()> + Send,
+{
+    callback: F,
+}
+
+impl<F> CallbackWriter<F>
+where
+    F: FnMut(&[u8]) -> io::Result<()> + Send,
+{
+    /// Creates a new writer that calls `callback` with every buffer written.
+    pub fn new(callback: F) -> Self {
+        Self { callback }
+    }
+}
+
+impl<F> Write for CallbackWriter<F>
+where
+    F: FnMut(&[u8]) -> io::Result<()> + Send,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.callback)(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F> fmt::Debug for CallbackWriter<F>
+where
+    F: FnMut(&[u8]) -> io::Result<()> + Send,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallbackWriter").finish()
+    }
+}
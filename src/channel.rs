@@ -0,0 +1,266 @@
+//! The channels used to pass messages between writers and the pool's threads.
+//!
+//! The pool needs multi-producer multi-consumer channels, which are provided by `flume` by
+//! default (the `flume_channels` feature) or by `crossbeam-channel` (the `crossbeam_channels`
+//! feature, which takes precedence if both are enabled, though `flume` is then built for
+//! nothing, so default features should be disabled).  `std::sync::mpsc` is not an option since
+//! its receivers cannot be shared between threads.  The errors of the chosen implementation are
+//! not exposed, so that enabling either feature doesn't change the crate's API.
+//!
+//! The pool's threads block on a [`Doorbell`] while idle, which is rung by the
+//! [`DoorbellSender`]s of the channels that carry work to them.
+
+use std::fmt;
+use std::sync::Arc;
+
+use parking_lot::{Condvar, Mutex};
+
+#[cfg(not(any(feature = "flume_channels", feature = "crossbeam_channels")))]
+compile_error!("One of the `flume_channels` or `crossbeam_channels` features must be enabled.");
+
+#[cfg(not(feature = "crossbeam_channels"))]
+pub(crate) use flume::{
+    bounded, unbounded, Receiver, RecvError, RecvTimeoutError, SendError, Sender,
+};
+
+#[cfg(feature = "crossbeam_channels")]
+pub(crate) use self::crossbeam::{bounded, unbounded, Receiver, Sender};
+#[cfg(feature = "crossbeam_channels")]
+pub(crate) use crossbeam_channel::{RecvError, RecvTimeoutError, SendError};
+
+/// Thin wrappers around `crossbeam-channel` that also track whether the other side of a channel
+/// has been dropped, which `crossbeam-channel` does not expose.
+#[cfg(feature = "crossbeam_channels")]
+mod crossbeam {
+    use std::fmt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crossbeam_channel::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+
+    /// The number of live senders and receivers of a channel.
+    #[derive(Debug)]
+    struct Counts {
+        senders: AtomicUsize,
+        receivers: AtomicUsize,
+    }
+
+    pub(crate) struct Sender<T> {
+        inner: crossbeam_channel::Sender<T>,
+        counts: Arc<Counts>,
+    }
+
+    pub(crate) struct Receiver<T> {
+        inner: crossbeam_channel::Receiver<T>,
+        counts: Arc<Counts>,
+    }
+
+    /// Wraps the two halves of a new channel.
+    fn wrap<T>(
+        (tx, rx): (crossbeam_channel::Sender<T>, crossbeam_channel::Receiver<T>),
+    ) -> (Sender<T>, Receiver<T>) {
+        let counts =
+            Arc::new(Counts { senders: AtomicUsize::new(1), receivers: AtomicUsize::new(1) });
+        (Sender { inner: tx, counts: counts.clone() }, Receiver { inner: rx, counts })
+    }
+
+    pub(crate) fn bounded<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+        wrap(crossbeam_channel::bounded(cap))
+    }
+
+    pub(crate) fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+        wrap(crossbeam_channel::unbounded())
+    }
+
+    impl<T> Sender<T> {
+        pub(crate) fn send(&self, msg: T) -> Result<(), SendError<T>> {
+            self.inner.send(msg)
+        }
+
+        pub(crate) fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+            self.inner.try_send(msg)
+        }
+
+        pub(crate) fn is_empty(&self) -> bool {
+            self.inner.is_empty()
+        }
+
+        /// True if all receivers have been dropped.
+        pub(crate) fn is_disconnected(&self) -> bool {
+            self.counts.receivers.load(Ordering::SeqCst) == 0
+        }
+    }
+
+    impl<T> Receiver<T> {
+        pub(crate) fn recv(&self) -> Result<T, RecvError> {
+            self.inner.recv()
+        }
+
+        pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+            self.inner.try_recv()
+        }
+
+        pub(crate) fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+            self.inner.recv_timeout(timeout)
+        }
+
+        pub(crate) fn is_empty(&self) -> bool {
+            self.inner.is_empty()
+        }
+
+        pub(crate) fn len(&self) -> usize {
+            self.inner.len()
+        }
+
+        pub(crate) fn capacity(&self) -> Option<usize> {
+            self.inner.capacity()
+        }
+
+        /// True if all senders have been dropped.
+        pub(crate) fn is_disconnected(&self) -> bool {
+            self.counts.senders.load(Ordering::SeqCst) == 0
+        }
+    }
+
+    impl<T> Clone for Sender<T> {
+        fn clone(&self) -> Self {
+            self.counts.senders.fetch_add(1, Ordering::SeqCst);
+            Self { inner: self.inner.clone(), counts: self.counts.clone() }
+        }
+    }
+
+    impl<T> Clone for Receiver<T> {
+        fn clone(&self) -> Self {
+            self.counts.receivers.fetch_add(1, Ordering::SeqCst);
+            Self { inner: self.inner.clone(), counts: self.counts.clone() }
+        }
+    }
+
+    impl<T> Drop for Sender<T> {
+        fn drop(&mut self) {
+            self.counts.senders.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    impl<T> Drop for Receiver<T> {
+        fn drop(&mut self) {
+            self.counts.receivers.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    impl<T> fmt::Debug for Sender<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Sender").field("inner", &self.inner).finish()
+        }
+    }
+
+    impl<T> fmt::Debug for Receiver<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Receiver").field("inner", &self.inner).finish()
+        }
+    }
+}
+
+/// Wakes the pool's idle threads when there may be work for them, so that they can block until
+/// then rather than polling their channels.  A thread notes [`Doorbell::rings`] before checking
+/// its channels, so that a ring between the check and [`Doorbell::wait`] is not missed.
+#[derive(Debug, Default)]
+pub(crate) struct Doorbell {
+    /// The number of times the doorbell has been rung.
+    rings: Mutex<u64>,
+    /// Notified for each kind of [`Waiters`], in order.
+    rung: [Condvar; 3],
+}
+
+/// The threads that wait together for a [`Doorbell`], so that a message wakes one thread that
+/// can act on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Waiters {
+    /// Threads that compress, including those that also write.
+    Compressors = 0,
+    /// Dedicated writer threads, see [`crate::PoolBuilder::writer_threads`].
+    Writers = 1,
+    /// Threads beyond the limit on active threads, which only wake when every thread is woken.
+    Parked = 2,
+}
+
+impl Doorbell {
+    /// The number of times the doorbell has been rung so far.
+    pub(crate) fn rings(&self) -> u64 {
+        *self.rings.lock()
+    }
+
+    /// Rings the doorbell, waking every waiting thread, e.g. on shutdown or when the limit on
+    /// active threads changes.
+    pub(crate) fn ring(&self) {
+        *self.rings.lock() += 1;
+        for rung in &self.rung {
+            rung.notify_all();
+        }
+    }
+
+    /// Rings the doorbell for a single message, waking one of `waiters`.
+    pub(crate) fn ring_one(&self, waiters: Waiters) {
+        *self.rings.lock() += 1;
+        self.rung[waiters as usize].notify_one();
+    }
+
+    /// Blocks one of `waiters` until the doorbell has been rung more than `seen` times.
+    pub(crate) fn wait(&self, seen: u64, waiters: Waiters) {
+        let mut rings = self.rings.lock();
+        while *rings == seen {
+            self.rung[waiters as usize].wait(&mut rings);
+        }
+    }
+}
+
+/// A sender that wakes one of its [`Waiters`] after each message it sends, and rings a
+/// [`Doorbell`] for every thread when it is dropped so that the pool's threads notice a
+/// disconnected channel.
+pub(crate) struct DoorbellSender<T> {
+    /// Only `None` while being dropped, so that the channel is disconnected before the ring.
+    inner: Option<Sender<T>>,
+    doorbell: Arc<Doorbell>,
+    waiters: Waiters,
+}
+
+impl<T> DoorbellSender<T> {
+    /// Wraps `inner` so that sending on it wakes one of `waiters`.
+    pub(crate) fn new(inner: Sender<T>, doorbell: Arc<Doorbell>, waiters: Waiters) -> Self {
+        Self { inner: Some(inner), doorbell, waiters }
+    }
+
+    fn inner(&self) -> &Sender<T> {
+        self.inner.as_ref().expect("Unreachable")
+    }
+
+    pub(crate) fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        let result = self.inner().send(msg);
+        self.doorbell.ring_one(self.waiters);
+        result
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.inner().is_empty()
+    }
+}
+
+impl<T> Clone for DoorbellSender<T> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone(), doorbell: self.doorbell.clone(), waiters: self.waiters }
+    }
+}
+
+impl<T> Drop for DoorbellSender<T> {
+    fn drop(&mut self) {
+        drop(self.inner.take());
+        self.doorbell.ring();
+    }
+}
+
+impl<T> fmt::Debug for DoorbellSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DoorbellSender").field("inner", &self.inner).finish()
+    }
+}
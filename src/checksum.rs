@@ -0,0 +1,147 @@
+//! Checksums of the uncompressed bytes of a stream, e.g. for the md5s that submission portals
+//! require, computed on the pool's threads as each block is written rather than by re-reading
+//! the output afterwards.
+//!
+//! Streams that only need hashing, e.g. to digest inputs alongside the compressed outputs, can
+//! be hashed on the same threads with [`PoolBuilder::exchange_hasher`] rather than by a second
+//! thread pool.
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use sha2::digest::DynDigest;
+
+use crate::noop::NoopCompressor;
+use crate::{Compressor, PoolBuilder, PoolResult, PooledWriter, RawObserver, Sink};
+
+/// The algorithms with which a stream may be checksummed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha256,
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    /// The conventional extension of a sidecar file holding a checksum, e.g. `md5`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Blake3 => "b3",
+        }
+    }
+
+    fn hasher(&self) -> Box<dyn DynDigest + Send> {
+        match self {
+            ChecksumAlgorithm::Md5 => Box::new(md5::Md5::default()),
+            ChecksumAlgorithm::Sha256 => Box::new(sha2::Sha256::default()),
+            ChecksumAlgorithm::Blake3 => Box::new(blake3::Hasher::default()),
+        }
+    }
+}
+
+/// A handle to the checksum of a writer's stream, which is available once the final block of
+/// the stream has been written, e.g. after [`Pool::stop_pool`](crate::Pool::stop_pool).
+#[derive(Debug, Clone, Default)]
+pub struct ChecksumHandle {
+    hex: Arc<Mutex<Option<String>>>,
+}
+
+impl ChecksumHandle {
+    /// The lower case hex checksum of the most recently finished stream, or `None` if no stream
+    /// has been finished yet.
+    pub fn hex(&self) -> Option<String> {
+        self.hex.lock().clone()
+    }
+}
+
+impl<W, C> PoolBuilder<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    /// Exchanges a writer for a [`PooledWriter`] whose uncompressed bytes are also checksummed
+    /// with `algorithm` on the pool's threads.  The checksum is available from the returned
+    /// handle once the stream is finished.
+    pub fn exchange_with_checksum(
+        &mut self,
+        writer: W,
+        algorithm: ChecksumAlgorithm,
+    ) -> (PooledWriter, ChecksumHandle) {
+        self.exchange_checksummed(writer, algorithm, None)
+    }
+
+    /// Exchanges a writer for a [`PooledWriter`] as with
+    /// [`PoolBuilder::exchange_with_checksum`], also writing the checksum to `sidecar` when the
+    /// stream is finished, in the format of `md5sum` and `sha256sum` with the file name `name`.
+    pub fn exchange_with_checksum_sidecar(
+        &mut self,
+        writer: W,
+        algorithm: ChecksumAlgorithm,
+        sidecar: W,
+        name: &str,
+    ) -> (PooledWriter, ChecksumHandle) {
+        self.exchange_checksummed(writer, algorithm, Some((sidecar, name.to_string())))
+    }
+
+    /// Creates a [`PooledWriter`] without an underlying writer whose bytes are only hashed with
+    /// `algorithm`, on the pool's threads alongside the compression of the other writers.  The
+    /// blocks are not compressed, and are discarded once hashed.  The digest is available from
+    /// the returned handle once the stream is finished.
+    ///
+    /// As with [`PoolBuilder::exchange_with_compressor`], returns an error if the pool's options
+    /// can't be used with a per-writer compressor, e.g. if block size tuning is enabled.
+    pub fn exchange_hasher(
+        &mut self,
+        algorithm: ChecksumAlgorithm,
+    ) -> PoolResult<(PooledWriter, ChecksumHandle)> {
+        self.check_override::<NoopCompressor>()?;
+        let index = self.compressor_override::<NoopCompressor>(None);
+        let (observer, handle) = checksum_observer::<W>(algorithm, None);
+        let mut sink = Sink::discard();
+        sink.observer = Some(observer);
+        let writer =
+            self.exchange_sink::<NoopCompressor>(sink, NoopCompressor::BLOCK_SIZE, Some(index));
+        Ok((writer, handle))
+    }
+
+    fn exchange_checksummed(
+        &mut self,
+        writer: W,
+        algorithm: ChecksumAlgorithm,
+        sidecar: Option<(W, String)>,
+    ) -> (PooledWriter, ChecksumHandle) {
+        let (observer, handle) = checksum_observer(algorithm, sidecar);
+        let mut sink = Sink::new(writer, None);
+        sink.observer = Some(observer);
+        let block_size = self.writer_block_size();
+        (self.exchange_sink::<C>(sink, block_size, None), handle)
+    }
+}
+
+/// Creates an observer of a stream's uncompressed bytes that checksums them with `algorithm`,
+/// writing the checksum to the sidecar if there is one, and the handle to the checksum.
+fn checksum_observer<W: Write + Send + 'static>(
+    algorithm: ChecksumAlgorithm,
+    mut sidecar: Option<(W, String)>,
+) -> (RawObserver, ChecksumHandle) {
+    let handle = ChecksumHandle::default();
+    let checksum = handle.clone();
+    let mut hasher = algorithm.hasher();
+    let observer: RawObserver = Box::new(move |raw: &[u8], is_last: bool| -> io::Result<()> {
+        hasher.update(raw);
+        if is_last {
+            let mut hex = String::new();
+            hasher.finalize_reset().iter().for_each(|b| write!(hex, "{:02x}", b).unwrap());
+            if let Some((sidecar, name)) = sidecar.as_mut() {
+                writeln!(sidecar, "{}  {}", hex, name)?;
+                sidecar.flush()?;
+            }
+            *checksum.hex.lock() = Some(hex);
+        }
+        Ok(())
+    });
+    (observer, handle)
+}
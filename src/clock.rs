@@ -0,0 +1,86 @@
+//! Abstractions over time, so that the pool's worker loop can be driven by virtual time.
+//!
+//! The pool uses a [`Clock`] for all of its timestamps and for waiting between retries of a
+//! failed write.  By default this is the [`SystemClock`], but simulations and tests may supply a
+//! [`ManualClock`] via [`PoolBuilder::clock`](crate::PoolBuilder::clock) so that time-dependent
+//! behaviour can be exercised without real waits.
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// A source of monotonic timestamps and a way to sleep.
+pub trait Clock: Debug + Send + Sync + 'static {
+    /// The time elapsed since some fixed point in the past, e.g. when the clock was created.
+    fn now(&self) -> Duration;
+
+    /// Sleeps the current thread for `duration`.
+    fn sleep(&self, duration: Duration);
+
+    /// The time elapsed since `earlier`, a value previously returned by [`Clock::now`].
+    fn elapsed(&self, earlier: Duration) -> Duration {
+        self.now().checked_sub(earlier).unwrap_or_default()
+    }
+}
+
+/// The real clock, based on [`Instant`] and [`std::thread::sleep`].
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    epoch: Instant,
+}
+
+impl SystemClock {
+    /// Creates a new system clock whose epoch is now.
+    pub fn new() -> Self {
+        Self { epoch: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.epoch.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// A virtual clock that only moves when advanced.
+///
+/// Sleeping on a manual clock advances it by the requested duration and yields the thread
+/// rather than waiting, so retried writes are retried straight away; it is intended for tests
+/// and simulations only.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    nanos: AtomicU64,
+}
+
+impl ManualClock {
+    /// Creates a new manual clock at time zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let nanos = std::cmp::min(duration.as_nanos(), u128::from(u64::MAX)) as u64;
+        self.nanos.fetch_add(nanos, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+        std::thread::yield_now();
+    }
+}
@@ -0,0 +1,155 @@
+//! Waiting on a [`Pool`](crate::Pool) to terminate from outside the code that owns it.
+//!
+//! A [`CompletionHandle`], from [`Pool::completion_handle`](crate::Pool::completion_handle),
+//! resolves once the pool's management thread exits for any reason: after a clean shutdown, after
+//! an error, or after a panic.  Supervisors that manage the pool as one component among many may
+//! block on it, poll it, or receive the outcome over a channel, while the owner of the pool still
+//! stops it with [`Pool::stop_pool`](crate::Pool::stop_pool) as usual.
+use std::any::Any;
+use std::io;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::{PoolError, PoolResult};
+
+#[derive(Debug, Default)]
+struct State {
+    result: Option<PoolResult<()>>,
+    listeners: Vec<mpsc::Sender<PoolResult<()>>>,
+}
+
+/// The terminal result of a pool, shared between the pool thread and any [`CompletionHandle`]s.
+#[derive(Debug, Default)]
+pub(crate) struct Completion {
+    state: Mutex<State>,
+    done: Condvar,
+}
+
+impl Completion {
+    /// Records the terminal result of the pool and wakes everything waiting on it.
+    pub(crate) fn complete(&self, result: &PoolResult<()>) {
+        let mut state = self.state.lock();
+        for listener in state.listeners.drain(..) {
+            // The listener may have gone away, which is fine
+            let _ = listener.send(duplicate(result));
+        }
+        state.result = Some(duplicate(result));
+        self.done.notify_all();
+    }
+
+    /// Records that the pool thread panicked with the given payload.
+    pub(crate) fn panicked(&self, payload: &(dyn Any + Send)) {
+        self.complete(&Err(PoolError::Panicked(panic_message(payload))));
+    }
+}
+
+/// A handle that resolves to the terminal result of a [`Pool`](crate::Pool) once its management
+/// thread exits.  Cloning the handle is cheap; every clone sees the same result.
+///
+/// Errors are reported as copies of the error returned by
+/// [`Pool::stop_pool`](crate::Pool::stop_pool); IO errors keep their kind and message only.  A
+/// panic is reported as [`PoolError::Panicked`].
+#[derive(Debug, Clone)]
+pub struct CompletionHandle {
+    completion: Arc<Completion>,
+}
+
+impl CompletionHandle {
+    pub(crate) fn new(completion: Arc<Completion>) -> Self {
+        Self { completion }
+    }
+
+    /// True if the pool has terminated.
+    pub fn is_finished(&self) -> bool {
+        self.completion.state.lock().result.is_some()
+    }
+
+    /// The terminal result of the pool, or `None` if it has not yet terminated.
+    pub fn try_result(&self) -> Option<PoolResult<()>> {
+        self.completion.state.lock().result.as_ref().map(duplicate)
+    }
+
+    /// Blocks until the pool has terminated and returns its terminal result.
+    pub fn wait(&self) -> PoolResult<()> {
+        let mut state = self.completion.state.lock();
+        loop {
+            if let Some(result) = &state.result {
+                return duplicate(result);
+            }
+            self.completion.done.wait(&mut state);
+        }
+    }
+
+    /// Blocks until the pool has terminated or `timeout` has elapsed, returning the terminal
+    /// result or `None` respectively.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<PoolResult<()>> {
+        let mut state = self.completion.state.lock();
+        if state.result.is_none() {
+            self.completion.done.wait_for(&mut state, timeout);
+        }
+        state.result.as_ref().map(duplicate)
+    }
+
+    /// Returns a channel that receives the terminal result of the pool once it has terminated,
+    /// e.g. for use in a supervisor's event loop.
+    pub fn receiver(&self) -> mpsc::Receiver<PoolResult<()>> {
+        let (tx, rx) = mpsc::channel();
+        let mut state = self.completion.state.lock();
+        match &state.result {
+            Some(result) => tx.send(duplicate(result)).expect("Receiver is held here."),
+            None => state.listeners.push(tx),
+        }
+        rx
+    }
+}
+
+/// Copies a result, since [`PoolError`] can't implement [`Clone`] due to [`io::Error`].
+fn duplicate(result: &PoolResult<()>) -> PoolResult<()> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(error) => Err(duplicate_error(error)),
+    }
+}
+
+/// Copies an error, keeping only the kind and message of IO errors.
+fn duplicate_error(error: &PoolError) -> PoolError {
+    match error {
+        PoolError::ChannelSend => PoolError::ChannelSend,
+        PoolError::ChannelReceive(e) => PoolError::ChannelReceive(*e),
+        PoolError::CompressionError(msg) => PoolError::CompressionError(msg.clone()),
+        PoolError::InvalidCompressionLevel { level, min, max } => {
+            PoolError::InvalidCompressionLevel { level: *level, min: *min, max: *max }
+        }
+        PoolError::UnsupportedOption(msg) => PoolError::UnsupportedOption(msg.clone()),
+        PoolError::WriterFinalized(index) => PoolError::WriterFinalized(*index),
+        PoolError::Writer { id, source } => {
+            PoolError::Writer { id: id.clone(), source: Box::new(duplicate_error(source)) }
+        }
+        PoolError::DuplicateWriterId(id) => PoolError::DuplicateWriterId(id.clone()),
+        PoolError::DuplicateDestination { path, writer } => {
+            PoolError::DuplicateDestination { path: path.clone(), writer: *writer }
+        }
+        PoolError::NoWriters => PoolError::NoWriters,
+        PoolError::UnknownWriter(index) => PoolError::UnknownWriter(*index),
+        PoolError::OutputSizeExceeded { writer, limit } => {
+            PoolError::OutputSizeExceeded { writer: *writer, limit: *limit }
+        }
+        PoolError::Panicked(msg) => PoolError::Panicked(msg.clone()),
+        PoolError::VerificationFailed(msg) => PoolError::VerificationFailed(msg.clone()),
+        PoolError::Io(e) => PoolError::Io(io::Error::new(e.kind(), e.to_string())),
+    }
+}
+
+/// The message of a panic, if it was raised with one.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
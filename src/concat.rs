@@ -0,0 +1,193 @@
+//! Concatenation of compressed files produced by a pool, e.g. shards written in parallel by
+//! separate writers, into a single file without decompressing them.
+//!
+//! Inputs may be read on several threads at once, each a bounded number of chunks ahead of the
+//! output, while the output is always written in the order of the inputs.
+//!
+//! ```rust,no_run
+//! use pooled_writer::concat::{concatenate_files, ConcatFormat};
+//!
+//! let shards = ["shard0.txt.gz", "shard1.txt.gz", "shard2.txt.gz"];
+//! concatenate_files(&shards, "merged.txt.gz", ConcatFormat::Bgzf, 4)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+
+use crate::channel::{self, Receiver, Sender};
+
+/// The number of bytes read from an input before they are handed to the output.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The maximum number of chunks read ahead of the output for each input.
+const CHUNKS_AHEAD: usize = 8;
+
+/// The format of the files being concatenated, which determines what must be stripped from
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConcatFormat {
+    /// BGZF, whose EOF marker block must only appear at the end of the file.  Empty blocks,
+    /// including the EOF marker block of each input, are dropped and a single EOF marker block
+    /// is written at the end of the output, as by [`bgzf::concatenate`](crate::bgzf::concatenate).
+    #[cfg(feature = "bgzf_compressor")]
+    Bgzf,
+    /// Formats in which a concatenation of streams is itself a valid stream, e.g. plain gzip,
+    /// zstd and xz.  Inputs are copied byte for byte.
+    Streams,
+}
+
+/// Concatenates the files at `inputs`, in order, into a new file at `output`, stripping
+/// whatever `format` requires.  With more than one thread, up to `threads` inputs are read at
+/// once.
+pub fn concatenate_files<P, Q>(
+    inputs: &[P],
+    output: Q,
+    format: ConcatFormat,
+    threads: usize,
+) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut output = BufWriter::new(File::create(output)?);
+    if threads <= 1 || inputs.len() <= 1 {
+        let inputs = inputs.iter().map(|input| Input::new(input.as_ref().to_path_buf()));
+        return concatenate(inputs, format, &mut output);
+    }
+
+    let (queue_tx, queue_rx) = channel::unbounded();
+    let mut chunk_rxs = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let (tx, rx) = channel::bounded(CHUNKS_AHEAD);
+        queue_tx.send((input.as_ref().to_path_buf(), tx)).expect("Unreachable");
+        chunk_rxs.push(rx);
+    }
+    drop(queue_tx);
+
+    let handles: Vec<JoinHandle<()>> = (0..threads.min(inputs.len()))
+        .map(|_| {
+            let queue_rx = queue_rx.clone();
+            std::thread::spawn(move || read_queued(&queue_rx))
+        })
+        .collect();
+
+    // Dropping the receivers, as they are consumed or on an error, stops the readers at their
+    // next chunk
+    let mut result = concatenate(chunk_rxs.into_iter().map(ChunkReader::new), format, &mut output);
+    // A reader that panicked ends its input early, so the output is incomplete
+    for handle in handles {
+        if handle.join().is_err() && result.is_ok() {
+            result = Err(io::Error::new(io::ErrorKind::Other, "a concatenation thread panicked"));
+        }
+    }
+    result
+}
+
+/// Writes the inputs read from `inputs`, in order, to `output`, stripping whatever `format`
+/// requires.
+fn concatenate<I, R, W>(inputs: I, format: ConcatFormat, output: &mut W) -> io::Result<()>
+where
+    I: IntoIterator<Item = R>,
+    R: Read,
+    W: Write,
+{
+    match format {
+        #[cfg(feature = "bgzf_compressor")]
+        ConcatFormat::Bgzf => crate::bgzf::concatenate(inputs, output),
+        ConcatFormat::Streams => {
+            for mut input in inputs {
+                io::copy(&mut input, output)?;
+            }
+            output.flush()
+        }
+    }
+}
+
+/// A chunk of an input, or the error that stopped it being read.
+type Chunk = io::Result<Vec<u8>>;
+
+/// Reads the inputs taken from `queue`, in order, sending the chunks of each to its channel
+/// until the queue is empty.
+fn read_queued(queue: &Receiver<(PathBuf, Sender<Chunk>)>) {
+    while let Ok((input, tx)) = queue.recv() {
+        let result = read_chunks(&input, |chunk| {
+            tx.send(Ok(chunk)).map_err(|_| {
+                io::Error::new(io::ErrorKind::BrokenPipe, "concatenated output has failed")
+            })
+        });
+        if let Err(e) = result {
+            let _ = tx.send(Err(e));
+        }
+    }
+}
+
+/// Reads the file at `input` in chunks of up to [`CHUNK_SIZE`] bytes and passes each to `f`.
+fn read_chunks<F>(input: &Path, mut f: F) -> io::Result<()>
+where
+    F: FnMut(Vec<u8>) -> io::Result<()>,
+{
+    let mut reader = File::open(input)?;
+    loop {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        (&mut reader).take(CHUNK_SIZE as u64).read_to_end(&mut chunk)?;
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        f(chunk)?;
+    }
+}
+
+/// An input file, opened when it is first read so that only one input is open at a time.
+struct Input {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+}
+
+impl Input {
+    fn new(path: PathBuf) -> Self {
+        Self { path, reader: None }
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.reader.is_none() {
+            self.reader = Some(BufReader::new(File::open(&self.path)?));
+        }
+        self.reader.as_mut().expect("Opened above").read(buf)
+    }
+}
+
+/// Reads an input from the chunks sent by a reading thread, failing with the error that
+/// stopped the input being read, if any.
+struct ChunkReader {
+    rx: Receiver<Chunk>,
+    chunk: Vec<u8>,
+    at: usize,
+}
+
+impl ChunkReader {
+    fn new(rx: Receiver<Chunk>) -> Self {
+        Self { rx, chunk: Vec::new(), at: 0 }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.at == self.chunk.len() {
+            match self.rx.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.at = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.at);
+        buf[..n].copy_from_slice(&self.chunk[self.at..self.at + n]);
+        self.at += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,242 @@
+//! An [`Encoder`] that encrypts streams in the GA4GH Crypt4GH format.
+use std::sync::Arc;
+
+use blake2::{Blake2b512, Digest};
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand_core::{OsRng, RngCore};
+use thiserror::Error;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::encoder::Encoder;
+use crate::CompressorCapabilities;
+
+/// The magic bytes at the start of every Crypt4GH stream.
+const MAGIC: &[u8; 8] = b"crypt4gh";
+
+/// The version of the Crypt4GH format that is written.
+const VERSION: u32 = 1;
+
+/// The number of plaintext bytes in each data segment; only the last may be shorter.
+pub const SEGMENT_SIZE: usize = 65536;
+
+/// The length of the nonce that precedes each encrypted header packet and data segment.
+const NONCE_LEN: usize = 12;
+
+/// The length of the MAC that follows each encrypted header packet and data segment.
+const MAC_LEN: usize = 16;
+
+/// The header packet encryption method for X25519 keys with ChaCha20-IETF-Poly1305.
+const X25519_CHACHA20_IETF_POLY1305: u32 = 0;
+
+/// The header packet type carrying the data encryption parameters.
+const DATA_ENCRYPTION_PARAMETERS: u32 = 0;
+
+/// The data encryption method for ChaCha20-IETF-Poly1305.
+const CHACHA20_IETF_POLY1305: u32 = 0;
+
+/// The plaintext length of a data encryption parameters packet: type, method and key.
+const PARAMETERS_LEN: usize = 4 + 4 + 32;
+
+/// The total length of a header packet, including its own length field.
+const HEADER_PACKET_LEN: usize = 4 + 4 + 32 + NONCE_LEN + PARAMETERS_LEN + MAC_LEN;
+
+/// The errors that may be returned by the [`Crypt4ghEncoder`].
+#[derive(Error, Debug)]
+pub enum Crypt4ghError {
+    #[error("At least one Crypt4GH recipient public key is required")]
+    NoRecipients,
+    #[error("Crypt4GH recipient public key is a low order point")]
+    InvalidRecipientKey,
+    #[error("Crypt4GH encryption failed")]
+    Encryption,
+}
+
+/// The keys with which a [`Crypt4ghEncoder`] encrypts each stream.
+#[derive(Clone)]
+pub struct Crypt4ghConfig {
+    /// The X25519 public keys of the readers, each of whom gets a header packet.
+    recipients: Arc<Vec<PublicKey>>,
+    /// The writer's X25519 secret key, or `None` to use a new ephemeral key for each stream.
+    /// Zeroized when dropped.
+    writer_key: Option<StaticSecret>,
+}
+
+impl Crypt4ghConfig {
+    /// Creates a configuration that encrypts each stream for the given recipients' X25519
+    /// public keys, using a new ephemeral writer key for each stream.
+    pub fn new<I>(recipients: I) -> Result<Self, Crypt4ghError>
+    where
+        I: IntoIterator<Item = [u8; 32]>,
+    {
+        let recipients: Vec<PublicKey> = recipients.into_iter().map(PublicKey::from).collect();
+        if recipients.is_empty() {
+            return Err(Crypt4ghError::NoRecipients);
+        }
+        Ok(Self { recipients: Arc::new(recipients), writer_key: None })
+    }
+
+    /// Sets the writer's X25519 secret key, so that readers can tell who wrote each stream.
+    pub fn writer_secret_key(mut self, key: [u8; 32]) -> Self {
+        self.writer_key = Some(StaticSecret::from(key));
+        self
+    }
+}
+
+/// An encoder that encrypts each stream in the GA4GH Crypt4GH format, version 1.
+///
+/// Each stream gets its own randomly generated data key, which is encrypted for each of the
+/// recipients in the header at the start of the stream.  The blocks are then encrypted as
+/// ChaCha20-IETF-Poly1305 data segments on the pool's threads, so the output can be decrypted by
+/// any Crypt4GH reader, e.g. `crypt4gh decrypt`.
+///
+/// Every segment but the last must be exactly [`SEGMENT_SIZE`] bytes, so the bytes of a block
+/// that don't fill a segment are carried over to the next, and the last segment is only written
+/// once the stream is finished.  Blocks may therefore be of any size, e.g. with a smaller
+/// [`PoolBuilder::block_size`] or after a partial flush, though bytes flushed mid-segment only
+/// reach the output with the rest of their segment.  The encoder is stateful, so each stream is
+/// encrypted by a single thread at a time.  Use it with [`Encoding`] and
+/// [`PoolBuilder::encoder`]:
+///
+/// ```rust,no_run
+/// use pooled_writer::crypt4gh::{Crypt4ghConfig, Crypt4ghEncoder};
+/// use pooled_writer::{encoder::Encoding, PoolBuilder};
+///
+/// let reader_public_key = [9u8; 32];
+/// let config = Crypt4ghConfig::new([reader_public_key])?;
+/// let mut builder = PoolBuilder::<_, Encoding<Crypt4ghEncoder>>::new().encoder(config);
+/// let writer = builder.exchange(std::fs::File::create("out.c4gh")?);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// [`Encoding`]: crate::encoder::Encoding
+/// [`PoolBuilder::encoder`]: crate::PoolBuilder::encoder
+/// [`PoolBuilder::block_size`]: crate::PoolBuilder::block_size
+pub struct Crypt4ghEncoder {
+    config: Crypt4ghConfig,
+    /// The data key with which the stream's segments are encrypted, zeroized when the encoder
+    /// is dropped after the stream's final block.
+    data_key: Zeroizing<[u8; 32]>,
+    cipher: ChaCha20Poly1305,
+    /// True once the header has been written to the stream.
+    header_written: bool,
+    /// The bytes of the segment being filled, carried over from the blocks encoded so far.
+    segment: Zeroizing<Vec<u8>>,
+}
+
+impl Crypt4ghEncoder {
+    /// Appends the header, with one packet holding the data key for each recipient.
+    fn write_header(&mut self, output: &mut Vec<u8>) -> Result<(), Crypt4ghError> {
+        let writer_key = self.config.writer_key.clone().unwrap_or_else(|| {
+            let mut key = [0u8; 32];
+            OsRng.fill_bytes(&mut key);
+            StaticSecret::from(key)
+        });
+        let writer_public_key = PublicKey::from(&writer_key);
+
+        output.extend_from_slice(MAGIC);
+        output.extend_from_slice(&VERSION.to_le_bytes());
+        output.extend_from_slice(&(self.config.recipients.len() as u32).to_le_bytes());
+
+        let mut parameters = Zeroizing::new(Vec::with_capacity(PARAMETERS_LEN));
+        parameters.extend_from_slice(&DATA_ENCRYPTION_PARAMETERS.to_le_bytes());
+        parameters.extend_from_slice(&CHACHA20_IETF_POLY1305.to_le_bytes());
+        parameters.extend_from_slice(&*self.data_key);
+
+        for recipient in self.config.recipients.iter() {
+            let shared = writer_key.diffie_hellman(recipient);
+            // A low order recipient key gives an all zero shared secret, whatever the writer key
+            if shared.as_bytes().iter().all(|&b| b == 0) {
+                return Err(Crypt4ghError::InvalidRecipientKey);
+            }
+            let mut hasher = Blake2b512::new();
+            hasher.update(shared.as_bytes());
+            hasher.update(recipient.as_bytes());
+            hasher.update(writer_public_key.as_bytes());
+            let mut shared_key = hasher.finalize();
+            let cipher = ChaCha20Poly1305::new(Key::from_slice(&shared_key[..32]));
+            shared_key.as_mut_slice().zeroize();
+
+            output.extend_from_slice(&(HEADER_PACKET_LEN as u32).to_le_bytes());
+            output.extend_from_slice(&X25519_CHACHA20_IETF_POLY1305.to_le_bytes());
+            output.extend_from_slice(writer_public_key.as_bytes());
+            seal(&cipher, &parameters, output)?;
+        }
+        self.header_written = true;
+        Ok(())
+    }
+}
+
+/// Appends a random nonce, `plaintext` encrypted with `cipher`, and the MAC to `output`.
+fn seal(
+    cipher: &ChaCha20Poly1305,
+    plaintext: &[u8],
+    output: &mut Vec<u8>,
+) -> Result<(), Crypt4ghError> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    output.extend_from_slice(&nonce);
+    let start = output.len();
+    output.extend_from_slice(plaintext);
+    let tag = cipher
+        .encrypt_in_place_detached(Nonce::from_slice(&nonce), b"", &mut output[start..])
+        .map_err(|_| Crypt4ghError::Encryption)?;
+    output.extend_from_slice(&tag);
+    Ok(())
+}
+
+impl Encoder for Crypt4ghEncoder {
+    type Error = Crypt4ghError;
+    type Config = Crypt4ghConfig;
+
+    const BLOCK_SIZE: usize = SEGMENT_SIZE;
+
+    fn capabilities() -> CompressorCapabilities {
+        CompressorCapabilities::new(Self::BLOCK_SIZE).deterministic(false).stateful(true)
+    }
+
+    fn new(config: Self::Config) -> Self {
+        let mut data_key = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(&mut *data_key);
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&*data_key));
+        let segment = Zeroizing::new(Vec::with_capacity(SEGMENT_SIZE));
+        Self { config, data_key, cipher, header_written: false, segment }
+    }
+
+    fn encode(&mut self, mut input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        if !self.header_written {
+            self.write_header(output)?;
+        }
+        // Fill the segment carried over from the previous blocks first
+        if !self.segment.is_empty() {
+            let len = input.len().min(SEGMENT_SIZE - self.segment.len());
+            self.segment.extend_from_slice(&input[..len]);
+            input = &input[len..];
+            if self.segment.len() < SEGMENT_SIZE {
+                return Ok(());
+            }
+            seal(&self.cipher, &self.segment, output)?;
+            self.segment.zeroize();
+        }
+        let segments = input.len() / SEGMENT_SIZE;
+        output.reserve(segments * (NONCE_LEN + SEGMENT_SIZE + MAC_LEN));
+        let mut chunks = input.chunks_exact(SEGMENT_SIZE);
+        for chunk in &mut chunks {
+            seal(&self.cipher, chunk, output)?;
+        }
+        self.segment.extend_from_slice(chunks.remainder());
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        if !self.header_written {
+            self.write_header(output)?;
+        }
+        if !self.segment.is_empty() {
+            seal(&self.cipher, &self.segment, output)?;
+            self.segment.zeroize();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+///! An implementation of [`Compressor`] for raw DEFLATE, with no gzip or BGZF framing.
+use std::io;
+
+use libdeflater::{CompressionLvl, Compressor as Deflater, Decompressor as Inflater};
+use thiserror::Error;
+
+use crate::{check_round_trip, Compressor, CompressorCapabilities};
+
+/// The minimum supported deflate compression level.
+const MIN_LEVEL: u8 = 1;
+
+/// The maximum supported deflate compression level.
+const MAX_LEVEL: u8 = 12;
+
+/// The errors that may be returned by the [`DeflateCompressor`].
+#[derive(Error, Debug)]
+pub enum DeflateError {
+    #[error("Invalid deflate compression level {0}, must be in 1..=12")]
+    InvalidCompressionLevel(u8),
+    #[error("Insufficient space to compress deflate block")]
+    InsufficientSpace,
+}
+
+/// A compressor that emits each block as an independent raw DEFLATE stream.
+///
+/// This is intended for building other container formats on top of the pool.  Since there is no
+/// framing the boundaries between blocks are not recoverable from the concatenated output, so the
+/// underlying writer is typically a [`CallbackWriter`](crate::callback::CallbackWriter), which
+/// receives each block in a separate call.  There is no EOF marker.
+pub struct DeflateCompressor {
+    inner: Deflater,
+    /// Created the first time a block is verified.
+    verifier: Option<Inflater>,
+}
+
+impl Compressor for DeflateCompressor {
+    type Error = DeflateError;
+    type CompressionLevel = CompressionLvl;
+
+    const BLOCK_SIZE: usize = 64 * 1024;
+
+    fn capabilities() -> CompressorCapabilities {
+        CompressorCapabilities::new(Self::BLOCK_SIZE)
+            .eof_marker(false)
+            .dictionaries(false)
+            .extra_subfields(false)
+            .compression_levels(MIN_LEVEL, MAX_LEVEL)
+            .deterministic(true)
+            .verification(true)
+    }
+
+    fn new(compression_level: Self::CompressionLevel) -> Self {
+        Self { inner: Deflater::new(compression_level), verifier: None }
+    }
+
+    fn default_compression_level() -> Self::CompressionLevel {
+        CompressionLvl::new(6).unwrap()
+    }
+
+    fn new_compression_level(compression_level: u8) -> Result<Self::CompressionLevel, Self::Error> {
+        if (MIN_LEVEL..=MAX_LEVEL).contains(&compression_level) {
+            CompressionLvl::new(i32::from(compression_level))
+                .map_err(|_e| DeflateError::InvalidCompressionLevel(compression_level))
+        } else {
+            Err(DeflateError::InvalidCompressionLevel(compression_level))
+        }
+    }
+
+    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        let start = output.len();
+        output.resize(start + self.inner.deflate_compress_bound(input.len()), 0);
+        let len = self
+            .inner
+            .deflate_compress(input, &mut output[start..])
+            .map_err(|_e| DeflateError::InsufficientSpace)?;
+        output.truncate(start + len);
+        Ok(())
+    }
+
+    fn verify(&mut self, input: &[u8], compressed: &[u8]) -> io::Result<()> {
+        let mut decompressed = vec![0; input.len()];
+        let len = self
+            .verifier
+            .get_or_insert_with(Inflater::new)
+            .deflate_decompress(compressed, &mut decompressed)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        decompressed.truncate(len);
+        check_round_trip(input, &decompressed)
+    }
+}
@@ -0,0 +1,235 @@
+//! A short self-test of a pool configuration that reports where the bottleneck lies.
+//!
+//! [`PoolBuilder::doctor`](crate::PoolBuilder::doctor) measures, on a synthetic workload, how fast
+//! a single thread can compress, how fast the caller's writers accept compressed bytes, and how
+//! fast a trial pool with the same configuration runs end to end.  Comparing the three tells
+//! whether the configuration is limited by compression, by IO, or by the coordination between
+//! threads, and suggests settings to try.
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::in_flight::InFlightLimit;
+use crate::{Compressor, PoolBuilder, PoolError, PoolResult};
+
+/// The number of bytes of synthetic data used by each measurement.
+const TRIAL_BYTES: usize = 4 * 1024 * 1024;
+
+/// Pool throughput below this fraction of the expected throughput is attributed to coordination
+/// between threads rather than to compression or IO.
+const CHANNEL_BOUND_FRACTION: f64 = 0.5;
+
+/// What limits the throughput of a pool configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bottleneck {
+    /// The threads can't compress any faster; the writers could accept more.
+    Compressor,
+    /// The writers can't accept compressed bytes any faster than the threads produce them.
+    Io,
+    /// The pool runs well below what compression and IO allow, e.g. because the queues are too
+    /// small.
+    Channel,
+}
+
+/// The measurements and diagnosis made by [`PoolBuilder::doctor`](crate::PoolBuilder::doctor).
+/// Throughputs are in MB/s of uncompressed data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DoctorReport {
+    /// The number of threads in the configuration.
+    pub threads: usize,
+    /// The compression throughput of a single thread.
+    pub compression_mb_per_sec_per_thread: f64,
+    /// The throughput at which a writer accepted the compressed bytes.
+    pub io_mb_per_sec: f64,
+    /// The end to end throughput of a trial pool with the configuration.
+    pub pool_mb_per_sec: f64,
+    /// What limits the throughput.
+    pub bottleneck: Bottleneck,
+    /// Settings that may improve the throughput.
+    pub suggestions: Vec<String>,
+}
+
+impl DoctorReport {
+    /// The throughput that compression alone would allow with all threads busy.
+    pub fn compression_capacity_mb_per_sec(&self) -> f64 {
+        self.compression_mb_per_sec_per_thread * self.threads as f64
+    }
+
+    /// Diagnoses the bottleneck from the measured throughputs.
+    fn new(
+        threads: usize,
+        compression_mb_per_sec_per_thread: f64,
+        io_mb_per_sec: f64,
+        pool_mb_per_sec: f64,
+    ) -> Self {
+        let capacity = compression_mb_per_sec_per_thread * threads as f64;
+        let expected = capacity.min(io_mb_per_sec);
+        let (bottleneck, suggestions) = if pool_mb_per_sec < expected * CHANNEL_BOUND_FRACTION {
+            (
+                Bottleneck::Channel,
+                vec![
+                    "increase the queue size, e.g. with a MaxThroughput profile".to_string(),
+                    "increase the work quantum so each thread does more per turn".to_string(),
+                ],
+            )
+        } else if io_mb_per_sec < capacity {
+            let needed = (io_mb_per_sec / compression_mb_per_sec_per_thread).ceil() as usize;
+            (
+                Bottleneck::Io,
+                vec![
+                    format!("{} threads would be enough to keep up with IO", needed.max(1)),
+                    "a higher compression level would write fewer bytes at little cost".to_string(),
+                ],
+            )
+        } else {
+            (
+                Bottleneck::Compressor,
+                vec![
+                    "use more threads, if cores are available".to_string(),
+                    "use a lower compression level".to_string(),
+                ],
+            )
+        };
+
+        Self {
+            threads,
+            compression_mb_per_sec_per_thread,
+            io_mb_per_sec,
+            pool_mb_per_sec,
+            bottleneck,
+            suggestions,
+        }
+    }
+}
+
+impl<W, C> PoolBuilder<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    /// Runs a short synthetic workload against this configuration and reports whether it is
+    /// limited by compression, IO or the coordination between threads, with suggested settings.
+    ///
+    /// `open` is called to open scratch writers like those the pool will be used with, e.g.
+    /// temporary files on the same disk, which receive a few MB of compressed data each; their
+    /// contents should be discarded afterwards.  A trial pool with the same settings as this
+    /// builder, e.g. its block size, thread roles, queue sizes and limits on the blocks and
+    /// bytes in flight, and one writer per thread, is built and stopped; this builder is left
+    /// unchanged.
+    pub fn doctor<F>(&self, mut open: F) -> PoolResult<DoctorReport>
+    where
+        F: FnMut() -> io::Result<W>,
+    {
+        let blocks = synthetic_blocks(self.writer_block_size());
+        let bytes = blocks.iter().map(Vec::len).sum::<usize>() as f64 / 1_000_000.0;
+
+        // Compression on a single thread
+        let mut compressor = C::new(self.compression_level.clone());
+        let mut compressed = vec![];
+        let start = Instant::now();
+        for block in &blocks {
+            compressor
+                .compress(block, &mut compressed)
+                .map_err(|e| PoolError::CompressionError(e.to_string()))?;
+        }
+        let compression_rate = rate(bytes, start.elapsed());
+
+        // Writing the compressed bytes directly
+        let mut writer = open()?;
+        let start = Instant::now();
+        writer.write_all(&compressed)?;
+        writer.flush()?;
+        let io_rate = rate(bytes, start.elapsed());
+
+        // The whole pool, with the workload spread over one writer per thread
+        let mut trial = self.trial_builder();
+        let mut writers = (0..self.threads)
+            .map(|_| Ok(trial.exchange(open()?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut pool = trial.build()?;
+        let start = Instant::now();
+        for (i, block) in blocks.iter().enumerate() {
+            writers[i % self.threads].write_all(block)?;
+        }
+        writers.into_iter().try_for_each(|w| w.close())?;
+        pool.stop_pool()?;
+        let pool_rate = rate(bytes, start.elapsed());
+
+        Ok(DoctorReport::new(self.threads, compression_rate, io_rate, pool_rate))
+    }
+
+    /// A builder with the same configuration as this one, but none of its writers or readers,
+    /// and a limit on the bytes in flight of its own.
+    fn trial_builder(&self) -> PoolBuilder<W, C> {
+        PoolBuilder {
+            compression_level: self.compression_level.clone(),
+            compression_level_number: self.compression_level_number,
+            queue_size: self.queue_size,
+            queue_size_thread_multiple: self.queue_size_thread_multiple,
+            retry_delay: self.retry_delay,
+            threads: self.threads,
+            compressor_threads: self.compressor_threads,
+            writer_threads: self.writer_threads,
+            drop_policy: self.drop_policy,
+            block_size: self.block_size,
+            block_size_tuning: self.block_size_tuning.clone(),
+            small_output: self.small_output.clone(),
+            extra_subfields: self.extra_subfields.clone(),
+            map_writers: self.map_writers.clone(),
+            reopen: self.reopen.clone(),
+            recompress: self.recompress.clone(),
+            dictionary: self.dictionary.clone(),
+            gzip_header: self.gzip_header.clone(),
+            block_checksum: self.block_checksum,
+            clock: self.clock.clone(),
+            virtual_offsets: self.virtual_offsets,
+            requeue_failed_blocks: self.requeue_failed_blocks,
+            write_retries: self.write_retries,
+            max_output_size: self.max_output_size,
+            batch_small_blocks: self.batch_small_blocks,
+            verify_blocks: self.verify_blocks,
+            max_in_flight_blocks: self.max_in_flight_blocks,
+            max_in_flight_bytes: self
+                .max_in_flight_bytes
+                .as_ref()
+                .map(|bytes| Arc::new(InFlightLimit::new(bytes.limit()))),
+            memory_budget: self.memory_budget,
+            compressor_per_writer: self.compressor_per_writer,
+            adaptive_compression: self.adaptive_compression.clone(),
+            autoscale: self.autoscale.clone(),
+            empty_pool_policy: self.empty_pool_policy,
+            panic_policy: self.panic_policy,
+            work_quantum: self.work_quantum,
+            work_weights: self.work_weights,
+            #[cfg(feature = "thread_priority")]
+            thread_priority: self.thread_priority,
+            spawner: self.spawner.clone(),
+            ..PoolBuilder::new()
+        }
+    }
+}
+
+/// The throughput in MB/s for `mb` megabytes processed in `elapsed`.
+fn rate(mb: f64, elapsed: Duration) -> f64 {
+    mb / elapsed.as_secs_f64().max(1e-9)
+}
+
+/// Generates blocks of moderately compressible, deterministic, text resembling sequencing data.
+fn synthetic_blocks(block_size: usize) -> Vec<Vec<u8>> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        state =
+            state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        (state >> 33) as usize
+    };
+    (0..std::cmp::max(TRIAL_BYTES / block_size, 1))
+        .map(|_| {
+            let mut block = Vec::with_capacity(block_size);
+            while block.len() < block_size {
+                let base = b"ACGT"[next() % 4];
+                block.push(if next() % 64 == 0 { b'\n' } else { base });
+            }
+            block
+        })
+        .collect()
+}
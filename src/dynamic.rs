@@ -0,0 +1,265 @@
+//! A [`Compressor`] whose implementation is chosen at runtime.
+//!
+//! [`PoolBuilder`] is generic over its compressor, which is convenient when the format is known
+//! at compile time but forces every caller that picks the format at runtime, e.g. from a command
+//! line flag, to be generic too.  [`DynCompressor`] is a single concrete compressor that wraps
+//! whichever compressor a [`CompressorChoice`] names:
+//!
+//! ```rust
+//! use std::io::Write;
+//! use pooled_writer::{dynamic::{CompressorChoice, DynCompressor}, PoolBuilder};
+//!
+//! let choice = CompressorChoice::from_name("noop", None)?;
+//! let mut builder = PoolBuilder::<Vec<u8>, DynCompressor>::new().compressor(choice);
+//! let mut writer = builder.exchange(vec![]);
+//! let mut pool = builder.build()?;
+//! writer.write_all(b"chosen at runtime")?;
+//! writer.close()?;
+//! pool.stop_pool()?;
+//! # Ok::<(), pooled_writer::PoolError>(())
+//! ```
+use std::any::type_name;
+use std::fmt::{self, Debug};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::noop::NoopCompressor;
+use crate::{
+    Compressor, CompressorCapabilities, ExtraSubfield, GzipHeader, PoolBuilder, PoolError,
+    PoolResult,
+};
+
+/// An object-safe view of a [`Compressor`], with errors converted to [`io::Error`].
+trait ErasedCompressor: Send {
+    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()>;
+
+    fn compress_with_extra_subfields(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        subfields: &[ExtraSubfield],
+    ) -> io::Result<()>;
+
+    fn set_gzip_header(&mut self, header: &GzipHeader);
+
+    fn verify(&mut self, input: &[u8], compressed: &[u8]) -> io::Result<()>;
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()>;
+}
+
+impl<C: Compressor> ErasedCompressor for C {
+    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        Compressor::compress(self, input, output).map_err(to_io_error)
+    }
+
+    fn compress_with_extra_subfields(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        subfields: &[ExtraSubfield],
+    ) -> io::Result<()> {
+        Compressor::compress_with_extra_subfields(self, input, output, subfields)
+            .map_err(to_io_error)
+    }
+
+    fn set_gzip_header(&mut self, header: &GzipHeader) {
+        Compressor::set_gzip_header(self, header)
+    }
+
+    fn verify(&mut self, input: &[u8], compressed: &[u8]) -> io::Result<()> {
+        Compressor::verify(self, input, compressed)
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        Compressor::finish(self, output).map_err(to_io_error)
+    }
+}
+
+fn to_io_error<E: std::error::Error>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+/// Creates a compressor, with an optional dictionary.
+type ErasedFactory = Arc<dyn Fn(Option<&[u8]>) -> Box<dyn ErasedCompressor> + Send + Sync>;
+
+/// A compressor and compression level chosen at runtime, used as the compression level of a
+/// [`DynCompressor`].
+#[derive(Clone)]
+pub struct CompressorChoice {
+    name: &'static str,
+    level: Option<u8>,
+    block_size: usize,
+    capabilities: CompressorCapabilities,
+    factory: ErasedFactory,
+}
+
+impl CompressorChoice {
+    /// Chooses the compressor `C` at the given compression level, or its default level if
+    /// `None`.
+    ///
+    /// Returns an error if the level is not valid for `C`.
+    pub fn new<C: Compressor>(level: Option<u8>) -> PoolResult<Self> {
+        let compression_level = match level {
+            Some(level) => {
+                C::capabilities().check_compression_level(level)?;
+                C::new_compression_level(level)
+                    .map_err(|e| PoolError::CompressionError(e.to_string()))?
+            }
+            None => C::default_compression_level(),
+        };
+        let block_size = C::block_size_for(&compression_level);
+        let capabilities = C::capabilities_for(&compression_level);
+        // Compression levels need only be `Send`, so share the level behind a lock
+        let compression_level = Mutex::new(compression_level);
+        let factory: ErasedFactory = Arc::new(move |dictionary| {
+            let level = compression_level.lock().clone();
+            match dictionary {
+                Some(dictionary) => Box::new(C::new_with_dictionary(level, dictionary)),
+                None => Box::new(C::new(level)),
+            }
+        });
+        Ok(Self { name: type_name::<C>(), level, block_size, capabilities, factory })
+    }
+
+    /// Chooses one of the compressors built into this crate by name, e.g. from a command line
+    /// flag: `noop`, and `bgzf`, `deflate`, `gzip`, `snappy`, `xz` or `zstd` if the
+    /// corresponding feature is enabled.
+    ///
+    /// Returns an error if the name is not recognised or the level is not valid for the
+    /// compressor.
+    pub fn from_name(name: &str, level: Option<u8>) -> PoolResult<Self> {
+        match name {
+            "noop" => Self::new::<NoopCompressor>(level),
+            #[cfg(feature = "bgzf_compressor")]
+            "bgzf" => Self::new::<crate::bgzf::BgzfCompressor>(level),
+            #[cfg(feature = "deflate_compressor")]
+            "deflate" => Self::new::<crate::deflate::DeflateCompressor>(level),
+            #[cfg(feature = "gzip_compressor")]
+            "gzip" => Self::new::<crate::gzip::GzipCompressor>(level),
+            #[cfg(feature = "snappy_compressor")]
+            "snappy" => Self::new::<crate::snappy::SnappyCompressor>(level),
+            #[cfg(feature = "xz_compressor")]
+            "xz" => Self::new::<crate::xz::XzCompressor>(level),
+            #[cfg(feature = "zstd_compressor")]
+            "zstd" => Self::new::<crate::zstd::ZstdCompressor>(level),
+            _ => Err(PoolError::UnsupportedOption(format!("unknown compressor {}", name))),
+        }
+    }
+
+    /// The type name of the chosen compressor.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The chosen compression level, or `None` for the compressor's default level.
+    pub fn level(&self) -> Option<u8> {
+        self.level
+    }
+
+    /// The capabilities of the chosen compressor.
+    pub fn capabilities(&self) -> CompressorCapabilities {
+        self.capabilities
+    }
+}
+
+impl Debug for CompressorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressorChoice")
+            .field("name", &self.name)
+            .field("level", &self.level)
+            .field("block_size", &self.block_size)
+            .finish()
+    }
+}
+
+/// A [`Compressor`] that delegates to the compressor named by its [`CompressorChoice`], so that
+/// code choosing the format at runtime needs only the one type.
+///
+/// Since the choice is made through the compression level, select it with
+/// [`PoolBuilder::compressor`] before configuring anything that depends on the compressor's
+/// capabilities; until then the pool passes data through uncompressed, as with
+/// [`NoopCompressor`].  Numeric compression levels, e.g. via
+/// [`PoolBuilder::compression_level`] or [`crate::ExchangeOptions`], are rejected since they do
+/// not say which compressor to use.
+pub struct DynCompressor {
+    inner: Box<dyn ErasedCompressor>,
+}
+
+impl Compressor for DynCompressor {
+    type Error = io::Error;
+    type CompressionLevel = CompressorChoice;
+
+    fn new(compression_level: Self::CompressionLevel) -> Self {
+        Self { inner: (compression_level.factory)(None) }
+    }
+
+    fn new_with_dictionary(compression_level: Self::CompressionLevel, dictionary: &[u8]) -> Self {
+        Self { inner: (compression_level.factory)(Some(dictionary)) }
+    }
+
+    fn block_size_for(compression_level: &Self::CompressionLevel) -> usize {
+        compression_level.block_size
+    }
+
+    fn capabilities_for(compression_level: &Self::CompressionLevel) -> CompressorCapabilities {
+        compression_level.capabilities()
+    }
+
+    fn default_compression_level() -> Self::CompressionLevel {
+        CompressorChoice::new::<NoopCompressor>(None).expect("Default level is valid")
+    }
+
+    fn new_compression_level(compression_level: u8) -> Result<Self::CompressionLevel, Self::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "compression level {} does not choose a compressor, use a CompressorChoice",
+                compression_level
+            ),
+        ))
+    }
+
+    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        self.inner.compress(input, output)
+    }
+
+    fn compress_with_extra_subfields(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        subfields: &[ExtraSubfield],
+    ) -> Result<(), Self::Error> {
+        self.inner.compress_with_extra_subfields(input, output, subfields)
+    }
+
+    fn set_gzip_header(&mut self, header: &GzipHeader) {
+        self.inner.set_gzip_header(header)
+    }
+
+    fn verify(&mut self, input: &[u8], compressed: &[u8]) -> io::Result<()> {
+        self.inner.verify(input, compressed)
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        self.inner.finish(output)
+    }
+}
+
+impl<W> PoolBuilder<W, DynCompressor>
+where
+    W: Write + Send + 'static,
+{
+    /// Chooses the compressor and compression level used by the [`crate::Pool`].  Must be
+    /// called before any writers are exchanged, and before options that depend on the
+    /// compressor's capabilities, e.g. [`PoolBuilder::dictionary`].
+    ///
+    /// Will panic if any writers have already been exchanged.
+    pub fn compressor(mut self, choice: CompressorChoice) -> Self {
+        assert!(self.writers.is_empty(), "Must choose the compressor before exchanging writers.");
+        self.compression_level_number = choice.level();
+        self.compression_level = choice;
+        self
+    }
+}
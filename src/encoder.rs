@@ -0,0 +1,443 @@
+//! Per-block transforms other than compression, e.g. encryption or encoding.
+//!
+//! The pool applies a [`Compressor`] to each block on its worker threads, but compression is
+//! just one transform that may be done per block.  An [`Encoder`] is the general form: it turns
+//! each block of input into a block of output and may append a trailer when a stream is
+//! finished.  Every [`Compressor`] is an [`Encoder`], and any [`Encoder`] can be used where a
+//! [`Compressor`] goes today by wrapping it in an [`Encoding`]:
+//!
+//! ```rust
+//! use std::convert::Infallible;
+//! use std::io::Write;
+//! use pooled_writer::{encoder::{Encoder, Encoding}, PoolBuilder};
+//!
+//! /// Encodes each block as upper case hex.
+//! struct Hex;
+//!
+//! impl Encoder for Hex {
+//!     type Error = Infallible;
+//!     type Config = ();
+//!
+//!     fn new(_config: Self::Config) -> Self {
+//!         Hex
+//!     }
+//!
+//!     fn encode(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+//!         output.extend(input.iter().flat_map(|b| format!("{:02X}", b).into_bytes()));
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut builder = PoolBuilder::<Vec<u8>, Encoding<Hex>>::new().encoder(());
+//! let mut writer = builder.exchange(vec![]);
+//! let mut pool = builder.build()?;
+//! writer.write_all(b"encoded on the pool's threads")?;
+//! writer.close()?;
+//! pool.stop_pool()?;
+//! # Ok::<(), pooled_writer::PoolError>(())
+//! ```
+use std::error::Error;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::{Compressor, CompressorCapabilities, PoolBuilder};
+
+/// A transform applied by the pool to each block of a stream, e.g. compression or encryption.
+///
+/// As with a [`Compressor`], each pool thread keeps one instance of the encoder, which encodes
+/// blocks from any stream, unless its capabilities report [`CompressorCapabilities::stateful`].
+pub trait Encoder: Sized + Send + 'static
+where
+    Self::Config: Clone + Send + 'static,
+    Self::Error: Error + Send + 'static,
+{
+    type Error;
+    /// The configuration from which each instance is created, e.g. a compression level or
+    /// a key.
+    type Config;
+
+    /// The largest number of input bytes in a single block.
+    const BLOCK_SIZE: usize = crate::BUFSIZE;
+
+    /// Describes what the encoder supports.  The default implementation reports only the
+    /// [`Encoder::BLOCK_SIZE`].
+    fn capabilities() -> CompressorCapabilities {
+        CompressorCapabilities::new(Self::BLOCK_SIZE)
+    }
+
+    /// The block size to use with `config`.  The default implementation returns
+    /// [`Encoder::BLOCK_SIZE`].
+    fn block_size_for(config: &Self::Config) -> usize {
+        Self::BLOCK_SIZE
+    }
+
+    /// Describes what the encoder supports with `config`.  The default implementation returns
+    /// [`Encoder::capabilities`].
+    fn capabilities_for(config: &Self::Config) -> CompressorCapabilities {
+        Self::capabilities()
+    }
+
+    /// Creates a new encoder with the given configuration.
+    fn new(config: Self::Config) -> Self;
+
+    /// Encodes a block of bytes into the `output` vec.
+    fn encode(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error>;
+
+    /// Finishes a stream by appending any trailer to the `output` vec, as with
+    /// [`Compressor::finish`].  The default implementation appends nothing.
+    fn finish(&mut self, output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        let _ = output;
+        Ok(())
+    }
+}
+
+impl<C: Compressor> Encoder for C {
+    type Error = C::Error;
+    type Config = C::CompressionLevel;
+
+    const BLOCK_SIZE: usize = <C as Compressor>::BLOCK_SIZE;
+
+    fn capabilities() -> CompressorCapabilities {
+        <C as Compressor>::capabilities()
+    }
+
+    fn block_size_for(config: &Self::Config) -> usize {
+        <C as Compressor>::block_size_for(config)
+    }
+
+    fn capabilities_for(config: &Self::Config) -> CompressorCapabilities {
+        <C as Compressor>::capabilities_for(config)
+    }
+
+    fn new(config: Self::Config) -> Self {
+        <C as Compressor>::new(config)
+    }
+
+    fn encode(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        self.compress(input, output)
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        Compressor::finish(self, output)
+    }
+}
+
+/// An [`Encoder`] that applies `A` and then `B` to each block, e.g. compression followed by
+/// encryption, so that both are done on the pool's threads.
+///
+/// The configuration is a pair of the two encoders' configurations, and blocks are of `A`'s
+/// block size.  When a stream is finished, `A`'s trailer is encoded by `B` as one more block
+/// before `B`'s own trailer is appended.  The chain is stateful if either encoder is.
+pub struct Chain<A: Encoder, B: Encoder> {
+    first: A,
+    second: B,
+    /// The output of `A` for the current block, reused across blocks.
+    scratch: Vec<u8>,
+}
+
+impl<A: Encoder, B: Encoder> Encoder for Chain<A, B> {
+    type Error = io::Error;
+    type Config = (A::Config, B::Config);
+
+    const BLOCK_SIZE: usize = A::BLOCK_SIZE;
+
+    fn capabilities() -> CompressorCapabilities {
+        combine(Self::BLOCK_SIZE, &[A::capabilities(), B::capabilities()])
+    }
+
+    fn block_size_for((first, _): &Self::Config) -> usize {
+        A::block_size_for(first)
+    }
+
+    fn capabilities_for((first, second): &Self::Config) -> CompressorCapabilities {
+        let stages = [A::capabilities_for(first), B::capabilities_for(second)];
+        combine(A::block_size_for(first), &stages)
+    }
+
+    fn new((first, second): Self::Config) -> Self {
+        Self { first: A::new(first), second: B::new(second), scratch: Vec::new() }
+    }
+
+    fn encode(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        self.scratch.clear();
+        self.first.encode(input, &mut self.scratch).map_err(to_io_error)?;
+        self.second.encode(&self.scratch, output).map_err(to_io_error)
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        self.scratch.clear();
+        self.first.finish(&mut self.scratch).map_err(to_io_error)?;
+        if !self.scratch.is_empty() {
+            self.second.encode(&self.scratch, output).map_err(to_io_error)?;
+        }
+        self.second.finish(output).map_err(to_io_error)
+    }
+}
+
+/// The capabilities of a sequence of stages whose blocks are of `block_size` bytes: the output
+/// has an EOF marker or is non-deterministic if that of any stage does or is, and the sequence
+/// is stateful if any stage is.
+fn combine(block_size: usize, stages: &[CompressorCapabilities]) -> CompressorCapabilities {
+    CompressorCapabilities::new(block_size)
+        .eof_marker(stages.iter().any(|c| c.supports_eof_marker))
+        .deterministic(stages.iter().all(|c| c.deterministic))
+        .stateful(stages.iter().any(|c| c.stateful))
+        .scratch_memory(stages.iter().map(|c| c.scratch_memory).sum())
+}
+
+/// An object safe form of [`Encoder`], for the stages of a [`Pipeline`].
+trait Stage: Send {
+    fn encode(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()>;
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()>;
+}
+
+/// A [`Stage`] that applies an [`Encoder`].
+struct EncoderStage<E: Encoder>(E);
+
+impl<E: Encoder> Stage for EncoderStage<E> {
+    fn encode(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        self.0.encode(input, output).map_err(to_io_error)
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        self.0.finish(output).map_err(to_io_error)
+    }
+}
+
+/// A [`Stage`] that applies a function to each block.
+struct FnStage<F>(Arc<F>);
+
+impl<F> Stage for FnStage<F>
+where
+    F: Fn(&[u8], &mut Vec<u8>) -> io::Result<()> + Send + Sync,
+{
+    fn encode(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        (self.0)(input, output)
+    }
+
+    fn finish(&mut self, _output: &mut Vec<u8>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates a new instance of a stage of a [`Pipeline`].
+type StageFactory = Arc<dyn Fn() -> Box<dyn Stage> + Send + Sync>;
+
+/// The ordered stages of a [`Pipeline`], e.g. compression, then encryption, then any custom
+/// processing.  The blocks are of the first stage's block size.
+#[derive(Clone, Default)]
+pub struct PipelineConfig {
+    stages: Vec<StageFactory>,
+    /// The capabilities of each stage.
+    capabilities: Vec<CompressorCapabilities>,
+    /// The block size of the first stage, if any.
+    block_size: Option<usize>,
+}
+
+impl PipelineConfig {
+    /// Creates an empty pipeline, which passes blocks through unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a stage that applies the [`Encoder`] `E`, e.g. a [`Compressor`], created from
+    /// `config`.
+    pub fn encoder<E: Encoder>(mut self, config: E::Config) -> Self {
+        self.block_size.get_or_insert_with(|| E::block_size_for(&config));
+        self.capabilities.push(E::capabilities_for(&config));
+        let config = Mutex::new(config);
+        self.stages.push(Arc::new(move || Box::new(EncoderStage(E::new(config.lock().clone())))));
+        self
+    }
+
+    /// Appends a stage that calls `f` with each block and the `output` vec to append to, e.g.
+    /// to add framing.  The function must not carry state from one block to the next.
+    pub fn map<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&[u8], &mut Vec<u8>) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.capabilities.push(CompressorCapabilities::new(crate::BUFSIZE));
+        let f = Arc::new(f);
+        self.stages.push(Arc::new(move || Box::new(FnStage(f.clone()))));
+        self
+    }
+}
+
+/// An [`Encoder`] that applies a chain of stages configured at runtime by a [`PipelineConfig`]
+/// to each block, in order, on the pool's threads.  Use it with
+/// [`PoolBuilder::exchange_with_encoder`] to configure a different pipeline for each writer:
+///
+/// ```rust
+/// use std::io::Write;
+/// use pooled_writer::encoder::{Pipeline, PipelineConfig};
+/// use pooled_writer::{bgzf::BgzfCompressor, Compressor, PoolBuilder};
+///
+/// let pipeline = PipelineConfig::new()
+///     .encoder::<BgzfCompressor>(BgzfCompressor::new_compression_level(3)?)
+///     .map(|block, output| {
+///         // Prefix each compressed block with its length
+///         output.extend_from_slice(&(block.len() as u32).to_le_bytes());
+///         output.extend_from_slice(block);
+///         Ok(())
+///     });
+/// let mut builder = PoolBuilder::<_, BgzfCompressor>::new();
+/// let mut writer = builder.exchange_with_encoder::<Pipeline>(vec![], pipeline)?;
+/// let mut pool = builder.build()?;
+/// writer.write_all(b"processed on the pool's threads")?;
+/// writer.close()?;
+/// pool.stop_pool()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// As with [`Chain`], when a stream is finished the trailer of each stage is processed by the
+/// stages after it.
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+    /// The input and output of the current stage, reused across blocks.
+    scratch: (Vec<u8>, Vec<u8>),
+}
+
+impl Pipeline {
+    /// Passes `input` through the stages from `first` onwards, appending the result to `output`.
+    fn run(&mut self, first: usize, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        let (current, next) = &mut self.scratch;
+        current.clear();
+        current.extend_from_slice(input);
+        for stage in &mut self.stages[first..] {
+            next.clear();
+            stage.encode(current, next)?;
+            std::mem::swap(current, next);
+        }
+        output.extend_from_slice(current);
+        Ok(())
+    }
+}
+
+impl Encoder for Pipeline {
+    type Error = io::Error;
+    type Config = PipelineConfig;
+
+    fn block_size_for(config: &Self::Config) -> usize {
+        config.block_size.unwrap_or(Self::BLOCK_SIZE)
+    }
+
+    fn capabilities_for(config: &Self::Config) -> CompressorCapabilities {
+        combine(Self::block_size_for(config), &config.capabilities)
+    }
+
+    fn new(config: Self::Config) -> Self {
+        let stages = config.stages.iter().map(|factory| factory()).collect();
+        Self { stages, scratch: (vec![], vec![]) }
+    }
+
+    fn encode(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        self.run(0, input, output)
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        for i in 0..self.stages.len() {
+            let mut trailer = vec![];
+            self.stages[i].finish(&mut trailer)?;
+            if !trailer.is_empty() {
+                self.run(i + 1, &trailer, output)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A [`Compressor`] that applies the [`Encoder`] `E`, so that any encoder can be used by a
+/// [`PoolBuilder`].
+///
+/// The encoder's configuration takes the place of the compression level, and must be set with
+/// [`PoolBuilder::encoder`]; until then every block fails to encode.  Numeric compression
+/// levels, e.g. via [`PoolBuilder::compression_level`], are rejected.
+pub struct Encoding<E: Encoder> {
+    /// The encoder, or `None` if no configuration was set.
+    inner: Option<E>,
+}
+
+impl<E: Encoder> Encoding<E> {
+    /// The encoder, or an error if no configuration was set.
+    fn inner(&mut self) -> io::Result<&mut E> {
+        self.inner.as_mut().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no encoder configuration was set, use PoolBuilder::encoder",
+            )
+        })
+    }
+}
+
+impl<E: Encoder> Compressor for Encoding<E> {
+    type Error = io::Error;
+    type CompressionLevel = Option<E::Config>;
+
+    const BLOCK_SIZE: usize = E::BLOCK_SIZE;
+
+    fn capabilities() -> CompressorCapabilities {
+        E::capabilities()
+    }
+
+    fn block_size_for(compression_level: &Self::CompressionLevel) -> usize {
+        match compression_level {
+            Some(config) => E::block_size_for(config),
+            None => E::BLOCK_SIZE,
+        }
+    }
+
+    fn capabilities_for(compression_level: &Self::CompressionLevel) -> CompressorCapabilities {
+        match compression_level {
+            Some(config) => E::capabilities_for(config),
+            None => E::capabilities(),
+        }
+    }
+
+    fn new(compression_level: Self::CompressionLevel) -> Self {
+        Self { inner: compression_level.map(E::new) }
+    }
+
+    fn default_compression_level() -> Self::CompressionLevel {
+        None
+    }
+
+    fn new_compression_level(compression_level: u8) -> Result<Self::CompressionLevel, Self::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "compression level {} does not configure an encoder, use PoolBuilder::encoder",
+                compression_level
+            ),
+        ))
+    }
+
+    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        self.inner()?.encode(input, output).map_err(to_io_error)
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        self.inner()?.finish(output).map_err(to_io_error)
+    }
+}
+
+fn to_io_error<E: Error>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+impl<W, E> PoolBuilder<W, Encoding<E>>
+where
+    W: Write + Send + 'static,
+    E: Encoder,
+{
+    /// Sets the configuration from which the pool creates its [`Encoder`]s.  Must be called
+    /// before any writers are exchanged.
+    ///
+    /// Will panic if any writers have already been exchanged.
+    pub fn encoder(mut self, config: E::Config) -> Self {
+        assert!(self.writers.is_empty(), "Must configure the encoder before exchanging writers.");
+        self.compression_level = Some(config);
+        self
+    }
+}
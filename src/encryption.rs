@@ -0,0 +1,125 @@
+//! Per-writer encryption keys, e.g. for per-customer outputs from one multi-tenant run.
+//!
+//! Keys are registered with the builder by [`PoolBuilder::encryption_key`], which returns an
+//! [`EncryptionKeyId`] to choose the key of each writer with [`ExchangeOptions::encryption`]:
+//!
+//! ```rust,no_run
+//! use pooled_writer::encryption::EncryptionKey;
+//! use pooled_writer::{bgzf::BgzfCompressor, ExchangeOptions, PoolBuilder};
+//!
+//! let mut builder = PoolBuilder::<_, BgzfCompressor>::new();
+//! let first = builder.encryption_key(EncryptionKey::AesGcm([1u8; 32].into()));
+//! let second = builder.encryption_key(EncryptionKey::AesGcm([2u8; 32].into()));
+//! let options = ExchangeOptions::new().encryption(first);
+//! let a = builder.exchange_with_options(std::fs::File::create("a.gz.enc")?, options)?;
+//! let options = ExchangeOptions::new().encryption(second);
+//! let b = builder.exchange_with_options(std::fs::File::create("b.gz.enc")?, options)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! The bytes of each key, and the data key of each Crypt4GH stream, are zeroized when they are
+//! dropped, while the ciphers set up from them are cleared only as far as the `aes-gcm` and
+//! `chacha20poly1305` crates do so.  The builder's copies of the keys are dropped when the pool
+//! is built.  The copy of its key kept for each writer, from which the pool's threads set up
+//! their encoders, is dropped once the writer's last block has been written, and each thread's
+//! encoder for the writer the next time the thread looks for work.  A Crypt4GH stream is
+//! encrypted by one encoder at a time, which is dropped as soon as the stream is finished.
+//!
+//! [`ExchangeOptions::encryption`]: crate::ExchangeOptions::encryption
+use std::fmt;
+use std::io::Write;
+
+#[cfg(feature = "aes_gcm_encoder")]
+use crate::aes::{AesGcmEncoder, AesGcmKey};
+#[cfg(feature = "crypt4gh_encoder")]
+use crate::crypt4gh::{Crypt4ghConfig, Crypt4ghEncoder};
+#[cfg(feature = "aes_gcm_encoder")]
+use crate::encoder::Chain;
+use crate::{
+    Compressor, EncryptionKeyId, ExchangeOptions, PoolBuilder, PoolError, PoolResult, PooledWriter,
+};
+
+/// A key with which a writer's output is encrypted on the pool's threads.
+#[derive(Clone)]
+pub enum EncryptionKey {
+    /// Each block is compressed by the pool's compressor and then encrypted with AES-256-GCM
+    /// under the key, as with a [`Chain`](crate::encoder::Chain) of the compressor and an
+    /// [`AesGcmEncoder`].
+    #[cfg(feature = "aes_gcm_encoder")]
+    AesGcm(AesGcmKey),
+    /// The stream is encrypted for the configured recipients in the Crypt4GH format, as with a
+    /// [`Crypt4ghEncoder`].  The bytes are not compressed, as Crypt4GH is usually applied to
+    /// formats that are compressed already, e.g. BAM.
+    #[cfg(feature = "crypt4gh_encoder")]
+    Crypt4gh(Crypt4ghConfig),
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "aes_gcm_encoder")]
+            EncryptionKey::AesGcm(_) => f.write_str("AesGcm(..)"),
+            #[cfg(feature = "crypt4gh_encoder")]
+            EncryptionKey::Crypt4gh(_) => f.write_str("Crypt4gh(..)"),
+        }
+    }
+}
+
+impl<W, C> PoolBuilder<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    /// Registers a key with which writers may be encrypted, returning the ID with which to
+    /// choose it for a writer with [`ExchangeOptions::encryption`].
+    pub fn encryption_key(&mut self, key: EncryptionKey) -> EncryptionKeyId {
+        self.encryption_keys.push(key);
+        EncryptionKeyId(self.encryption_keys.len() - 1)
+    }
+
+    /// Exchanges a writer for a [`PooledWriter`] whose output is encrypted with the key chosen
+    /// by `options`, which must have a key set.
+    ///
+    /// Returns an error if the key was not registered with this builder, if the EOF marker is
+    /// omitted, or if a compression level is set for a Crypt4GH key, as well as for the reasons
+    /// that [`PoolBuilder::exchange_with_encoder`] does.
+    pub(crate) fn exchange_encrypted(
+        &mut self,
+        writer: W,
+        mut options: ExchangeOptions,
+    ) -> PoolResult<PooledWriter> {
+        let id = options.encryption.expect("Checked by the caller");
+        let key =
+            self.encryption_keys.get(id.0).cloned().ok_or_else(|| {
+                PoolError::UnsupportedOption("unknown encryption key".to_string())
+            })?;
+        if options.omit_eof_marker {
+            return Err(PoolError::UnsupportedOption(
+                "the EOF marker cannot be omitted from an encrypted writer".to_string(),
+            ));
+        }
+
+        // The compression level is fixed when the writer's encoder is configured
+        let mut pooled = match key {
+            #[cfg(feature = "aes_gcm_encoder")]
+            EncryptionKey::AesGcm(key) => {
+                let level = match options.compression_level.take() {
+                    Some(level) => C::new_compression_level(level).expect("Validated before use"),
+                    None => self.compression_level.clone(),
+                };
+                self.exchange_with_encoder::<Chain<C, AesGcmEncoder>>(writer, (level, key))?
+            }
+            #[cfg(feature = "crypt4gh_encoder")]
+            EncryptionKey::Crypt4gh(config) => {
+                if options.compression_level.is_some() {
+                    return Err(PoolError::UnsupportedOption(
+                        "Crypt4GH writers are not compressed".to_string(),
+                    ));
+                }
+                self.exchange_with_encoder::<Crypt4ghEncoder>(writer, config)?
+            }
+        };
+        pooled.options = options;
+        Ok(pooled)
+    }
+}
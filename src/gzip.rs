@@ -0,0 +1,412 @@
+///! An implementation of [`Compressor`] for plain multi-member gzip, as produced by `pigz`, and
+///! of [`Decompressor`] for reading it back, e.g. to transcode it to another format.
+use std::io::{self, Read};
+
+use libdeflater::{CompressionLvl, Compressor as Deflater, Decompressor as Inflater};
+use thiserror::Error;
+
+use crate::reader::{Decompressor, PooledReader};
+use crate::{check_round_trip, Compressor, CompressorCapabilities, GzipHeader};
+
+/// The minimum supported gzip compression level.
+const MIN_LEVEL: u8 = 1;
+
+/// The maximum supported gzip compression level.
+const MAX_LEVEL: u8 = 12;
+
+/// The errors that may be returned by the [`GzipCompressor`].
+#[derive(Error, Debug)]
+pub enum GzipError {
+    #[error("Invalid gzip compression level {0}, must be in 1..=12")]
+    InvalidCompressionLevel(u8),
+    #[error("Insufficient space to compress gzip member")]
+    InsufficientSpace,
+}
+
+/// A gzip compressor that emits each block as an independent gzip member.
+///
+/// A sequence of gzip members is itself a valid gzip file, so unlike BGZF the output of a
+/// pooled writer is readable by any standard `gunzip`.  There is no EOF marker.
+pub struct GzipCompressor {
+    inner: Deflater,
+    /// Created the first time a block is verified.
+    verifier: Option<Inflater>,
+    /// The header written at the start of each member, if not the default.
+    header: Option<GzipHeader>,
+}
+
+impl Compressor for GzipCompressor {
+    type Error = GzipError;
+    type CompressionLevel = CompressionLvl;
+
+    /// The same block size as `pigz`.
+    const BLOCK_SIZE: usize = 128 * 1024;
+
+    fn capabilities() -> CompressorCapabilities {
+        CompressorCapabilities::new(Self::BLOCK_SIZE)
+            .eof_marker(false)
+            .dictionaries(false)
+            .extra_subfields(false)
+            .compression_levels(MIN_LEVEL, MAX_LEVEL)
+            .deterministic(true)
+            .verification(true)
+            .gzip_header(true, true)
+    }
+
+    fn new(compression_level: Self::CompressionLevel) -> Self {
+        Self { inner: Deflater::new(compression_level), verifier: None, header: None }
+    }
+
+    fn default_compression_level() -> Self::CompressionLevel {
+        CompressionLvl::new(6).unwrap()
+    }
+
+    fn new_compression_level(compression_level: u8) -> Result<Self::CompressionLevel, Self::Error> {
+        if (MIN_LEVEL..=MAX_LEVEL).contains(&compression_level) {
+            CompressionLvl::new(i32::from(compression_level))
+                .map_err(|_e| GzipError::InvalidCompressionLevel(compression_level))
+        } else {
+            Err(GzipError::InvalidCompressionLevel(compression_level))
+        }
+    }
+
+    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        let start = output.len();
+        output.resize(start + self.inner.gzip_compress_bound(input.len()), 0);
+        let len = self
+            .inner
+            .gzip_compress(input, &mut output[start..])
+            .map_err(|_e| GzipError::InsufficientSpace)?;
+        output.truncate(start + len);
+        if let Some(header) = &self.header {
+            header.apply(output, start);
+        }
+        Ok(())
+    }
+
+    fn set_gzip_header(&mut self, header: &GzipHeader) {
+        self.header = Some(header.clone());
+    }
+
+    fn verify(&mut self, input: &[u8], compressed: &[u8]) -> io::Result<()> {
+        let mut decompressed = vec![0; input.len()];
+        let len = self
+            .verifier
+            .get_or_insert_with(Inflater::new)
+            .gzip_decompress(compressed, &mut decompressed)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        decompressed.truncate(len);
+        check_round_trip(input, &decompressed)
+    }
+}
+
+/// A [`PooledReader`] of a multi-member gzip stream, whose members are decompressed in parallel
+/// by the threads of a pool and read in order.  Create one with
+/// [`PoolBuilder::exchange_reader`](crate::PoolBuilder::exchange_reader).
+pub type PooledGzipReader<R> = PooledReader<R, GzipDecompressor>;
+
+/// The largest ratio of uncompressed to compressed bytes that DEFLATE can achieve.
+const MAX_RATIO: usize = 1032;
+
+/// The number of extra bits after each length code, from code 257.
+const LENGTH_EXTRA_BITS: [u32; 29] =
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+/// The number of extra bits after each distance code.
+const DISTANCE_EXTRA_BITS: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The order in which the code lengths of the code length alphabet are stored.
+const CODE_LENGTH_ORDER: [usize; 19] =
+    [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// An implementation of [`Decompressor`] for multi-member gzip, e.g. as written by `pigz` or by a
+/// pool with the [`GzipCompressor`], which splits a stream into its members and decompresses each
+/// one independently.
+///
+/// Unlike BGZF, a gzip member doesn't record its length, so each member is found by scanning
+/// its DEFLATE stream on the thread that reads from the [`PooledReader`].  Scanning decodes
+/// every Huffman code of the member, which costs about as much as inflating it, so the reader
+/// is limited by the speed of the scan, however many threads the pool has; the `gzip_scan`
+/// example measures both for a given file.  The input is read a byte at a time, so should be
+/// buffered, e.g. by wrapping a file in a [`std::io::BufReader`].  A single-member file, as written by `gzip`, is read and
+/// decompressed as one block, so gains nothing from the pool's threads; members must also be
+/// smaller than 4GiB.
+pub struct GzipDecompressor {
+    inflater: Inflater,
+}
+
+impl Decompressor for GzipDecompressor {
+    type Error = io::Error;
+
+    fn new() -> Self {
+        Self { inflater: Inflater::new() }
+    }
+
+    fn read_block<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+        let mut member = Vec::new();
+        let mut input = MemberReader { reader, member: &mut member, bits: 0, count: 0 };
+        if !input.header()? {
+            return Ok(None);
+        }
+        input.deflate()?;
+        input.bytes(8)?;
+        Ok(Some(member))
+    }
+
+    fn decompress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let isize_at = input.len() - 4;
+        let len = u32::from_le_bytes([
+            input[isize_at],
+            input[isize_at + 1],
+            input[isize_at + 2],
+            input[isize_at + 3],
+        ]) as usize;
+        if len > input.len().saturating_mul(MAX_RATIO) {
+            return Err(invalid(format!("gzip member claims {} uncompressed bytes", len)));
+        }
+        let start = output.len();
+        output.resize(start + len, 0);
+        let n = self
+            .inflater
+            .gzip_decompress(input, &mut output[start..])
+            .map_err(|e| invalid(e.to_string()))?;
+        if n != len {
+            return Err(invalid(format!(
+                "gzip member decompressed to {} bytes, expected {}",
+                n, len
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Reads a gzip member from `reader`, keeping each byte read in `member`, and reading no further
+/// than the end of the member.
+struct MemberReader<'a, R: Read> {
+    reader: &'a mut R,
+    member: &'a mut Vec<u8>,
+    /// The bits of the last byte read that are still to be used, in the low bits.
+    bits: u32,
+    /// The number of bits still to be used.
+    count: u32,
+}
+
+impl<R: Read> MemberReader<'_, R> {
+    /// Reads the next `n` bytes.
+    fn bytes(&mut self, n: usize) -> io::Result<&[u8]> {
+        let start = self.member.len();
+        self.member.resize(start + n, 0);
+        self.reader.read_exact(&mut self.member[start..])?;
+        Ok(&self.member[start..])
+    }
+
+    /// Reads the next byte.
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Reads the next `n` bits, least significant first.
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            self.bits |= u32::from(self.byte()?) << self.count;
+            self.count += 8;
+        }
+        let value = self.bits & ((1 << n) - 1);
+        self.bits >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Reads the member header, returning false if the stream had already ended.
+    fn header(&mut self) -> io::Result<bool> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut first = [0u8];
+        if self.reader.read(&mut first)? == 0 {
+            return Ok(false);
+        }
+        self.member.push(first[0]);
+        let header = self.bytes(9)?;
+        if first[0] != 0x1f || header[..2] != [0x8b, 0x08] {
+            return Err(invalid("not a gzip member: bad magic or compression method"));
+        }
+        let flags = header[2];
+        if flags & 0x04 != 0 {
+            let xlen = self.bytes(2)?;
+            let xlen = usize::from(u16::from_le_bytes([xlen[0], xlen[1]]));
+            self.bytes(xlen)?;
+        }
+        // The file name, then the comment, each terminated by a zero byte
+        for flag in [0x08, 0x10] {
+            if flags & flag != 0 {
+                while self.byte()? != 0 {}
+            }
+        }
+        if flags & 0x02 != 0 {
+            self.bytes(2)?;
+        }
+        Ok(true)
+    }
+
+    /// Reads the DEFLATE stream of the member, up to and including its last byte.
+    fn deflate(&mut self) -> io::Result<()> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        loop {
+            let last = self.bits(1)? == 1;
+            match self.bits(2)? {
+                0 => {
+                    // A stored block starts at the next byte
+                    self.bits = 0;
+                    self.count = 0;
+                    let header = self.bytes(4)?;
+                    let len = u16::from_le_bytes([header[0], header[1]]);
+                    if len != !u16::from_le_bytes([header[2], header[3]]) {
+                        return Err(invalid("stored DEFLATE block length doesn't match"));
+                    }
+                    self.bytes(usize::from(len))?;
+                }
+                1 => {
+                    let mut lengths = [8u8; 288];
+                    lengths[144..256].fill(9);
+                    lengths[256..280].fill(7);
+                    let literals = Huffman::new(&lengths)?;
+                    let distances = Huffman::new(&[5; 30])?;
+                    self.codes(&literals, &distances)?;
+                }
+                2 => {
+                    let (literals, distances) = self.dynamic_codes()?;
+                    self.codes(&literals, &distances)?;
+                }
+                _ => return Err(invalid("invalid DEFLATE block type")),
+            }
+            if last {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads the code lengths of a block with dynamic Huffman codes, returning its literal and
+    /// length code and its distance code.
+    fn dynamic_codes(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let literals = self.bits(5)? as usize + 257;
+        let distances = self.bits(5)? as usize + 1;
+        let code_lengths = self.bits(4)? as usize + 4;
+        if literals > 286 || distances > 30 {
+            return Err(invalid("too many DEFLATE codes"));
+        }
+
+        let mut lengths = [0u8; 19];
+        for &at in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[at] = self.bits(3)? as u8;
+        }
+        let code = Huffman::new(&lengths)?;
+
+        let mut lengths = vec![0u8; literals + distances];
+        let mut at = 0;
+        while at < lengths.len() {
+            let (length, repeat) = match code.decode(self)? {
+                symbol @ 0..=15 => (symbol as u8, 1),
+                16 if at > 0 => (lengths[at - 1], 3 + self.bits(2)? as usize),
+                16 => return Err(invalid("DEFLATE code length repeated with none before it")),
+                17 => (0, 3 + self.bits(3)? as usize),
+                _ => (0, 11 + self.bits(7)? as usize),
+            };
+            if at + repeat > lengths.len() {
+                return Err(invalid("too many DEFLATE code lengths"));
+            }
+            lengths[at..at + repeat].fill(length);
+            at += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(invalid("DEFLATE block has no end of block code"));
+        }
+        Ok((Huffman::new(&lengths[..literals])?, Huffman::new(&lengths[literals..])?))
+    }
+
+    /// Reads the compressed data of a block up to its end of block code.
+    fn codes(&mut self, literals: &Huffman, distances: &Huffman) -> io::Result<()> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        loop {
+            let symbol = literals.decode(self)?;
+            match symbol {
+                0..=255 => continue,
+                256 => return Ok(()),
+                _ => {
+                    let extra = LENGTH_EXTRA_BITS
+                        .get(usize::from(symbol) - 257)
+                        .ok_or_else(|| invalid("invalid DEFLATE length code"))?;
+                    self.bits(*extra)?;
+                    let extra = DISTANCE_EXTRA_BITS
+                        .get(usize::from(distances.decode(self)?))
+                        .ok_or_else(|| invalid("invalid DEFLATE distance code"))?;
+                    self.bits(*extra)?;
+                }
+            }
+        }
+    }
+}
+
+/// A canonical Huffman code of DEFLATE, decoded a bit at a time.
+struct Huffman {
+    /// The number of codes of each length.
+    counts: [u16; 16],
+    /// The symbols, ordered by their codes.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the code from the code length of each symbol, 0 for symbols without a code.
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0u16; 16];
+        lengths.iter().for_each(|&l| counts[usize::from(l)] += 1);
+        counts[0] = 0;
+
+        // Check that no more codes of each length are used than are left
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "over-subscribed DEFLATE Huffman code",
+                ));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; usize::from(offsets[15] + counts[15])];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[usize::from(offsets[usize::from(len)])] = symbol as u16;
+                offsets[usize::from(len)] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    /// Reads the next symbol from `input`.
+    fn decode<R: Read>(&self, input: &mut MemberReader<'_, R>) -> io::Result<u16> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in &self.counts[1..] {
+            code |= input.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidData, "invalid DEFLATE Huffman code"))
+    }
+}
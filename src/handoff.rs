@@ -0,0 +1,124 @@
+//! Composing pools into multi-stage pipelines, e.g. a compression pool feeding an upload pool,
+//! in which the output of each writer of one pool is handed off to a writer of the next.
+//!
+//! A [`Handoff`] wraps a [`PooledWriter`] of the downstream pool and is exchanged with the
+//! upstream pool in place of a plain writer.  The hand-off is bounded by the downstream pool's
+//! queues, so the upstream pool's threads block while the downstream pool is busy rather than
+//! buffering without limit.  A [`PoolChain`] then shuts the stages down in order: each pool is
+//! stopped, then the downstream writers it was feeding are closed, before the next pool is
+//! stopped.
+//!
+//! ```rust,no_run
+//! use std::io::Write;
+//! use pooled_writer::{bgzf::BgzfCompressor, noop::NoopCompressor, PoolBuilder};
+//! use pooled_writer::handoff::{Handoff, PoolChain};
+//!
+//! let mut upload = PoolBuilder::<_, NoopCompressor>::new();
+//! let uploaded = upload.exchange(std::fs::File::create("out.gz")?);
+//! let upload = upload.build()?;
+//!
+//! let handoff = Handoff::new(uploaded);
+//! let mut compress = PoolBuilder::<_, BgzfCompressor>::new();
+//! let mut writer = compress.exchange(handoff.clone());
+//! let compress = compress.build()?;
+//!
+//! let mut chain = PoolChain::new().stage(compress, vec![handoff]).stage(upload, vec![]);
+//! writer.write_all(b"hello")?;
+//! writer.close()?;
+//! chain.stop()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::{Pool, PoolResult, PooledWriter};
+
+/// A writer that hands the bytes written to it, e.g. the compressed blocks of an upstream
+/// pool, to a [`PooledWriter`] of a downstream pool.  Clones share the downstream writer, so a
+/// clone can be kept to close it with [`Handoff::close`] or a [`PoolChain`].  Writes fail once
+/// the downstream writer has been closed.
+#[derive(Debug, Clone)]
+pub struct Handoff {
+    writer: Arc<Mutex<Option<PooledWriter>>>,
+}
+
+impl Handoff {
+    /// Creates a hand-off to `downstream`.
+    pub fn new(downstream: PooledWriter) -> Self {
+        Self { writer: Arc::new(Mutex::new(Some(downstream))) }
+    }
+
+    /// Closes the downstream writer, finalizing its stream, if it has not already been closed.
+    /// This should only be done once the upstream pool has been stopped.
+    pub fn close(&self) -> io::Result<()> {
+        match self.writer.lock().take() {
+            Some(writer) => writer.close(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for Handoff {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.writer.lock().as_mut() {
+            Some(writer) => writer.write(buf),
+            None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "downstream writer closed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.writer.lock().as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A stage of a [`PoolChain`]: a pool and the hand-offs to the next stage that it writes to.
+#[derive(Debug)]
+struct Stage {
+    pool: Pool,
+    handoffs: Vec<Handoff>,
+}
+
+/// A sequence of pools, each feeding the next through [`Handoff`]s, that are shut down
+/// together from the most upstream stage to the most downstream.
+#[derive(Debug, Default)]
+pub struct PoolChain {
+    stages: Vec<Stage>,
+}
+
+impl PoolChain {
+    /// Creates an empty chain.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `pool` as the next stage downstream, along with the `handoffs` exchanged with it to
+    /// feed the stage after it.
+    pub fn stage(mut self, pool: Pool, handoffs: Vec<Handoff>) -> Self {
+        self.stages.push(Stage { pool, handoffs });
+        self
+    }
+
+    /// The pools in the chain, from the most upstream to the most downstream.
+    pub fn pools(&self) -> impl Iterator<Item = &Pool> {
+        self.stages.iter().map(|s| &s.pool)
+    }
+
+    /// Stops each pool in turn, from the most upstream, closing the hand-offs of each stage
+    /// once its pool has stopped so that the next pool sees the end of each stream before it
+    /// is itself stopped.  Every stage is stopped even if an earlier one fails, and the first
+    /// error is returned.
+    pub fn stop(&mut self) -> PoolResult<()> {
+        let mut result = Ok(());
+        for stage in &mut self.stages {
+            let stopped = stage.pool.stop_pool();
+            let closed = stage.handoffs.iter().try_for_each(Handoff::close);
+            result = result.and(stopped).and(closed.map_err(Into::into));
+        }
+        result
+    }
+}
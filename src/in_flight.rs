@@ -0,0 +1,73 @@
+//! The limits on what is in flight, i.e. sent to the pool but not yet written: the blocks of
+//! each writer, see [`PoolBuilder::max_in_flight_blocks`], and the bytes across all the writers
+//! of a pool, see [`PoolBuilder::max_in_flight_bytes`].
+//!
+//! Each block takes its share of the limit when it is sent to the pool, and gives it back once
+//! it has been written, so the limit covers blocks waiting in the compressor queue, being
+//! compressed, and waiting in the writers' reorder buffers.  Writers waiting for the limit are
+//! woken as soon as a block is written, or once the pool stops.
+//!
+//! [`PoolBuilder::max_in_flight_blocks`]: crate::PoolBuilder::max_in_flight_blocks
+//! [`PoolBuilder::max_in_flight_bytes`]: crate::PoolBuilder::max_in_flight_bytes
+use parking_lot::{Condvar, Mutex};
+
+#[derive(Debug, Default)]
+struct InFlight {
+    /// The amount in flight.
+    used: usize,
+    /// True once the pool has stopped, after which nothing more is taken.
+    closed: bool,
+}
+
+/// The amount in flight for the writers sharing the limit, counted in blocks or in bytes.
+#[derive(Debug)]
+pub(crate) struct InFlightLimit {
+    limit: usize,
+    state: Mutex<InFlight>,
+    released: Condvar,
+}
+
+impl InFlightLimit {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self { limit, state: Mutex::new(InFlight::default()), released: Condvar::new() }
+    }
+
+    /// The amount that may be in flight.
+    pub(crate) fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Takes `amount` from the limit, waiting until enough is given back.  Returns false if the
+    /// pool stops first.  A block larger than the whole limit is let through once nothing else
+    /// is in flight.
+    pub(crate) fn acquire(&self, amount: usize) -> bool {
+        let mut state = self.state.lock();
+        while !state.closed && state.used > 0 && state.used + amount > self.limit {
+            self.released.wait(&mut state);
+        }
+        if state.closed {
+            return false;
+        }
+        state.used += amount;
+        true
+    }
+
+    /// Takes `amount` from the limit without waiting, for a block that is already compressed
+    /// when it joins the pool, e.g. one carried over by an attached writer.
+    pub(crate) fn take(&self, amount: usize) {
+        self.state.lock().used += amount;
+    }
+
+    /// Gives `amount` back to the limit once its block has been written.
+    pub(crate) fn release(&self, amount: usize) {
+        let mut state = self.state.lock();
+        state.used = state.used.saturating_sub(amount);
+        self.released.notify_all();
+    }
+
+    /// Wakes the writers waiting for the limit once the pool has stopped, failing their waits.
+    pub(crate) fn close(&self) {
+        self.state.lock().closed = true;
+        self.released.notify_all();
+    }
+}
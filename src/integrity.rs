@@ -0,0 +1,69 @@
+//! Checksums of each compressed block, computed on the pool's threads and recorded in a
+//! per-writer [`BlockManifest`], so that outputs can be validated block by block downstream,
+//! e.g. after being transferred.
+use std::io::Write;
+
+use crate::{BlockManifest, Compressor, PoolBuilder};
+
+/// The algorithms with which each compressed block may be checksummed, trading speed against
+/// compatibility with downstream validators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockChecksum {
+    /// The CRC32 of gzip and zip, as checked by most tools.
+    Crc32,
+    /// The Castagnoli CRC32, which is hardware accelerated on CPUs with SSE 4.2 or the ARMv8
+    /// CRC extension.
+    Crc32c,
+    /// The 64 bit XXH3 hash, the fastest in software.
+    Xxh3,
+}
+
+impl BlockChecksum {
+    /// All the algorithms.
+    pub const ALL: [BlockChecksum; 3] =
+        [BlockChecksum::Crc32, BlockChecksum::Crc32c, BlockChecksum::Xxh3];
+
+    /// The name of the algorithm, as recorded in [`BlockManifest::algorithm`].
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlockChecksum::Crc32 => "crc32",
+            BlockChecksum::Crc32c => "crc32c",
+            BlockChecksum::Xxh3 => "xxh3",
+        }
+    }
+
+    /// The algorithm with the given name, e.g. as read back from a [`BlockManifest`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|a| a.name() == name)
+    }
+
+    /// Computes the checksum of `bytes`.
+    pub fn checksum(&self, bytes: &[u8]) -> u64 {
+        (self.function())(bytes)
+    }
+
+    fn function(&self) -> fn(&[u8]) -> u64 {
+        match self {
+            BlockChecksum::Crc32 => |bytes| u64::from(crc32fast::hash(bytes)),
+            BlockChecksum::Crc32c => |bytes| u64::from(crc32c::crc32c(bytes)),
+            BlockChecksum::Xxh3 => xxhash_rust::xxh3::xxh3_64,
+        }
+    }
+}
+
+impl<W, C> PoolBuilder<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    /// Enables checksumming of each compressed block with `algorithm`, on the pool's threads as
+    /// blocks are compressed.  The checksums are recorded, with the name of the algorithm, in a
+    /// [`BlockManifest`] for each writer, see [`Pool::block_manifest`].  Applies to writers
+    /// exchanged after this is called.
+    ///
+    /// [`Pool::block_manifest`]: crate::Pool::block_manifest
+    pub fn block_checksums(mut self, algorithm: BlockChecksum) -> Self {
+        self.block_checksum = Some((algorithm.name(), algorithm.function()));
+        self
+    }
+}
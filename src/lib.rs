@@ -18,19 +18,20 @@
 //! and a writing queue.  All concurrency is managed via message passing over channels.
 //!
 //! Every time the internal buffer of a [`PooledWriter`] reaches capacity (defined by
-//! [`Compressor::BLOCK_SIZE`]) it sends two messages:
-//! 1. It sends a message over the corresponding writer's channel to the writer pool, enqueueing
-//!    a one-shot receiver channel in the writers queue that will receive the compressed bytes
-//!    once the compressor is done. This is done to maintain the output order.
-//! 2. It sends a message to the compressor pool that contains a buffer of bytes to compress
-//!    as well as the sender side of the one-shot channel to send the compressed bytes on.
+//! [`Compressor::BLOCK_SIZE`]) it numbers the block with the writer's next sequence number and:
+//! 1. Takes a slot in the corresponding writer's queue, a bounded channel that limits how many of
+//!    the writer's blocks may be waiting to be compressed or written.
+//! 2. Sends a message to the compressor pool that contains a buffer of bytes to compress and the
+//!    block's sequence number.
 //!
 //! The threads in the thread pool loop continuously until the pool is shut down, and attempt
-//! first receive and compress one block, then secondly to receive and write one compressed block.
-//! A third internal channel is used to manage the queue of writes to be performed so that the
-//! individual per-writer channels (of which there may be many) are only polled if there is likely
-//! to be data available for writing.  When data is available to be written, the appropriate
-//! underlying writer is locked, and the data written.
+//! first receive and compress one block, then secondly to write one compressed block.  Once
+//! compressed, a block is held in its writer's reorder buffer until every block before it, by
+//! sequence number, has been written, which maintains the output order.  A third internal
+//! channel is used to manage the queue of writes to be performed so that the reorder buffers of
+//! the individual writers (of which there may be many) are only checked if there is likely to be
+//! data available for writing.  When data is available to be written, the appropriate underlying
+//! writer is locked, and the data written.
 //!
 //! When all writing to [`PooledWriter`]s is complete, the writers should be close()'d or drop()'d
 //! and then the pool should be stopped using [`Pool::stop_pool`].  Writers that are not closed
@@ -92,22 +93,96 @@
     clippy::module_name_repetitions
 )]
 
+pub mod adaptive;
+#[cfg(feature = "aes_gcm_encoder")]
+pub mod aes;
+pub mod autoscale;
 #[cfg(feature = "bgzf_compressor")]
 pub mod bgzf;
+#[cfg(any(
+    feature = "bgzf_compressor",
+    feature = "gzip_compressor",
+    feature = "deflate_compressor"
+))]
+pub mod block;
+pub mod callback;
+mod channel;
+#[cfg(feature = "checksums")]
+pub mod checksum;
+pub mod clock;
+pub mod completion;
+pub mod concat;
+#[cfg(feature = "crypt4gh_encoder")]
+pub mod crypt4gh;
+#[cfg(feature = "deflate_compressor")]
+pub mod deflate;
+pub mod doctor;
+pub mod dynamic;
+pub mod encoder;
+#[cfg(any(feature = "aes_gcm_encoder", feature = "crypt4gh_encoder"))]
+pub mod encryption;
+#[cfg(feature = "gzip_compressor")]
+pub mod gzip;
+pub mod handoff;
+mod in_flight;
+#[cfg(feature = "block_checksums")]
+pub mod integrity;
+pub mod marshal;
+pub mod migrate;
+pub mod noop;
+pub mod offsets;
+pub mod parallel;
+#[cfg(feature = "indicatif_progress")]
+pub mod progress;
+pub mod reader;
+pub mod scope;
+pub mod shared;
+#[cfg(feature = "snappy_compressor")]
+pub mod snappy;
+pub mod spawn;
+pub mod stats;
+pub mod transcode;
+pub mod tuning;
+#[cfg(feature = "xz_compressor")]
+pub mod xz;
+#[cfg(feature = "zstd_compressor")]
+pub mod zstd;
 
 use std::time::Duration;
 use std::{
+    any::{Any, TypeId},
+    collections::VecDeque,
     error::Error,
-    io::{self, Read, Write},
-    sync::Arc,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
     thread::JoinHandle,
 };
 
 use bytes::{Bytes, BytesMut};
-use flume::{self, bounded, Receiver, Sender};
-use parking_lot::{lock_api::RawMutex, Mutex};
+use parking_lot::{lock_api::RawMutex, Condvar, MappedMutexGuard, Mutex, MutexGuard};
 use thiserror::Error;
 
+use crate::adaptive::{AdaptiveCompression, LevelController};
+use crate::autoscale::{AutoScaling, ThreadScaler};
+use crate::channel::{bounded, Doorbell, DoorbellSender, Receiver, Sender, Waiters};
+use crate::clock::{Clock, SystemClock};
+use crate::completion::{panic_message, Completion, CompletionHandle};
+use crate::in_flight::InFlightLimit;
+use crate::offsets::{BlockOffsets, PendingVirtualOffset};
+pub use crate::reader::Decompressor;
+use crate::reader::{PooledReader, Task};
+use crate::spawn::{Spawner, ThreadSpawner};
+use crate::stats::{LevelCounters, PoolStats, WriterCounters};
+use crate::tuning::{BlockSizeTuner, BlockSizeTuning};
+#[cfg(feature = "thread_priority")]
+pub use thread_priority::ThreadPriority;
+
 /// 128 KB default buffer size, same as pigz.
 pub(crate) const BUFSIZE: usize = 128 * 1024;
 
@@ -121,19 +196,806 @@ pub enum PoolError {
     #[error("Failed to send over channel")]
     ChannelSend,
     #[error(transparent)]
-    ChannelReceive(#[from] flume::RecvError),
+    ChannelReceive(#[from] RecvError),
 
     // TODO: figure out how to better pass in an generic / dynamic error type to this.
     #[error("Error compressing data: {0}")]
     CompressionError(String),
+    #[error("Invalid compression level {level}, must be between {min} and {max}")]
+    InvalidCompressionLevel { level: u8, min: u8, max: u8 },
+    #[error("Unsupported option for compressor: {0}")]
+    UnsupportedOption(String),
+    #[error("Attempted to write to writer {0} after it was finalized")]
+    WriterFinalized(usize),
+    #[error("Writer {id}: {source}")]
+    Writer { id: String, source: Box<PoolError> },
+    #[error("Writer ID {0} is already used by another writer")]
+    DuplicateWriterId(String),
+    #[error("Destination {} is already written by writer {writer}", .path.display())]
+    DuplicateDestination { path: PathBuf, writer: usize },
+    #[error("No writers were exchanged before the pool was built")]
+    NoWriters,
+    #[error("Writer {0} is not in the pool, or does not have the requested type")]
+    UnknownWriter(usize),
+    #[error("The output of writer {writer} exceeded the limit of {limit} compressed bytes")]
+    OutputSizeExceeded { writer: usize, limit: u64 },
+    #[error("The pool thread panicked: {0}")]
+    Panicked(String),
+    #[error("Compressed block failed verification: {0}")]
+    VerificationFailed(String),
     #[error(transparent)]
     Io(#[from] io::Error),
 }
 
+/// The error for receiving on a channel between the pool's threads whose senders have all been
+/// dropped.  It is the same whichever of the `flume_channels` or `crossbeam_channels` features
+/// is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("receiving on a closed channel")]
+pub struct RecvError;
+
 ////////////////////////////////////////////////////////////////////////////////
 // The PooledWriter and it's impls
 ////////////////////////////////////////////////////////////////////////////////
 
+/// What a [`PooledWriter`] should do with its stream when it is dropped without having been
+/// explicitly finalized via [`PooledWriter::finalize`] or [`PooledWriter::close`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Send any buffered bytes and finalize the stream (e.g. append the BGZF EOF block).
+    Finalize,
+    /// Send any buffered bytes but do not finalize the stream, leaving it open for further
+    /// writes to the underlying writer by other means.
+    FlushPartial,
+    /// Discard any buffered bytes and leave the stream unfinalized.
+    Discard,
+}
+
+impl Default for DropPolicy {
+    fn default() -> Self {
+        DropPolicy::Finalize
+    }
+}
+
+/// What [`PoolBuilder::build`] should do if no writers have been exchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyPoolPolicy {
+    /// Return a pool that starts no threads, and which may be stopped as normal.
+    NoOp,
+    /// Return a [`PoolError::NoWriters`] error.
+    Error,
+}
+
+impl Default for EmptyPoolPolicy {
+    fn default() -> Self {
+        EmptyPoolPolicy::NoOp
+    }
+}
+
+/// What the pool should do if one of its threads panics, e.g. in a compressor or writer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Resume the panic in the pool's management thread, so that it is re-raised by
+    /// [`Pool::stop_pool`].
+    ResumeUnwind,
+    /// Report the panic as a [`PoolError::Panicked`] from [`Pool::stop_pool`], leaving the rest
+    /// of the process, and the pool's other threads, running.
+    ConvertToError,
+    /// Abort the process as soon as the thread panics.
+    AbortProcess,
+}
+
+impl Default for PanicPolicy {
+    fn default() -> Self {
+        PanicPolicy::ResumeUnwind
+    }
+}
+
+impl PanicPolicy {
+    /// Applies the policy to the payload of a panic caught from a pool thread.
+    fn handle(self, payload: Box<dyn Any + Send>) -> PoolError {
+        match self {
+            PanicPolicy::ResumeUnwind => std::panic::resume_unwind(payload),
+            PanicPolicy::ConvertToError => PoolError::Panicked(panic_message(payload.as_ref())),
+            PanicPolicy::AbortProcess => std::process::abort(),
+        }
+    }
+}
+
+/// Aborts the process if dropped while its thread is panicking, see
+/// [`PanicPolicy::AbortProcess`].
+struct AbortOnPanic;
+
+impl Drop for AbortOnPanic {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            std::process::abort();
+        }
+    }
+}
+
+/// How the stream of a small output is encoded, see [`PoolBuilder::small_output_bypass`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmallOutputPolicy {
+    /// Compress the output as normal.
+    Compress,
+    /// Write the bytes uncompressed, without any compression framing or EOF marker.
+    Uncompressed,
+    /// Compress the output at the given (typically low) compression level.
+    CompressionLevel(u8),
+}
+
+/// A hook that is called with the writer index and the policy applied when an output is small
+/// enough to bypass normal compression, e.g. so that the caller can rename the output to drop a
+/// `.gz` extension.
+pub type SmallOutputHook = Arc<dyn Fn(usize, SmallOutputPolicy) + Send + Sync>;
+
+/// The configuration for bypassing normal compression of small outputs.
+#[derive(Clone)]
+struct SmallOutputBypass {
+    /// Outputs with fewer than this many bytes in total are bypassed.
+    threshold: usize,
+    /// How bypassed outputs are encoded.
+    policy: SmallOutputPolicy,
+    /// An optional hook to notify when an output is bypassed.
+    hook: Option<SmallOutputHook>,
+}
+
+impl std::fmt::Debug for SmallOutputBypass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SmallOutputBypass")
+            .field("threshold", &self.threshold)
+            .field("policy", &self.policy)
+            .field("hook", &self.hook.is_some())
+            .finish()
+    }
+}
+
+/// A factory that opens the numbered outputs of a writer that is split by record count, see
+/// [`PoolBuilder::exchange_split_by_records`].
+pub type OutputFactory<W> = Box<dyn FnMut(usize) -> io::Result<W> + Send>;
+
+/// A hook that wraps each writer exchanged with a builder, see [`PoolBuilder::map_writers`].
+pub type WriterMapper<W> = Arc<dyn Fn(W) -> W + Send + Sync>;
+
+/// A hook that opens a replacement for a writer whose writes have failed, given the index of
+/// the writer, the number of compressed bytes of its current output that were written in full
+/// blocks, and the error.  See [`PoolBuilder::reopen_failed_writers`].
+pub type ReopenHook<W> = Arc<dyn Fn(usize, u64, &io::Error) -> io::Result<W> + Send + Sync>;
+
+/// An entry in the manifest of a writer that is split by record count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitOutput {
+    /// The number of the output, as passed to the [`OutputFactory`].
+    pub index: usize,
+    /// The number of records written to the output.
+    pub records: u64,
+}
+
+/// The checksums of the compressed blocks written for a writer, in order, along with the name
+/// of the algorithm used, e.g. `crc32c`, so that outputs can be validated block by block
+/// downstream.  See [`Pool::block_manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockManifest {
+    /// The name of the checksum algorithm.
+    pub algorithm: &'static str,
+    /// The blocks written, in the order they were written.
+    pub blocks: Vec<BlockRecord>,
+}
+
+/// An entry in a [`BlockManifest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockRecord {
+    /// The number of compressed bytes in the block.
+    pub compressed_len: usize,
+    /// The number of uncompressed bytes in the block.
+    pub uncompressed_len: usize,
+    /// The checksum of the compressed bytes of the block.
+    pub checksum: u64,
+}
+
+/// Computes the checksum of the compressed bytes of a block.
+type BlockChecksumFn = fn(&[u8]) -> u64;
+
+/// The checksums of the blocks written for a single writer.
+#[derive(Debug)]
+struct BlockChecksums {
+    /// The function computing each checksum.
+    checksum: BlockChecksumFn,
+    /// The checksums of the blocks written so far.
+    manifest: Mutex<BlockManifest>,
+}
+
+/// Per-writer settings, given when a writer is exchanged with
+/// [`PoolBuilder::exchange_with_options`] or changed later with [`PooledWriter::reconfigure`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExchangeOptions {
+    /// The compression level for the writer's blocks, or `None` for the level of the writer's
+    /// compressor.
+    pub compression_level: Option<u8>,
+    /// If true, [`PooledWriter::flush_partial`] does nothing, so that bytes are coalesced into
+    /// full blocks however often the producer flushes.
+    pub coalesce_partial_flushes: bool,
+    /// If true, the underlying writer is flushed after each block is written to it.
+    pub flush_each_block: bool,
+    /// If set, the writer is in streaming mode, for interactive outputs such as logs sent over a
+    /// socket: [`Write::flush`] sends the buffered bytes immediately as a small frame rather
+    /// than waiting for a full block, a write sends them once the oldest has been buffered for
+    /// this long, and the underlying writer is flushed after each frame is written to it.
+    pub max_frame_delay: Option<Duration>,
+    /// If true, the EOF marker is not appended when the stream is finalized, e.g. so that BGZF
+    /// shards can later be concatenated into one valid file without EOF blocks in the middle.
+    /// Only for compressors that are not stateful, whose EOF marker is a separate trailer.
+    pub omit_eof_marker: bool,
+    /// The key with which the writer's output is encrypted, if any, registered with
+    /// `PoolBuilder::encryption_key`.  Can only be set when the writer is exchanged.
+    pub encryption: Option<EncryptionKeyId>,
+}
+
+impl ExchangeOptions {
+    /// Creates the default options, which leave the writer's behavior unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the compression level for the writer's blocks.
+    pub fn compression_level(mut self, level: u8) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
+
+    /// Sets whether partial flushes are coalesced into full blocks.
+    pub fn coalesce_partial_flushes(mut self, coalesce: bool) -> Self {
+        self.coalesce_partial_flushes = coalesce;
+        self
+    }
+
+    /// Sets whether the underlying writer is flushed after each block.
+    pub fn flush_each_block(mut self, flush: bool) -> Self {
+        self.flush_each_block = flush;
+        self
+    }
+
+    /// Enables streaming mode, in which bytes are sent as frames smaller than a block on each
+    /// flush, or by the first write once they have been buffered for `max_delay`.  Bytes still
+    /// buffered when writes stop are only sent by the next write or flush, so producers should
+    /// flush before going idle.
+    pub fn streaming(mut self, max_delay: Duration) -> Self {
+        self.max_frame_delay = Some(max_delay);
+        self
+    }
+
+    /// Sets whether the EOF marker is omitted when the stream is finalized.
+    pub fn omit_eof_marker(mut self, omit: bool) -> Self {
+        self.omit_eof_marker = omit;
+        self
+    }
+
+    /// Sets the key with which the writer's output is encrypted, see [`encryption`].
+    #[cfg(any(feature = "aes_gcm_encoder", feature = "crypt4gh_encoder"))]
+    pub fn encryption(mut self, key: EncryptionKeyId) -> Self {
+        self.encryption = Some(key);
+        self
+    }
+}
+
+/// Identifies a key registered with a [`PoolBuilder`] with which writers may be encrypted,
+/// see [`ExchangeOptions::encryption`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EncryptionKeyId(usize);
+
+/// The record-count based splitting state of a [`PooledWriter`].
+#[derive(Debug)]
+struct RecordSplit {
+    /// The number of records after which a new output is started.
+    records_per_output: u64,
+    /// The number of records written to each output so far, the last being the current output.
+    records: Vec<u64>,
+    /// The number of blocks that had been sent when the current output was started.
+    blocks_at_start: u64,
+}
+
+/// The state for a single writer that is shared between its [`PooledWriter`], the pool threads
+/// and the [`Pool`].
+#[derive(Debug, Default)]
+struct WriterShared {
+    /// The statistics counters for the writer.
+    counters: WriterCounters,
+    /// The compressed offsets of the blocks written, if virtual offset tracking is enabled.
+    offsets: Option<Arc<BlockOffsets>>,
+    /// True if the uncompressed bytes are needed when writing, for a tee writer or an observer.
+    needs_raw: bool,
+    /// The limit on the writer's blocks in flight, if any.
+    in_flight: Option<InFlightLimit>,
+    /// The limit on the bytes in flight shared with the pool's other writers, if any.
+    in_flight_bytes: Option<Arc<InFlightLimit>>,
+    /// The index of the [`CompressorOverride`] used for the writer, if it doesn't use the pool's
+    /// own compressor.
+    compressor: Option<usize>,
+    /// The writer's own compressor, if its compressor is stateful or one is used per writer.
+    stream: Option<StreamCompressor>,
+    /// The stable ID given to the writer, if any, see [`PoolBuilder::set_writer_id`].
+    id: Mutex<Option<Arc<str>>>,
+    /// The checksums of the blocks written, if block checksums are enabled.
+    block_checksums: Option<BlockChecksums>,
+    /// The limit on the compressed size of each output, see [`PoolBuilder::max_output_size`].
+    max_output_size: Option<u64>,
+    /// True once the output has exceeded its size limit, after which its blocks are dropped.
+    size_exceeded: AtomicBool,
+    /// The writer's blocks that have been compressed but not yet written.
+    reorder: Mutex<ReorderBuffer>,
+    /// Notified, with the lock on `reorder`, whenever a block of the writer has been written or
+    /// the pool has stopped.
+    written: Condvar,
+    /// True once the pool's threads have exited, after which no more blocks are written.
+    stopped: AtomicBool,
+    /// True once the writer is being detached from the pool, after which its blocks are left
+    /// in the reorder buffer for [`Pool::detach_writer`] to take.
+    detached: AtomicBool,
+    /// True while the writer is held by a [`QuiesceGuard`], during which its blocks are left in
+    /// the reorder buffer until the guard is dropped.
+    quiesced: AtomicBool,
+    /// Set by a pool thread that finds the writer's blocks ready to be written, so that a thread
+    /// already writing them looks again before it releases the writer, rather than the first
+    /// waiting for its lock.
+    write_requested: AtomicBool,
+    /// Blocks of fewer bytes than this are compressed in batches, see
+    /// [`PoolBuilder::batch_small_blocks`].
+    batch_below: Option<usize>,
+    /// The writer's small blocks waiting to be compressed together as a batch.
+    batch: Mutex<Vec<CompressorMessage>>,
+}
+
+/// The messages for a writer that are ready to be written, held until every message before them
+/// has been written so that the writer's blocks are written in the order they were sent.
+#[derive(Debug, Default)]
+struct ReorderBuffer {
+    /// The sequence number of the next message to be written.
+    next: u64,
+    /// The messages from the next onwards, by sequence number, or `None` where a message is
+    /// still being compressed.
+    slots: VecDeque<Option<WriterMessage>>,
+}
+
+impl ReorderBuffer {
+    /// Holds `message` until every message before it has been written.
+    fn insert(&mut self, message: WriterMessage) {
+        let index = (message.sequence - self.next) as usize;
+        if index >= self.slots.len() {
+            self.slots.resize_with(index + 1, || None);
+        }
+        self.slots[index] = Some(message);
+    }
+
+    /// The number of messages that have been compressed, whether or not they have been written.
+    fn compressed(&self) -> u64 {
+        self.next + self.slots.iter().flatten().count() as u64
+    }
+
+    /// Removes and returns the next message to be written, if it is ready.
+    fn pop_next(&mut self) -> Option<WriterMessage> {
+        let message = self.slots.front_mut()?.take()?;
+        self.slots.pop_front();
+        self.next += 1;
+        Some(message)
+    }
+}
+
+impl WriterShared {
+    /// Returns a [`PoolError::OutputSizeExceeded`] if the output of the writer at
+    /// `writer_index` has exceeded its size limit.
+    fn check_output_size(&self, writer_index: usize) -> PoolResult<()> {
+        match self.max_output_size {
+            Some(limit) if self.size_exceeded.load(Ordering::Relaxed) => {
+                Err(self.label_error(PoolError::OutputSizeExceeded { writer: writer_index, limit }))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits until `blocks` of the writer's blocks have been written.  Returns an error if the
+    /// output exceeds its size limit first, or [`PoolError::ChannelSend`] if the pool stops.
+    fn wait_for_written(&self, writer_index: usize, blocks: u64) -> PoolResult<()> {
+        let mut reorder = self.reorder.lock();
+        while self.counters.blocks_written() < blocks {
+            self.check_output_size(writer_index)?;
+            if self.stopped.load(Ordering::Relaxed) {
+                return Err(PoolError::ChannelSend);
+            }
+            self.written.wait(&mut reorder);
+        }
+        Ok(())
+    }
+
+    /// Wakes anything waiting for the writer's blocks to be written.
+    fn notify_written(&self) {
+        // Taking the lock orders the notification after a waiter's check of the counters
+        drop(self.reorder.lock());
+        self.written.notify_all();
+    }
+
+    /// Wraps an error concerning the writer in a [`PoolError::Writer`] naming the writer, if it
+    /// has been given an ID.
+    fn label_error(&self, error: PoolError) -> PoolError {
+        match self.id.lock().as_ref() {
+            Some(id) => PoolError::Writer { id: id.to_string(), source: Box::new(error) },
+            None => error,
+        }
+    }
+}
+
+/// The compressor for a single writer whose compressor carries state across blocks, see
+/// [`CompressorCapabilities::stateful`] and [`PoolBuilder::compressor_per_writer`].  The writer's
+/// blocks are compressed by it one at a time, in the order they were written, on whichever
+/// thread picks each one up.
+#[derive(Default)]
+struct StreamCompressor {
+    state: Mutex<StreamState>,
+    turn: Condvar,
+}
+
+#[derive(Default)]
+struct StreamState {
+    /// The compressor for the current stream, created by its first block and dropped after its
+    /// final block.
+    compressor: Option<Box<dyn BlockCompressor>>,
+    /// The number of the next block to be compressed.
+    next_block: u64,
+    /// True if a block failed to compress, after which the stream can't be continued.
+    failed: bool,
+}
+
+impl StreamCompressor {
+    /// Waits until all blocks before `block_number` have been compressed, then calls `f` with
+    /// the stream's compressor, if one has been created for the current stream.
+    fn in_order<F>(&self, block_number: u64, f: F) -> PoolResult<()>
+    where
+        F: FnOnce(&mut Option<Box<dyn BlockCompressor>>) -> PoolResult<()>,
+    {
+        let mut state = self.state.lock();
+        while !state.failed && state.next_block != block_number {
+            self.turn.wait(&mut state);
+        }
+        if state.failed {
+            return Err(PoolError::CompressionError(
+                "an earlier block of the stream failed to compress".to_string(),
+            ));
+        }
+        let result = f(&mut state.compressor);
+        state.failed = result.is_err();
+        state.next_block += 1;
+        self.turn.notify_all();
+        result
+    }
+}
+
+impl std::fmt::Debug for StreamCompressor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock();
+        f.debug_struct("StreamCompressor")
+            .field("next_block", &state.next_block)
+            .field("failed", &state.failed)
+            .finish()
+    }
+}
+
+/// Opens the next output of a sink after each stream in it has been finalized.
+struct Rotation<W> {
+    /// Opens the numbered outputs.
+    factory: OutputFactory<W>,
+    /// The number of the next output to open.
+    next_index: usize,
+    /// True if the current output has been finalized and the next block starts a new output.
+    pending: bool,
+}
+
+/// Compresses a whole output with the pool's compressor at the level used for re-compression.
+type RecompressFn = Arc<dyn Fn(&[u8]) -> PoolResult<Vec<u8>> + Send + Sync>;
+
+/// Gathers the uncompressed bytes of an output that may be re-compressed once finished, see
+/// [`PoolBuilder::recompress_small_outputs`].
+struct Recompress {
+    /// The file that the output is written to, if known.
+    destination: Option<PathBuf>,
+    /// The largest output, in uncompressed bytes, that is re-compressed.
+    max_bytes: usize,
+    /// The uncompressed bytes of the output so far, or `None` once there are too many.
+    raw: Option<Vec<u8>>,
+    /// True once the last block of the output has been written.
+    finished: bool,
+    /// Compresses the whole output.
+    compress: RecompressFn,
+}
+
+impl Recompress {
+    /// Records the uncompressed bytes of a block once it has been written.
+    fn record(&mut self, raw: &[u8], is_last: bool) {
+        if let Some(bytes) = self.raw.as_mut() {
+            if bytes.len() + raw.len() <= self.max_bytes {
+                bytes.extend_from_slice(raw);
+            } else {
+                self.raw = None;
+            }
+        }
+        self.finished = is_last;
+    }
+
+    /// Takes the task that re-compresses the output to a temporary file alongside its
+    /// destination, and replaces the destination with it if it is smaller.  Failures are
+    /// ignored, leaving the original output in place.
+    fn task(&mut self) -> Option<Task> {
+        let raw = self.raw.take()?;
+        let destination = self.destination.clone()?;
+        let compress = self.compress.clone();
+        Some(Box::new(move || {
+            let mut temp = destination.clone().into_os_string();
+            temp.push(".recompress");
+            let temp = PathBuf::from(temp);
+            let compressed = compress(&raw).map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+            let replaced = compressed.and_then(|compressed| {
+                if compressed.len() as u64 >= std::fs::metadata(&destination)?.len() {
+                    return Ok(false);
+                }
+                std::fs::write(&temp, &compressed)?;
+                std::fs::rename(&temp, &destination)?;
+                Ok(true)
+            });
+            if !matches!(replaced, Ok(true)) {
+                let _ = std::fs::remove_file(&temp);
+            }
+        }))
+    }
+}
+
+/// The destination(s) of a single writer's stream within the pool.
+struct Sink<W: Write> {
+    /// The writer that receives the compressed bytes, or `None` if they are discarded.
+    writer: Option<W>,
+    /// An optional writer that receives the uncompressed bytes of each block, in the same order.
+    tee: Option<W>,
+    /// How to open further outputs, if the writer is split by record count.
+    rotation: Option<Rotation<W>>,
+    /// Called with the uncompressed bytes of each block once it is written, and whether it is
+    /// the last block of the stream.
+    observer: Option<RawObserver>,
+    /// Called with the compressed bytes of each block once it is written, and whether it is the
+    /// last block of the stream.
+    block_observer: Option<BlockObserver>,
+    /// Called with each block, compressed and uncompressed, once it is written.
+    framer: Option<BlockFramer>,
+    /// Opens a replacement for the writer if writing to it fails.
+    reopen: Option<ReopenHook<W>>,
+    /// Seeks the writer, if it is seekable.
+    seek: Option<fn(&mut W, SeekFrom) -> io::Result<u64>>,
+    /// The state for re-compressing the output once finished, if it is small enough.
+    recompress: Option<Recompress>,
+    /// The number of compressed bytes of whole blocks written to the current output.
+    output_offset: u64,
+    /// The limit on the compressed size of each output, if any.
+    max_output_size: Option<u64>,
+    /// How much of the block currently being written has been written.
+    progress: WriteProgress,
+}
+
+/// Tracks how far writing the blocks of a sink has got, so that a block that is re-submitted
+/// after a failed write is only written once: blocks that were already written are skipped, and
+/// a partially written block is resumed where it left off.
+#[derive(Debug, Default)]
+struct WriteProgress {
+    /// The number of the next block to be written.
+    next_block: u64,
+    /// The number of compressed bytes of the next block already written.
+    written: usize,
+    /// The number of uncompressed bytes of the next block already written to the tee.
+    tee_written: usize,
+    /// True if the observer has already been called for the next block.
+    observed: bool,
+    /// True if the block observer has already been called for the next block.
+    block_observed: bool,
+    /// True if the framer has already been called for the next block.
+    framed: bool,
+}
+
+/// Writes `buffer` to `writer` starting from `*written`, advancing `*written` as bytes are
+/// written so that a failed write can be resumed without writing any byte twice.
+fn write_from<W: Write>(writer: &mut W, buffer: &[u8], written: &mut usize) -> io::Result<()> {
+    while *written < buffer.len() {
+        match writer.write(&buffer[*written..]) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(n) => *written += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// A function that observes the uncompressed bytes of each block of a stream, in order.
+type RawObserver = Box<dyn FnMut(&[u8], bool) -> io::Result<()> + Send>;
+
+/// A function that observes the compressed bytes of each block of a stream, in order.
+type BlockObserver = Box<dyn FnMut(&[u8], bool) -> io::Result<()> + Send>;
+
+/// A function that is given each block of a stream, in order, with its uncompressed bytes.
+type BlockFramer = Box<dyn FnMut(&WriterMessage) -> io::Result<()> + Send>;
+
+impl<W: Write> Sink<W> {
+    /// Creates a sink that writes to a single writer and an optional tee.
+    fn new(writer: W, tee: Option<W>) -> Self {
+        Self {
+            writer: Some(writer),
+            tee,
+            rotation: None,
+            observer: None,
+            block_observer: None,
+            framer: None,
+            reopen: None,
+            seek: None,
+            recompress: None,
+            output_offset: 0,
+            max_output_size: None,
+            progress: WriteProgress::default(),
+        }
+    }
+
+    /// Creates a sink that discards the compressed bytes.
+    fn discard() -> Self {
+        Self {
+            writer: None,
+            tee: None,
+            rotation: None,
+            observer: None,
+            block_observer: None,
+            framer: None,
+            reopen: None,
+            seek: None,
+            recompress: None,
+            output_offset: 0,
+            max_output_size: None,
+            progress: WriteProgress::default(),
+        }
+    }
+
+    /// Writes a compressed block, and its uncompressed bytes to the tee if present.  A block
+    /// that has already been written is skipped, and one that was partially written is resumed,
+    /// so that retrying a failed write never duplicates any output.
+    fn write_block(&mut self, message: &WriterMessage) -> io::Result<()> {
+        if message.block_number < self.progress.next_block {
+            return Ok(());
+        }
+        if let Some(rotation) = self.rotation.as_mut() {
+            if rotation.pending {
+                if let Some(writer) = self.writer.as_mut() {
+                    writer.flush()?;
+                }
+                self.writer = Some((rotation.factory)(rotation.next_index)?);
+                self.output_offset = 0;
+                rotation.next_index += 1;
+                rotation.pending = false;
+            }
+        }
+
+        let progress = &mut self.progress;
+        if let Some(writer) = self.writer.as_mut() {
+            write_from(writer, &message.buffer, &mut progress.written)?;
+        }
+        if let (Some(tee), Some(raw)) = (self.tee.as_mut(), message.raw.as_ref()) {
+            write_from(tee, raw, &mut progress.tee_written)?;
+        }
+        if let (Some(observer), Some(raw)) = (self.observer.as_mut(), message.raw.as_ref()) {
+            if !progress.observed {
+                observer(raw, message.is_last)?;
+                progress.observed = true;
+            }
+        }
+        if let Some(observer) = self.block_observer.as_mut() {
+            if !progress.block_observed {
+                observer(&message.buffer, message.is_last)?;
+                progress.block_observed = true;
+            }
+        }
+        if let Some(framer) = self.framer.as_mut() {
+            if !progress.framed {
+                framer(message)?;
+                progress.framed = true;
+            }
+        }
+        self.progress =
+            WriteProgress { next_block: message.block_number + 1, ..Default::default() };
+        self.output_offset += message.buffer.len() as u64;
+        if let (Some(recompress), Some(raw)) = (self.recompress.as_mut(), message.raw.as_ref()) {
+            recompress.record(raw, message.is_last);
+        }
+
+        if let Some(rotation) = self.rotation.as_mut() {
+            rotation.pending = message.is_last;
+        }
+        if message.flush {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Carries out a control message sent by the writer's [`PooledWriter`], sending back the
+    /// result.
+    fn control(&mut self, control: &WriterControl) {
+        match control {
+            WriterControl::Seek(pos, reply) => {
+                let result = match (self.seek, self.writer.as_mut()) {
+                    (Some(seek), Some(writer)) => writer.flush().and_then(|_| seek(writer, *pos)),
+                    _ => Err(io::Error::new(io::ErrorKind::Unsupported, "writer is not seekable")),
+                };
+                if let Ok(position) = result {
+                    self.output_offset = position;
+                }
+                let _ = reply.send(result);
+            }
+        }
+    }
+
+    /// Returns true if writing the block would take the current output over its size limit.  If
+    /// the writer is split into several outputs and the current one is not empty, the block is
+    /// written to the next output instead.  Blocks that were already started are never over.
+    fn exceeds_size_limit(&mut self, message: &WriterMessage) -> bool {
+        let limit = match self.max_output_size {
+            Some(limit) => limit,
+            None => return false,
+        };
+        let started = message.block_number < self.progress.next_block || self.progress.written > 0;
+        if started || self.output_offset + message.buffer.len() as u64 <= limit {
+            return false;
+        }
+        match self.rotation.as_mut() {
+            Some(rotation) if self.output_offset > 0 => {
+                rotation.pending = true;
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Replaces the writer using the reopen hook after writing to it failed with `error`, so
+    /// that the block being written is written again in full to the replacement.  Returns false
+    /// if there is no reopen hook.
+    fn reopen(&mut self, writer_index: usize, error: &io::Error) -> io::Result<bool> {
+        let reopen = match (&self.reopen, &self.writer) {
+            (Some(reopen), Some(_)) => reopen.clone(),
+            _ => return Ok(false),
+        };
+        self.writer = Some(reopen(writer_index, self.output_offset, error)?);
+        self.progress.written = 0;
+        Ok(true)
+    }
+
+    /// Returns the task that re-compresses the output, if it has just been finished, is small
+    /// enough, and its destination is known, first flushing the writer so that the output on
+    /// disk is complete before it is replaced.
+    fn take_recompression(&mut self) -> io::Result<Option<Task>> {
+        let ready = self.recompress.as_ref().map_or(false, |r| r.finished && r.raw.is_some());
+        if !ready {
+            return Ok(None);
+        }
+        self.flush()?;
+        Ok(self.recompress.as_mut().and_then(Recompress::task))
+    }
+
+    /// Flushes the writer and the tee if present.
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        if let Some(tee) = self.tee.as_mut() {
+            tee.flush()?;
+        }
+        Ok(())
+    }
+}
+
 /// A [`PooledWriter`] is created by exchanging a writer with a [`Pool`].
 ///
 /// The pooled writer will internally buffer writes, sending bytes to the [`Pool`]
@@ -146,16 +1008,51 @@ pub struct PooledWriter {
     /// The index/serial number of the pooled writer within the pool
     writer_index: usize,
     /// Channel to send messages containing bytes to compress to the compressors' pool.
-    compressor_tx: Sender<CompressorMessage>,
-    /// Channel to send the receiving end of the one-shot channel that will be
-    /// used to send the compressed bytes. This effectively "place holds" the
-    /// position of the compressed bytes in the writers queue until the compressed bytes
-    /// are ready.
-    writer_tx: Sender<Receiver<WriterMessage>>,
+    compressor_tx: DoorbellSender<CompressorMessage>,
+    /// Channel to take a slot in the writer's queue for each message sent to the pool, which is
+    /// freed once the message has been written.  This limits how many of the writer's blocks
+    /// may be waiting to be compressed or written.
+    writer_tx: Sender<()>,
     /// The internal buffer to gather bytes to send.
     buffer: BytesMut,
     /// The desired size of the internal buffer.
     buffer_size: usize,
+    /// The buffers returned by the pool once their blocks have been compressed.
+    buffers: BufferRecycler<BytesMut>,
+    /// What to do with the stream if the writer is dropped before being finalized.
+    drop_policy: DropPolicy,
+    /// True once the stream has been finalized, after which nothing more is sent.
+    finalized: bool,
+    /// The state shared with the pool for this writer.
+    shared: Arc<WriterShared>,
+    /// The block size tuner, if block size tuning is enabled.
+    tuner: Option<Arc<BlockSizeTuner>>,
+    /// How to handle the output if it turns out to be small, if configured.
+    small_output: Option<SmallOutputBypass>,
+    /// The number of blocks sent to the pool so far.
+    blocks_sent: u64,
+    /// The number of messages, blocks or otherwise, sent to the pool so far, which is the
+    /// sequence number of the next.
+    messages_sent: u64,
+    /// The record-count based splitting state, if the writer is split by records.
+    split: Option<RecordSplit>,
+    /// The per-writer settings applied to each block as it is sent.
+    options: ExchangeOptions,
+    /// Checks that a compression level is valid for the writer's compressor.
+    level_check: fn(u8) -> PoolResult<()>,
+    /// True if the writer's compressor can add extra subfields to block headers.
+    supports_subfields: bool,
+    /// The extra subfields to add to the header of the block currently being filled.
+    block_subfields: Vec<ExtraSubfield>,
+    /// True if the underlying writer can be sought, see [`PoolBuilder::exchange_seekable`].
+    seekable: bool,
+    /// Starts the pool before the first block is sent, if the writer was exchanged with a
+    /// [`scope::PoolScope`] whose pool has not started yet.
+    start: Option<scope::StartHook>,
+    /// The clock used to time how long bytes have been buffered in streaming mode.
+    clock: Arc<dyn Clock>,
+    /// When the oldest buffered byte was written, in streaming mode.
+    buffered_since: Option<Duration>,
 }
 
 impl PooledWriter {
@@ -163,24 +1060,140 @@ impl PooledWriter {
     ///
     /// # Arguments
     /// - `index` - a usize representing that this is the nth pooled writer created within the pool
+    /// - `block_size` - The size of the blocks sent to the pool, unless tuned.
     /// - `compressor_tx` - The channel to send uncompressed bytes to the compressor pool.
-    /// - `writer_tx` - The `Send` end of the channel that transmits the `Receiver` end of the one-shot
-    ///                 channel, which will be consumed when the compressor sends the compressed bytes.
+    /// - `writer_tx` - The `Send` end of the channel of slots in the writer's queue.
+    /// - `buffers` - The buffers returned by the pool once their blocks have been compressed.
+    /// - `drop_policy` - What to do with the stream if the writer is dropped without being finalized.
+    /// - `shared` - The state for this writer that is shared with the pool.
+    /// - `tuner` - The block size tuner for this writer, if block size tuning is enabled.
+    /// - `small_output` - How to handle the output if it turns out to be small, if configured.
+    /// - `clock` - The clock used to time how long bytes have been buffered.
+    #[allow(clippy::too_many_arguments)]
     fn new<C>(
         index: usize,
-        compressor_tx: Sender<CompressorMessage>,
-        writer_tx: Sender<Receiver<WriterMessage>>,
+        block_size: usize,
+        compressor_tx: DoorbellSender<CompressorMessage>,
+        writer_tx: Sender<()>,
+        buffers: BufferRecycler<BytesMut>,
+        drop_policy: DropPolicy,
+        shared: Arc<WriterShared>,
+        tuner: Option<Arc<BlockSizeTuner>>,
+        small_output: Option<SmallOutputBypass>,
+        clock: Arc<dyn Clock>,
     ) -> Self
     where
         C: Compressor,
     {
+        let buffer_size = tuner.as_ref().map_or(block_size, |t| t.next_block_size());
+        shared.counters.set_block_size(buffer_size);
         Self {
             writer_index: index,
             compressor_tx,
             writer_tx,
-            buffer: BytesMut::with_capacity(C::BLOCK_SIZE),
-            buffer_size: C::BLOCK_SIZE,
+            buffer: buffers.take(block_size),
+            buffer_size,
+            buffers,
+            drop_policy,
+            finalized: false,
+            shared,
+            tuner,
+            small_output,
+            blocks_sent: 0,
+            messages_sent: 0,
+            split: None,
+            options: ExchangeOptions::default(),
+            level_check: check_compression_level::<C>,
+            supports_subfields: C::capabilities().supports_extra_subfields,
+            block_subfields: Vec::new(),
+            seekable: false,
+            start: None,
+            clock,
+            buffered_since: None,
+        }
+    }
+
+    /// Applies new per-writer settings.  The settings take effect at the next block boundary:
+    /// they apply to the block currently being filled and every block after it, while blocks
+    /// already sent to the pool keep the settings they were sent with.  Blocks are still
+    /// written in order, whatever their settings.
+    ///
+    /// Returns an error if the writer has been finalized, or the compression level is not valid
+    /// for the writer's compressor, or a compression level is set or the EOF marker omitted and
+    /// the compressor is stateful, or the encryption key is changed, in which case the settings
+    /// are left unchanged.
+    pub fn reconfigure(&mut self, options: ExchangeOptions) -> PoolResult<()> {
+        if self.finalized {
+            return Err(self.shared.label_error(PoolError::WriterFinalized(self.writer_index)));
+        }
+        if options.encryption != self.options.encryption {
+            return Err(PoolError::UnsupportedOption(
+                "the encryption key of a writer cannot be changed".to_string(),
+            ));
+        }
+        if let Some(level) = options.compression_level {
+            (self.level_check)(level)?;
+            if self.shared.stream.is_some() {
+                return Err(stateful_level_error());
+            }
+        }
+        if options.omit_eof_marker && self.shared.stream.is_some() {
+            return Err(stateful_eof_error());
+        }
+        self.buffered_since = match options.max_frame_delay {
+            Some(_) if !self.buffer.is_empty() => {
+                self.buffered_since.or_else(|| Some(self.clock.now()))
+            }
+            _ => None,
+        };
+        self.options = options;
+        Ok(())
+    }
+
+    /// The per-writer settings currently in effect.
+    pub fn options(&self) -> ExchangeOptions {
+        self.options
+    }
+
+    /// The stable ID given to this writer with [`PoolBuilder::set_writer_id`], if any.
+    pub fn id(&self) -> Option<String> {
+        self.shared.id.lock().as_ref().map(|id| id.to_string())
+    }
+
+    /// The size of block currently being filled by this writer.  This is
+    /// [`Compressor::BLOCK_SIZE`] unless set with [`PoolBuilder::block_size`] or block size tuning
+    /// is enabled.
+    pub fn block_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// The number of bytes that may be written before the current block is full and sent to the
+    /// pool.  Record writers may use this to pack records tightly to block boundaries, e.g. by
+    /// calling [`PooledWriter::flush_partial`] when the next record would not fit, so that
+    /// records do not span blocks.
+    pub fn remaining_in_block(&self) -> usize {
+        self.buffer_size - self.buffer.len()
+    }
+
+    /// Attaches an extra subfield to the header of the block currently being filled, e.g. the
+    /// number of records that start in the block, alongside any added by the pool's
+    /// [`PoolBuilder::extra_subfields`] hook.  The subfield is added to the next block sent to
+    /// the pool, whether it is sent because it is full or is flushed.  Subfields are not added
+    /// to small outputs that are written uncompressed.
+    ///
+    /// Returns an error if the writer has been finalized or its compressor does not support
+    /// extra subfields.
+    pub fn add_block_subfield(&mut self, subfield: ExtraSubfield) -> PoolResult<()> {
+        if self.finalized {
+            return Err(self.shared.label_error(PoolError::WriterFinalized(self.writer_index)));
         }
+        if !self.supports_subfields {
+            return Err(PoolError::UnsupportedOption(
+                "compressor does not support extra header subfields".to_string(),
+            ));
+        }
+        self.block_subfields.push(subfield);
+        Ok(())
     }
 
     /// Test whether the internal buffer has reached capacity.
@@ -203,43 +1216,335 @@ impl PooledWriter {
         Ok(())
     }
 
+    /// Starts the pool if the writer was exchanged with a [`scope::PoolScope`] whose pool has
+    /// not started yet.
+    fn ensure_started(&mut self) -> std::io::Result<()> {
+        match self.start.take() {
+            Some(start) => start.call().map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Swaps the internal buffer for an empty one, reusing a recycled buffer if there is one,
+    /// and returns the bytes buffered so far.
+    fn take_buffer(&mut self) -> BytesMut {
+        let buffer = self.buffers.take(self.buffer_size);
+        std::mem::replace(&mut self.buffer, buffer)
+    }
+
     /// Send a single block
     fn send_block(&mut self, is_last: bool) -> std::io::Result<()> {
-        let bytes = self.buffer.split_to(self.buffer.len()).freeze();
-        let (mut m, r) = CompressorMessage::new_parts(self.writer_index, bytes);
+        self.ensure_started()?;
+        let full = self.buffer_full();
+        self.shared.counters.record_block(self.buffer.len(), !is_last && !full);
+        self.blocks_sent += 1;
+        let bytes = self.take_buffer();
+        let mut m = CompressorMessage::new(self.writer_index, bytes);
         m.is_last = is_last;
+        m.level = self.options.compression_level;
+        m.flush = self.options.flush_each_block || self.options.max_frame_delay.is_some();
+        m.omit_eof = self.options.omit_eof_marker;
+        m.subfields = std::mem::take(&mut self.block_subfields);
+        self.buffered_since = None;
+        if let Some(tuner) = &self.tuner {
+            if full {
+                m.tuner = Some(tuner.clone());
+            } else {
+                tuner.abandon(self.buffer_size);
+            }
+            self.buffer_size = tuner.next_block_size();
+            self.shared.counters.set_block_size(self.buffer_size);
+        }
+        self.submit(m)
+    }
+
+    /// Take a slot for a block in the writer queue and then send the block to the compressor
+    /// pool, first waiting if the limit on blocks or bytes in flight has been reached.
+    fn submit(&mut self, mut m: CompressorMessage) -> std::io::Result<()> {
+        m.block_number = self.blocks_sent - 1;
+        m.sequence = self.next_sequence();
+        if let Some(blocks) = &self.shared.in_flight {
+            if !blocks.acquire(1) {
+                return Err(io::Error::new(io::ErrorKind::Other, PoolError::ChannelSend));
+            }
+        }
+        if let Some(bytes) = &self.shared.in_flight_bytes {
+            if !bytes.acquire(m.buffer.len()) {
+                return Err(io::Error::new(io::ErrorKind::Other, PoolError::ChannelSend));
+            }
+        }
         self.writer_tx
-            .send(r)
+            .send(())
             .map_err(|_e| io::Error::new(io::ErrorKind::Other, PoolError::ChannelSend))?;
+        // A small block joins the writer's batch, and only the first block of a batch sends a
+        // message for the whole batch
+        if self.shared.batch_below.map_or(false, |below| m.buffer.len() < below) {
+            let mut batch = self.shared.batch.lock();
+            batch.push(m);
+            if batch.len() > 1 {
+                return Ok(());
+            }
+            m = CompressorMessage::new(self.writer_index, BytesMut::new());
+            m.batched = true;
+        }
         self.compressor_tx
             .send(m)
             .map_err(|_e_| io::Error::new(io::ErrorKind::Other, PoolError::ChannelSend))
     }
 
-    /// Flush any remaining bytes and consume self, triggering drops of the senders.
-    pub fn close(mut self) -> std::io::Result<()> {
-        self.flush_bytes(true)
+    /// Takes the sequence number of the next message sent to the pool, which orders it among
+    /// all the others sent by the writer.
+    fn next_sequence(&mut self) -> u64 {
+        self.messages_sent += 1;
+        self.messages_sent - 1
     }
-}
 
-impl Drop for PooledWriter {
-    /// Drop [`PooledWriter`].
+    /// Sends any buffered bytes as a partial block, then waits for every block sent so far to be
+    /// written and seeks the underlying writer to `pos`, returning its new position, e.g. to
+    /// patch a header once the body has been written.  Subsequent blocks are written from the
+    /// new position.  The underlying writer is flushed before it is sought.
     ///
-    /// This will flush the writer.
-    fn drop(&mut self) {
-        self.flush_bytes(true).unwrap();
+    /// Returns an error if the writer was not exchanged with [`PoolBuilder::exchange_seekable`],
+    /// or if seeking the underlying writer fails.
+    pub fn seek_barrier(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.check_not_finalized()?;
+        if !self.seekable {
+            let error = PoolError::UnsupportedOption("the writer is not seekable".to_string());
+            return Err(io::Error::new(io::ErrorKind::Other, error));
+        }
+        if !self.buffer.is_empty() {
+            self.send_block(false)?;
+        }
+        self.ensure_started()?;
+
+        let (reply_tx, reply_rx) = channel::unbounded(); // oneshot channel
+        let mut m = CompressorMessage::new(self.writer_index, BytesMut::new());
+        m.sequence = self.next_sequence();
+        m.control = Some(WriterControl::Seek(pos, reply_tx));
+        self.writer_tx
+            .send(())
+            .map_err(|_e| io::Error::new(io::ErrorKind::Other, PoolError::ChannelSend))?;
+        self.compressor_tx
+            .send(m)
+            .map_err(|_e| io::Error::new(io::ErrorKind::Other, PoolError::ChannelSend))?;
+        reply_rx
+            .recv()
+            .map_err(|_e| io::Error::new(io::ErrorKind::Other, PoolError::ChannelSend))?
     }
-}
 
-impl Write for PooledWriter {
-    /// Send all bytes in `buf` to the [`Pool`].
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let mut bytes_added = 0;
+    /// Returns the virtual offset of the current position in the uncompressed stream, i.e. of
+    /// the next byte to be written, which resolves once the containing block has been written.
+    ///
+    /// Returns an error if virtual offset tracking was not enabled with
+    /// [`PoolBuilder::virtual_offsets`] when this writer was exchanged.
+    pub fn virtual_offset(&self) -> PoolResult<PendingVirtualOffset> {
+        let offsets = self.shared.offsets.as_ref().ok_or_else(|| {
+            PoolError::UnsupportedOption("virtual offset tracking is not enabled".to_string())
+        })?;
+        Ok(PendingVirtualOffset::new(self.blocks_sent, self.buffer.len() as u16, offsets.clone()))
+    }
+
+    /// Signals the end of a record, for writers exchanged with
+    /// [`PoolBuilder::exchange_split_by_records`].  Once the configured number of records have
+    /// been written the current output is finalized and subsequent bytes go to the next output.
+    /// For other writers this does nothing.
+    pub fn end_record(&mut self) -> std::io::Result<()> {
+        let rotate = match self.split.as_mut() {
+            Some(split) => {
+                let records = split.records.last_mut().expect("Unreachable");
+                *records += 1;
+                *records == split.records_per_output
+            }
+            None => false,
+        };
+
+        if rotate && !self.finalized {
+            self.send_block(true)?;
+            let blocks_sent = self.blocks_sent;
+            let split = self.split.as_mut().expect("Unreachable");
+            split.records.push(0);
+            split.blocks_at_start = blocks_sent;
+        }
+        Ok(())
+    }
+
+    /// Returns the manifest of outputs written so far, with the number of records in each, for
+    /// writers exchanged with [`PoolBuilder::exchange_split_by_records`].  The last entry is the
+    /// output currently being written, unless no bytes have yet been written to it.  Returns
+    /// `None` for other writers.
+    pub fn split_manifest(&self) -> Option<Vec<SplitOutput>> {
+        self.split.as_ref().map(|split| {
+            let mut outputs: Vec<_> = split
+                .records
+                .iter()
+                .enumerate()
+                .map(|(index, &records)| SplitOutput { index, records })
+                .collect();
+            if self.current_output_is_empty() {
+                outputs.pop();
+            }
+            outputs
+        })
+    }
+
+    /// True if this writer was split by records and nothing has been written to its current
+    /// output since the last one was finalized.
+    fn current_output_is_empty(&self) -> bool {
+        self.split.as_ref().map_or(false, |split| {
+            split.records.len() > 1
+                && split.blocks_at_start == self.blocks_sent
+                && self.buffer.is_empty()
+        })
+    }
+
+    /// Send any buffered bytes to the pool as a (possibly partial) block without finalizing the
+    /// stream.  Unlike [`Write::flush`], which only sends full blocks, this always sends whatever
+    /// is buffered.  Nothing is sent if the buffer is empty, or if partial flushes are coalesced,
+    /// see [`ExchangeOptions::coalesce_partial_flushes`].
+    pub fn flush_partial(&mut self) -> std::io::Result<()> {
+        if !self.finalized && !self.buffer.is_empty() && !self.options.coalesce_partial_flushes {
+            self.send_block(false)?;
+        }
+        Ok(())
+    }
+
+    /// Sends any buffered bytes to the pool as a (possibly partial) block, even if partial
+    /// flushes are coalesced, and then waits until every block sent so far has been written to
+    /// the underlying writer.  Once this returns, and until more is written to this writer, the
+    /// pool will not write to the underlying writer, so the application may write to it
+    /// directly, e.g. through a [`shared::SharedWriter`].
+    ///
+    /// Returns an error if the pool stops before the blocks are written.
+    pub fn quiesce(&mut self) -> std::io::Result<()> {
+        if !self.finalized && !self.buffer.is_empty() {
+            self.send_block(false)?;
+        }
+        if self.writer_tx.is_disconnected() {
+            return Err(io::Error::new(io::ErrorKind::Other, PoolError::ChannelSend));
+        }
+        self.shared
+            .wait_for_written(self.writer_index, self.blocks_sent)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    /// Send any buffered bytes and finalize the stream (e.g. append the BGZF EOF block),
+    /// consuming the writer.
+    ///
+    /// Returns a [`PoolError::OutputSizeExceeded`] error if the output has already exceeded its
+    /// size limit, see [`PoolBuilder::max_output_size`].
+    pub fn finalize(mut self) -> std::io::Result<()> {
+        self.finalize_stream()?;
+        self.check_output_size()
+    }
+
+    /// Flush any remaining bytes and consume self, triggering drops of the senders.
+    ///
+    /// This is equivalent to [`PooledWriter::finalize`].
+    pub fn close(mut self) -> std::io::Result<()> {
+        self.finalize_stream()?;
+        self.check_output_size()
+    }
+
+    /// Finalizes the stream if that has not already been done.
+    fn finalize_stream(&mut self) -> std::io::Result<()> {
+        if !self.finalized {
+            self.finalized = true;
+            if self.current_output_is_empty() {
+                return Ok(());
+            }
+            match self.small_output_policy() {
+                Some(policy) => self.send_small_output(policy)?,
+                None => self.flush_bytes(true)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the policy to apply if the whole output is still buffered and is smaller than
+    /// the configured small output threshold.
+    fn small_output_policy(&self) -> Option<SmallOutputPolicy> {
+        match &self.small_output {
+            Some(bypass) if self.blocks_sent == 0 && self.buffer.len() < bypass.threshold => {
+                Some(bypass.policy)
+            }
+            _ => None,
+        }
+    }
+
+    /// Sends the entire (small) output as a single final block encoded according to `policy`.
+    fn send_small_output(&mut self, policy: SmallOutputPolicy) -> std::io::Result<()> {
+        self.ensure_started()?;
+        if let Some(hook) = self.small_output.as_ref().and_then(|b| b.hook.as_ref()) {
+            hook(self.writer_index, policy);
+        }
+        self.shared.counters.record_block(self.buffer.len(), false);
+        self.blocks_sent += 1;
+        let bytes = self.take_buffer();
+        let mut m = CompressorMessage::new(self.writer_index, bytes);
+        m.is_last = true;
+        m.encoding = policy;
+        m.flush = self.options.flush_each_block;
+        m.omit_eof = self.options.omit_eof_marker;
+        m.subfields = std::mem::take(&mut self.block_subfields);
+        self.submit(m)
+    }
+}
+
+impl Drop for PooledWriter {
+    /// Drop [`PooledWriter`].
+    ///
+    /// If the writer has not already been finalized this will apply the writer's [`DropPolicy`].
+    fn drop(&mut self) {
+        if !self.finalized {
+            match self.drop_policy {
+                DropPolicy::Finalize => self.finalize_stream().unwrap(),
+                DropPolicy::FlushPartial => self.flush_partial().unwrap(),
+                DropPolicy::Discard => self.buffer.clear(),
+            }
+            self.finalized = true;
+        }
+    }
+}
+
+impl PooledWriter {
+    /// Returns an error if the stream has already been finalized, after which nothing more may
+    /// be written.
+    fn check_not_finalized(&self) -> std::io::Result<()> {
+        if self.finalized {
+            let error = self.shared.label_error(PoolError::WriterFinalized(self.writer_index));
+            Err(io::Error::new(io::ErrorKind::Other, error))
+        } else {
+            self.check_output_size()
+        }
+    }
+
+    /// Returns an error if the writer has been poisoned because its output exceeded its size
+    /// limit, see [`PoolBuilder::max_output_size`].
+    fn check_output_size(&self) -> std::io::Result<()> {
+        self.shared
+            .check_output_size(self.writer_index)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+impl Write for PooledWriter {
+    /// Send all bytes in `buf` to the [`Pool`].
+    ///
+    /// There is no limit on the size of a single write other than the `isize::MAX` bytes that
+    /// any slice may hold: large writes are split into blocks, blocking as needed while the pool's
+    /// queues are full.  Returns a [`PoolError::WriterFinalized`] error if the stream has already
+    /// been finalized.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.check_not_finalized()?;
+        let mut bytes_added = 0;
 
         while bytes_added < buf.len() {
             let bytes_to_append =
                 std::cmp::min(buf.len() - bytes_added, self.buffer_size - self.buffer.len());
 
+            if self.options.max_frame_delay.is_some() && self.buffered_since.is_none() {
+                self.buffered_since = Some(self.clock.now());
+            }
             self.buffer.extend_from_slice(&buf[bytes_added..bytes_added + bytes_to_append]);
             bytes_added += bytes_to_append;
             if self.buffer_full() {
@@ -247,12 +1552,28 @@ impl Write for PooledWriter {
             }
         }
 
+        // In streaming mode, don't hold on to bytes for longer than the maximum frame delay
+        if let (Some(delay), Some(since)) = (self.options.max_frame_delay, self.buffered_since) {
+            if !self.buffer.is_empty() && self.clock.elapsed(since) >= delay {
+                self.send_block(false)?;
+            }
+        }
+
         Ok(buf.len())
     }
 
-    /// Send whatever is in the current buffer even if it is not a full buffer.
+    /// Send whatever is in the current buffer even if it is not a full buffer.  Only full blocks
+    /// are sent, unless the writer is in streaming mode (see [`ExchangeOptions::streaming`]),
+    /// in which case any buffered bytes are sent as a smaller frame.
+    ///
+    /// Returns a [`PoolError::WriterFinalized`] error if the stream has already been finalized.
     fn flush(&mut self) -> std::io::Result<()> {
-        self.flush_bytes(false)
+        self.check_not_finalized()?;
+        if self.options.max_frame_delay.is_some() && !self.buffer.is_empty() {
+            self.send_block(false)
+        } else {
+            self.flush_bytes(false)
+        }
     }
 }
 
@@ -265,6 +1586,13 @@ impl Write for PooledWriter {
 /// An implementation must be provided as a type to the [`Pool::new`] function so that the pool
 /// knows what kind of compression to use.
 ///
+/// By default each pool thread keeps one instance of the compressor, which compresses blocks from
+/// any stream, so it must not carry state from one block to the next.  Compressors that do, e.g.
+/// to keep a running CRC for a whole-stream trailer written by [`Compressor::finish`], should
+/// report [`CompressorCapabilities::stateful`]: each stream then gets its own instance, which is
+/// given the stream's blocks one at a time and in order.  This limits each stateful stream to the
+/// throughput of a single thread, though many streams may still be compressed in parallel.
+///
 /// See the module level example for more details.
 pub trait Compressor: Sized + Send + 'static
 where
@@ -275,12 +1603,45 @@ where
     type CompressionLevel;
 
     /// The `BLOCK_SIZE` is used to set the buffer size of the [`PooledWriter`]s and should match the max
-    /// size allowed by the block compression format being used.
+    /// size allowed by the block compression format being used.  It is the default for each
+    /// pool, which may use smaller blocks, see [`PoolBuilder::block_size`].
     const BLOCK_SIZE: usize = 65280;
 
+    /// Describes what the compressor supports, so that options can be validated before any data
+    /// is written.
+    ///
+    /// The default implementation reports only the [`Compressor::BLOCK_SIZE`] and an unrestricted
+    /// range of compression levels.
+    fn capabilities() -> CompressorCapabilities {
+        CompressorCapabilities::new(Self::BLOCK_SIZE)
+    }
+
+    /// The block size to use when compressing at `compression_level`.  The default
+    /// implementation returns [`Compressor::BLOCK_SIZE`]; compressors that are chosen at runtime,
+    /// such as [`dynamic::DynCompressor`], return the size for the chosen compressor.
+    fn block_size_for(compression_level: &Self::CompressionLevel) -> usize {
+        Self::BLOCK_SIZE
+    }
+
+    /// Describes what the compressor supports when compressing at `compression_level`.  The
+    /// default implementation returns [`Compressor::capabilities`].
+    fn capabilities_for(compression_level: &Self::CompressionLevel) -> CompressorCapabilities {
+        Self::capabilities()
+    }
+
     /// Create a new compressor with the given compression level.
     fn new(compression_level: Self::CompressionLevel) -> Self;
 
+    /// Create a new compressor with the given compression level that uses a pre-trained
+    /// `dictionary`, e.g. to improve the ratio achieved on many small, similar blocks.
+    ///
+    /// Compressors that support dictionaries should report so via
+    /// [`CompressorCapabilities::supports_dictionaries`].  The default implementation ignores
+    /// the dictionary.
+    fn new_with_dictionary(compression_level: Self::CompressionLevel, dictionary: &[u8]) -> Self {
+        Self::new(compression_level)
+    }
+
     /// Returns the default compression level for the compressor.
     fn default_compression_level() -> Self::CompressionLevel;
 
@@ -289,494 +1650,7113 @@ where
     /// The validity of the compression level should be checked here.
     fn new_compression_level(compression_level: u8) -> Result<Self::CompressionLevel, Self::Error>;
 
-    /// Compress a set of bytes into the `output` vec. If `is_last` is true, and depending on the
-    /// block compression format, an EOF block may be appended as well.
-    fn compress(
+    /// Compress a block of bytes into the `output` vec.  After the final block of a stream the
+    /// pool calls [`Compressor::finish`] to append any trailer.
+    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error>;
+
+    /// Compress a set of bytes into the `output` vec as with [`Compressor::compress`], adding
+    /// the given `subfields` to the header of the compressed block.
+    ///
+    /// Only formats with extensible block headers (e.g. the gzip FEXTRA field) can support this,
+    /// and they should report so via [`CompressorCapabilities::supports_extra_subfields`].  The
+    /// default implementation ignores the subfields.
+    fn compress_with_extra_subfields(
         &mut self,
         input: &[u8],
         output: &mut Vec<u8>,
-        is_last: bool,
-    ) -> Result<(), Self::Error>;
-}
+        subfields: &[ExtraSubfield],
+    ) -> Result<(), Self::Error> {
+        self.compress(input, output)
+    }
 
-////////////////////////////////////////////////////////////////////////////////
-// The messages passed between threads
-////////////////////////////////////////////////////////////////////////////////
+    /// Sets the fields of the header written at the start of each gzip member compressed after
+    /// this is called, see [`PoolBuilder::gzip_header`].
+    ///
+    /// Compressors of gzip based formats should report so via
+    /// [`CompressorCapabilities::supports_gzip_header`].  The default implementation ignores the
+    /// header.
+    fn set_gzip_header(&mut self, header: &GzipHeader) {}
 
-/// A message that is sent from a [`PooledWriter`] to the compressor threadpool within a [`Pool`].
-#[derive(Debug)]
-struct CompressorMessage {
-    /// The index of the destination writer
-    writer_index: usize,
-    /// The bytes to compress.
-    buffer: Bytes,
-    /// Where the compressed bytes will be sent after compression.
-    oneshot: Sender<WriterMessage>,
-    /// A sentinel value to let the compressor know that the BGZF stream needs an EOF.
-    is_last: bool,
-}
+    /// Checks that `compressed`, the output of a single call to [`Compressor::compress`] or
+    /// [`Compressor::compress_with_extra_subfields`], decompresses back to `input`, returning an
+    /// error describing any mismatch.  See [`PoolBuilder::verify_blocks`].
+    ///
+    /// Compressors that can verify their output should report so via
+    /// [`CompressorCapabilities::supports_verification`].  The default implementation returns an
+    /// error, since it has no way to decompress the block.
+    fn verify(&mut self, input: &[u8], compressed: &[u8]) -> io::Result<()> {
+        let _ = (input, compressed);
+        Err(io::Error::new(io::ErrorKind::Unsupported, "compressor does not support verification"))
+    }
 
-impl CompressorMessage {
-    fn new_parts(writer_index: usize, buffer: Bytes) -> (Self, Receiver<WriterMessage>) {
-        let (tx, rx) = flume::unbounded(); // oneshot channel
-        let new = Self { writer_index, buffer, oneshot: tx, is_last: false };
-        (new, rx)
+    /// Finish a stream by appending any trailer the format requires after its final block, e.g.
+    /// the BGZF EOF block, to the `output` vec, which already holds the final compressed block.
+    /// This is called once per stream, by the thread that compressed the final block, after
+    /// which a stateful compressor is dropped.
+    ///
+    /// Formats with a trailer should report so via
+    /// [`CompressorCapabilities::supports_eof_marker`].  The default implementation appends
+    /// nothing, which suits formats whose blocks are complete in themselves (e.g. zstd frames or
+    /// gzip members).
+    fn finish(&mut self, output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        Ok(())
     }
 }
 
-/// The compressed bytes to be written to a file.
-///
-/// This is sent from the compressor threadpool to the writer queue in the writer threadpool
-/// via the one-shot channel provided by the [`PooledWriter`].
-#[derive(Debug)]
-struct WriterMessage {
-    buffer: Vec<u8>,
+/// An additional subfield to be added to the gzip FEXTRA field of a block header, as described
+/// in [RFC 1952](https://www.rfc-editor.org/rfc/rfc1952#section-2.3.1.1).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraSubfield {
+    /// The two subfield ID bytes, `SI1` and `SI2`.
+    pub id: [u8; 2],
+    /// The subfield data.
+    pub data: Vec<u8>,
 }
 
-////////////////////////////////////////////////////////////////////////////////
-// The PoolBuilder struct and impls
-////////////////////////////////////////////////////////////////////////////////
+impl ExtraSubfield {
+    /// Creates a new subfield with the given ID bytes and data.
+    ///
+    /// Will panic if the data is longer than the 65535 bytes allowed by the gzip format.
+    pub fn new(si1: u8, si2: u8, data: Vec<u8>) -> Self {
+        assert!(data.len() <= u16::MAX as usize, "Extra subfield data is too long.");
+        Self { id: [si1, si2], data }
+    }
 
-/// A struct to make building up a Pool simpler.  The builder should be constructed using
-/// [`PoolBuilder::new`], which provides the user control over the sizes of the queues used for
-/// compression and writing.  It should be noted that a single compression queue is created,
-/// and one writer queue per writer exchanged.  A good starting point for these queue sizes is
-/// two times the number of threads.
-///
-/// Once created various functions can configure aspects of the pool.  It is best practice, though
-/// not required, to configure the builder _before_ exchanging writers.  The exception is
-/// `queue_size` that may _not_ be set after any writers have been exchanged.  If not set manually
-/// then `queue_size` defaults to the number of threads multiplied by
-/// [`PoolBuilder::QUEUE_SIZE_THREAD_MULTIPLES`].
-///
-/// Once the builder is configured writers may be exchanged for [`PooledWriter`]s using the
-/// [`PoolBuilder::exchange`] function, which consumes the provided writer and returns a new
-/// writer that can be used in it's place.
-///
-/// After exchanging all writers the pool may be created and started with [`PoolBuilder::build`]
-/// which consumes the builder and after which no more writers may be exchanged.
-pub struct PoolBuilder<W, C>
-where
-    W: Write + Send + 'static,
-    C: Compressor,
-{
-    writer_index: usize,
-    compression_level: C::CompressionLevel,
-    queue_size: Option<usize>,
-    threads: usize,
-    compressor_tx: Option<Sender<CompressorMessage>>,
-    compressor_rx: Option<Receiver<CompressorMessage>>,
-    writers: Vec<W>,
-    writer_txs: Vec<Sender<Receiver<WriterMessage>>>,
-    writer_rxs: Vec<Receiver<Receiver<WriterMessage>>>,
+    /// The number of bytes the subfield occupies in the header, including its ID and length.
+    pub fn encoded_len(&self) -> usize {
+        4 + self.data.len()
+    }
+
+    /// Appends the encoded subfield to `output`.
+    pub fn encode(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&self.id);
+        output.extend_from_slice(&(self.data.len() as u16).to_le_bytes());
+        output.extend_from_slice(&self.data);
+    }
 }
 
-impl<W, C> PoolBuilder<W, C>
-where
-    W: Write + Send + 'static,
-    C: Compressor,
-{
-    /// By default queue sizes will be set to threads * this constant.
-    pub const QUEUE_SIZE_THREAD_MULTIPLES: usize = 50;
+/// The `FEXTRA` bit of the gzip header `FLG` byte.
+const GZIP_FEXTRA: u8 = 0x04;
 
-    /// The default number of threads that will be used if not otherwise configured
-    pub const DEFAULT_THREADS: usize = 4;
+/// The `FNAME` bit of the gzip header `FLG` byte.
+const GZIP_FNAME: u8 = 0x08;
 
-    /// Creates a new PoolBuilder that can be used to configure and build a [`Pool`].
+/// The fields of the header at the start of each gzip member, e.g. each BGZF block, written by
+/// compressors of gzip based formats, as described in
+/// [RFC 1952](https://www.rfc-editor.org/rfc/rfc1952#section-2.3).  See
+/// [`PoolBuilder::gzip_header`].
+///
+/// The defaults match what the compressors write otherwise: a zero `MTIME`, as reproducible
+/// outputs require, the `XFL` chosen by the compressor, an `OS` of 255 (unknown) and no `FNAME`.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzipHeader {
+    /// The modification time, in seconds since the Unix epoch, or 0 if not available.
+    pub mtime: u32,
+    /// The extra flags, or `None` to keep those chosen by the compressor.
+    pub xfl: Option<u8>,
+    /// The operating system on which the output was written, e.g. 3 for Unix.
+    pub os: u8,
+    /// The original file name, written as the `FNAME` field, if any.
+    pub file_name: Option<Vec<u8>>,
+}
+
+impl Default for GzipHeader {
+    fn default() -> Self {
+        Self { mtime: 0, xfl: None, os: 255, file_name: None }
+    }
+}
+
+impl GzipHeader {
+    /// Creates a header with the default fields.
     pub fn new() -> Self {
-        PoolBuilder {
-            writer_index: 0,
-            compression_level: C::default_compression_level(),
-            queue_size: None,
-            threads: Self::DEFAULT_THREADS,
-            compressor_tx: None,
-            compressor_rx: None,
-            writers: vec![],
-            writer_txs: vec![],
-            writer_rxs: vec![],
-        }
+        Self::default()
     }
 
-    /// Sets the number of threads that will be used by the [[Pool]].
-    ///
-    /// Will panic if set to 0.
-    pub fn threads(mut self, threads: usize) -> Self {
-        assert!(threads > 0, "Must provide a number of threads greater than 0.");
-        self.threads = threads;
+    /// Sets the modification time.
+    pub fn mtime(mut self, mtime: u32) -> Self {
+        self.mtime = mtime;
         self
     }
 
-    /// Sets the size of queues used by the pool [[Pool]].  The same size is used for
-    /// a) the queue of byte buffers to be compressed, b) the per-sample queues to receive
-    /// compressed bytes, and c) a control queue to manage writing to the underlying writers.
-    ///
-    /// In the worst case scenario the pool can be holding both queue_size uncompressed blocks
-    /// _and_ queue_size compressed blocks in memory when it cannot keep up with the incoming
-    /// load of writes.
-    ///
-    ///
-    ///
-    /// Will panic if called _after_ writers have been created because queues will already have
-    /// been created.
-    pub fn queue_size(mut self, queue_size: usize) -> Self {
-        assert!(self.writers.is_empty(), "Cannot set queue_size after writers are exchanged.");
-        self.queue_size.insert(queue_size);
+    /// Sets the extra flags.
+    pub fn xfl(mut self, xfl: u8) -> Self {
+        self.xfl = Some(xfl);
         self
     }
 
-    /// Sets the compression level that will be used by the [[Pool]].
-    pub fn compression_level(mut self, level: u8) -> PoolResult<Self> {
-        self.compression_level = C::new_compression_level(level)
-            .map_err(|e| PoolError::CompressionError(e.to_string()))?;
-        Ok(self)
+    /// Sets the operating system.
+    pub fn os(mut self, os: u8) -> Self {
+        self.os = os;
+        self
     }
 
-    /// If queues/channels are not yet setup, initialize them.
-    fn ensure_queue_is_setup(&mut self) {
-        if self.compressor_tx.is_none() && self.compressor_rx.is_none() {
-            if self.queue_size.is_none() {
-                self.queue_size.insert(self.threads * Self::QUEUE_SIZE_THREAD_MULTIPLES);
-            }
+    /// Sets the original file name, which should be ISO 8859-1 (LATIN-1) encoded.
+    ///
+    /// Will panic if the name contains a zero byte, which would terminate the field.
+    pub fn file_name(mut self, name: impl Into<Vec<u8>>) -> Self {
+        let name = name.into();
+        assert!(!name.contains(&0), "Gzip file name must not contain a zero byte.");
+        self.file_name = Some(name);
+        self
+    }
 
-            let (tx, rx) = bounded(self.queue_size.unwrap());
-            self.compressor_tx.insert(tx);
-            self.compressor_rx.insert(rx);
+    /// Rewrites the header of the gzip member starting at `start` within `output`, which must
+    /// not already have an `FNAME`, `FCOMMENT` or `FHCRC` field.  Inserting the file name does
+    /// not update any length stored elsewhere in the member, such as the BGZF `BSIZE`.
+    pub fn apply(&self, output: &mut Vec<u8>, start: usize) {
+        output[start + 4..start + 8].copy_from_slice(&self.mtime.to_le_bytes());
+        if let Some(xfl) = self.xfl {
+            output[start + 8] = xfl;
+        }
+        output[start + 9] = self.os;
+        if let Some(name) = &self.file_name {
+            let mut at = start + 10;
+            if output[start + 3] & GZIP_FEXTRA != 0 {
+                at += 2 + u16::from_le_bytes([output[at], output[at + 1]]) as usize;
+            }
+            output[start + 3] |= GZIP_FNAME;
+            output.splice(at..at, name.iter().copied().chain(std::iter::once(0)));
         }
     }
+}
 
-    /// Exchanges a writer for a [[PooledWriter]].
-    pub fn exchange(&mut self, writer: W) -> PooledWriter {
-        // Make sure queue/channel configuration is done
-        self.ensure_queue_is_setup();
+/// A hook that is called on a pool thread for each block to be compressed, with the writer index
+/// and the uncompressed bytes of the block, and returns any extra subfields to add to the block
+/// header.  See [`PoolBuilder::extra_subfields`].
+pub type ExtraSubfieldHook = Arc<dyn Fn(usize, &[u8]) -> Vec<ExtraSubfield> + Send + Sync>;
 
-        let (tx, rx): (Sender<Receiver<WriterMessage>>, Receiver<Receiver<WriterMessage>>) =
-            flume::bounded(self.queue_size.expect("Unreachable"));
+/// A hook that is called on each pool thread when it starts, e.g. to set its priority.
+type ThreadStartHook = Arc<dyn Fn() + Send + Sync>;
 
-        let p = PooledWriter::new::<C>(
-            self.writer_index,
-            self.compressor_tx.as_ref().expect("Unreachable").clone(),
-            tx.clone(),
-        );
+/// An object-safe view of a [`Compressor`], so that writers within one pool may use different
+/// compressors.
+trait BlockCompressor: Send {
+    /// Compresses a block as with [`Compressor::compress`], adding `subfields` to the block
+    /// header if given, checks the compressed block with [`Compressor::verify`] if `verify`, and
+    /// finishes the stream with [`Compressor::finish`] if `is_last`.
+    fn compress_block(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        is_last: bool,
+        subfields: Option<&[ExtraSubfield]>,
+        verify: bool,
+    ) -> PoolResult<()>;
 
-        self.writer_index += 1;
-        self.writers.push(writer);
-        self.writer_txs.push(tx);
-        self.writer_rxs.push(rx);
-        p
+    /// Sets the gzip member header as with [`Compressor::set_gzip_header`].
+    fn set_gzip_header(&mut self, header: &GzipHeader);
+}
+
+impl<C: Compressor> BlockCompressor for C {
+    fn compress_block(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        is_last: bool,
+        subfields: Option<&[ExtraSubfield]>,
+        verify: bool,
+    ) -> PoolResult<()> {
+        let start = output.len();
+        match subfields {
+            Some(subfields) => self.compress_with_extra_subfields(input, output, subfields),
+            None => self.compress(input, output),
+        }
+        .map_err(|e| PoolError::CompressionError(e.to_string()))?;
+        if verify {
+            self.verify(input, &output[start..])
+                .map_err(|e| PoolError::VerificationFailed(e.to_string()))?;
+        }
+        if is_last {
+            self.finish(output).map_err(|e| PoolError::CompressionError(e.to_string()))?;
+        }
+        Ok(())
     }
 
-    /// Consumes the builder and generates the [[Pool]] ready for use.
-    pub fn build(mut self) -> PoolResult<Pool> {
-        // Make sure the queue/channel configuration is done - this could be necessary if
-        // a pool is created by zero writers exchanged.
-        self.ensure_queue_is_setup();
+    fn set_gzip_header(&mut self, header: &GzipHeader) {
+        Compressor::set_gzip_header(self, header);
+    }
+}
 
-        // Create the channel to gracefully signal a shutdown of the pool
-        let (shutdown_tx, shutdown_rx) = flume::unbounded();
+/// Compares the result of decompressing a block with the block's original bytes, for use by
+/// implementations of [`Compressor::verify`].
+pub(crate) fn check_round_trip(input: &[u8], decompressed: &[u8]) -> io::Result<()> {
+    if input == decompressed {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "block of {} bytes decompressed to {} different bytes",
+                input.len(),
+                decompressed.len()
+            ),
+        ))
+    }
+}
 
-        // Start the pool manager thread and thread pools
-        let handle = std::thread::spawn(move || {
-            Pool::pool_main::<W, C>(
-                self.threads,
-                self.compression_level,
-                self.compressor_rx.expect("Unreachable."),
-                self.writer_rxs,
-                self.writers,
-                shutdown_rx,
-            )
-        });
+/// Creates a compressor at the given level, or the default level if `None`, using the pool's
+/// dictionary if one is set.
+type CompressorFactory =
+    Arc<dyn Fn(Option<u8>, Option<&[u8]>) -> Box<dyn BlockCompressor> + Send + Sync>;
+
+/// A compressor, and level, used by writers that don't use the pool's own compressor and level.
+/// Each pool thread creates its own instance of each override the first time it is needed.
+#[derive(Clone)]
+struct CompressorOverride {
+    /// The type of compressor and the compression level, if not the default.
+    key: (TypeId, Option<u8>),
+    /// Creates a new instance of the compressor.
+    factory: CompressorFactory,
+    /// The level that [`PoolStats::levels`] are recorded under, if this is the pool's own
+    /// compressor type.
+    stats_level: Option<Option<u8>>,
+    /// True if the compressor carries state across blocks, see
+    /// [`CompressorCapabilities::stateful`].
+    stateful: bool,
+    /// Set if the override is used by a single writer, see
+    /// [`PoolBuilder::exchange_with_encoder`], so that it is retired once the writer finishes.
+    retirement: Option<Arc<Retirement>>,
+}
+
+/// Retires the override used by a single writer once the writer's last block has been written:
+/// the configuration held by its factory, e.g. an encryption key, is dropped straight away, and
+/// each pool thread drops its instance of the compressor the next time it looks for work.
+struct Retirement {
+    retired: AtomicBool,
+    /// Drops the configuration held by the override's factory.
+    release: Box<dyn Fn() + Send + Sync>,
+}
+
+impl Retirement {
+    fn retire(&self) {
+        self.retired.store(true, Ordering::Relaxed);
+        (self.release)();
+    }
+
+    fn is_retired(&self) -> bool {
+        self.retired.load(Ordering::Relaxed)
+    }
+}
+
+/// The compressors used by a single pool thread: its instance of the pool's compressor, plus
+/// instances of any per-writer compressors and levels, each created the first time it is needed.
+struct ThreadCompressors<C: Compressor> {
+    /// The pool's compression level.
+    level: C::CompressionLevel,
+    /// The pool's dictionary, if one is set.
+    dictionary: Option<Arc<Vec<u8>>>,
+    /// The pool's gzip member header, if one is set.
+    gzip_header: Option<Arc<GzipHeader>>,
+    /// The instance of the pool's compressor at the pool's level.
+    compressor: C,
+    /// The compressors used by writers that don't use the pool's own.
+    overrides: Arc<Vec<CompressorOverride>>,
+    /// The other instances, by override (or `None` for the pool's compressor) and level.
+    instances: Vec<CompressorInstance>,
+}
+
+/// An instance of a compressor, with the override (or `None` for the pool's compressor) and
+/// level that it was created for.
+type CompressorInstance = (Option<usize>, Option<u8>, Box<dyn BlockCompressor>);
+
+impl<C: Compressor> ThreadCompressors<C> {
+    /// Creates the compressors for a thread, initially just the pool's compressor.
+    fn new(
+        level: C::CompressionLevel,
+        dictionary: Option<Arc<Vec<u8>>>,
+        gzip_header: Option<Arc<GzipHeader>>,
+        overrides: Arc<Vec<CompressorOverride>>,
+    ) -> Self {
+        let compressor = Self::new_compressor(&dictionary, &gzip_header, level.clone());
+        Self { level, dictionary, gzip_header, compressor, overrides, instances: vec![] }
+    }
+
+    /// Creates an instance of the pool's compressor at `level`, using the dictionary and gzip
+    /// member header if set.
+    fn new_compressor(
+        dictionary: &Option<Arc<Vec<u8>>>,
+        gzip_header: &Option<Arc<GzipHeader>>,
+        level: C::CompressionLevel,
+    ) -> C {
+        let mut compressor = match dictionary {
+            Some(dictionary) => C::new_with_dictionary(level, dictionary),
+            None => C::new(level),
+        };
+        if let Some(header) = gzip_header {
+            Compressor::set_gzip_header(&mut compressor, header);
+        }
+        compressor
+    }
+
+    /// Returns the compressor for blocks of a writer using the given override, if any, at the
+    /// given level, if not the override's or pool's level.
+    fn get(
+        &mut self,
+        override_index: Option<usize>,
+        level: Option<u8>,
+    ) -> &mut dyn BlockCompressor {
+        let level = match override_index {
+            Some(i) => level.or(self.overrides[i].key.1),
+            None if level.is_none() => return &mut self.compressor,
+            None => level,
+        };
+        let position =
+            self.instances.iter().position(|(o, l, _)| *o == override_index && *l == level);
+        let position = match position {
+            Some(position) => position,
+            None => {
+                let instance = self.create(override_index, level);
+                self.instances.push((override_index, level, instance));
+                self.instances.len() - 1
+            }
+        };
+        self.instances[position].2.as_mut()
+    }
+
+    /// Creates a new compressor for blocks of a writer using the given override, if any, at the
+    /// given level, if not the override's or pool's level.
+    fn create(&self, override_index: Option<usize>, level: Option<u8>) -> Box<dyn BlockCompressor> {
+        let dictionary = self.dictionary.as_ref().map(|d| d.as_slice());
+        let header = &self.gzip_header;
+        match (override_index, level) {
+            (Some(i), level) => {
+                let mut compressor =
+                    (self.overrides[i].factory)(level.or(self.overrides[i].key.1), dictionary);
+                if let Some(header) = header {
+                    compressor.set_gzip_header(header);
+                }
+                compressor
+            }
+            (None, Some(level)) => Box::new(Self::new_compressor(
+                &self.dictionary,
+                header,
+                C::new_compression_level(level).expect("Validated before use"),
+            )),
+            (None, None) => {
+                Box::new(Self::new_compressor(&self.dictionary, header, self.level.clone()))
+            }
+        }
+    }
+
+    /// Drops the instances of overrides that have been retired since their writers finished.
+    fn drop_retired(&mut self) {
+        let overrides = &self.overrides;
+        self.instances.retain(|(o, _, _)| {
+            o.and_then(|i| overrides[i].retirement.as_ref()).map_or(true, |r| !r.is_retired())
+        });
+    }
+
+    /// Discards the compressor returned by [`ThreadCompressors::get`] for the same arguments,
+    /// e.g. because it may be in a bad state after a failure, so that a new one is created.
+    fn reset(&mut self, override_index: Option<usize>, level: Option<u8>) {
+        match (override_index, level) {
+            (None, None) => {
+                self.compressor =
+                    Self::new_compressor(&self.dictionary, &self.gzip_header, self.level.clone());
+            }
+            (override_index, level) => {
+                let level = level.or_else(|| override_index.and_then(|i| self.overrides[i].key.1));
+                self.instances.retain(|(o, l, _)| !(*o == override_index && *l == level));
+            }
+        }
+    }
+}
+
+/// Describes the features supported by a [`Compressor`], as returned by
+/// [`Compressor::capabilities`].
+///
+/// Generic tooling can use this to validate user supplied options (e.g. from the command line)
+/// and to report a helpful error before a pool is ever built.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressorCapabilities {
+    /// True if the format has an EOF marker or other trailer that is appended when a stream is
+    /// finished, i.e. if [`Compressor::finish`] appends anything.
+    pub supports_eof_marker: bool,
+    /// True if the compressor can make use of a pre-trained dictionary.
+    pub supports_dictionaries: bool,
+    /// True if the compressor can add [`ExtraSubfield`]s to block headers.
+    pub supports_extra_subfields: bool,
+    /// The lowest compression level accepted by [`Compressor::new_compression_level`].
+    pub min_compression_level: u8,
+    /// The highest compression level accepted by [`Compressor::new_compression_level`].
+    pub max_compression_level: u8,
+    /// The largest number of uncompressed bytes that may be put into a single block.
+    pub max_block_size: usize,
+    /// True if compressing the same input with the same level always gives the same output.
+    pub deterministic: bool,
+    /// True if the compressor carries state across the blocks of a stream, e.g. a running CRC
+    /// or a single zstd frame, so that each stream needs its own compressor instance.
+    pub stateful: bool,
+    /// True if the compressor can check its compressed blocks with [`Compressor::verify`].
+    pub supports_verification: bool,
+    /// The approximate number of bytes of scratch memory, e.g. windows and match finders, that
+    /// each instance of the compressor allocates, or 0 if unknown or negligible.
+    pub scratch_memory: usize,
+    /// True if the compressor writes gzip members whose headers may be set with a
+    /// [`GzipHeader`], see [`Compressor::set_gzip_header`].
+    pub supports_gzip_header: bool,
+    /// True if the gzip member headers may also carry a file name, which formats with a fixed
+    /// header layout, such as BGZF, do not allow.
+    pub supports_gzip_file_name: bool,
+}
+
+impl CompressorCapabilities {
+    /// Creates a new set of capabilities with the given maximum block size, no EOF marker, no
+    /// dictionary support, an unrestricted range of compression levels, deterministic output, no
+    /// state across blocks, no verification, no reported scratch memory and no gzip header.
+    pub fn new(max_block_size: usize) -> Self {
+        Self {
+            supports_eof_marker: false,
+            supports_dictionaries: false,
+            supports_extra_subfields: false,
+            min_compression_level: u8::MIN,
+            max_compression_level: u8::MAX,
+            max_block_size,
+            deterministic: true,
+            stateful: false,
+            supports_verification: false,
+            scratch_memory: 0,
+            supports_gzip_header: false,
+            supports_gzip_file_name: false,
+        }
+    }
+
+    /// Sets whether the format has an EOF marker.
+    pub fn eof_marker(mut self, supported: bool) -> Self {
+        self.supports_eof_marker = supported;
+        self
+    }
+
+    /// Sets whether the compressor supports dictionaries.
+    pub fn dictionaries(mut self, supported: bool) -> Self {
+        self.supports_dictionaries = supported;
+        self
+    }
+
+    /// Sets whether the compressor can add extra subfields to block headers.
+    pub fn extra_subfields(mut self, supported: bool) -> Self {
+        self.supports_extra_subfields = supported;
+        self
+    }
+
+    /// Sets the inclusive range of valid compression levels.
+    pub fn compression_levels(mut self, min: u8, max: u8) -> Self {
+        assert!(min <= max, "Minimum compression level must not exceed the maximum.");
+        self.min_compression_level = min;
+        self.max_compression_level = max;
+        self
+    }
+
+    /// Sets whether the compressor output is deterministic.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Sets whether the compressor carries state across the blocks of a stream.
+    pub fn stateful(mut self, stateful: bool) -> Self {
+        self.stateful = stateful;
+        self
+    }
+
+    /// Sets whether the compressor can verify its compressed blocks.
+    pub fn verification(mut self, supported: bool) -> Self {
+        self.supports_verification = supported;
+        self
+    }
+
+    /// Sets the approximate number of bytes of scratch memory allocated by each instance.
+    pub fn scratch_memory(mut self, bytes: usize) -> Self {
+        self.scratch_memory = bytes;
+        self
+    }
+
+    /// Sets whether the compressor's gzip member headers may be set, and whether they may carry
+    /// a file name.
+    pub fn gzip_header(mut self, supported: bool, file_name: bool) -> Self {
+        self.supports_gzip_header = supported;
+        self.supports_gzip_file_name = supported && file_name;
+        self
+    }
+
+    /// Returns an error if `header` cannot be written by the compressor.
+    pub fn check_gzip_header(&self, header: &GzipHeader) -> PoolResult<()> {
+        if !self.supports_gzip_header {
+            Err(PoolError::UnsupportedOption("compressor does not write gzip headers".to_string()))
+        } else if header.file_name.is_some() && !self.supports_gzip_file_name {
+            Err(PoolError::UnsupportedOption(
+                "compressor does not allow a file name in its gzip headers".to_string(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The inclusive range of valid compression levels.
+    pub fn level_range(&self) -> std::ops::RangeInclusive<u8> {
+        self.min_compression_level..=self.max_compression_level
+    }
+
+    /// Returns an error if `level` is outside of the supported range of compression levels.
+    pub fn check_compression_level(&self, level: u8) -> PoolResult<()> {
+        if self.level_range().contains(&level) {
+            Ok(())
+        } else {
+            Err(PoolError::InvalidCompressionLevel {
+                level,
+                min: self.min_compression_level,
+                max: self.max_compression_level,
+            })
+        }
+    }
+
+    /// Returns an error if `block_size` is zero or larger than the maximum block size.
+    pub fn check_block_size(&self, block_size: usize) -> PoolResult<()> {
+        if block_size > 0 && block_size <= self.max_block_size {
+            Ok(())
+        } else {
+            Err(PoolError::UnsupportedOption(format!(
+                "block size {} must be between 1 and {}",
+                block_size, self.max_block_size
+            )))
+        }
+    }
+}
+
+/// The error for a per-block compression level used with a writer's own compressor, which can't
+/// change level part way through a stream.
+fn stateful_level_error() -> PoolError {
+    PoolError::UnsupportedOption(
+        "per-block compression levels cannot be used with a per-writer compressor".to_string(),
+    )
+}
+
+/// The error for omitting the EOF marker of a writer's own compressor, which must finish its
+/// stream.
+fn stateful_eof_error() -> PoolError {
+    PoolError::UnsupportedOption(
+        "the EOF marker cannot be omitted with a per-writer compressor".to_string(),
+    )
+}
+
+/// The file a writer writes to, recorded with [`PoolBuilder::set_destination`].
+#[derive(Debug)]
+struct Destination {
+    /// The resolved path of the file.
+    path: PathBuf,
+    /// The device and inode of the file, if it existed when it was recorded.
+    file_id: Option<(u64, u64)>,
+    /// The index of the writer.
+    writer: usize,
+}
+
+/// The device and inode of the file at `path`, if it exists, which identify the file whichever
+/// hard link it is reached by.
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).ok().map(|metadata| (metadata.dev(), metadata.ino()))
+}
+
+/// Files are only compared by path where there is no portable equivalent of an inode.
+#[cfg(not(unix))]
+fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Resolves the path of a destination to a canonical form, resolving the path of its directory
+/// if the file does not exist yet.
+fn resolve_destination(path: &Path) -> io::Result<PathBuf> {
+    match std::fs::canonicalize(path) {
+        Ok(path) => Ok(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let name = path.file_name().ok_or(e)?;
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            Ok(std::fs::canonicalize(dir)?.join(name))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns an error if `level` is not a valid compression level for the compressor `C`.
+fn check_compression_level<C: Compressor>(level: u8) -> PoolResult<()> {
+    C::capabilities().check_compression_level(level)?;
+    C::new_compression_level(level).map_err(|e| PoolError::CompressionError(e.to_string()))?;
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// The messages passed between threads
+////////////////////////////////////////////////////////////////////////////////
+
+/// A message that is sent from a [`PooledWriter`] to the compressor threadpool within a [`Pool`].
+#[derive(Debug)]
+struct CompressorMessage {
+    /// The index of the destination writer
+    writer_index: usize,
+    /// The bytes to compress, in a buffer that is recycled once they have been compressed.
+    buffer: BytesMut,
+    /// The position of the message among all those sent by the writer, counting from zero.
+    sequence: u64,
+    /// A sentinel value to let the compressor know that the stream needs to be finished.
+    is_last: bool,
+    /// The tuner to report to if this is a full trial block during block size tuning.
+    tuner: Option<Arc<BlockSizeTuner>>,
+    /// How the block should be encoded; anything other than [`SmallOutputPolicy::Compress`] is
+    /// only used for small outputs.
+    encoding: SmallOutputPolicy,
+    /// The thread on which compressing the block failed, if it has been re-queued.
+    failed_on: Option<usize>,
+    /// The compression level for the block, if not the level of the writer's compressor.
+    level: Option<u8>,
+    /// True if the underlying writer should be flushed once the block is written.
+    flush: bool,
+    /// True if the EOF marker should not be appended if this is the last block.
+    omit_eof: bool,
+    /// The number of the block among all those sent by the writer, counting from zero.
+    block_number: u64,
+    /// Extra subfields attached to the block by the writer, see
+    /// [`PooledWriter::add_block_subfield`].
+    subfields: Vec<ExtraSubfield>,
+    /// An operation on the underlying writer to carry out in place of writing a block.
+    control: Option<WriterControl>,
+    /// True if the message stands for the writer's batch of small blocks, in place of a block.
+    batched: bool,
+}
+
+impl CompressorMessage {
+    fn new(writer_index: usize, buffer: BytesMut) -> Self {
+        Self {
+            writer_index,
+            buffer,
+            sequence: 0,
+            is_last: false,
+            tuner: None,
+            encoding: SmallOutputPolicy::Compress,
+            failed_on: None,
+            level: None,
+            flush: false,
+            omit_eof: false,
+            block_number: 0,
+            subfields: Vec::new(),
+            control: None,
+            batched: false,
+        }
+    }
+}
+
+/// The compressed bytes to be written to a file.
+///
+/// This is placed in the writer's [`ReorderBuffer`] by the compressor threadpool, from which the
+/// writer threadpool writes it in sequence.
+#[derive(Debug)]
+struct WriterMessage {
+    buffer: Vec<u8>,
+    /// The position of the message among all those sent by the writer, counting from zero.
+    sequence: u64,
+    /// The uncompressed bytes, if they are also to be written to a tee writer.
+    raw: Option<Bytes>,
+    /// When compression of the block finished, according to the pool's [`Clock`].
+    compressed_at: Duration,
+    /// True if this is the last block of the stream.
+    is_last: bool,
+    /// The number of uncompressed bytes in the block.
+    uncompressed_len: usize,
+    /// True if the underlying writer should be flushed once the block is written.
+    flush: bool,
+    /// The number of the block among all those sent by the writer, counting from zero.
+    block_number: u64,
+    /// The checksum of the compressed bytes, if block checksums are enabled.
+    checksum: Option<u64>,
+    /// An operation on the underlying writer to carry out in place of writing a block.
+    control: Option<WriterControl>,
+}
+
+/// An operation on a writer's underlying writer, sent by its [`PooledWriter`] through the
+/// pool's queues like a block, so that it is carried out once every block before it has been
+/// written and before any block after it.
+#[derive(Debug)]
+enum WriterControl {
+    /// Seek the underlying writer, sending back its new position.
+    Seek(SeekFrom, Sender<io::Result<u64>>),
+}
+
+/// A buffer that can be emptied and reused by a [`BufferRecycler`].
+trait RecycledBuffer {
+    fn with_capacity(capacity: usize) -> Self;
+    fn clear(&mut self);
+    fn reserve(&mut self, additional: usize);
+}
+
+impl RecycledBuffer for BytesMut {
+    fn with_capacity(capacity: usize) -> Self {
+        BytesMut::with_capacity(capacity)
+    }
+
+    fn clear(&mut self) {
+        BytesMut::clear(self);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        BytesMut::reserve(self, additional);
+    }
+}
+
+impl RecycledBuffer for Vec<u8> {
+    fn with_capacity(capacity: usize) -> Self {
+        Vec::with_capacity(capacity)
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        Vec::reserve(self, additional);
+    }
+}
+
+/// Block buffers that are done with, to be filled again rather than allocating a new buffer for
+/// every block: the uncompressed buffers returned by the compressor threads once their blocks
+/// have been compressed, for [`PooledWriter`]s to reuse, and the compressed buffers returned
+/// once their blocks have been written, for the compressor threads to reuse.
+#[derive(Debug, Clone)]
+struct BufferRecycler<B> {
+    tx: Sender<B>,
+    rx: Receiver<B>,
+}
+
+impl<B: RecycledBuffer> BufferRecycler<B> {
+    /// Creates a recycler holding at most `capacity` buffers waiting to be reused.
+    fn new(capacity: usize) -> Self {
+        let (tx, rx) = channel::bounded(capacity);
+        Self { tx, rx }
+    }
+
+    /// Returns `buffer` to be reused, or drops it if enough buffers are already waiting.
+    fn recycle(&self, mut buffer: B) {
+        buffer.clear();
+        let _ = self.tx.try_send(buffer);
+    }
+
+    /// Takes an empty buffer with room for at least `capacity` bytes, reusing a returned
+    /// buffer if there is one.
+    fn take(&self, capacity: usize) -> B {
+        match self.rx.try_recv() {
+            Ok(mut buffer) => {
+                buffer.reserve(capacity);
+                buffer
+            }
+            Err(_) => B::with_capacity(capacity),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// The PoolBuilder struct and impls
+////////////////////////////////////////////////////////////////////////////////
+
+/// Curated presets for the pool's queue sizes, retry delay, memory limits, batching and work
+/// weights, see [`PoolBuilder::profile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// The defaults: moderately sized queues and a 25ms delay between retries of a failed write.
+    Balanced,
+    /// Short queues and writes favored over compression, so that blocks reach the underlying
+    /// writers soon after they are written, small blocks from partial flushes compressed in
+    /// batches, and a short delay between retries of a failed write.
+    LowLatency,
+    /// Deep queues so that writers rarely block on a busy pool, at the cost of memory, with
+    /// partial flushes coalesced into full blocks and any small blocks compressed in batches.
+    MaxThroughput,
+    /// Very short queues, a limit on the bytes in flight across all writers, and writes
+    /// favored over compression, to bound the memory held by blocks, at the cost of writers
+    /// blocking more often.
+    LowMemory,
+}
+
+impl Profile {
+    /// The queue size, as a multiple of the number of threads, used by this profile.
+    pub fn queue_size_thread_multiple(self) -> usize {
+        match self {
+            Profile::Balanced => 50,
+            Profile::LowLatency => 8,
+            Profile::MaxThroughput => 200,
+            Profile::LowMemory => 2,
+        }
+    }
+
+    /// How long a pool thread waits before retrying a failed write under this profile.
+    pub fn retry_delay(self) -> Duration {
+        match self {
+            Profile::Balanced | Profile::MaxThroughput | Profile::LowMemory => {
+                Duration::from_millis(25)
+            }
+            Profile::LowLatency => Duration::from_millis(1),
+        }
+    }
+
+    /// The limit on the uncompressed bytes in flight across all writers under this profile, if
+    /// any, see [`PoolBuilder::max_in_flight_bytes`].
+    pub fn max_in_flight_bytes(self) -> Option<usize> {
+        match self {
+            Profile::LowMemory => Some(16 * 1024 * 1024),
+            Profile::Balanced | Profile::LowLatency | Profile::MaxThroughput => None,
+        }
+    }
+
+    /// The size below which blocks are compressed in batches under this profile, if any, see
+    /// [`PoolBuilder::batch_small_blocks`].
+    pub fn batch_small_blocks(self) -> Option<usize> {
+        match self {
+            Profile::LowLatency | Profile::MaxThroughput => Some(16 * 1024),
+            Profile::Balanced | Profile::LowMemory => None,
+        }
+    }
+
+    /// Whether partial flushes are coalesced into full blocks under this profile, see
+    /// [`ExchangeOptions::coalesce_partial_flushes`].
+    pub fn coalesce_partial_flushes(self) -> bool {
+        self == Profile::MaxThroughput
+    }
+
+    /// How compression and write work are weighed under this profile, see
+    /// [`PoolBuilder::work_weights`].
+    pub fn work_weights(self) -> WorkWeights {
+        match self {
+            Profile::LowLatency | Profile::LowMemory => WorkWeights::new(1, 4),
+            Profile::Balanced | Profile::MaxThroughput => WorkWeights::default(),
+        }
+    }
+}
+
+/// How much work of each kind a pool thread does in turn, see [`PoolBuilder::work_quantum`].
+///
+/// Each pool thread takes turns at compressing blocks and writing compressed blocks, choosing
+/// the kind of work for each turn by [`WorkWeights`].  In a turn it does up to `compressions`
+/// compressions or up to `writes` writes, stopping early if there is no more work of that kind
+/// or once the optional time slice has elapsed.  Blocks decompressed for [`PooledReader`]s are
+/// done in turns of their own, of up to `compressions` blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkQuantum {
+    /// The maximum number of blocks compressed in each turn.
+    pub compressions: usize,
+    /// The maximum number of blocks written in each turn.
+    pub writes: usize,
+    /// If set, a thread moves on once it has spent this long on one kind of work, after doing
+    /// at least one piece of work.
+    pub time_slice: Option<Duration>,
+}
+
+impl WorkQuantum {
+    /// Creates a quantum of up to `compressions` compressions and `writes` writes per turn.
+    ///
+    /// Will panic if either is 0.
+    pub fn new(compressions: usize, writes: usize) -> Self {
+        assert!(compressions > 0 && writes > 0, "Work quanta must be greater than 0.");
+        Self { compressions, writes, time_slice: None }
+    }
+
+    /// Sets the time slice after which a thread moves on to the other kind of work.
+    pub fn time_slice(mut self, time_slice: Duration) -> Self {
+        self.time_slice = Some(time_slice);
+        self
+    }
+
+    /// True if the time slice is set and `elapsed` exceeds it.
+    fn expired(&self, elapsed: Duration) -> bool {
+        self.time_slice.map_or(false, |t| elapsed >= t)
+    }
+}
+
+impl Default for WorkQuantum {
+    /// One compression and one write per turn, with no time slice.
+    fn default() -> Self {
+        Self::new(1, 1)
+    }
+}
+
+/// How a pool thread chooses which kind of work to do next, see [`PoolBuilder::work_weights`].
+///
+/// Rather than alternating between compressing and writing, each thread looks at the backlog of
+/// each kind of work before every turn: the blocks waiting to be compressed, or decompressed,
+/// scaled by `compress`, and the compressed blocks waiting to be written, scaled by `write`.  It
+/// takes a turn at the kind with the largest weighted backlog, moving on to the next largest only
+/// if there turns out to be none of it, so that idle threads steal whichever work is piling up.
+/// Ties go to writing, which frees the memory held by compressed blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkWeights {
+    /// The weight of each block waiting to be compressed or decompressed.
+    pub compress: u64,
+    /// The weight of each compressed block waiting to be written.
+    pub write: u64,
+}
+
+impl WorkWeights {
+    /// Creates weights for compression and write work.
+    ///
+    /// Will panic if either is 0.
+    pub fn new(compress: u64, write: u64) -> Self {
+        assert!(compress > 0 && write > 0, "Work weights must be greater than 0.");
+        Self { compress, write }
+    }
+
+    /// The kinds of work in the order they should be tried, given the number of blocks waiting
+    /// to be compressed, decompressed and written.
+    fn order(&self, compress: usize, decompress: usize, write: usize) -> [WorkKind; 3] {
+        let mut backlogs = [
+            (WorkKind::Write, write as u64 * self.write),
+            (WorkKind::Compress, compress as u64 * self.compress),
+            (WorkKind::Decompress, decompress as u64 * self.compress),
+        ];
+        backlogs.sort_by_key(|&(_, backlog)| std::cmp::Reverse(backlog));
+        backlogs.map(|(kind, _)| kind)
+    }
+}
+
+impl Default for WorkWeights {
+    /// Compression and write work weighted equally.
+    fn default() -> Self {
+        Self::new(1, 1)
+    }
+}
+
+/// A kind of work done by a pool thread, see [`WorkWeights`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WorkKind {
+    Compress,
+    Decompress,
+    Write,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::Balanced
+    }
+}
+
+/// A struct to make building up a Pool simpler.  The builder should be constructed using
+/// [`PoolBuilder::new`], which provides the user control over the sizes of the queues used for
+/// compression and writing.  It should be noted that a single compression queue is created,
+/// and one writer queue per writer exchanged.  A good starting point for these queue sizes is
+/// two times the number of threads.
+///
+/// Once created various functions can configure aspects of the pool.  It is best practice, though
+/// not required, to configure the builder _before_ exchanging writers.  The exception is
+/// `queue_size` that may _not_ be set after any writers have been exchanged.  If not set manually
+/// then `queue_size` defaults to the number of threads multiplied by
+/// [`PoolBuilder::QUEUE_SIZE_THREAD_MULTIPLES`], or by the multiple of the configured
+/// [`Profile`].
+///
+/// Once the builder is configured writers may be exchanged for [`PooledWriter`]s using the
+/// [`PoolBuilder::exchange`] function, which consumes the provided writer and returns a new
+/// writer that can be used in it's place.
+///
+/// After exchanging all writers the pool may be created and started with [`PoolBuilder::build`]
+/// which consumes the builder and after which no more writers may be exchanged.
+pub struct PoolBuilder<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    writer_index: usize,
+    compression_level: C::CompressionLevel,
+    compression_level_number: Option<u8>,
+    queue_size: Option<usize>,
+    queue_size_thread_multiple: usize,
+    retry_delay: Duration,
+    threads: usize,
+    compressor_threads: Option<usize>,
+    writer_threads: Option<usize>,
+    drop_policy: DropPolicy,
+    block_size: Option<usize>,
+    block_size_tuning: Option<BlockSizeTuning>,
+    small_output: Option<SmallOutputBypass>,
+    extra_subfields: Option<ExtraSubfieldHook>,
+    map_writers: Option<WriterMapper<W>>,
+    reopen: Option<ReopenHook<W>>,
+    recompress: Option<(usize, C::CompressionLevel)>,
+    destinations: Vec<Destination>,
+    dictionary: Option<Arc<Vec<u8>>>,
+    gzip_header: Option<Arc<GzipHeader>>,
+    block_checksum: Option<(&'static str, BlockChecksumFn)>,
+    clock: Arc<dyn Clock>,
+    compressor_tx: Option<DoorbellSender<CompressorMessage>>,
+    compressor_rx: Option<Receiver<CompressorMessage>>,
+    task_tx: Option<DoorbellSender<Task>>,
+    task_rx: Option<Receiver<Task>>,
+    buffers: Option<BufferRecycler<BytesMut>>,
+    doorbell: Arc<Doorbell>,
+    readers: usize,
+    writers: Vec<Sink<W>>,
+    writer_txs: Vec<Sender<()>>,
+    writer_rxs: Vec<Receiver<()>>,
+    virtual_offsets: bool,
+    requeue_failed_blocks: bool,
+    write_retries: u32,
+    max_output_size: Option<u64>,
+    batch_small_blocks: Option<usize>,
+    coalesce_partial_flushes: bool,
+    verify_blocks: bool,
+    max_in_flight_blocks: Option<usize>,
+    max_in_flight_bytes: Option<Arc<InFlightLimit>>,
+    memory_budget: Option<usize>,
+    compressor_per_writer: bool,
+    adaptive_compression: Option<AdaptiveCompression>,
+    autoscale: Option<AutoScaling>,
+    empty_pool_policy: EmptyPoolPolicy,
+    panic_policy: PanicPolicy,
+    work_quantum: WorkQuantum,
+    work_weights: WorkWeights,
+    #[cfg(feature = "thread_priority")]
+    thread_priority: Option<ThreadPriority>,
+    #[cfg(any(feature = "aes_gcm_encoder", feature = "crypt4gh_encoder"))]
+    encryption_keys: Vec<encryption::EncryptionKey>,
+    spawner: Spawner,
+    compressor_overrides: Vec<CompressorOverride>,
+    writer_states: Vec<Arc<WriterShared>>,
+}
+
+impl<W, C> PoolBuilder<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    /// By default queue sizes will be set to threads * this constant.
+    pub const QUEUE_SIZE_THREAD_MULTIPLES: usize = 50;
+
+    /// The default number of threads that will be used if not otherwise configured
+    pub const DEFAULT_THREADS: usize = 4;
+
+    /// Creates a new PoolBuilder that can be used to configure and build a [`Pool`].
+    pub fn new() -> Self {
+        PoolBuilder {
+            writer_index: 0,
+            compression_level: C::default_compression_level(),
+            compression_level_number: None,
+            queue_size: None,
+            queue_size_thread_multiple: Self::QUEUE_SIZE_THREAD_MULTIPLES,
+            retry_delay: Profile::default().retry_delay(),
+            threads: Self::DEFAULT_THREADS,
+            compressor_threads: None,
+            writer_threads: None,
+            drop_policy: DropPolicy::default(),
+            block_size: None,
+            block_size_tuning: None,
+            small_output: None,
+            extra_subfields: None,
+            map_writers: None,
+            reopen: None,
+            recompress: None,
+            destinations: vec![],
+            dictionary: None,
+            gzip_header: None,
+            block_checksum: None,
+            clock: Arc::new(SystemClock::new()),
+            compressor_tx: None,
+            compressor_rx: None,
+            task_tx: None,
+            task_rx: None,
+            buffers: None,
+            doorbell: Arc::default(),
+            readers: 0,
+            writers: vec![],
+            writer_txs: vec![],
+            writer_rxs: vec![],
+            virtual_offsets: false,
+            requeue_failed_blocks: false,
+            write_retries: 0,
+            max_output_size: None,
+            batch_small_blocks: None,
+            coalesce_partial_flushes: false,
+            verify_blocks: false,
+            max_in_flight_blocks: None,
+            max_in_flight_bytes: None,
+            memory_budget: None,
+            compressor_per_writer: false,
+            adaptive_compression: None,
+            autoscale: None,
+            empty_pool_policy: EmptyPoolPolicy::default(),
+            panic_policy: PanicPolicy::default(),
+            work_quantum: WorkQuantum::default(),
+            work_weights: WorkWeights::default(),
+            #[cfg(feature = "thread_priority")]
+            thread_priority: None,
+            #[cfg(any(feature = "aes_gcm_encoder", feature = "crypt4gh_encoder"))]
+            encryption_keys: vec![],
+            spawner: Spawner::default(),
+            compressor_overrides: vec![],
+            writer_states: vec![],
+        }
+    }
+
+    /// Sets the number of threads that will be used by the [[Pool]].
+    ///
+    /// Will panic if set to 0.
+    pub fn threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0, "Must provide a number of threads greater than 0.");
+        self.threads = threads;
+        self
+    }
+
+    /// Dedicates `threads` of the pool's threads to compressing blocks, and decompressing those
+    /// of pooled readers, leaving the writing of compressed blocks to the other threads, e.g. to
+    /// tune a CPU-heavy workload separately from an IO-heavy one.  By default every thread does
+    /// both.  Unless also set with [`PoolBuilder::writer_threads`], the remaining threads of
+    /// [`PoolBuilder::threads`], and at least one, are writer threads.
+    ///
+    /// Will panic if set to 0.
+    pub fn compressor_threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0, "Must provide a number of compressor threads greater than 0.");
+        self.compressor_threads = Some(threads);
+        self
+    }
+
+    /// Dedicates `threads` of the pool's threads to writing compressed blocks to the underlying
+    /// writers, leaving compression to the other threads, see
+    /// [`PoolBuilder::compressor_threads`].  Unless also set with
+    /// [`PoolBuilder::compressor_threads`], the remaining threads of [`PoolBuilder::threads`],
+    /// and at least one, are compressor threads.
+    ///
+    /// Will panic if set to 0.
+    pub fn writer_threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0, "Must provide a number of writer threads greater than 0.");
+        self.writer_threads = Some(threads);
+        self
+    }
+
+    /// The numbers of dedicated compressor and writer threads, or `None` if every thread does
+    /// both.
+    fn thread_roles(&self) -> Option<(usize, usize)> {
+        let rest = |n: usize| self.threads.saturating_sub(n).max(1);
+        match (self.compressor_threads, self.writer_threads) {
+            (None, None) => None,
+            (Some(compressors), Some(writers)) => Some((compressors, writers)),
+            (Some(compressors), None) => Some((compressors, rest(compressors))),
+            (None, Some(writers)) => Some((rest(writers), writers)),
+        }
+    }
+
+    /// Sets the size of queues used by the pool [[Pool]].  The same size is used for
+    /// a) the queue of byte buffers to be compressed, b) the per-sample queues to receive
+    /// compressed bytes, and c) a control queue to manage writing to the underlying writers.
+    ///
+    /// In the worst case scenario the pool can be holding both queue_size uncompressed blocks
+    /// _and_ queue_size compressed blocks in memory when it cannot keep up with the incoming
+    /// load of writes.
+    ///
+    ///
+    ///
+    /// Will panic if called _after_ writers have been created because queues will already have
+    /// been created.
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        assert!(self.writers.is_empty(), "Cannot set queue_size after writers are exchanged.");
+        self.queue_size.insert(queue_size);
+        self
+    }
+
+    /// Configures the queue sizes, retry delay, limit on the bytes in flight, batching of small
+    /// blocks, coalescing of partial flushes and work weights from one of the curated
+    /// [`Profile`]s.  Any of these set before this is replaced, while those set afterwards take
+    /// precedence over the profile.  Writers exchanged with their own [`ExchangeOptions`] use
+    /// those options rather than the profile's coalescing of partial flushes.
+    ///
+    /// Will panic if called _after_ writers have been created because queues will already have
+    /// been created.
+    pub fn profile(mut self, profile: Profile) -> Self {
+        assert!(self.writers.is_empty(), "Cannot set a profile after writers are exchanged.");
+        self.queue_size = None;
+        self.queue_size_thread_multiple = profile.queue_size_thread_multiple();
+        self.retry_delay = profile.retry_delay();
+        self.max_in_flight_bytes =
+            profile.max_in_flight_bytes().map(|max| Arc::new(InFlightLimit::new(max)));
+        self.batch_small_blocks = profile.batch_small_blocks();
+        self.coalesce_partial_flushes = profile.coalesce_partial_flushes();
+        self.work_weights = profile.work_weights();
+        self
+    }
+
+    /// Sets how long a pool thread waits before retrying a failed write, see
+    /// [`PoolBuilder::retry_failed_writes`].  Idle pool threads don't poll for work: they block
+    /// until a block or task is sent to the pool, or the pool is stopped.  Defaults to 25ms.
+    pub fn retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Sets the compression level that will be used by the [[Pool]].
+    pub fn compression_level(mut self, level: u8) -> PoolResult<Self> {
+        C::capabilities().check_compression_level(level)?;
+        self.compression_level = C::new_compression_level(level)
+            .map_err(|e| PoolError::CompressionError(e.to_string()))?;
+        self.compression_level_number = Some(level);
+        Ok(self)
+    }
+
+    /// Sets what [`PoolBuilder::build`] does if no writers have been exchanged.  Defaults to
+    /// [`EmptyPoolPolicy::NoOp`], since a pool without writers has no work to do.
+    pub fn empty_pool_policy(mut self, policy: EmptyPoolPolicy) -> Self {
+        self.empty_pool_policy = policy;
+        self
+    }
+
+    /// Sets what the pool does if one of its threads panics, choosing between failing fast and
+    /// keeping the rest of the process available.  Defaults to [`PanicPolicy::ResumeUnwind`].
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.panic_policy = policy;
+        self
+    }
+
+    /// Sets the [`DropPolicy`] applied by [`PooledWriter`]s that are dropped without having been
+    /// finalized.  Applies to writers exchanged after this is called.  Defaults to
+    /// [`DropPolicy::Finalize`].
+    pub fn drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.drop_policy = policy;
+        self
+    }
+
+    /// Sets the number of uncompressed bytes in each block sent to the pool, in place of the
+    /// compressor's [`Compressor::BLOCK_SIZE`], e.g. smaller BGZF blocks for finer grained
+    /// random access at some cost to the compression ratio.  Applies to writers exchanged after
+    /// this is called that use the pool's compressor.
+    ///
+    /// Returns an error if the block size is zero or larger than the compressor's maximum block
+    /// size.
+    pub fn block_size(mut self, block_size: usize) -> PoolResult<Self> {
+        self.capabilities().check_block_size(block_size)?;
+        self.block_size = Some(block_size);
+        Ok(self)
+    }
+
+    /// Enables the experimental block size tuning mode, in which each writer tries each of the
+    /// candidate block sizes during a warm-up window and then locks in the one that gives the
+    /// best compression ratio (preferring the fastest among near ties).  Applies to writers
+    /// exchanged after this is called.
+    ///
+    /// Returns an error if no candidates are given or any candidate exceeds the compressor's
+    /// maximum block size.
+    pub fn block_size_tuning(mut self, tuning: BlockSizeTuning) -> PoolResult<Self> {
+        if tuning.candidates.is_empty() {
+            return Err(PoolError::UnsupportedOption(
+                "block size tuning requires at least one candidate".to_string(),
+            ));
+        }
+        let caps = self.capabilities();
+        tuning.candidates.iter().try_for_each(|&size| caps.check_block_size(size))?;
+        self.block_size_tuning = Some(tuning);
+        Ok(self)
+    }
+
+    /// Bypasses normal compression for outputs whose total size is less than `threshold` bytes,
+    /// encoding them according to `policy` instead.  Since compressing a tiny output can cost
+    /// more in headers than it saves, this is useful when many small summary files are written.
+    /// Applies to writers exchanged after this is called.
+    ///
+    /// Only outputs that are still entirely buffered when finalized are eligible, so thresholds
+    /// above the block size have the same effect as a threshold of the block size.
+    pub fn small_output_bypass(
+        mut self,
+        threshold: usize,
+        policy: SmallOutputPolicy,
+    ) -> PoolResult<Self> {
+        if let SmallOutputPolicy::CompressionLevel(level) = policy {
+            check_compression_level::<C>(level)?;
+        }
+        let hook = self.small_output.take().and_then(|b| b.hook);
+        self.small_output = Some(SmallOutputBypass { threshold, policy, hook });
+        Ok(self)
+    }
+
+    /// Sets a hook to be called when an output is small enough to bypass normal compression.
+    /// Must be called after [`PoolBuilder::small_output_bypass`], and applies to writers
+    /// exchanged after it is called.
+    pub fn on_small_output<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(usize, SmallOutputPolicy) + Send + Sync + 'static,
+    {
+        let bypass = self
+            .small_output
+            .as_mut()
+            .expect("Must configure small_output_bypass before setting a hook.");
+        bypass.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets a hook that is called on a pool thread for every block that is compressed, returning
+    /// extra subfields to add to the header of the compressed block.  This is useful for custom
+    /// indexing or provenance schemes that embed per-block metadata.
+    ///
+    /// Returns an error if the compressor does not support extra subfields.
+    pub fn extra_subfields<F>(mut self, hook: F) -> PoolResult<Self>
+    where
+        F: Fn(usize, &[u8]) -> Vec<ExtraSubfield> + Send + Sync + 'static,
+    {
+        if !self.capabilities().supports_extra_subfields {
+            return Err(PoolError::UnsupportedOption(
+                "compressor does not support extra header subfields".to_string(),
+            ));
+        }
+        self.extra_subfields = Some(Arc::new(hook));
+        Ok(self)
+    }
+
+    /// Sets a hook that wraps every writer exchanged after it is called, e.g. in a counting or
+    /// rate limited adapter, so that such concerns need not be handled at every call to
+    /// [`PoolBuilder::exchange`] and its variants.  The hook is applied to the writer that
+    /// receives the compressed bytes, including each output opened for a writer that is split by
+    /// records, but not to the uncompressed copy of a tee.  To wrap writers in adapters of a
+    /// different type, use a boxed writer such as `Box<dyn Write + Send>` for `W`.
+    pub fn map_writers<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(W) -> W + Send + Sync + 'static,
+    {
+        self.map_writers = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets a pre-trained dictionary that is used by every compressor in the pool, e.g. a zstd
+    /// dictionary trained on samples of the data, which can greatly improve the ratio achieved
+    /// on many small, similar blocks.  Outputs must be decompressed with the same dictionary.
+    ///
+    /// Returns an error if the compressor does not support dictionaries.
+    pub fn dictionary(mut self, dictionary: Vec<u8>) -> PoolResult<Self> {
+        if !self.capabilities().supports_dictionaries {
+            return Err(PoolError::UnsupportedOption(
+                "compressor does not support dictionaries".to_string(),
+            ));
+        }
+        self.dictionary = Some(Arc::new(dictionary));
+        Ok(self)
+    }
+
+    /// Sets the fields of the header written at the start of each gzip member, e.g. each BGZF
+    /// block, such as a fixed `MTIME` for reproducible outputs, the `OS` byte, or the `FNAME`
+    /// that some consumers expect.  Writers exchanged with their own compressor must also
+    /// support the header.
+    ///
+    /// Returns an error if the compressor does not write gzip members, or the header has a file
+    /// name and the format does not allow one, e.g. BGZF.
+    pub fn gzip_header(mut self, header: GzipHeader) -> PoolResult<Self> {
+        self.capabilities().check_gzip_header(&header)?;
+        self.gzip_header = Some(Arc::new(header));
+        Ok(self)
+    }
+
+    /// Enables tracking of the compressed offset of every block, so that
+    /// [`PooledWriter::virtual_offset`] may be used to obtain BGZF-style virtual offsets, e.g. to
+    /// build a BAM index on the fly.  Applies to writers exchanged after this is called.
+    ///
+    /// Returns an error if the compressor's blocks may be too large for the 16 bit within-block
+    /// offset of a virtual offset.
+    pub fn virtual_offsets(mut self, track: bool) -> PoolResult<Self> {
+        let max_block_size = self.capabilities().max_block_size;
+        if track && max_block_size > 1 << 16 {
+            return Err(PoolError::UnsupportedOption(format!(
+                "virtual offsets require blocks of at most 65536 bytes, not {}",
+                max_block_size
+            )));
+        }
+        self.virtual_offsets = track;
+        Ok(self)
+    }
+
+    /// Enables adaptive compression, in which the pool lowers the compression level while the
+    /// queue of blocks waiting to be compressed is persistently full and raises it again while
+    /// the queue is idle, trading ratio for sustained throughput.  The level starts at, and never
+    /// exceeds, [`AdaptiveCompression::max_level`].  Writers with their own compression level or
+    /// compressor are not affected.
+    ///
+    /// Returns an error if any level in the configured range is not valid for the compressor.
+    pub fn adaptive_compression(mut self, config: AdaptiveCompression) -> PoolResult<Self> {
+        (config.min_level..=config.max_level).try_for_each(check_compression_level::<C>)?;
+        self.adaptive_compression = Some(config);
+        Ok(self)
+    }
+
+    /// Enables auto-scaling, in which the pool starts with [`AutoScaling::min_threads`] threads
+    /// at work, puts another to work while the queue of blocks waiting to be compressed is
+    /// persistently full and retires one again while the queue is idle, for applications whose
+    /// load is bursty.  All the threads are still spawned when the pool is built, the retired
+    /// ones idling until needed, so the range is capped at the number of threads, or of
+    /// compressor threads if there are dedicated writer threads, see
+    /// [`PoolBuilder::writer_threads`].  The current number is given by
+    /// [`Pool::max_active_threads`].  A limit set with [`Pool::set_max_active_threads`] caps
+    /// the range until it is raised again, however busy the pool.
+    pub fn autoscale(mut self, config: AutoScaling) -> Self {
+        self.autoscale = Some(config);
+        self
+    }
+
+    /// Limits how many blocks of each writer may be in flight, i.e. sent to the pool but not yet
+    /// written, at once.  Once the limit is reached further writes block that writer only, which
+    /// bounds the memory used by a single extremely hot writer.  Applies to writers exchanged
+    /// after this is called.  By default there is no limit beyond the queue size.
+    ///
+    /// Will panic if set to 0.
+    pub fn max_in_flight_blocks(mut self, max: usize) -> Self {
+        assert!(max > 0, "Must allow at least one block in flight.");
+        self.max_in_flight_blocks = Some(max);
+        self
+    }
+
+    /// Limits the uncompressed bytes in flight, i.e. sent to the pool but not yet written, across
+    /// all the writers exchanged after this is called, covering the blocks waiting to be
+    /// compressed and those compressed and waiting to be written.  Once the limit is reached
+    /// further writes block, whichever writer they are to, until enough has been written, which
+    /// bounds the memory used by many writers to a slow filesystem.  A block larger than the
+    /// limit is sent once nothing else is in flight.  By default there is no limit beyond the
+    /// queue sizes.
+    ///
+    /// Will panic if set to 0.
+    pub fn max_in_flight_bytes(mut self, max: usize) -> Self {
+        assert!(max > 0, "Must allow at least one byte in flight.");
+        self.max_in_flight_bytes = Some(Arc::new(InFlightLimit::new(max)));
+        self
+    }
+
+    /// Caps the scratch memory of the pool's compressors, as reported for the compression level
+    /// by [`CompressorCapabilities::scratch_memory`], at `bytes`.  When the pool is built the
+    /// number of threads, each of which keeps a compressor, is reduced to fit the budget; if even
+    /// one compressor doesn't fit, the compression level is first lowered until one does.
+    /// Compressors that don't report their scratch memory are not limited, and neither are
+    /// per-writer compressors.
+    ///
+    /// [`PoolBuilder::build`] returns an error if no compression level fits the budget.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Gives each writer its own compressor instance, which compresses the writer's
+    /// blocks one at a time and in order on whichever thread picks each up, rather than each
+    /// thread keeping an instance shared by all writers.  This is always done for compressors
+    /// that report [`CompressorCapabilities::stateful`]; enabling it for others lets them build
+    /// up context across a writer's blocks.  Each stream is then limited to the throughput of a
+    /// single thread, and per-block compression levels can't be used, see
+    /// [`ExchangeOptions::compression_level`].  Applies to writers exchanged after this is
+    /// called.
+    pub fn compressor_per_writer(mut self, per_writer: bool) -> Self {
+        self.compressor_per_writer = per_writer;
+        self
+    }
+
+    /// Sets how much compression or write work each pool thread does in a turn.  Larger write
+    /// quanta let IO-heavy configurations drain more writes without thrashing between the two
+    /// kinds of work.  Defaults to one of each, see [`WorkQuantum`].
+    pub fn work_quantum(mut self, quantum: WorkQuantum) -> Self {
+        assert!(
+            quantum.compressions > 0 && quantum.writes > 0,
+            "Work quanta must be greater than 0."
+        );
+        self.work_quantum = quantum;
+        self
+    }
+
+    /// Sets how each pool thread weighs the backlog of compression work against that of write
+    /// work when choosing what to do next, e.g. a larger write weight to keep the compressed
+    /// blocks held in memory down.  Defaults to equal weights, see [`WorkWeights`].
+    pub fn work_weights(mut self, weights: WorkWeights) -> Self {
+        self.work_weights = weights;
+        self
+    }
+
+    /// Enables re-queuing of blocks that fail to compress, e.g. due to a transient allocation
+    /// failure on a memory-pressured host.  The failing thread's compressor is discarded and
+    /// replaced, and the block is re-queued once, to be compressed by a different thread where
+    /// more than one is active.  If it fails again the pool fails as it would without this.
+    /// Defaults to `false`.
+    pub fn requeue_failed_blocks(mut self, requeue: bool) -> Self {
+        self.requeue_failed_blocks = requeue;
+        self
+    }
+
+    /// Retries writing a block to its underlying writer up to `retries` times if it fails, e.g.
+    /// due to a transient network filesystem error, waiting for [`PoolBuilder::retry_delay`]
+    /// between attempts.  Each block is tracked by its number within the writer's stream, so a
+    /// retried block is written exactly once: any part of it written before the failure is not
+    /// written again.  If the last retry also fails the pool fails as it would without this.
+    /// Defaults to 0.
+    pub fn retry_failed_writes(mut self, retries: u32) -> Self {
+        self.write_retries = retries;
+        self
+    }
+
+    /// Limits the compressed size of each output of the writers exchanged after it is called to
+    /// `limit` bytes, e.g. to protect quota-limited storage from a runaway output.  A block that
+    /// would take an output over the limit is not written: if the writer is split into several
+    /// outputs with [`PoolBuilder::exchange_split_by_records`], the block starts the next output
+    /// instead, otherwise the writer is poisoned.  Writing to a poisoned writer fails with
+    /// [`PoolError::OutputSizeExceeded`], its remaining blocks are dropped, and the pool fails
+    /// with the same error once stopped, while the pool's other writers are unaffected.
+    ///
+    /// Splitting an output this way happens between blocks rather than between records, so is
+    /// only suitable for formats whose blocks are independent, e.g. BGZF, and only the last
+    /// output ends with the format's end of stream marker.  Such outputs are not reported by
+    /// [`PooledWriter::split_manifest`].
+    pub fn max_output_size(mut self, limit: u64) -> Self {
+        self.max_output_size = Some(limit);
+        self
+    }
+
+    /// Compresses the blocks of fewer than `max_len` uncompressed bytes, e.g. those sent by
+    /// frequent partial flushes, in batches, cutting the overhead of passing each to the pool's
+    /// threads.  While one small block of a writer waits to be compressed, the writer's later
+    /// small blocks join it, and the whole batch is compressed by one thread in one task, each
+    /// block still as its own block.  Applies to writers exchanged after it is called, other
+    /// than those whose compressor is stateful.  Defaults to compressing every block on its own.
+    pub fn batch_small_blocks(mut self, max_len: usize) -> Self {
+        self.batch_small_blocks = Some(max_len);
+        self
+    }
+
+    /// Re-compresses finished outputs of up to `max_bytes` uncompressed bytes at `level`, e.g. a
+    /// higher level than the pool's, on pool threads that are otherwise idle, capturing a better
+    /// ratio for small outputs without delaying the main work.  Each output is re-compressed to
+    /// a temporary file alongside it, which then replaces the output if it is smaller.
+    /// Applies to writers exchanged after it is called whose destination is recorded with
+    /// [`PoolBuilder::set_destination`], and which use the pool's compressor.  Outputs are not
+    /// re-compressed if virtual offsets, block checksums, extra subfields or the small output
+    /// bypass are enabled, or if the writer is split by records.  Since the uncompressed bytes
+    /// of each output are kept until it is finished or exceeds `max_bytes`, this costs up to
+    /// `max_bytes` of memory per writer.  Re-compression still pending when the pool is stopped
+    /// is finished first.
+    ///
+    /// Returns an error if the level is not valid for the compressor.
+    pub fn recompress_small_outputs(mut self, max_bytes: usize, level: u8) -> PoolResult<Self> {
+        check_compression_level::<C>(level)?;
+        let level = C::new_compression_level(level)
+            .map_err(|e| PoolError::CompressionError(e.to_string()))?;
+        self.recompress = Some((max_bytes, level));
+        Ok(self)
+    }
+
+    /// Sets a hook that opens a replacement for a writer exchanged after it is called, once
+    /// writing a block to it has failed and any retries are exhausted, e.g. after a transient
+    /// out of space error has been resolved.  The hook is called on a pool thread with the index
+    /// of the writer, the number of compressed bytes of its current output that were written in
+    /// whole blocks, and the error.  The replacement should continue the output from that offset,
+    /// e.g. by truncating the file to it and appending, since the block that failed and those
+    /// after it are written to the replacement in full.  Bytes buffered by the failed writer
+    /// itself are lost, so unbuffered writers should be used.  If the hook returns an error, the
+    /// pool fails with it as it would without this.
+    ///
+    /// The hook is called again each time the replacement fails, so should give up eventually.
+    /// It applies to the writer that receives the compressed bytes, not the uncompressed copy of
+    /// a tee, and the writers it returns are wrapped with [`PoolBuilder::map_writers`].
+    pub fn reopen_failed_writers<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(usize, u64, &io::Error) -> io::Result<W> + Send + Sync + 'static,
+    {
+        self.reopen = Some(Arc::new(hook));
+        self
+    }
+
+    /// Enables verification of every compressed block: after compressing a block, the pool
+    /// thread decompresses it (or otherwise checks it, see [`Compressor::verify`]) before queuing
+    /// it to be written, and fails the pool with [`PoolError::VerificationFailed`] on a mismatch.
+    /// This guards long running pipelines against silent compressor bugs or bad memory, at the
+    /// cost of the time taken to decompress.  Defaults to `false`.
+    ///
+    /// Returns an error if the compressor does not support verification.  Should be called
+    /// before writers are exchanged with [`PoolBuilder::exchange_with_compressor`], which then
+    /// checks that their compressors support verification too.
+    pub fn verify_blocks(mut self, verify: bool) -> PoolResult<Self> {
+        if verify && !self.capabilities().supports_verification {
+            return Err(PoolError::UnsupportedOption(
+                "compressor does not support verification".to_string(),
+            ));
+        }
+        self.verify_blocks = verify;
+        Ok(self)
+    }
+
+    /// Sets the [`Clock`] used by the pool for timestamps and for waiting between retries of a
+    /// failed write.  Defaults to the [`SystemClock`]; a [`clock::ManualClock`] may be used to run
+    /// the pool with virtual time in tests and simulations.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets the scheduling priority of the pool threads, e.g. [`ThreadPriority::Min`] to lower
+    /// their niceness so that compression bursts at high levels don't starve latency-critical
+    /// application threads.  Each pool thread applies the priority when it starts; if the
+    /// operating system refuses the change the thread carries on at its default priority.
+    /// Defaults to leaving the priority unchanged.
+    #[cfg(feature = "thread_priority")]
+    pub fn thread_priority(mut self, priority: ThreadPriority) -> Self {
+        self.thread_priority = Some(priority);
+        self
+    }
+
+    /// Names the pool threads `{prefix}-main`, for the thread that manages the pool, and
+    /// `{prefix}-0`, `{prefix}-1` and so on for the others, so that they can be told apart in
+    /// profilers and debuggers.  By default the threads are unnamed.
+    pub fn thread_name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.spawner.name_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Sets the stack size of the pool threads, in bytes.  Defaults to the standard library's
+    /// default stack size.
+    pub fn thread_stack_size(mut self, stack_size: usize) -> Self {
+        self.spawner.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Spawns the pool threads with `spawner` rather than [`std::thread::Builder`], e.g. to
+    /// register them with an application that tracks its threads.  The spawner is handed each
+    /// thread, with its name and stack size if set, and must start a new thread that calls
+    /// [`spawn::PoolThread::run`], returning its handle; [`spawn::PoolThread::spawn`] does this
+    /// the default way.  [`PoolBuilder::build`] fails if the thread that manages the pool can't
+    /// be spawned, while the pool carries on with its other threads if any of those can't be
+    /// spawned, and fails once stopped.
+    pub fn thread_spawner(mut self, spawner: ThreadSpawner) -> Self {
+        self.spawner.spawner = Some(spawner);
+        self
+    }
+
+    /// Generates a [[Pool]] with no threads, for when no writers have been exchanged.
+    fn build_no_op(self) -> Pool {
+        // The pool thread never runs, so the channel it would disconnect is disconnected now
+        let (_, done_rx) = channel::bounded::<()>(1);
+        let completion = Arc::new(Completion::default());
+        completion.complete(&Ok(()));
+        let block_size = self.writer_block_size();
+        Pool {
+            compressor_tx: self.compressor_tx,
+            shutdown_tx: None,
+            write_available_tx: None,
+            doorbell: self.doorbell,
+            pool_handle: None,
+            done_rx,
+            writer_states: vec![],
+            sinks: vec![],
+            threads: self.threads,
+            block_size,
+            max_active_threads: Arc::new(AtomicUsize::new(self.threads)),
+            thread_cap: Arc::new(AtomicUsize::new(self.threads)),
+            level_counters: Arc::default(),
+            adaptive: None,
+            completion,
+            compressor_type: TypeId::of::<C>(),
+        }
+    }
+
+    /// The capabilities of the compressor at the configured compression level.
+    fn capabilities(&self) -> CompressorCapabilities {
+        C::capabilities_for(&self.compression_level)
+    }
+
+    /// The size of the blocks sent by writers using the pool's compressor: the block size set
+    /// with [`PoolBuilder::block_size`], or else that of the compressor at the configured
+    /// compression level.
+    fn writer_block_size(&self) -> usize {
+        self.block_size.unwrap_or_else(|| C::block_size_for(&self.compression_level))
+    }
+
+    /// If queues/channels are not yet setup, initialize them.
+    fn ensure_queue_is_setup(&mut self) {
+        if self.compressor_tx.is_none() && self.compressor_rx.is_none() {
+            if self.queue_size.is_none() {
+                self.queue_size.insert(self.threads * self.queue_size_thread_multiple);
+            }
+
+            let (tx, rx) = bounded(self.queue_size.unwrap());
+            self.compressor_tx.insert(DoorbellSender::new(
+                tx,
+                self.doorbell.clone(),
+                Waiters::Compressors,
+            ));
+            self.compressor_rx.insert(rx);
+            let (tx, rx) = channel::unbounded();
+            self.task_tx =
+                Some(DoorbellSender::new(tx, self.doorbell.clone(), Waiters::Compressors));
+            self.task_rx = Some(rx);
+            self.buffers = Some(BufferRecycler::new(self.queue_size.unwrap()));
+        }
+    }
+
+    /// Exchanges a reader of a stream compressed in the format `D` for a [`PooledReader`], whose
+    /// blocks are decompressed in parallel by the pool's threads and read in order.  Up to twice
+    /// as many blocks as there are threads are decompressed ahead of the reader.  The reader
+    /// should be read to the end before the pool is stopped.
+    pub fn exchange_reader<R: Read, D: Decompressor>(&mut self, reader: R) -> PooledReader<R, D> {
+        self.ensure_queue_is_setup();
+        self.readers += 1;
+        let tasks = self.task_tx.as_ref().expect("Unreachable").clone();
+        PooledReader::new(reader, tasks, self.threads * 2)
+    }
+
+    /// Exchanges a writer for a [[PooledWriter]].
+    pub fn exchange(&mut self, writer: W) -> PooledWriter {
+        self.exchange_sink::<C>(Sink::new(writer, None), self.writer_block_size(), None)
+    }
+
+    /// Gives a writer exchanged with this builder a stable ID, e.g. the name of its output, so
+    /// that it can be identified independently of the order in which writers were exchanged,
+    /// including across pools when a pipeline is restarted.  The ID is reported by
+    /// [`PooledWriter::id`] and in [`WriterStats::id`](stats::WriterStats::id), can be looked
+    /// up with [`Pool::writer_index`], and errors concerning the writer are wrapped in a
+    /// [`PoolError::Writer`] naming it.
+    ///
+    /// Returns a [`PoolError::DuplicateWriterId`] error if another writer already has the ID.
+    pub fn set_writer_id(&mut self, writer: &PooledWriter, id: &str) -> PoolResult<()> {
+        let taken = self
+            .writer_states
+            .iter()
+            .any(|s| !Arc::ptr_eq(s, &writer.shared) && s.id.lock().as_deref() == Some(id));
+        if taken {
+            return Err(PoolError::DuplicateWriterId(id.to_string()));
+        }
+        *writer.shared.id.lock() = Some(Arc::from(id));
+        Ok(())
+    }
+
+    /// Records that a writer exchanged with this builder writes to the file at `path`, so that
+    /// two writers cannot be given the same destination, which would interleave their streams
+    /// and corrupt the output.  Paths are compared once symbolic links and relative components
+    /// are resolved, and on unix files that already exist are also compared by device and
+    /// inode, so that hard links to the same file are detected.  The file need not exist yet,
+    /// though its directory must.  Recording another destination for the writer replaces its
+    /// previous one.
+    ///
+    /// Returns a [`PoolError::DuplicateDestination`] error if another writer already has the
+    /// destination, or [`PoolError::UnknownWriter`] if the writer was not exchanged with this
+    /// builder.
+    pub fn set_destination(
+        &mut self,
+        writer: &PooledWriter,
+        path: impl AsRef<Path>,
+    ) -> PoolResult<()> {
+        let index = writer.writer_index;
+        match self.writer_states.get(index) {
+            Some(state) if Arc::ptr_eq(state, &writer.shared) => {}
+            _ => return Err(PoolError::UnknownWriter(index)),
+        }
+        let path = resolve_destination(path.as_ref())?;
+        let file_id = file_id(&path);
+        let duplicate = self.destinations.iter().find(|d| {
+            d.writer != index && (d.path == path || (file_id.is_some() && d.file_id == file_id))
+        });
+        if let Some(other) = duplicate {
+            return Err(PoolError::DuplicateDestination { path, writer: other.writer });
+        }
+        if let Some(recompress) = self.writers[index].recompress.as_mut() {
+            recompress.destination = Some(path.clone());
+        }
+        self.destinations.retain(|d| d.writer != index);
+        self.destinations.push(Destination { path, file_id, writer: index });
+        Ok(())
+    }
+
+    /// Exchanges a writer for a [[PooledWriter]] whose stream starts with `header`, e.g. the
+    /// magic bytes and header of a format.  The header is the first data compressed for the
+    /// writer, ahead of anything written to it, and is written even if nothing else is, unless
+    /// the writer is dropped with [`DropPolicy::Discard`].
+    ///
+    /// Returns an error if the header is longer than the writer's block size.
+    pub fn exchange_with_header(&mut self, writer: W, header: &[u8]) -> PoolResult<PooledWriter> {
+        let block_size = match &self.block_size_tuning {
+            Some(tuning) => tuning.candidates.iter().copied().min().expect("Unreachable"),
+            None => self.writer_block_size(),
+        };
+        if header.len() > block_size {
+            return Err(PoolError::UnsupportedOption(format!(
+                "header of {} bytes is longer than the block size of {}",
+                header.len(),
+                block_size
+            )));
+        }
+        let mut pooled = self.exchange(writer);
+        pooled.buffer.extend_from_slice(header);
+        Ok(pooled)
+    }
+
+    /// Returns an error if `options` can't be applied to a writer using the pool's compressor.
+    fn check_options(&self, options: &ExchangeOptions) -> PoolResult<()> {
+        if let Some(level) = options.compression_level {
+            check_compression_level::<C>(level)?;
+            if self.compressor_per_writer || self.capabilities().stateful {
+                return Err(stateful_level_error());
+            }
+        }
+        if options.omit_eof_marker && (self.compressor_per_writer || self.capabilities().stateful) {
+            return Err(stateful_eof_error());
+        }
+        Ok(())
+    }
+
+    /// Exchanges a writer for a [[PooledWriter]] whose blocks are compressed at `level` rather
+    /// than the pool's compression level, e.g. level 1 for temporary files and a higher level
+    /// for final deliverables within one pool.
+    ///
+    /// Returns an error if the level is not valid for the pool's compressor.
+    pub fn exchange_with_level(&mut self, writer: W, level: u8) -> PoolResult<PooledWriter> {
+        self.exchange_with_options(writer, ExchangeOptions::new().compression_level(level))
+    }
+
+    /// Exchanges a writer for a [[PooledWriter]] with the given per-writer settings, which may
+    /// be changed later with [`PooledWriter::reconfigure`].
+    ///
+    /// Returns an error if the compression level is not valid for the pool's compressor, or if
+    /// a compression level is set or the EOF marker omitted and the compressor is stateful.  See
+    /// the `encryption` module for the errors when an encryption key is set.
+    pub fn exchange_with_options(
+        &mut self,
+        writer: W,
+        options: ExchangeOptions,
+    ) -> PoolResult<PooledWriter> {
+        self.check_options(&options)?;
+        #[cfg(any(feature = "aes_gcm_encoder", feature = "crypt4gh_encoder"))]
+        if options.encryption.is_some() {
+            return self.exchange_encrypted(writer, options);
+        }
+        let mut pooled = self.exchange(writer);
+        pooled.options = options;
+        Ok(pooled)
+    }
+
+    /// Creates a [[PooledWriter]] without an underlying writer, whose blocks are compressed and
+    /// accounted for like those of any other writer, e.g. in [`Pool::stats`] and virtual offsets,
+    /// but then discarded.  This allows a pipeline to be benchmarked or validated end to end
+    /// without touching storage.
+    pub fn exchange_dry_run(&mut self) -> PooledWriter {
+        self.exchange_sink::<C>(Sink::discard(), self.writer_block_size(), None)
+    }
+
+    /// Exchanges a pair of writers for a single [[PooledWriter]] that writes each block both
+    /// compressed to `compressed` and uncompressed to `raw`, in the same order.  This is useful
+    /// for pipelines that need an archival compressed copy alongside a live uncompressed stream.
+    pub fn exchange_tee_uncompressed(&mut self, compressed: W, raw: W) -> PooledWriter {
+        self.exchange_sink::<C>(Sink::new(compressed, Some(raw)), self.writer_block_size(), None)
+    }
+
+    /// Exchanges an [`OutputFactory`] for a single [[PooledWriter]] whose stream is split into
+    /// numbered outputs of `records_per_output` records each.  Record boundaries are signalled by
+    /// calling [`PooledWriter::end_record`], and each output is a complete stream (e.g. ending
+    /// with the BGZF EOF block).  The factory is called with 0 to open the first output before
+    /// this returns, and on a pool thread to open each subsequent output.
+    /// [`PooledWriter::split_manifest`] reports the number of records in each output.
+    ///
+    /// Returns an error if `records_per_output` is zero, if virtual offset tracking is enabled,
+    /// or if the first output cannot be opened.
+    pub fn exchange_split_by_records(
+        &mut self,
+        records_per_output: u64,
+        mut factory: OutputFactory<W>,
+    ) -> PoolResult<PooledWriter> {
+        if records_per_output == 0 {
+            return Err(PoolError::UnsupportedOption(
+                "records per output must be greater than zero".to_string(),
+            ));
+        }
+        if self.virtual_offsets {
+            return Err(PoolError::UnsupportedOption(
+                "virtual offsets cannot be tracked across split outputs".to_string(),
+            ));
+        }
+
+        let writer = factory(0)?;
+        let mut sink = Sink::new(writer, None);
+        sink.rotation = Some(Rotation { factory, next_index: 1, pending: false });
+        let mut writer = self.exchange_sink::<C>(sink, self.writer_block_size(), None);
+        writer.split =
+            Some(RecordSplit { records_per_output, records: vec![0], blocks_at_start: 0 });
+        Ok(writer)
+    }
+
+    /// Exchanges a writer for a [[PooledWriter]] whose blocks are compressed with the compressor
+    /// `D`, rather than the pool's compressor `C`, at the given compression level or at `D`'s
+    /// default level if `None`.  This allows a single pool to serve, for example, some BGZF, some
+    /// gzip and some uncompressed outputs.  The writer uses `D`'s block size.  Each pool thread
+    /// keeps one instance of each distinct compressor and level used this way.
+    ///
+    /// Block size tuning and the small output bypass do not apply to such writers.  Returns an
+    /// error if the level is not valid for `D`, if block size tuning is enabled, if virtual
+    /// offset tracking is enabled and `D`'s blocks may be too large for it, or if block
+    /// verification is enabled and `D` does not support it.
+    pub fn exchange_with_compressor<D>(
+        &mut self,
+        writer: W,
+        level: Option<u8>,
+    ) -> PoolResult<PooledWriter>
+    where
+        D: Compressor,
+    {
+        if let Some(level) = level {
+            check_compression_level::<D>(level)?;
+        }
+        self.check_override::<D>()?;
+        let index = self.compressor_override::<D>(level);
+        Ok(self.exchange_sink::<D>(Sink::new(writer, None), D::BLOCK_SIZE, Some(index)))
+    }
+
+    /// Exchanges a writer for a [[PooledWriter]] whose blocks are transformed by the [`Encoder`]
+    /// `E` created from `config`, rather than by the pool's compressor `C`.  Encoders may be
+    /// chained with [`Chain`], e.g. to compress and then encrypt each block on the pool's threads
+    /// rather than encrypting on the writer thread.  The writer uses `E`'s block size.  Each
+    /// pool thread keeps one instance of the encoder for each writer exchanged this way, until
+    /// it next looks for work after the writer's last block has been written, when `config` is
+    /// also dropped.
+    ///
+    /// As with [`PoolBuilder::exchange_with_compressor`], returns an error if block size tuning
+    /// is enabled, if virtual offset tracking is enabled and `E`'s blocks may be too large for
+    /// it, or if block verification is enabled and `E` does not support it.
+    ///
+    /// [`Encoder`]: encoder::Encoder
+    /// [`Chain`]: encoder::Chain
+    pub fn exchange_with_encoder<E>(
+        &mut self,
+        writer: W,
+        config: E::Config,
+    ) -> PoolResult<PooledWriter>
+    where
+        E: encoder::Encoder,
+    {
+        self.check_override::<encoder::Encoding<E>>()?;
+        let block_size = E::block_size_for(&config);
+        let stateful = E::capabilities_for(&config).stateful;
+
+        // Each writer gets its own override, since writers of one encoder may differ in config,
+        // which is dropped once the writer has finished
+        let config = Arc::new(Mutex::new(Some(config)));
+        let factory_config = config.clone();
+        let factory: CompressorFactory = Arc::new(move |_level, _dictionary| {
+            let level = factory_config.lock().clone();
+            Box::new(<encoder::Encoding<E> as Compressor>::new(level))
+        });
+        let retirement = Retirement {
+            retired: AtomicBool::new(false),
+            release: Box::new(move || drop(config.lock().take())),
+        };
+        self.compressor_overrides.push(CompressorOverride {
+            key: (TypeId::of::<encoder::Encoding<E>>(), None),
+            factory,
+            stats_level: None,
+            stateful,
+            retirement: Some(Arc::new(retirement)),
+        });
+        let index = self.compressor_overrides.len() - 1;
+        let sink = Sink::new(writer, None);
+        Ok(self.exchange_sink::<encoder::Encoding<E>>(sink, block_size, Some(index)))
+    }
+
+    /// Lowers the number of threads, and if need be the compression level, so that the pool's
+    /// compressors fit in the memory budget, if one is set.
+    fn fit_memory_budget(&mut self) -> PoolResult<()> {
+        let budget = match self.memory_budget {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+        if self.capabilities().scratch_memory > budget {
+            // Choose the highest level at which a single compressor fits
+            let caps = C::capabilities();
+            let fits = caps.level_range().rev().find_map(|number| {
+                let level = C::new_compression_level(number).ok()?;
+                let memory = C::capabilities_for(&level).scratch_memory;
+                if memory <= budget {
+                    Some((number, level))
+                } else {
+                    None
+                }
+            });
+            let (number, level) = fits.ok_or_else(|| {
+                PoolError::UnsupportedOption(format!(
+                    "no compression level fits in a memory budget of {} bytes",
+                    budget
+                ))
+            })?;
+            self.compression_level = level;
+            self.compression_level_number = Some(number);
+        }
+        if let Some(max_threads) = budget.checked_div(self.capabilities().scratch_memory) {
+            self.threads = self.threads.min(max_threads).max(1);
+            if let Some(compressors) = &mut self.compressor_threads {
+                *compressors = (*compressors).min(max_threads).max(1);
+            }
+        }
+        Ok(())
+    }
+
+    /// The index of the [`CompressorOverride`] for the compressor `D` at the given compression
+    /// level, or `D`'s default level if `None`, adding one if there isn't one already.
+    fn compressor_override<D: Compressor>(&mut self, level: Option<u8>) -> usize {
+        let key = (TypeId::of::<D>(), level);
+        match self.compressor_overrides.iter().position(|o| o.key == key) {
+            Some(index) => index,
+            None => {
+                let factory: CompressorFactory = Arc::new(|level, dictionary| {
+                    let level = match level {
+                        Some(level) => {
+                            D::new_compression_level(level).expect("Validated before use")
+                        }
+                        None => D::default_compression_level(),
+                    };
+                    match dictionary {
+                        Some(dictionary) => Box::new(D::new_with_dictionary(level, dictionary)),
+                        None => Box::new(D::new(level)),
+                    }
+                });
+                let stats_level = if key.0 == TypeId::of::<C>() { Some(level) } else { None };
+                let stateful = D::capabilities().stateful;
+                self.compressor_overrides.push(CompressorOverride {
+                    key,
+                    factory,
+                    stats_level,
+                    stateful,
+                    retirement: None,
+                });
+                self.compressor_overrides.len() - 1
+            }
+        }
+    }
+
+    /// Returns an error if writers can't use the compressor `D` in place of the pool's own.
+    fn check_override<D: Compressor>(&self) -> PoolResult<()> {
+        let caps = D::capabilities();
+        if self.block_size_tuning.is_some() {
+            return Err(PoolError::UnsupportedOption(
+                "block size tuning cannot be used with a per-writer compressor".to_string(),
+            ));
+        }
+        if self.verify_blocks && !caps.supports_verification {
+            return Err(PoolError::UnsupportedOption(
+                "compressor does not support verification".to_string(),
+            ));
+        }
+        if self.virtual_offsets && caps.max_block_size > 1 << 16 {
+            return Err(PoolError::UnsupportedOption(format!(
+                "virtual offsets require blocks of at most 65536 bytes, not {}",
+                caps.max_block_size
+            )));
+        }
+        if let Some(header) = &self.gzip_header {
+            caps.check_gzip_header(header)?;
+        }
+        Ok(())
+    }
+
+    /// Exchanges a [`Sink`] for a [[PooledWriter]], whose blocks are of `block_size` bytes and
+    /// compressed by the compressor `D` with the given [`CompressorOverride`], or the pool's
+    /// compressor if `None`.
+    fn exchange_sink<D: Compressor>(
+        &mut self,
+        mut sink: Sink<W>,
+        block_size: usize,
+        compressor: Option<usize>,
+    ) -> PooledWriter {
+        // Make sure queue/channel configuration is done
+        self.ensure_queue_is_setup();
+
+        sink.reopen = self.reopen.clone();
+        sink.max_output_size = self.max_output_size;
+        sink.recompress = self.recompressor(&sink, compressor);
+        if let Some(map) = &self.map_writers {
+            sink.writer = sink.writer.take().map(|writer| map(writer));
+            if let Some(mut rotation) = sink.rotation.take() {
+                let map = map.clone();
+                let mut factory = rotation.factory;
+                rotation.factory = Box::new(move |index| factory(index).map(|writer| map(writer)));
+                sink.rotation = Some(rotation);
+            }
+            if let Some(reopen) = sink.reopen.take() {
+                let map = map.clone();
+                sink.reopen = Some(Arc::new(move |index, offset, error: &io::Error| {
+                    reopen(index, offset, error).map(|writer| map(writer))
+                }));
+            }
+        }
+
+        let stateful = self.compressor_per_writer
+            || match compressor {
+                Some(index) => self.compressor_overrides[index].stateful,
+                None => self.capabilities().stateful,
+            };
+        let (tx, rx): (Sender<()>, Receiver<()>) =
+            channel::bounded(self.queue_size.expect("Unreachable"));
+
+        let shared = Arc::new(WriterShared {
+            counters: WriterCounters::default(),
+            offsets: if self.virtual_offsets { Some(Arc::default()) } else { None },
+            needs_raw: sink.tee.is_some()
+                || sink.observer.is_some()
+                || sink.framer.is_some()
+                || sink.recompress.is_some(),
+            in_flight: self.max_in_flight_blocks.map(InFlightLimit::new),
+            in_flight_bytes: self.max_in_flight_bytes.clone(),
+            compressor,
+            stream: if stateful { Some(StreamCompressor::default()) } else { None },
+            id: Mutex::default(),
+            block_checksums: self.block_checksum.map(|(algorithm, checksum)| BlockChecksums {
+                checksum,
+                manifest: Mutex::new(BlockManifest { algorithm, blocks: vec![] }),
+            }),
+            max_output_size: self.max_output_size,
+            size_exceeded: AtomicBool::new(false),
+            reorder: Mutex::default(),
+            written: Condvar::new(),
+            stopped: AtomicBool::new(false),
+            detached: AtomicBool::new(false),
+            quiesced: AtomicBool::new(false),
+            write_requested: AtomicBool::new(false),
+            batch_below: if stateful { None } else { self.batch_small_blocks },
+            batch: Mutex::default(),
+        });
+        let (tuning, small_output) = match compressor {
+            Some(_) => (None, None),
+            None => (self.block_size_tuning.as_ref(), self.small_output.clone()),
+        };
+        let mut p = PooledWriter::new::<D>(
+            self.writer_index,
+            block_size,
+            self.compressor_tx.as_ref().expect("Unreachable").clone(),
+            tx.clone(),
+            self.buffers.clone().expect("Unreachable"),
+            self.drop_policy,
+            shared.clone(),
+            tuning.map(|t| Arc::new(BlockSizeTuner::new(t))),
+            small_output,
+            self.clock.clone(),
+        );
+        p.options.coalesce_partial_flushes = self.coalesce_partial_flushes;
+
+        self.writer_index += 1;
+        self.writers.push(sink);
+        self.writer_txs.push(tx);
+        self.writer_rxs.push(rx);
+        self.writer_states.push(shared);
+        p
+    }
+
+    /// The state for re-compressing the output of `sink` once finished, if enabled and
+    /// applicable to a sink using the given [`CompressorOverride`].
+    fn recompressor(&self, sink: &Sink<W>, compressor: Option<usize>) -> Option<Recompress> {
+        let (max_bytes, level) = self.recompress.clone()?;
+        let applicable = compressor.is_none()
+            && sink.writer.is_some()
+            && sink.rotation.is_none()
+            && sink.block_observer.is_none()
+            && !self.virtual_offsets
+            && self.block_checksum.is_none()
+            && self.extra_subfields.is_none()
+            && self.small_output.is_none();
+        if !applicable {
+            return None;
+        }
+        let block_size = self.block_size.unwrap_or_else(|| C::block_size_for(&level));
+        let dictionary = self.dictionary.clone();
+        let gzip_header = self.gzip_header.clone();
+        let compress: RecompressFn = Arc::new(move |raw: &[u8]| {
+            let mut compressor =
+                ThreadCompressors::<C>::new_compressor(&dictionary, &gzip_header, level.clone());
+            let mut output = vec![];
+            let mut chunks = raw.chunks(block_size).peekable();
+            if chunks.peek().is_none() {
+                compressor.compress_block(&[], &mut output, true, None, false)?;
+            }
+            while let Some(chunk) = chunks.next() {
+                let is_last = chunks.peek().is_none();
+                compressor.compress_block(chunk, &mut output, is_last, None, false)?;
+            }
+            Ok(output)
+        });
+        Some(Recompress {
+            destination: None,
+            max_bytes,
+            raw: Some(vec![]),
+            finished: false,
+            compress,
+        })
+    }
+
+    /// Consumes the builder and generates the [[Pool]] ready for use.
+    ///
+    /// If no writers or readers have been exchanged the [`EmptyPoolPolicy`] applies: by default a
+    /// pool that starts no threads is returned.
+    pub fn build(mut self) -> PoolResult<Pool> {
+        // Make sure the queue/channel configuration is done - this could be necessary if
+        // a pool is created by zero writers exchanged.
+        self.ensure_queue_is_setup();
+
+        if self.writers.is_empty() && self.readers == 0 {
+            return match self.empty_pool_policy {
+                EmptyPoolPolicy::NoOp => Ok(self.build_no_op()),
+                EmptyPoolPolicy::Error => Err(PoolError::NoWriters),
+            };
+        }
+
+        self.fit_memory_budget()?;
+        let compressor_threads = self.thread_roles().map(|(compressors, writers)| {
+            self.threads = compressors + writers;
+            compressors
+        });
+
+        // Create the channel to gracefully signal a shutdown of the pool
+        let (shutdown_tx, shutdown_rx) = channel::unbounded();
+        let shutdown_tx =
+            DoorbellSender::new(shutdown_tx, self.doorbell.clone(), Waiters::Compressors);
+        let doorbell = self.doorbell.clone();
+
+        // Add locks to the writers, which are shared with the pool for quiescing
+        let writers: Vec<_> = self.writers.drain(..).map(|w| Arc::new(Mutex::new(w))).collect();
+        let sinks = writers.iter().map(|w| w.clone() as Arc<dyn Any + Send + Sync>).collect();
+
+        // Start the pool manager thread and thread pools
+        let writer_states = self.writer_states.clone();
+        let threads = self.threads;
+        let block_size = self.writer_block_size();
+        let autoscaler = self
+            .autoscale
+            .as_ref()
+            .map(|c| Arc::new(ThreadScaler::new(c, self.compressor_threads.unwrap_or(threads))));
+        let max_active_threads = Arc::new(AtomicUsize::new(
+            autoscaler.as_ref().map_or(threads, |a| a.initial_threads()),
+        ));
+        let pool_max_active_threads = max_active_threads.clone();
+        let thread_cap = Arc::new(AtomicUsize::new(threads));
+        let pool_thread_cap = thread_cap.clone();
+        let level_counters = Arc::new(LevelCounters::default());
+        let pool_level_counters = level_counters.clone();
+        let adaptive =
+            self.adaptive_compression.as_ref().map(|c| Arc::new(LevelController::new(c)));
+        let pool_adaptive = adaptive.clone();
+        let (done_tx, done_rx) = channel::bounded::<()>(1);
+        let completion = Arc::new(Completion::default());
+        let pool_completion = completion.clone();
+        #[cfg(feature = "thread_priority")]
+        let on_thread_start = self.thread_priority.map(|priority| -> ThreadStartHook {
+            Arc::new(move || {
+                // Best effort: a refused priority change shouldn't take down the pool
+                let _ = thread_priority::set_current_thread_priority(priority);
+            })
+        });
+        #[cfg(not(feature = "thread_priority"))]
+        let on_thread_start = None;
+        let panic_policy = self.panic_policy;
+
+        // Queues up which writers have blocks that may be ready to be written, waking only the
+        // dedicated writer threads if there are any
+        let (write_available_tx, write_available_rx) = channel::unbounded();
+        let write_waiters =
+            if compressor_threads.is_some() { Waiters::Writers } else { Waiters::Compressors };
+        let write_available_tx =
+            DoorbellSender::new(write_available_tx, self.doorbell.clone(), write_waiters);
+        let pool_write_available_tx = write_available_tx.clone();
+        // And one for background work, such as re-compressing small outputs, that is only done
+        // by threads that are otherwise idle
+        let (background_tx, background_rx) = channel::unbounded();
+        let background_tx =
+            DoorbellSender::new(background_tx, self.doorbell.clone(), Waiters::Compressors);
+        // And one for blocks that failed to compress and are re-queued to be tried again
+        let (retry_tx, retry_rx) = channel::unbounded();
+        let retry_tx = DoorbellSender::new(retry_tx, self.doorbell.clone(), Waiters::Compressors);
+
+        let compressor_rx = self.compressor_rx.expect("Unreachable.");
+        // Compressed buffers are returned once written, as many as the compressor queue holds
+        let compressed_buffers =
+            BufferRecycler::new(compressor_rx.capacity().unwrap_or(self.threads));
+        let context = PoolContext {
+            num_threads: self.threads,
+            compressor_threads,
+            compression_level_number: self.compression_level_number,
+            level_counters: pool_level_counters,
+            compressor_rx,
+            task_rx: self.task_rx.expect("Unreachable."),
+            buffers: self.buffers.expect("Unreachable."),
+            compressed_buffers,
+            writer_rxs: self.writer_rxs,
+            writers,
+            writer_states: self.writer_states,
+            extra_subfields: self.extra_subfields,
+            compressor_overrides: Arc::new(self.compressor_overrides),
+            adaptive: pool_adaptive,
+            max_active_threads: pool_max_active_threads,
+            thread_cap: pool_thread_cap,
+            autoscaler,
+            requeue_failed_blocks: self.requeue_failed_blocks,
+            write_retries: self.write_retries,
+            verify_blocks: self.verify_blocks,
+            quantum: self.work_quantum,
+            weights: self.work_weights,
+            retry_delay: self.retry_delay,
+            clock: self.clock,
+            on_thread_start,
+            panic_policy,
+            shutdown_rx,
+            doorbell: self.doorbell,
+            write_available_tx,
+            write_available_rx,
+            background_tx,
+            background_rx,
+            retry_tx,
+            retry_rx,
+        };
+        let compression_level = self.compression_level;
+        let dictionary = self.dictionary;
+        let gzip_header = self.gzip_header;
+        let spawner = self.spawner.clone();
+        let pool_spawner = self.spawner;
+        let handle = spawner.spawn("main", move || {
+            // Dropped when the pool thread exits, however it exits, which disconnects `done_rx`
+            let _done = done_tx;
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                Pool::pool_main::<W, C>(
+                    context,
+                    compression_level,
+                    dictionary,
+                    gzip_header,
+                    pool_spawner,
+                )
+            }));
+            let result = match result {
+                Ok(result) => result,
+                Err(payload) if panic_policy == PanicPolicy::ResumeUnwind => {
+                    pool_completion.panicked(payload.as_ref());
+                    std::panic::resume_unwind(payload)
+                }
+                Err(payload) => Err(panic_policy.handle(payload)),
+            };
+            pool_completion.complete(&result);
+            result
+        })?;
 
         let mut pool = Pool {
             compressor_tx: self.compressor_tx,
             shutdown_tx: Some(shutdown_tx),
+            write_available_tx: Some(pool_write_available_tx),
+            doorbell,
             pool_handle: Some(handle),
+            done_rx,
+            writer_states,
+            sinks,
+            threads,
+            block_size,
+            max_active_threads,
+            thread_cap,
+            level_counters,
+            adaptive,
+            completion,
+            compressor_type: TypeId::of::<C>(),
+        };
+
+        Ok(pool)
+    }
+}
+
+impl<W, C> PoolBuilder<W, C>
+where
+    W: Write + Seek + Send + 'static,
+    C: Compressor,
+{
+    /// Exchanges a seekable writer for a [[PooledWriter]] that may seek it between blocks with
+    /// [`PooledWriter::seek_barrier`].
+    pub fn exchange_seekable(&mut self, writer: W) -> PooledWriter {
+        let mut sink = Sink::new(writer, None);
+        let seek: fn(&mut W, SeekFrom) -> io::Result<u64> = W::seek;
+        sink.seek = Some(seek);
+        let mut writer = self.exchange_sink::<C>(sink, self.writer_block_size(), None);
+        writer.seekable = true;
+        writer
+    }
+}
+
+impl<C> PoolBuilder<Box<dyn Write + Send>, C>
+where
+    C: Compressor,
+{
+    /// Exchanges a callback for a [[PooledWriter]], where the callback is called on a pool
+    /// thread with the compressed bytes of each block, in order.  This avoids the need for a
+    /// newtype implementing [`Write`] for quick integrations, e.g. pushing blocks to an existing
+    /// channel.  See [`callback::CallbackWriter`].
+    pub fn exchange_callback<F>(&mut self, callback: F) -> PooledWriter
+    where
+        F: FnMut(&[u8]) -> io::Result<()> + Send + 'static,
+    {
+        self.exchange(Box::new(callback::CallbackWriter::new(callback)))
+    }
+
+    /// Exchanges a writer that cannot be moved to the pool's threads, e.g. a wrapper over a
+    /// non-[`Send`] FFI handle, for a [[PooledWriter]] and a [`marshal::Pump`].  The compressed
+    /// blocks are delivered back to the thread that owns the pump, which writes them to
+    /// `writer` each time it is pumped, and should be finished once the pool has been stopped.
+    /// See [`marshal::marshal`].
+    pub fn exchange_marshaled<V: Write>(&mut self, writer: V) -> (PooledWriter, marshal::Pump<V>) {
+        let (marshaled, pump) = marshal::marshal(writer);
+        (self.exchange(Box::new(marshaled)), pump)
+    }
+}
+
+impl<W, C> Default for PoolBuilder<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// The Pool struct and impls
+////////////////////////////////////////////////////////////////////////////////
+
+/// A [`Pool`] orchestrates two different threadpools, a compressor pool and a writer pool.
+///
+/// The pool is suitable for scenarios where there are many more writers than threads, efficiently
+/// managing resources for M writers to N threads.
+#[derive(Debug)]
+pub struct Pool {
+    /// The join handle for the thread that manages all pool resources and coordination.
+    pool_handle: Option<JoinHandle<PoolResult<()>>>,
+    /// The send end of the channel for communicating with the compressor pool.
+    compressor_tx: Option<DoorbellSender<CompressorMessage>>,
+    /// Sentinel channel to tell the pool management thread to shutdown.
+    shutdown_tx: Option<DoorbellSender<()>>,
+    /// Tells the pool's threads that a writer's blocks may be ready to be written, e.g. once a
+    /// [`QuiesceGuard`] is dropped.
+    write_available_tx: Option<DoorbellSender<usize>>,
+    /// Wakes the pool's idle threads, e.g. when the number of active threads changes.
+    doorbell: Arc<Doorbell>,
+    /// Disconnected when the pool management thread exits.
+    done_rx: Receiver<()>,
+    /// The state shared with each writer.
+    writer_states: Vec<Arc<WriterShared>>,
+    /// The locked sink of each writer, with its writer type erased.
+    sinks: Vec<Arc<dyn Any + Send + Sync>>,
+    /// The number of threads in the pool.
+    threads: usize,
+    /// The block size of the pool's compressor.
+    block_size: usize,
+    /// The number of threads that may currently do work, as chosen by the auto-scaler if any.
+    max_active_threads: Arc<AtomicUsize>,
+    /// The limit on the threads doing work set with [`Pool::set_max_active_threads`].
+    thread_cap: Arc<AtomicUsize>,
+    /// The statistics for each compression level used.
+    level_counters: Arc<LevelCounters>,
+    /// The controller of the compression level, if adaptive compression is enabled.
+    adaptive: Option<Arc<LevelController>>,
+    /// The terminal result of the pool thread, once it has exited.
+    completion: Arc<Completion>,
+    /// The type of the pool's compressor, as writers detached from the pool may only be
+    /// attached to pools with the same compressor.
+    compressor_type: TypeId,
+}
+
+/// The state shared by the threads of a pool, see [`Pool::pool_main`].
+struct PoolContext<W: Write> {
+    /// The number of threads in the pool.
+    num_threads: usize,
+    /// The number of threads dedicated to compression, the rest being dedicated to writing, or
+    /// `None` if every thread does both.
+    compressor_threads: Option<usize>,
+    /// The number of the pool's compression level, if one was set.
+    compression_level_number: Option<u8>,
+    /// The statistics for each compression level used.
+    level_counters: Arc<LevelCounters>,
+    /// The receiving end of the channel for communicating with the compressor pool.
+    compressor_rx: Receiver<CompressorMessage>,
+    /// The receiving end of the channel of blocks to decompress for [`PooledReader`]s.
+    task_rx: Receiver<Task>,
+    /// Where uncompressed block buffers are returned once compressed, to be reused.
+    buffers: BufferRecycler<BytesMut>,
+    /// Where compressed block buffers are returned once written, to be reused.
+    compressed_buffers: BufferRecycler<Vec<u8>>,
+    /// The receive halves of the channels of slots in each writer's queue.
+    writer_rxs: Vec<Receiver<()>>,
+    /// The writers that were exchanged for [`PooledWriter`]s.
+    writers: Vec<Arc<Mutex<Sink<W>>>>,
+    /// The state shared with each writer.
+    writer_states: Vec<Arc<WriterShared>>,
+    /// An optional hook supplying extra header subfields for each block.
+    extra_subfields: Option<ExtraSubfieldHook>,
+    /// The compressors used by writers that don't use the pool's own.
+    compressor_overrides: Arc<Vec<CompressorOverride>>,
+    /// The controller of the compression level, if adaptive compression is enabled.
+    adaptive: Option<Arc<LevelController>>,
+    /// The number of threads that may currently do work, as chosen by the auto-scaler if any.
+    max_active_threads: Arc<AtomicUsize>,
+    /// The limit on the threads doing work set by the application.
+    thread_cap: Arc<AtomicUsize>,
+    /// The scaler of the number of active threads, if auto-scaling is enabled.
+    autoscaler: Option<Arc<ThreadScaler>>,
+    /// Whether blocks that fail to compress are re-queued once.
+    requeue_failed_blocks: bool,
+    /// How many times writing a block is retried if it fails.
+    write_retries: u32,
+    /// Whether each compressed block is verified before it is written.
+    verify_blocks: bool,
+    /// How much work of each kind a thread does in turn.
+    quantum: WorkQuantum,
+    /// How a thread chooses which kind of work to do next.
+    weights: WorkWeights,
+    /// How long a thread waits before retrying a failed write.
+    retry_delay: Duration,
+    /// The clock used for timestamps and for waiting between write retries.
+    clock: Arc<dyn Clock>,
+    /// An optional hook called on each pool thread when it starts.
+    on_thread_start: Option<ThreadStartHook>,
+    /// What to do if a pool thread panics.
+    panic_policy: PanicPolicy,
+    /// Sentinel channel to tell the pool management thread to shutdown.
+    shutdown_rx: Receiver<()>,
+    /// Rung whenever there may be work for an idle thread, or on shutdown.
+    doorbell: Arc<Doorbell>,
+    /// The indices of the writers with blocks that may be ready to be written.
+    write_available_tx: DoorbellSender<usize>,
+    write_available_rx: Receiver<usize>,
+    /// Background work, such as re-compressing small outputs, that is only done by threads that
+    /// are otherwise idle.
+    background_tx: DoorbellSender<Task>,
+    background_rx: Receiver<Task>,
+    /// Blocks that failed to compress and are re-queued to be tried again.
+    retry_tx: DoorbellSender<CompressorMessage>,
+    retry_rx: Receiver<CompressorMessage>,
+}
+
+/// Rings the pool's doorbell when a pool thread exits, however it exits, so that the other
+/// threads check again for shutdown.
+struct RingOnExit<'a>(&'a Doorbell);
+
+impl Drop for RingOnExit<'_> {
+    fn drop(&mut self) {
+        self.0.ring();
+    }
+}
+
+impl<W: Write + Send + 'static> PoolContext<W> {
+    /// Runs the pool thread numbered `thread_idx`, compressing with `compressors`, until
+    /// shutdown is requested and all the channels are empty.
+    fn run_thread<C: Compressor>(
+        &self,
+        thread_idx: usize,
+        mut compressors: ThreadCompressors<C>,
+    ) -> PoolResult<()> {
+        let _abort = (self.panic_policy == PanicPolicy::AbortProcess).then(|| AbortOnPanic);
+        let _ring = RingOnExit(&self.doorbell);
+        if let Some(hook) = &self.on_thread_start {
+            hook();
+        }
+
+        // With dedicated roles the first threads only compress, and the rest only write
+        let compresses = self.compressor_threads.map_or(true, |n| thread_idx < n);
+        let writes = self.compressor_threads.map_or(true, |n| thread_idx >= n);
+        let quantum = WorkQuantum {
+            compressions: if compresses { self.quantum.compressions } else { 0 },
+            writes: if writes { self.quantum.writes } else { 0 },
+            ..self.quantum
+        };
+        let waiters = if compresses { Waiters::Compressors } else { Waiters::Writers };
+
+        loop {
+            compressors.drop_retired();
+            // Noted before looking for work, so that work sent after the channels were found
+            // empty still wakes the thread
+            let rings = self.doorbell.rings();
+            let mut did_something = false;
+            let mut bounced_retry = false;
+
+            // Threads beyond the current limit on active threads only wait for shutdown
+            if compresses && thread_idx >= self.active_threads() {
+                if self.finished() {
+                    break;
+                }
+                self.doorbell.wait(rings, Waiters::Parked);
+                continue;
+            }
+
+            // Take a turn at the kind of work with the largest weighted backlog, moving on to
+            // the next only if there turns out to be none of it
+            let order = self.weights.order(
+                self.compressor_rx.len() + self.retry_rx.len(),
+                self.task_rx.len(),
+                self.write_available_rx.len(),
+            );
+            for kind in order {
+                did_something = match kind {
+                    WorkKind::Compress => self.compress_quantum(
+                        &mut compressors,
+                        thread_idx,
+                        &quantum,
+                        &mut bounced_retry,
+                    )?,
+                    WorkKind::Decompress => self.decompress_quantum(&quantum),
+                    WorkKind::Write => self.write_quantum(&quantum)?,
+                };
+                if did_something {
+                    break;
+                }
+            }
+
+            // If we didn't do anything either block until there may be more work, or if
+            // shutdown is requested and all the channels are empty, terminate.  Background work
+            // is only done when there is nothing else to do.
+            if !did_something {
+                if let Ok(task) = self.background_rx.try_recv() {
+                    task();
+                } else if self.finished() {
+                    break;
+                } else if bounced_retry {
+                    // Ringing for the bounced block would wake this thread straight back up, so
+                    // leave it to another thread for a while
+                    self.clock.sleep(self.retry_delay);
+                } else {
+                    self.doorbell.wait(rings, waiters);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// True if shutdown is requested and all the channels are empty.
+    fn finished(&self) -> bool {
+        self.shutdown_rx.is_disconnected()
+            && self.write_available_rx.is_empty()
+            && self.compressor_rx.is_empty()
+            && self.task_rx.is_empty()
+            && self.background_rx.is_empty()
+            && self.retry_rx.is_empty()
+            && self
+                .writer_rxs
+                .iter()
+                .zip(self.writer_states.iter())
+                .all(|(w, s)| w.is_empty() || s.detached.load(Ordering::Relaxed))
+    }
+
+    /// The number of threads that may do work, within the application's limit.
+    fn active_threads(&self) -> usize {
+        std::cmp::min(
+            self.max_active_threads.load(Ordering::Relaxed),
+            self.thread_cap.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Compresses up to a quantum of blocks, preferring re-queued blocks.  Returns true if there
+    /// were any.
+    fn compress_quantum<C: Compressor>(
+        &self,
+        compressors: &mut ThreadCompressors<C>,
+        thread_idx: usize,
+        quantum: &WorkQuantum,
+        bounced_retry: &mut bool,
+    ) -> PoolResult<bool> {
+        let mut did_something = false;
+        let phase_start = self.clock.now();
+        for n in 0..quantum.compressions {
+            if n > 0 && quantum.expired(self.clock.elapsed(phase_start)) {
+                break;
+            }
+            let message = match self.next_compression(thread_idx, bounced_retry) {
+                Some(message) => message,
+                None => break,
+            };
+
+            // A batch of small blocks is compressed in one task, each as its own block, in place
+            // of the message that stands for the batch
+            let batch = if message.batched {
+                let state = &self.writer_states[message.writer_index];
+                let batch = std::mem::take(&mut *state.batch.lock());
+                if batch.len() > 1 {
+                    state.counters.record_batch(batch.len());
+                }
+                batch
+            } else {
+                Vec::new()
+            };
+            let messages = std::iter::once(message).filter(|m| !m.batched).chain(batch);
+            for message in messages {
+                self.compress_one(compressors, thread_idx, message)?;
+                did_something = true;
+            }
+        }
+        Ok(did_something)
+    }
+
+    /// Takes the next block to compress, preferring re-queued blocks, and lets adaptive
+    /// compression and the auto-scaler observe the compressor queue.  Blocks that failed on this
+    /// thread are left to a different thread, setting `bounced_retry`.
+    fn next_compression(
+        &self,
+        thread_idx: usize,
+        bounced_retry: &mut bool,
+    ) -> Option<CompressorMessage> {
+        let compressing_threads = self.compressor_threads.unwrap_or(self.num_threads);
+        let message = match self.retry_rx.try_recv() {
+            Ok(message)
+                if message.failed_on == Some(thread_idx)
+                    && self.active_threads().min(compressing_threads) > 1 =>
+            {
+                self.retry_tx.send(message);
+                *bounced_retry = true;
+                self.compressor_rx.try_recv().ok()
+            }
+            Ok(message) => Some(message),
+            Err(_) => self.compressor_rx.try_recv().ok(),
+        };
+        let capacity = self.compressor_rx.capacity().unwrap_or(usize::MAX);
+        if let Some(adaptive) = &self.adaptive {
+            adaptive.observe(self.compressor_rx.len(), capacity);
+        }
+        if let Some(autoscaler) = &self.autoscaler {
+            let queued = self.compressor_rx.len();
+            let cap = self.thread_cap.load(Ordering::Relaxed);
+            if autoscaler.observe(queued, capacity, &self.max_active_threads, cap) {
+                self.doorbell.ring();
+            }
+        }
+        message
+    }
+
+    /// Compresses the block in `message` for its writer and queues it to be written, or passes
+    /// on a control message to the writer without compressing.  A block that fails to compress
+    /// is re-queued to be tried once more if [`PoolBuilder::requeue_failed_blocks`] is set.
+    fn compress_one<C: Compressor>(
+        &self,
+        compressors: &mut ThreadCompressors<C>,
+        thread_idx: usize,
+        mut message: CompressorMessage,
+    ) -> PoolResult<()> {
+        let state = &self.writer_states[message.writer_index];
+        if let Some(control) = message.control.take() {
+            state.reorder.lock().insert(WriterMessage {
+                buffer: vec![],
+                sequence: message.sequence,
+                raw: None,
+                compressed_at: self.clock.now(),
+                is_last: false,
+                uncompressed_len: 0,
+                flush: false,
+                block_number: message.block_number,
+                checksum: None,
+                control: Some(control),
+            });
+            self.write_available_tx.send(message.writer_index);
+            return Ok(());
+        }
+
+        // Compress will correctly resize the compressed vec.
+        let chunk = &message.buffer;
+        let mut compressed = self.compressed_buffers.take(0);
+        let start = self.clock.now();
+        let target = self.compression_target(&message, state);
+        let mut subfields = match (&self.extra_subfields, message.encoding) {
+            (Some(hook), SmallOutputPolicy::Compress) => Some(hook(message.writer_index, chunk)),
+            _ => None,
+        };
+        if !message.subfields.is_empty() && message.encoding != SmallOutputPolicy::Uncompressed {
+            subfields.get_or_insert_with(Vec::new).extend(message.subfields.iter().cloned());
+        }
+        let stream = state.stream.as_ref();
+        let result = match (target, stream) {
+            (Some((override_index, level)), None) => {
+                compressors.get(override_index, level).compress_block(
+                    chunk,
+                    &mut compressed,
+                    message.is_last && !message.omit_eof,
+                    subfields.as_deref(),
+                    self.verify_blocks,
+                )
+            }
+            // A stateful compressor is used for one stream of one writer
+            (Some((override_index, level)), Some(stream)) => {
+                stream.in_order(message.block_number, |slot| {
+                    let result = slot
+                        .get_or_insert_with(|| compressors.create(override_index, level))
+                        .compress_block(
+                            chunk,
+                            &mut compressed,
+                            message.is_last,
+                            subfields.as_deref(),
+                            self.verify_blocks,
+                        );
+                    if message.is_last {
+                        *slot = None;
+                    }
+                    result
+                })
+            }
+            (None, stream) => {
+                compressed.extend_from_slice(chunk);
+                match stream {
+                    Some(stream) => stream.in_order(message.block_number, |_| Ok(())),
+                    None => Ok(()),
+                }
+            }
+        };
+
+        match result {
+            // Blocks of a stateful stream can't be retried out of order
+            Err(_)
+                if self.requeue_failed_blocks
+                    && message.failed_on.is_none()
+                    && stream.is_none() =>
+            {
+                // Quarantine this thread's compressor, which may be in a bad state, and re-queue
+                // the block to be tried once more
+                if let Some((override_index, level)) = target {
+                    compressors.reset(override_index, level);
+                }
+                state.counters.record_requeue();
+                message.failed_on = Some(thread_idx);
+                self.retry_tx.send(message);
+            }
+            Err(e) => return Err(e),
+            Ok(()) => {
+                // Statistics are only kept for the pool's compressor type
+                let level = target.and_then(|(override_index, level)| match override_index {
+                    Some(i) => {
+                        self.compressor_overrides[i].stats_level.map(|default| level.or(default))
+                    }
+                    None => Some(level.or(self.compression_level_number)),
+                });
+                if let Some(level) = level {
+                    self.level_counters.record(
+                        level,
+                        chunk.len(),
+                        compressed.len(),
+                        self.clock.elapsed(start),
+                    );
+                }
+                if let Some(tuner) = &message.tuner {
+                    tuner.record(chunk.len(), compressed.len(), self.clock.elapsed(start));
+                }
+                let checksum = state.block_checksums.as_ref().map(|c| (c.checksum)(&compressed));
+                // The uncompressed bytes are passed on if needed, otherwise their buffer is
+                // recycled
+                let uncompressed_len = message.buffer.len();
+                let buffer = std::mem::take(&mut message.buffer);
+                let raw = if state.needs_raw {
+                    Some(buffer.freeze())
+                } else {
+                    self.buffers.recycle(buffer);
+                    None
+                };
+                state.reorder.lock().insert(WriterMessage {
+                    buffer: compressed,
+                    sequence: message.sequence,
+                    raw,
+                    compressed_at: self.clock.now(),
+                    is_last: message.is_last,
+                    uncompressed_len,
+                    flush: message.flush,
+                    block_number: message.block_number,
+                    checksum,
+                    control: None,
+                });
+                self.write_available_tx.send(message.writer_index);
+            }
+        }
+        Ok(())
+    }
+
+    /// The compressor override, if any, and level to compress the block in `message` with, or
+    /// `None` if it is written uncompressed.
+    fn compression_target(
+        &self,
+        message: &CompressorMessage,
+        state: &WriterShared,
+    ) -> Option<(Option<usize>, Option<u8>)> {
+        match message.encoding {
+            SmallOutputPolicy::Compress => Some(match &self.adaptive {
+                Some(adaptive)
+                    if message.level.is_none()
+                        && state.compressor.is_none()
+                        && state.stream.is_none() =>
+                {
+                    (None, Some(adaptive.level()))
+                }
+                _ => (state.compressor, message.level),
+            }),
+            SmallOutputPolicy::Uncompressed => None,
+            SmallOutputPolicy::CompressionLevel(level) => Some((None, Some(level))),
+        }
+    }
+
+    /// Decompresses up to a quantum of blocks for pooled readers.  Returns true if there were
+    /// any.
+    fn decompress_quantum(&self, quantum: &WorkQuantum) -> bool {
+        let mut did_something = false;
+        for _ in 0..quantum.compressions {
+            match self.task_rx.try_recv() {
+                Ok(task) => task(),
+                Err(_) => break,
+            }
+            did_something = true;
+        }
+        did_something
+    }
+
+    /// Writes the ready blocks of up to a quantum of writers.  Returns true if there were any.
+    fn write_quantum(&self, quantum: &WorkQuantum) -> PoolResult<bool> {
+        let mut did_something = false;
+        let phase_start = self.clock.now();
+        for n in 0..quantum.writes {
+            if n > 0 && quantum.expired(self.clock.elapsed(phase_start)) {
+                break;
+            }
+            let writer_index = match self.write_available_rx.try_recv() {
+                Ok(writer_index) => writer_index,
+                Err(_) => break,
+            };
+            did_something |= self.write_ready(writer_index)?;
+        }
+        Ok(did_something)
+    }
+
+    /// Writes every block of the writer at `writer_index` that is ready, unless another thread
+    /// holds the writer, in which case that thread writes them once it is done.  Returns true if
+    /// there were any.
+    fn write_ready(&self, writer_index: usize) -> PoolResult<bool> {
+        let state = &self.writer_states[writer_index];
+        let mut did_something = false;
+        state.write_requested.store(true, Ordering::SeqCst);
+        loop {
+            // The blocks of a quiesced writer are written once its guard is dropped
+            if state.quiesced.load(Ordering::SeqCst) {
+                break;
+            }
+            let mut writer = match self.writers[writer_index].try_lock() {
+                Some(writer) => writer,
+                None => break,
+            };
+            state.write_requested.store(false, Ordering::SeqCst);
+            did_something |= self.write_blocks(&mut writer, writer_index)?;
+            drop(writer);
+            if !state.write_requested.load(Ordering::SeqCst) {
+                break;
+            }
+        }
+        Ok(did_something)
+    }
+
+    /// Writes every block of the writer at `writer_index` that is ready while its lock is held.
+    /// Blocks compressed out of order wait for those before them, and are written by whichever
+    /// thread writes the block before them.  Returns true if there were any.
+    fn write_blocks(&self, writer: &mut Sink<W>, writer_index: usize) -> PoolResult<bool> {
+        let state = &self.writer_states[writer_index];
+        // The blocks of a writer being detached are left for it to take
+        if state.detached.load(Ordering::Relaxed) {
+            state.notify_written();
+            return Ok(false);
+        }
+
+        let mut did_something = false;
+        loop {
+            let next = state.reorder.lock().pop_next();
+            let message = match next {
+                Some(message) => message,
+                None => break,
+            };
+            did_something = true;
+            // Free the message's slot in the writer's queue
+            let _ = self.writer_rxs[writer_index].try_recv();
+            if let Some(control) = &message.control {
+                writer.control(control);
+                continue;
+            }
+            // Every block of the writer has been compressed once its last is ready, so a
+            // compressor used only by the writer is retired, waking the idle threads to drop theirs
+            let overrides = &self.compressor_overrides;
+            let retirement = state.compressor.and_then(|i| overrides[i].retirement.as_ref());
+            if let Some(retirement) = retirement.filter(|_| message.is_last) {
+                retirement.retire();
+                self.doorbell.ring();
+            }
+            state.counters.record_reorder_wait(self.clock.elapsed(message.compressed_at));
+            if writer.exceeds_size_limit(&message) {
+                state.size_exceeded.store(true, Ordering::Relaxed);
+            }
+            // The blocks of a writer over its size limit are dropped
+            if !state.size_exceeded.load(Ordering::Relaxed) {
+                self.write_with_retries(writer, writer_index, &message)?;
+                state.counters.record_write(message.buffer.len(), message.uncompressed_len);
+                if let Some(offsets) = &state.offsets {
+                    offsets.record_block(message.buffer.len());
+                }
+                if let (Some(checksums), Some(checksum)) =
+                    (&state.block_checksums, message.checksum)
+                {
+                    checksums.manifest.lock().blocks.push(BlockRecord {
+                        compressed_len: message.buffer.len(),
+                        uncompressed_len: message.uncompressed_len,
+                        checksum,
+                    });
+                }
+            }
+            if let Some(blocks) = &state.in_flight {
+                blocks.release(1);
+            }
+            if let Some(bytes) = &state.in_flight_bytes {
+                bytes.release(message.uncompressed_len);
+            }
+            self.compressed_buffers.recycle(message.buffer);
+            state.notify_written();
+            let recompression =
+                writer.take_recompression().map_err(|e| state.label_error(e.into()))?;
+            if let Some(task) = recompression {
+                let _ = self.background_tx.send(task);
+            }
+        }
+        Ok(did_something)
+    }
+
+    /// Writes the block in `message` to `writer`, the writer at `writer_index`, retrying up to
+    /// [`PoolBuilder::retry_failed_writes`] times if it fails, and then reopening the writer if
+    /// it has a hook to do so, see [`PoolBuilder::reopen_failed_writers`].
+    fn write_with_retries(
+        &self,
+        writer: &mut Sink<W>,
+        writer_index: usize,
+        message: &WriterMessage,
+    ) -> PoolResult<()> {
+        let state = &self.writer_states[writer_index];
+        let mut attempt = 0;
+        while let Err(e) = writer.write_block(message) {
+            if attempt == self.write_retries {
+                match writer.reopen(writer_index, &e) {
+                    Ok(true) => {
+                        state.counters.record_reopen();
+                        attempt = 0;
+                        continue;
+                    }
+                    Ok(false) => return Err(state.label_error(e.into())),
+                    Err(e) => return Err(state.label_error(e.into())),
+                }
+            }
+            attempt += 1;
+            state.counters.record_write_retry();
+            self.clock.sleep(self.retry_delay);
+        }
+        Ok(())
+    }
+}
+
+impl Pool {
+    /// The main "run" method for the pool that orchestrates all the pieces.
+    ///
+    /// The [`PooledWriter`]s are sending to the compressor, the compressor compresses them, then forwards the compressed bytes.
+    /// The bytes are forwarded to a queue per writer and the writer threads are iterating over that queue pulling down
+    /// all values in the queue at once and writing till the queue is empty.
+    ///
+    /// # Arguments
+    /// - `context` - The state shared by the pool's threads.
+    /// - `compression_level` - The compression level to use for the [`Compressor`] pool.
+    /// - `dictionary` - An optional pre-trained dictionary used by every compressor.
+    /// - `gzip_header` - An optional gzip member header written by every compressor.
+    /// - `spawner` - How the pool threads are spawned.
+    #[allow(clippy::unnecessary_wraps, clippy::needless_collect, clippy::needless_pass_by_value)]
+    fn pool_main<W, C>(
+        context: PoolContext<W>,
+        compression_level: C::CompressionLevel,
+        dictionary: Option<Arc<Vec<u8>>>,
+        gzip_header: Option<Arc<GzipHeader>>,
+        spawner: Spawner,
+    ) -> PoolResult<()>
+    where
+        W: Write + Send + 'static,
+        C: Compressor,
+    {
+        let context = Arc::new(context);
+
+        // The blocks carried over by writers attached from another pool are ready to be written
+        for (index, state) in context.writer_states.iter().enumerate() {
+            if !state.reorder.lock().slots.is_empty() {
+                context.write_available_tx.send(index);
+            }
+        }
+
+        let thread_handles: Vec<io::Result<JoinHandle<PoolResult<()>>>> = (0..context.num_threads)
+            .map(|thread_idx| {
+                let compressors = ThreadCompressors::<C>::new(
+                    compression_level.clone(),
+                    dictionary.clone(),
+                    gzip_header.clone(),
+                    context.compressor_overrides.clone(),
+                );
+                let context = context.clone();
+                spawner.spawn(&thread_idx.to_string(), move || {
+                    context.run_thread(thread_idx, compressors)
+                })
+            })
+            .collect();
+
+        // Close writer handles, keeping the first error from any thread, including any that
+        // couldn't be spawned
+        let result = thread_handles.into_iter().fold(Ok(()), |result, handle| {
+            let thread_result = match handle.map(JoinHandle::join) {
+                Ok(Ok(thread_result)) => thread_result,
+                Ok(Err(e)) => Err(context.panic_policy.handle(e)),
+                Err(e) => Err(PoolError::Io(e)),
+            };
+            result.and(thread_result)
+        });
+
+        // Wake anything waiting on offsets, on the limits on what is in flight, or quiescing
+        // for blocks that will now never be written
+        let writer_states = &context.writer_states;
+        writer_states.iter().filter_map(|s| s.offsets.as_ref()).for_each(|o| o.close());
+        for state in writer_states {
+            state.stopped.store(true, Ordering::Relaxed);
+            state.notify_written();
+            state.in_flight.iter().chain(state.in_flight_bytes.as_deref()).for_each(|l| l.close());
+        }
+
+        // Flush each writer
+        let flushed = context.writers.iter().try_for_each(|w| w.lock().flush());
+
+        // Report the first writer whose output exceeded its size limit
+        let sizes = writer_states
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, state)| state.check_output_size(index));
+
+        result.and(flushed.map_err(PoolError::from)).and(sizes)
+    }
+
+    /// Returns a snapshot of the statistics for all writers in the pool.  May be called at any
+    /// time, including after the pool has been stopped.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            writers: self
+                .writer_states
+                .iter()
+                .enumerate()
+                .map(|(index, state)| {
+                    let id = state.id.lock().as_ref().map(|id| id.to_string());
+                    state.counters.snapshot(index, id)
+                })
+                .collect(),
+            levels: self.level_counters.snapshot(),
+        }
+    }
+
+    /// The checksums of the blocks written so far for the writer at `writer_index`, or `None`
+    /// if block checksums are not enabled with `PoolBuilder::block_checksums` (behind the
+    /// `block_checksums` feature).  The manifest is complete once the pool has been stopped.
+    pub fn block_manifest(&self, writer_index: usize) -> Option<BlockManifest> {
+        let checksums = self.writer_states.get(writer_index)?.block_checksums.as_ref()?;
+        let manifest = checksums.manifest.lock().clone();
+        Some(manifest)
+    }
+
+    /// A handle that resolves to the terminal result of the pool once its management thread
+    /// exits for any reason, for supervisors that need to know when the pool terminates without
+    /// owning it.  See [`CompletionHandle`].
+    pub fn completion_handle(&self) -> CompletionHandle {
+        CompletionHandle::new(self.completion.clone())
+    }
+
+    /// The compression level currently chosen by adaptive compression, or `None` if adaptive
+    /// compression is not enabled, see [`PoolBuilder::adaptive_compression`].
+    pub fn adaptive_compression_level(&self) -> Option<u8> {
+        self.adaptive.as_ref().map(|a| a.level())
+    }
+
+    /// The number of threads in the pool.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// The index of the writer given the stable ID `id` with [`PoolBuilder::set_writer_id`],
+    /// e.g. to find its entry in [`Pool::stats`], or `None` if no writer has that ID.
+    pub fn writer_index(&self, id: &str) -> Option<usize> {
+        self.writer_states.iter().position(|s| s.id.lock().as_deref() == Some(id))
+    }
+
+    /// Waits until every block sent by the writer at `writer_index` has been written, then
+    /// returns exclusive access to its underlying writer, of type `W`, until the guard is
+    /// dropped, e.g. to patch the output or to sync it to disk mid-stream.  Blocks sent by the
+    /// [`PooledWriter`] while the guard is held are written once it is dropped, so bytes still
+    /// buffered by the pooled writer should be sent first with [`PooledWriter::flush_partial`].
+    /// May also be called once the pool has been stopped, e.g. to inspect the finished output.
+    ///
+    /// Returns [`PoolError::UnknownWriter`] if there is no such writer, or its writer is not a
+    /// `W`, and [`PoolError::ChannelSend`] if the pool stops before the blocks are written.
+    pub fn quiesce_writer<W: Write + Send + 'static>(
+        &self,
+        writer_index: usize,
+    ) -> PoolResult<QuiesceGuard<'_, W>> {
+        let sink = self
+            .sinks
+            .get(writer_index)
+            .and_then(|sink| sink.downcast_ref::<Mutex<Sink<W>>>())
+            .ok_or(PoolError::UnknownWriter(writer_index))?;
+        let state = &self.writer_states[writer_index];
+        state.wait_for_written(writer_index, state.counters.blocks_sent())?;
+        let writer = MutexGuard::try_map(sink.lock(), |sink| sink.writer.as_mut())
+            .map_err(|_| PoolError::UnknownWriter(writer_index))?;
+        state.quiesced.store(true, Ordering::SeqCst);
+        Ok(QuiesceGuard {
+            writer: Some(writer),
+            state,
+            writer_index,
+            write_available: self.write_available_tx.as_ref(),
+        })
+    }
+
+    /// The block size of the pool's compressor, i.e. [`Compressor::BLOCK_SIZE`] unless set with
+    /// [`PoolBuilder::block_size`].  Individual writers may use other block sizes if block size
+    /// tuning is enabled, see [`PooledWriter::block_size`].
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Temporarily restricts how many of the pool's threads may do work concurrently, e.g. while
+    /// the embedding application goes through its own CPU heavy phase.  The remaining threads
+    /// idle until the limit is raised again.  Values larger than the number of threads in the
+    /// pool are treated as the number of threads.  If the pool has dedicated writer threads, see
+    /// [`PoolBuilder::writer_threads`], the limit applies to the compressor threads only.  With
+    /// [`PoolBuilder::autoscale`] the pool scales the number of threads at work within the
+    /// limit.
+    ///
+    /// Will panic if set to 0.
+    pub fn set_max_active_threads(&self, threads: usize) {
+        assert!(threads > 0, "Must allow at least one active thread.");
+        self.thread_cap.store(std::cmp::min(threads, self.threads), Ordering::Relaxed);
+        self.doorbell.ring();
+    }
+
+    /// The number of threads that may currently do work concurrently.
+    pub fn max_active_threads(&self) -> usize {
+        std::cmp::min(
+            self.max_active_threads.load(Ordering::Relaxed),
+            self.thread_cap.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Shutdown all pool resources and close all channels.
+    ///
+    /// Ideally the [`PooledWriter`]s should all have been flushed first, that is up to the user. Any
+    /// further attempts to send to the [`Pool`] will return an error.
+    pub fn stop_pool(&mut self) -> Result<(), PoolError> {
+        // The pool's threads only exit once every queue is empty, so there is no need to wait for
+        // the compressor queue to drain: everything already sent is compressed and written
+        drop(self.compressor_tx.take());
+
+        // Shutdown called to force writers to start checking their receivers for disconnection / empty
+        drop(self.shutdown_tx.take());
+
+        // Block until the pool thread finishes and pull any errors from it
+        self.join_pool_thread()
+    }
+
+    /// Waits for the pool thread, if one was started, to finish and returns its result.
+    fn join_pool_thread(&mut self) -> PoolResult<()> {
+        match self.pool_handle.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => std::panic::resume_unwind(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Shutdown all pool resources and close all channels as with [`Pool::stop_pool`], calling
+    /// `progress` with the pool's statistics roughly every `interval` while waiting, and once
+    /// more when done.  [`PoolStats::remaining_bytes`] and [`PoolStats::remaining_blocks`] give
+    /// an estimate of the work remaining, e.g. so that a CLI can report how much is left to
+    /// finalize instead of appearing hung.
+    pub fn stop_pool_with_progress<F>(
+        &mut self,
+        interval: Duration,
+        mut progress: F,
+    ) -> Result<(), PoolError>
+    where
+        F: FnMut(&PoolStats),
+    {
+        drop(self.compressor_tx.take());
+        drop(self.shutdown_tx.take());
+
+        // Block until the pool thread finishes, waking only to report progress
+        progress(&self.stats());
+        while let Err(channel::RecvTimeoutError::Timeout) = self.done_rx.recv_timeout(interval) {
+            progress(&self.stats());
+        }
+        progress(&self.stats());
+
+        self.join_pool_thread()
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // Check if `stop_pool` has already been called. If it hasn't, call it.
+        if self.compressor_tx.is_some() && self.pool_handle.is_some() {
+            self.stop_pool().unwrap();
+        }
+    }
+}
+
+/// Exclusive access to the underlying writer of a [`PooledWriter`], returned by
+/// [`Pool::quiesce_writer`] once all the blocks sent so far have been written.  The pool's
+/// threads write no more blocks to the writer until the guard is dropped, without waiting for
+/// it: they leave the writer's blocks for a thread to write once the guard is dropped.
+pub struct QuiesceGuard<'a, W> {
+    /// Only `None` while being dropped, so that the writer is released before the pool's
+    /// threads are told to write its blocks.
+    writer: Option<MappedMutexGuard<'a, W>>,
+    state: &'a WriterShared,
+    writer_index: usize,
+    write_available: Option<&'a DoorbellSender<usize>>,
+}
+
+impl<W> Deref for QuiesceGuard<'_, W> {
+    type Target = W;
+
+    fn deref(&self) -> &W {
+        self.writer.as_ref().expect("Unreachable")
+    }
+}
+
+impl<W> DerefMut for QuiesceGuard<'_, W> {
+    fn deref_mut(&mut self) -> &mut W {
+        self.writer.as_mut().expect("Unreachable")
+    }
+}
+
+impl<W> Drop for QuiesceGuard<'_, W> {
+    fn drop(&mut self) {
+        self.state.quiesced.store(false, Ordering::SeqCst);
+        drop(self.writer.take());
+        if let Some(write_available) = self.write_available {
+            let _ = write_available.send(self.writer_index);
+        }
+    }
+}
+
+impl<W> std::fmt::Debug for QuiesceGuard<'_, W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QuiesceGuard").finish()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Convenience functions
+////////////////////////////////////////////////////////////////////////////////
+
+/// Compresses each input file in `paths` to its paired output file using a single pool of
+/// `threads` threads and the given compression level, returning once every output is complete.
+///
+/// This covers the common case of compressing whole files in one call; use a [`PoolBuilder`]
+/// directly for anything more involved.  Outputs are all created up front, while the inputs are
+/// opened and read one at a time.
+///
+/// ```rust,no_run
+/// use pooled_writer::{bgzf::BgzfCompressor, compress_files};
+///
+/// compress_files::<BgzfCompressor, _, _>([("reads.sam", "reads.sam.gz")], 8, 5)?;
+/// # Ok::<(), pooled_writer::PoolError>(())
+/// ```
+///
+/// Will panic if `threads` is zero.
+pub fn compress_files<C, I, P>(paths: I, threads: usize, compression_level: u8) -> PoolResult<()>
+where
+    C: Compressor,
+    I: IntoIterator<Item = (P, P)>,
+    P: AsRef<Path>,
+{
+    let mut builder =
+        PoolBuilder::<_, C>::new().threads(threads).compression_level(compression_level)?;
+    let mut jobs = vec![];
+    for (input, output) in paths {
+        let output = io::BufWriter::new(File::create(output)?);
+        jobs.push((input, builder.exchange(output)));
+    }
+    let mut pool = builder.build()?;
+
+    let result = jobs.into_iter().try_for_each(|(input, mut writer)| -> PoolResult<()> {
+        let mut reader = io::BufReader::new(File::open(input)?);
+        io::copy(&mut reader, &mut writer)?;
+        Ok(writer.close()?)
+    });
+    let stopped = pool.stop_pool();
+    result.and(stopped)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use std::{
+        assert_eq, format,
+        fs::File,
+        io::{BufReader, BufWriter},
+        path::{Path, PathBuf},
+        vec,
+    };
+
+    use crate::bgzf::BgzfCompressor;
+
+    use super::*;
+    use ::bgzf::Reader;
+    use proptest::prelude::*;
+    use tempfile::tempdir;
+
+    fn create_output_writer<P: AsRef<Path>>(path: P) -> BufWriter<File> {
+        BufWriter::new(File::create(path).unwrap())
+    }
+
+    fn create_output_file_name(name: impl AsRef<Path>, dir: impl AsRef<Path>) -> PathBuf {
+        let path = dir.as_ref().to_path_buf();
+        path.join(name)
+    }
+
+    #[test]
+    fn test_simple() {
+        let dir = tempdir().unwrap();
+        let output_names: Vec<PathBuf> = (0..20)
+            .into_iter()
+            .map(|i| create_output_file_name(format!("test.{}.txt.gz", i), &dir.path()))
+            .collect();
+
+        let output_writers: Vec<BufWriter<File>> =
+            output_names.iter().map(create_output_writer).collect();
+        let mut builder =
+            PoolBuilder::<_, BgzfCompressor>::new().threads(8).compression_level(2).unwrap();
+        let mut pooled_writers: Vec<PooledWriter> =
+            output_writers.into_iter().map(|w| builder.exchange(w)).collect();
+        let mut pool = builder.build().unwrap();
+
+        for (i, writer) in pooled_writers.iter_mut().enumerate() {
+            writer.write_all(format!("This is writer {}.", i).as_bytes()).unwrap();
+        }
+        pooled_writers.into_iter().try_for_each(|mut w| w.flush()).unwrap();
+        pool.stop_pool();
+
+        for (i, path) in output_names.iter().enumerate() {
+            let mut reader = Reader::new(BufReader::new(File::open(path).unwrap()));
+            let mut actual = vec![];
+            reader.read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, format!("This is writer {}.", i).as_bytes());
+        }
+    }
+
+    proptest! {
+        // This test takes around 20 minutes on a 32 core machine to run but is very comprehensive.
+        // Run with `cargo test -- --ignored`
+        #[ignore]
+        #[test]
+        fn test_complete(
+            input_size in 1..=BUFSIZE * 4,
+            buf_size in 1..=BUFSIZE,
+            num_output_files in 1..2*num_cpus::get(),
+            threads in 1..=2+num_cpus::get(),
+            comp_level in 1..=8_u8,
+            write_size in 1..=2*BUFSIZE,
+        ) {
+            let dir = tempdir().unwrap();
+            let output_names: Vec<PathBuf> = (0..num_output_files)
+                .into_iter()
+                .map(|i| create_output_file_name(format!("test.{}.txt.gz", i), &dir.path()))
+                .collect();
+            let output_writers: Vec<_> = output_names.iter().map(create_output_writer).collect();
+
+            let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+                .threads(threads)
+                .compression_level(comp_level)?;
+
+            let mut pooled_writers: Vec<_> = output_writers.into_iter().map(|w| builder.exchange(w)).collect();
+            let mut pool = builder.build()?;
+
+            let inputs: Vec<Vec<u8>> = (0..num_output_files).map(|_| {
+                (0..input_size).map(|_| rand::random::<u8>()).collect()
+            }).collect();
+
+            let chunks = (input_size as f64 / write_size as f64).ceil() as usize;
+
+            // write a chunk to each writer (could randomly select the writers?)
+            for i in (0..chunks) {
+                for (j, writer) in pooled_writers.iter_mut().enumerate() {
+                    let input = &inputs[j];
+                    let bytes = &input[write_size * i..std::cmp::min(write_size * (i + 1), input.len())];
+                    writer.write_all(bytes).unwrap()
+                }
+            }
+
+            pooled_writers.into_iter().try_for_each(|mut w| w.flush()).unwrap();
+            pool.stop_pool();
+
+            for (i, path) in output_names.iter().enumerate() {
+                let mut reader = Reader::new(BufReader::new(File::open(path).unwrap()));
+                let mut actual = vec![];
+                reader.read_to_end(&mut actual).unwrap();
+                assert_eq!(actual, inputs[i]);
+            }
+
+        }
+    }
+
+    fn bgzf_eof() -> Vec<u8> {
+        let mut eof = vec![];
+        ::bgzf::Compressor::append_eof(&mut eof);
+        eof
+    }
+
+    #[test]
+    fn test_drop_policy() {
+        let dir = tempdir().unwrap();
+        let finalized = create_output_file_name("finalized.txt.gz", &dir.path());
+        let partial = create_output_file_name("partial.txt.gz", &dir.path());
+
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut finalized_writer = builder.exchange(create_output_writer(&finalized));
+        let mut builder = builder.drop_policy(DropPolicy::FlushPartial);
+        let mut partial_writer = builder.exchange(create_output_writer(&partial));
+        let mut pool = builder.build().unwrap();
+
+        finalized_writer.write_all(b"finalized").unwrap();
+        partial_writer.write_all(b"partial").unwrap();
+        finalized_writer.finalize().unwrap();
+        drop(partial_writer);
+        pool.stop_pool().unwrap();
+
+        let eof = bgzf_eof();
+        let finalized_bytes = std::fs::read(&finalized).unwrap();
+        let partial_bytes = std::fs::read(&partial).unwrap();
+        assert!(finalized_bytes.ends_with(&eof));
+        assert!(!finalized_bytes.ends_with(&[eof.clone(), eof.clone()].concat()));
+        assert!(!partial_bytes.ends_with(&eof));
+
+        let mut actual = vec![];
+        Reader::new(&partial_bytes[..]).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, b"partial");
+    }
+
+    #[test]
+    fn test_partial_blocks_reported_in_stats() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("partial.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        writer.write_all(&vec![b'A'; BgzfCompressor::BLOCK_SIZE]).unwrap();
+        for _ in 0..3 {
+            writer.write_all(b"a few bytes").unwrap();
+            writer.flush_partial().unwrap();
+        }
+        writer.write_all(b"the end").unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let stats = pool.stats();
+        let writer_stats = &stats.writers[0];
+        assert_eq!(writer_stats.blocks, 5);
+        assert_eq!(writer_stats.partial_blocks, 3);
+        assert_eq!(
+            writer_stats.uncompressed_bytes as usize,
+            BgzfCompressor::BLOCK_SIZE + 3 * "a few bytes".len() + "the end".len()
+        );
+        assert_eq!(writer_stats.compressed_bytes, std::fs::metadata(&path).unwrap().len());
+        assert_eq!(writer_stats.reorder_wait.count, 5);
+        assert!(writer_stats.reorder_wait.p50 <= writer_stats.reorder_wait.p99);
+        assert!(writer_stats.reorder_wait.p99 <= writer_stats.reorder_wait.max);
+        assert_eq!(stats.partial_blocks(), 3);
+    }
+
+    #[test]
+    fn test_per_level_stats() {
+        let dir = tempdir().unwrap();
+        let small = create_output_file_name("small.txt.gz", &dir.path());
+        let large = create_output_file_name("large.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+            .threads(2)
+            .compression_level(9)
+            .unwrap()
+            .small_output_bypass(1024, SmallOutputPolicy::CompressionLevel(1))
+            .unwrap();
+        let mut small_writer = builder.exchange(create_output_writer(&small));
+        let mut large_writer = builder.exchange(create_output_writer(&large));
+        let mut pool = builder.build().unwrap();
+
+        small_writer.write_all(b"a tiny summary").unwrap();
+        large_writer.write_all(&vec![b'A'; 3 * BgzfCompressor::BLOCK_SIZE]).unwrap();
+        small_writer.close().unwrap();
+        large_writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let stats = pool.stats();
+        assert_eq!(stats.levels.iter().map(|l| l.level).collect::<Vec<_>>(), [Some(1), Some(9)]);
+        let fast = stats.level(Some(1)).unwrap();
+        assert_eq!((fast.blocks, fast.uncompressed_bytes), (1, 14));
+        let best = stats.level(Some(9)).unwrap();
+        // Including the final, empty, block that carries the EOF marker
+        assert_eq!(best.blocks, 4);
+        assert_eq!(best.uncompressed_bytes, 3 * BgzfCompressor::BLOCK_SIZE as u64);
+        assert_eq!(best.compressed_bytes, std::fs::metadata(&large).unwrap().len());
+        assert!(best.compression_ratio() > 0.0);
+        assert!(best.throughput_mb_per_sec() >= 0.0);
+        assert!(stats.level(None).is_none());
+    }
+
+    #[test]
+    fn test_block_size_tuning() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("tuned.txt.gz", &dir.path());
+        let candidates = vec![4096, 8192, 16384];
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+            .threads(2)
+            .block_size_tuning(BlockSizeTuning::new(candidates.clone()).trials_per_size(2))
+            .unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        let input: Vec<u8> = (0..1_000_000).map(|i| (i % 251) as u8).collect();
+        for chunk in input.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        assert!(candidates.contains(&pool.stats().writers[0].block_size));
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, input);
+    }
+
+    #[test]
+    fn test_block_size_tuning_rejects_oversized_candidates() {
+        let result = PoolBuilder::<BufWriter<File>, BgzfCompressor>::new()
+            .block_size_tuning(BlockSizeTuning::new(vec![BgzfCompressor::BLOCK_SIZE + 1]));
+        assert!(matches!(result, Err(PoolError::UnsupportedOption(_))));
+    }
+
+    #[test]
+    fn test_small_output_bypass() {
+        let dir = tempdir().unwrap();
+        let small = create_output_file_name("small.txt.gz", &dir.path());
+        let large = create_output_file_name("large.txt.gz", &dir.path());
+        let bypassed = Arc::new(Mutex::new(vec![]));
+        let hook_bypassed = bypassed.clone();
+
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+            .threads(2)
+            .small_output_bypass(1024, SmallOutputPolicy::Uncompressed)
+            .unwrap()
+            .on_small_output(move |index, policy| hook_bypassed.lock().push((index, policy)));
+        let mut small_writer = builder.exchange(create_output_writer(&small));
+        let mut large_writer = builder.exchange(create_output_writer(&large));
+        let mut pool = builder.build().unwrap();
+
+        small_writer.write_all(b"a tiny summary").unwrap();
+        large_writer.write_all(&vec![b'A'; 2048]).unwrap();
+        small_writer.close().unwrap();
+        large_writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        assert_eq!(std::fs::read(&small).unwrap(), b"a tiny summary");
+        assert_eq!(*bypassed.lock(), vec![(0, SmallOutputPolicy::Uncompressed)]);
+        let mut actual = vec![];
+        Reader::new(File::open(&large).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, vec![b'A'; 2048]);
+    }
+
+    #[test]
+    fn test_extra_subfields() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("extra.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+            .threads(2)
+            .extra_subfields(|index, block| {
+                vec![ExtraSubfield::new(b'X', b'Y', vec![index as u8, block.len() as u8])]
+            })
+            .unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        // XLEN covers the BC subfield plus the six byte XY subfield
+        assert_eq!(u16::from_le_bytes([bytes[10], bytes[11]]), 12);
+        assert_eq!(&bytes[18..24], &[b'X', b'Y', 2, 0, 0, 5]);
+        let block_size = u16::from_le_bytes([bytes[16], bytes[17]]) as usize + 1;
+        assert_eq!(&bytes[block_size..], &bgzf_eof()[..]);
+
+        let mut actual = vec![];
+        Reader::new(&bytes[..]).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, b"hello");
+    }
+
+    #[test]
+    fn test_add_block_subfield() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("block_subfield.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2).block_size(4).unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+        writer.add_block_subfield(ExtraSubfield::new(b'R', b'C', vec![2])).unwrap();
+        writer.write_all(b"abcdefgh").unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        // Only the first block carries the five byte RC subfield
+        assert_eq!(u16::from_le_bytes([bytes[10], bytes[11]]), 11);
+        assert_eq!(&bytes[18..23], &[b'R', b'C', 1, 0, 2]);
+        let first = u16::from_le_bytes([bytes[16], bytes[17]]) as usize + 1;
+        assert_eq!(u16::from_le_bytes([bytes[first + 10], bytes[first + 11]]), 6);
+
+        let mut actual = vec![];
+        Reader::new(&bytes[..]).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, b"abcdefgh");
+    }
+
+    #[test]
+    fn test_virtual_offsets() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("offsets.txt.gz", &dir.path());
+        let mut builder =
+            PoolBuilder::<_, BgzfCompressor>::new().threads(2).virtual_offsets(true).unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        let record = vec![b'R'; 1000];
+        let mut offsets = vec![];
+        for _ in 0..200 {
+            offsets.push(writer.virtual_offset().unwrap());
+            writer.write_all(&record).unwrap();
+        }
+        offsets.push(writer.virtual_offset().unwrap());
+        let block_offsets = writer.shared.offsets.clone().unwrap();
+        writer.close().unwrap();
+
+        let resolved: Vec<u64> = offsets.iter().map(|o| o.wait().unwrap()).collect();
+        pool.stop_pool().unwrap();
+
+        // Every record lies at the expected position within its block
+        let block_size = BgzfCompressor::BLOCK_SIZE;
+        for (i, (offset, pending)) in resolved.iter().zip(&offsets).enumerate() {
+            assert_eq!((offset & 0xffff) as usize, (i * record.len()) % block_size);
+            assert_eq!(pending.block() as usize, (i * record.len()) / block_size);
+        }
+
+        // Block starts point at BGZF block headers in the output
+        let bytes = std::fs::read(&path).unwrap();
+        for offset in &resolved {
+            let start = (offset >> 16) as usize;
+            assert_eq!(&bytes[start..start + 2], &[0x1f, 0x8b]);
+        }
+
+        // Block starts are only kept from the oldest block that still has a pending offset
+        let retained = block_offsets.retained();
+        let last = offsets.pop().unwrap();
+        offsets.clear();
+        assert!(block_offsets.retained() < retained);
+        drop(last);
+        assert_eq!(block_offsets.retained(), 0);
+    }
+
+    #[test]
+    fn test_block_size() {
+        assert!(PoolBuilder::<Vec<u8>, BgzfCompressor>::new().block_size(0).is_err());
+        assert!(PoolBuilder::<Vec<u8>, BgzfCompressor>::new()
+            .block_size(BgzfCompressor::BLOCK_SIZE + 1)
+            .is_err());
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("small_blocks.txt.gz", &dir.path());
+        let block_size = 4096;
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+            .threads(2)
+            .block_size(block_size)
+            .unwrap()
+            .virtual_offsets(true)
+            .unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+        assert_eq!(writer.block_size(), block_size);
+        assert_eq!(pool.block_size(), block_size);
+
+        let record = vec![b'R'; 100];
+        let mut offsets = vec![];
+        for _ in 0..200 {
+            offsets.push(writer.virtual_offset().unwrap());
+            writer.write_all(&record).unwrap();
+        }
+        writer.close().unwrap();
+        let resolved: Vec<u64> = offsets.iter().map(|o| o.wait().unwrap()).collect();
+        pool.stop_pool().unwrap();
+
+        for (i, offset) in resolved.iter().enumerate() {
+            assert_eq!((offset & 0xffff) as usize, (i * record.len()) % block_size);
+        }
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, record.repeat(200));
+    }
+
+    #[test]
+    fn test_exchange_tee_uncompressed() {
+        let dir = tempdir().unwrap();
+        let compressed = create_output_file_name("tee.txt.gz", &dir.path());
+        let raw = create_output_file_name("tee.txt", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(4);
+        let mut writer = builder.exchange_tee_uncompressed(
+            create_output_writer(&compressed),
+            create_output_writer(&raw),
+        );
+        let mut pool = builder.build().unwrap();
+
+        let input: Vec<u8> = (0..500_000).map(|i| (i % 256) as u8).collect();
+        writer.write_all(&input).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        assert_eq!(std::fs::read(&raw).unwrap(), input);
+        let mut actual = vec![];
+        Reader::new(File::open(&compressed).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, input);
+    }
+
+    #[test]
+    fn test_set_max_active_threads() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("quiet.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(4);
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        pool.set_max_active_threads(1);
+        assert_eq!(pool.max_active_threads(), 1);
+        let input = vec![b'Q'; 300_000];
+        writer.write_all(&input).unwrap();
+        pool.set_max_active_threads(100);
+        assert_eq!(pool.max_active_threads(), pool.threads());
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, input);
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("clock.txt.gz", &dir.path());
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2).clock(clock.clone());
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        writer.write_all(b"virtual time").unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        // Idle threads block rather than sleeping, so virtual time only moves when advanced
+        assert_eq!(clock.now(), Duration::default());
+        assert!(pool.stats().levels.iter().all(|l| l.compression_time == Duration::default()));
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, b"virtual time");
+    }
+
+    #[test]
+    fn test_idle_threads_block() {
+        // Were idle threads to sleep between polls, this would take an hour
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new()
+            .threads(4)
+            .retry_delay(Duration::from_secs(3600));
+        let mut writer = builder.exchange(vec![]);
+        let mut pool = builder.build().unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+
+        let data = b"wake up\n".repeat(50_000);
+        writer.write_all(&data).unwrap();
+        writer.quiesce().unwrap();
+        pool.set_max_active_threads(1);
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut actual = vec![];
+        let compressed = pool.quiesce_writer::<Vec<u8>>(0).unwrap().clone();
+        Reader::new(compressed.as_slice()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, [data.clone(), data].concat());
+    }
+
+    #[test]
+    fn test_compressor_and_writer_threads() {
+        let builders = [
+            (PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(4).writer_threads(1), 4),
+            (PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(4).compressor_threads(1), 4),
+            (
+                PoolBuilder::<Vec<u8>, BgzfCompressor>::new()
+                    .compressor_threads(2)
+                    .writer_threads(3),
+                5,
+            ),
+        ];
+        for (mut builder, threads) in builders {
+            let mut writers: Vec<_> = (0..3).map(|_| builder.exchange(vec![])).collect();
+            let mut pool = builder.build().unwrap();
+            assert_eq!(pool.threads(), threads);
+
+            let data = b"dedicated\n".repeat(30_000);
+            for (i, writer) in writers.iter_mut().enumerate() {
+                writer.write_all(&data).unwrap();
+                // The limit on active threads leaves the writer threads active
+                pool.set_max_active_threads(i + 1);
+            }
+            writers.into_iter().try_for_each(|w| w.close()).unwrap();
+            pool.stop_pool().unwrap();
+
+            for index in 0..3 {
+                let compressed = pool.quiesce_writer::<Vec<u8>>(index).unwrap().clone();
+                let mut actual = vec![];
+                Reader::new(compressed.as_slice()).read_to_end(&mut actual).unwrap();
+                assert_eq!(actual, data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_profiles() {
+        for profile in
+            [Profile::Balanced, Profile::LowLatency, Profile::MaxThroughput, Profile::LowMemory]
+        {
+            let dir = tempdir().unwrap();
+            let path = create_output_file_name("profile.txt.gz", &dir.path());
+            let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2).profile(profile);
+            let mut writer = builder.exchange(create_output_writer(&path));
+            assert_eq!(builder.queue_size, Some(2 * profile.queue_size_thread_multiple()));
+            assert_eq!(
+                builder.max_in_flight_bytes.as_ref().map(|limit| limit.limit()),
+                profile.max_in_flight_bytes()
+            );
+            assert_eq!(builder.batch_small_blocks, profile.batch_small_blocks());
+            assert_eq!(builder.work_weights, profile.work_weights());
+            assert_eq!(
+                writer.options().coalesce_partial_flushes,
+                profile.coalesce_partial_flushes()
+            );
+            let mut pool = builder.build().unwrap();
+
+            let data = vec![b'A'; 3 * BgzfCompressor::BLOCK_SIZE];
+            writer.write_all(&data).unwrap();
+            writer.close().unwrap();
+            pool.stop_pool().unwrap();
+
+            let mut actual = vec![];
+            Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, data);
+        }
+
+        // Explicit settings after the profile take precedence
+        let builder = PoolBuilder::<File, BgzfCompressor>::new()
+            .profile(Profile::LowMemory)
+            .queue_size(7)
+            .retry_delay(Duration::from_millis(3));
+        assert_eq!(builder.queue_size, Some(7));
+        assert_eq!(builder.retry_delay, Duration::from_millis(3));
+    }
+
+    #[test]
+    fn test_bgzf_concatenate() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<_> = (0..3)
+            .map(|i| create_output_file_name(&format!("shard{}.txt.gz", i), &dir.path()))
+            .collect();
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut writers: Vec<_> =
+            paths.iter().map(|p| builder.exchange(create_output_writer(p))).collect();
+        let mut pool = builder.build().unwrap();
+
+        let mut expected = vec![];
+        for (i, writer) in writers.iter_mut().enumerate() {
+            let data = format!("shard {}\n", i).repeat(20_000);
+            writer.write_all(data.as_bytes()).unwrap();
+            expected.extend_from_slice(data.as_bytes());
+        }
+        writers.into_iter().try_for_each(|w| w.close()).unwrap();
+        pool.stop_pool().unwrap();
+
+        let merged_path = create_output_file_name("merged.txt.gz", &dir.path());
+        let inputs = paths.iter().map(|p| BufReader::new(File::open(p).unwrap()));
+        let mut merged = create_output_writer(&merged_path);
+        crate::bgzf::concatenate(inputs, &mut merged).unwrap();
+        drop(merged);
+
+        let bytes = std::fs::read(&merged_path).unwrap();
+        assert!(bytes.ends_with(&bgzf_eof()));
+        assert!(!bytes[..bytes.len() - bgzf_eof().len()].ends_with(&bgzf_eof()));
+        let mut actual = vec![];
+        Reader::new(bytes.as_slice()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_concatenate_files() {
+        use crate::concat::{concatenate_files, ConcatFormat};
+
+        let dir = tempdir().unwrap();
+        let paths: Vec<_> = (0..5)
+            .map(|i| create_output_file_name(&format!("shard{}.txt.gz", i), &dir.path()))
+            .collect();
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let omit = ExchangeOptions::new().omit_eof_marker(true);
+        let mut writers: Vec<_> = paths
+            .iter()
+            .enumerate()
+            .map(|(i, p)| match i % 2 {
+                0 => builder.exchange(create_output_writer(p)),
+                _ => builder.exchange_with_options(create_output_writer(p), omit).unwrap(),
+            })
+            .collect();
+        let mut pool = builder.build().unwrap();
+
+        let mut expected = vec![];
+        for (i, writer) in writers.iter_mut().enumerate() {
+            let data = format!("shard {}\n", i).repeat(50_000 * i);
+            writer.write_all(data.as_bytes()).unwrap();
+            expected.extend_from_slice(data.as_bytes());
+        }
+        writers.into_iter().try_for_each(|w| w.close()).unwrap();
+        pool.stop_pool().unwrap();
+
+        for threads in [1, 3] {
+            let merged = create_output_file_name("merged.txt.gz", &dir.path());
+            concatenate_files(&paths, &merged, ConcatFormat::Bgzf, threads).unwrap();
+            let bytes = std::fs::read(&merged).unwrap();
+            let eof = bgzf_eof();
+            assert!(bytes.ends_with(&eof));
+            assert_eq!(bytes.windows(eof.len()).filter(|w| *w == eof.as_slice()).count(), 1);
+            let mut actual = vec![];
+            Reader::new(bytes.as_slice()).read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, expected);
+
+            // Streams are copied byte for byte
+            let copied = create_output_file_name("copied.txt.gz", &dir.path());
+            concatenate_files(&paths, &copied, ConcatFormat::Streams, threads).unwrap();
+            let shards: Vec<u8> = paths.iter().flat_map(|p| std::fs::read(p).unwrap()).collect();
+            assert_eq!(std::fs::read(&copied).unwrap(), shards);
+        }
+
+        let missing = dir.path().join("missing.txt.gz");
+        let inputs = [paths[0].clone(), missing];
+        let merged = create_output_file_name("merged.txt.gz", &dir.path());
+        assert!(concatenate_files(&inputs, &merged, ConcatFormat::Bgzf, 2).is_err());
+    }
+
+    #[test]
+    fn test_max_output_size() {
+        // Pseudo-random bytes that don't compress, so each block is about a block in size
+        let mut seed = 17u32;
+        let data: Vec<u8> = (0..4 * BgzfCompressor::BLOCK_SIZE)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 24) as u8
+            })
+            .collect();
+        let limit = 3 * BgzfCompressor::BLOCK_SIZE as u64 / 2;
+
+        // A writer that isn't split is poisoned, without affecting the other writers
+        let dir = tempdir().unwrap();
+        let capped = create_output_file_name("capped.gz", &dir.path());
+        let other = create_output_file_name("other.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2).max_output_size(limit);
+        let mut capped_writer = builder.exchange(create_output_writer(&capped));
+        let mut other_writer = builder.exchange(create_output_writer(&other));
+        let mut pool = builder.build().unwrap();
+        other_writer.write_all(b"unaffected").unwrap();
+        other_writer.close().unwrap();
+        let _ = capped_writer.write_all(&data);
+        let _ = capped_writer.close();
+        assert!(matches!(
+            pool.stop_pool(),
+            Err(PoolError::OutputSizeExceeded { writer: 0, limit: l }) if l == limit
+        ));
+        let len = std::fs::metadata(&capped).unwrap().len();
+        assert!(len > 0 && len <= limit);
+        let mut actual = vec![];
+        Reader::new(File::open(&other).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, b"unaffected");
+
+        // A split writer moves on to the next output instead
+        let prefix = dir.path().to_path_buf();
+        let path = move |i: usize| prefix.join(format!("part{}.gz", i));
+        let factory_path = path.clone();
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2).max_output_size(limit);
+        let mut writer = builder
+            .exchange_split_by_records(u64::MAX, Box::new(move |i| File::create(factory_path(i))))
+            .unwrap();
+        let mut pool = builder.build().unwrap();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut joined = vec![];
+        for i in 0..4 {
+            let bytes = std::fs::read(path(i)).unwrap();
+            assert!(bytes.len() as u64 <= limit);
+            joined.extend(bytes);
+        }
+        assert!(!path(4).exists());
+        let mut actual = vec![];
+        Reader::new(joined.as_slice()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_exchange_split_by_records() {
+        let dir = tempdir().unwrap();
+        let prefix = dir.path().to_path_buf();
+        let path = move |i: usize| prefix.join(format!("chunk{}.txt.gz", i));
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let factory_path = path.clone();
+        let mut writer = builder
+            .exchange_split_by_records(3, Box::new(move |i| File::create(factory_path(i))))
+            .unwrap();
+        let mut pool = builder.build().unwrap();
+
+        for i in 0..7 {
+            writeln!(writer, "record {}", i).unwrap();
+            writer.end_record().unwrap();
+        }
+        let manifest = writer.split_manifest().unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        assert_eq!(
+            manifest,
+            vec![
+                SplitOutput { index: 0, records: 3 },
+                SplitOutput { index: 1, records: 3 },
+                SplitOutput { index: 2, records: 1 },
+            ]
+        );
+        for (i, records) in [0..3, 3..6, 6..7].iter().enumerate() {
+            let expected: String = records.clone().map(|r| format!("record {}\n", r)).collect();
+            let mut actual = vec![];
+            Reader::new(File::open(path(i)).unwrap()).read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, expected.as_bytes());
+        }
+        assert!(!path(3).exists());
+    }
+
+    #[test]
+    fn test_compressed_block() {
+        let data = b"some uncompressed bytes".to_vec();
+        let mut compressed = vec![];
+        BgzfCompressor::new(BgzfCompressor::default_compression_level())
+            .compress(&data, &mut compressed)
+            .unwrap();
+        let block = crate::block::CompressedBlock::new(3, 7, compressed, &data);
+        assert_eq!((block.writer_id, block.seq, block.uncompressed_len), (3, 7, data.len()));
+
+        let mut actual = vec![];
+        Reader::new(block.bytes.as_slice()).read_to_end(&mut actual).unwrap();
+        assert!(block.matches(&actual));
+        assert!(!block.matches(b"other bytes"));
+    }
+
+    #[test]
+    fn test_exchange_blocks() {
+        let data = b"framed block by block\n".repeat(20_000);
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(2);
+        let other = builder.exchange(vec![]);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut writer = builder.exchange_blocks(move |block| {
+            tx.send(block.clone()).unwrap();
+            Ok(())
+        });
+        let mut pool = builder.build().unwrap();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        other.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let blocks: Vec<_> = rx.iter().collect();
+        assert!(blocks.len() > 1);
+        let mut actual = vec![];
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!((block.writer_id, block.seq), (1, i as u64));
+            let mut uncompressed = vec![];
+            Reader::new(block.bytes.as_slice()).read_to_end(&mut uncompressed).unwrap();
+            assert!(block.matches(&uncompressed));
+            actual.extend_from_slice(&uncompressed);
+        }
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd_compressor")]
+    fn test_zstd_compressor() {
+        use crate::zstd::ZstdCompressor;
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("test.txt.zst", &dir.path());
+        let mut builder =
+            PoolBuilder::<_, ZstdCompressor>::new().threads(2).compression_level(19).unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        let data: Vec<u8> =
+            (0..5 * ZstdCompressor::BLOCK_SIZE / 2).map(|i| (i % 97) as u8).collect();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let actual = ::zstd::decode_all(File::open(&path).unwrap()).unwrap();
+        assert_eq!(actual, data);
+        assert!(PoolBuilder::<File, ZstdCompressor>::new().compression_level(23).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "zstd_compressor")]
+    fn test_zstd_dictionary() {
+        use crate::zstd::ZstdCompressor;
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("test.txt.zst", &dir.path());
+        let dictionary = b"chr1\t12345\tread_name\tACGTACGTACGT\tIIIIIIIIIIII\n".repeat(64);
+        let mut builder = PoolBuilder::<_, ZstdCompressor>::new()
+            .threads(2)
+            .dictionary(dictionary.clone())
+            .unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        let data = b"chr1\t12399\tread_other\tACGTACGTTCGT\tIIIIIIIIIIII\n".repeat(10_000);
+        for record in data.chunks(48) {
+            writer.write_all(record).unwrap();
+            writer.flush().unwrap();
+        }
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut actual = vec![];
+        ::zstd::stream::read::Decoder::with_dictionary(File::open(&path).unwrap(), &dictionary)
+            .unwrap()
+            .read_to_end(&mut actual)
+            .unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_dictionary_requires_support() {
+        let result = PoolBuilder::<File, BgzfCompressor>::new().dictionary(b"dictionary".to_vec());
+        assert!(matches!(result, Err(PoolError::UnsupportedOption(_))));
+    }
+
+    #[test]
+    fn test_stop_pool_with_progress() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("progress.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        let data = vec![b'A'; 10 * BgzfCompressor::BLOCK_SIZE];
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+
+        let mut reports = vec![];
+        pool.stop_pool_with_progress(Duration::from_millis(1), |stats| {
+            reports.push((stats.remaining_blocks(), stats.remaining_bytes()));
+        })
+        .unwrap();
+
+        // The final report is always made, and shows that nothing remains
+        assert_eq!(reports.last(), Some(&(0, 0)));
+        assert!(reports.windows(2).all(|w| w[1].0 <= w[0].0 && w[1].1 <= w[0].1));
+        let stats = pool.stats();
+        assert_eq!(stats.writers[0].blocks_written, 11);
+        assert_eq!(stats.writers[0].uncompressed_bytes_written, data.len() as u64);
+    }
+
+    #[test]
+    #[cfg(feature = "gzip_compressor")]
+    fn test_gzip_compressor() {
+        use crate::gzip::GzipCompressor;
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("test.txt.gz", &dir.path());
+        let mut builder =
+            PoolBuilder::<_, GzipCompressor>::new().threads(2).compression_level(9).unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        let data: Vec<u8> =
+            (0..5 * GzipCompressor::BLOCK_SIZE / 2).map(|i| (i % 89) as u8).collect();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut actual = vec![];
+        flate2::read::MultiGzDecoder::new(File::open(&path).unwrap())
+            .read_to_end(&mut actual)
+            .unwrap();
+        assert_eq!(actual, data);
+        assert!(PoolBuilder::<File, GzipCompressor>::new().compression_level(13).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "gzip_compressor")]
+    fn test_gzip_header() {
+        use crate::gzip::GzipCompressor;
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("header.txt.gz", &dir.path());
+        let header = GzipHeader::new().mtime(1_700_000_000).os(3).file_name("header.txt");
+        let mut builder =
+            PoolBuilder::<_, GzipCompressor>::new().threads(2).gzip_header(header).unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        let data = b"named and dated\n".repeat(20_000);
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let decoder = flate2::read::GzDecoder::new(File::open(&path).unwrap());
+        let member = decoder.header().unwrap();
+        assert_eq!(member.mtime(), 1_700_000_000);
+        assert_eq!(member.operating_system(), 3);
+        assert_eq!(member.filename(), Some(&b"header.txt"[..]));
+        let mut actual = vec![];
+        flate2::read::MultiGzDecoder::new(File::open(&path).unwrap())
+            .read_to_end(&mut actual)
+            .unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_bgzf_gzip_header() {
+        let with_name = GzipHeader::new().file_name("out.bam");
+        assert!(PoolBuilder::<File, BgzfCompressor>::new().gzip_header(with_name).is_err());
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("header.txt.gz", &dir.path());
+        let header = GzipHeader::new().mtime(42).os(3);
+        let mut builder =
+            PoolBuilder::<_, BgzfCompressor>::new().threads(2).gzip_header(header).unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[4..8], &42u32.to_le_bytes());
+        assert_eq!(bytes[9], 3);
+        let block_size = u16::from_le_bytes([bytes[16], bytes[17]]) as usize + 1;
+        assert_eq!(&bytes[block_size..], &bgzf_eof()[..]);
+        let mut actual = vec![];
+        Reader::new(&bytes[..]).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, b"hello");
+    }
+
+    #[test]
+    fn test_write_edge_cases() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("edge.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        // Empty writes are accepted and a single large write is split into many blocks
+        assert_eq!(writer.write(&[]).unwrap(), 0);
+        let data: Vec<u8> = (0..(7 * BgzfCompressor::BLOCK_SIZE + 11)).map(|i| i as u8).collect();
+        assert_eq!(writer.write(&data).unwrap(), data.len());
+        assert_eq!(pool.stats().writers[0].blocks, 7);
+
+        // Writes after the stream has been finalized fail with a typed error
+        writer.finalize_stream().unwrap();
+        for result in [writer.write(b"late").map(|_| ()), writer.flush()] {
+            let err = result.unwrap_err();
+            let inner = err.get_ref().and_then(|e| e.downcast_ref::<PoolError>());
+            assert!(matches!(inner, Some(PoolError::WriterFinalized(0))));
+        }
+        drop(writer);
+        pool.stop_pool().unwrap();
+
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_write_after_pool_stopped() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("stopped.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(1);
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+        pool.stop_pool().unwrap();
+
+        let data = vec![0; BgzfCompressor::BLOCK_SIZE];
+        let err = writer.write(&data).unwrap_err();
+        let inner = err.get_ref().and_then(|e| e.downcast_ref::<PoolError>());
+        assert!(matches!(inner, Some(PoolError::ChannelSend)));
+        writer.drop_policy = DropPolicy::Discard;
+    }
+
+    /// Set once the block starting with the matching prefix has failed to compress.
+    static FAILED_REQUEUE: std::sync::atomic::AtomicBool =
+        std::sync::atomic::AtomicBool::new(false);
+    static FAILED_NO_REQUEUE: std::sync::atomic::AtomicBool =
+        std::sync::atomic::AtomicBool::new(false);
+
+    /// A BGZF compressor that fails, once, to compress blocks with certain prefixes.
+    struct FlakyCompressor(BgzfCompressor);
+
+    impl Compressor for FlakyCompressor {
+        type Error = io::Error;
+        type CompressionLevel = <BgzfCompressor as Compressor>::CompressionLevel;
+
+        fn new(level: Self::CompressionLevel) -> Self {
+            Self(BgzfCompressor::new(level))
+        }
+
+        fn default_compression_level() -> Self::CompressionLevel {
+            BgzfCompressor::default_compression_level()
+        }
+
+        fn new_compression_level(level: u8) -> Result<Self::CompressionLevel, Self::Error> {
+            BgzfCompressor::new_compression_level(level)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+        }
+
+        fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+            let flag = if input.starts_with(b"requeue") {
+                Some(&FAILED_REQUEUE)
+            } else if input.starts_with(b"no-requeue") {
+                Some(&FAILED_NO_REQUEUE)
+            } else {
+                None
+            };
+            if flag.map_or(false, |f| !f.swap(true, Ordering::SeqCst)) {
+                return Err(io::Error::new(io::ErrorKind::Other, "transient failure"));
+            }
+            self.0.compress(input, output).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        }
+
+        fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+            self.0.finish(output).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        }
+    }
+
+    #[test]
+    fn test_requeue_failed_blocks() {
+        for requeue in [true, false] {
+            let dir = tempdir().unwrap();
+            let path = create_output_file_name("flaky.txt.gz", &dir.path());
+            let mut builder =
+                PoolBuilder::<_, FlakyCompressor>::new().threads(2).requeue_failed_blocks(requeue);
+            let mut writer = builder.exchange(create_output_writer(&path));
+            let mut pool = builder.build().unwrap();
+
+            let data = if requeue { "requeue\n" } else { "no-requeue\n" }.repeat(20_000);
+            writer.write_all(data.as_bytes()).unwrap();
+            writer.close().unwrap();
+            let result = pool.stop_pool();
+
+            if requeue {
+                result.unwrap();
+                assert_eq!(pool.stats().writers[0].requeued_blocks, 1);
+                let mut actual = vec![];
+                Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+                assert_eq!(actual, data.as_bytes());
+            } else {
+                assert!(matches!(result, Err(PoolError::CompressionError(_))));
+            }
+        }
+    }
+
+    /// A BGZF compressor that corrupts the last compressed byte of every block.
+    struct CorruptingCompressor(BgzfCompressor);
+
+    impl Compressor for CorruptingCompressor {
+        type Error = io::Error;
+        type CompressionLevel = <BgzfCompressor as Compressor>::CompressionLevel;
+
+        fn capabilities() -> CompressorCapabilities {
+            BgzfCompressor::capabilities()
+        }
+
+        fn new(level: Self::CompressionLevel) -> Self {
+            Self(BgzfCompressor::new(level))
+        }
+
+        fn default_compression_level() -> Self::CompressionLevel {
+            BgzfCompressor::default_compression_level()
+        }
+
+        fn new_compression_level(level: u8) -> Result<Self::CompressionLevel, Self::Error> {
+            BgzfCompressor::new_compression_level(level)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+        }
+
+        fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+            self.0.compress(input, output).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            // The last byte before the CRC and ISIZE footer
+            let at = output.len() - 9;
+            output[at] ^= 0xff;
+            Ok(())
+        }
+
+        fn verify(&mut self, input: &[u8], compressed: &[u8]) -> io::Result<()> {
+            self.0.verify(input, compressed)
+        }
+
+        fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+            self.0.finish(output).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        }
+    }
+
+    #[test]
+    fn test_verify_blocks() {
+        let dir = tempdir().unwrap();
+        let data = b"verified\n".repeat(20_000);
+
+        let path = create_output_file_name("verified.txt.gz", &dir.path());
+        let mut builder =
+            PoolBuilder::<_, BgzfCompressor>::new().threads(2).verify_blocks(true).unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+
+        let path = create_output_file_name("corrupt.txt.gz", &dir.path());
+        let mut builder =
+            PoolBuilder::<_, CorruptingCompressor>::new().threads(2).verify_blocks(true).unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+        let _ = writer.write_all(&data);
+        let _ = writer.close();
+        assert!(matches!(pool.stop_pool(), Err(PoolError::VerificationFailed(_))));
+
+        // Compressors that can't verify their blocks are rejected up front
+        let result = PoolBuilder::<Vec<u8>, FlakyCompressor>::new().verify_blocks(true);
+        assert!(matches!(result, Err(PoolError::UnsupportedOption(_))));
+        let mut builder =
+            PoolBuilder::<Vec<u8>, BgzfCompressor>::new().verify_blocks(true).unwrap();
+        let result = builder.exchange_with_compressor::<FlakyCompressor>(vec![], None);
+        assert!(matches!(result, Err(PoolError::UnsupportedOption(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "xz_compressor")]
+    fn test_xz_compressor() {
+        use crate::xz::XzCompressor;
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("test.txt.xz", &dir.path());
+        let mut builder =
+            PoolBuilder::<_, XzCompressor>::new().threads(2).compression_level(1).unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        let data: Vec<u8> = (0..5 * XzCompressor::BLOCK_SIZE / 2).map(|i| (i % 83) as u8).collect();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut actual = vec![];
+        xz2::read::XzDecoder::new_multi_decoder(File::open(&path).unwrap())
+            .read_to_end(&mut actual)
+            .unwrap();
+        assert_eq!(actual, data);
+        assert!(PoolBuilder::<File, XzCompressor>::new().compression_level(10).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "crypt4gh_encoder")]
+    fn test_crypt4gh_encoder() {
+        use crate::crypt4gh::{Crypt4ghConfig, Crypt4ghEncoder, SEGMENT_SIZE};
+        use crate::encoder::Encoding;
+        use blake2::{Blake2b512, Digest};
+        use chacha20poly1305::aead::{Aead, KeyInit};
+        use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+        use x25519_dalek::{PublicKey, StaticSecret};
+
+        let reader_key = StaticSecret::from([7u8; 32]);
+        let reader_public_key = PublicKey::from(&reader_key);
+        let config = Crypt4ghConfig::new([*reader_public_key.as_bytes()]).unwrap();
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("test.txt.c4gh", &dir.path());
+        let mut builder =
+            PoolBuilder::<_, Encoding<Crypt4ghEncoder>>::new().threads(2).encoder(config);
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        // Bytes flushed mid-segment are carried over, so every segment but the last is full
+        let data: Vec<u8> = (0..5 * SEGMENT_SIZE / 2).map(|i| (i % 97) as u8).collect();
+        writer.write_all(&data[..1000]).unwrap();
+        writer.flush_partial().unwrap();
+        writer.write_all(&data[1000..]).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        // Decrypt the single header packet to recover the data key, then each segment
+        let encrypted = std::fs::read(&path).unwrap();
+        let u32_at = |i: usize| u32::from_le_bytes(encrypted[i..i + 4].try_into().unwrap());
+        assert_eq!(&encrypted[..8], b"crypt4gh");
+        assert_eq!((u32_at(8), u32_at(12)), (1, 1));
+        let packet_len = u32_at(16) as usize;
+        let writer_public_key: [u8; 32] = encrypted[24..56].try_into().unwrap();
+        let mut hasher = Blake2b512::new();
+        hasher.update(reader_key.diffie_hellman(&PublicKey::from(writer_public_key)).as_bytes());
+        hasher.update(reader_public_key.as_bytes());
+        hasher.update(writer_public_key);
+        let shared_key = hasher.finalize();
+        let parameters = ChaCha20Poly1305::new(Key::from_slice(&shared_key[..32]))
+            .decrypt(Nonce::from_slice(&encrypted[56..68]), &encrypted[68..16 + packet_len])
+            .unwrap();
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&parameters[8..40]));
+
+        let segments = encrypted[16 + packet_len..].chunks(12 + SEGMENT_SIZE + 16);
+        let actual: Vec<u8> = segments
+            .flat_map(|s| cipher.decrypt(Nonce::from_slice(&s[..12]), &s[12..]).unwrap())
+            .collect();
+        assert_eq!(actual, data);
+        assert!(Crypt4ghConfig::new([]).is_err());
+    }
+
+    #[test]
+    fn test_compress_files() {
+        let dir = tempdir().unwrap();
+        let inputs: Vec<PathBuf> =
+            (0..3).map(|i| create_output_file_name(format!("in.{}.txt", i), &dir.path())).collect();
+        let outputs: Vec<PathBuf> = inputs.iter().map(|p| p.with_extension("txt.gz")).collect();
+        let data: Vec<Vec<u8>> =
+            (0..3).map(|i| format!("file {}\n", i).repeat(10_000).into_bytes()).collect();
+        inputs.iter().zip(&data).for_each(|(p, d)| std::fs::write(p, d).unwrap());
+
+        let paths = inputs.iter().cloned().zip(outputs.iter().cloned());
+        compress_files::<BgzfCompressor, _, _>(paths, 2, 3).unwrap();
+
+        for (output, expected) in outputs.iter().zip(&data) {
+            let mut actual = vec![];
+            Reader::new(File::open(output).unwrap()).read_to_end(&mut actual).unwrap();
+            assert_eq!(&actual, expected);
+        }
+
+        let missing = create_output_file_name("missing.txt", &dir.path());
+        let out = create_output_file_name("missing.txt.gz", &dir.path());
+        assert!(compress_files::<BgzfCompressor, _, _>([(missing, out)], 2, 3).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "aes_gcm_encoder")]
+    fn test_exchange_with_encoder_chain() {
+        use crate::aes::{AesGcmEncoder, NONCE_LEN, TAG_LEN};
+        use crate::encoder::Chain;
+        use aes_gcm::aead::{Aead, KeyInit};
+        use aes_gcm::{Aes256Gcm, Key, Nonce};
+
+        type Chained = Chain<BgzfCompressor, AesGcmEncoder>;
+        let key = [3u8; 32];
+        let level = BgzfCompressor::new_compression_level(3).unwrap();
+        let dir = tempdir().unwrap();
+        let plain = create_output_file_name("plain.txt.gz", &dir.path());
+        let encrypted = create_output_file_name("encrypted.txt.gz.enc", &dir.path());
+        let mut builder =
+            PoolBuilder::<_, BgzfCompressor>::new().threads(2).compression_level(3).unwrap();
+        let mut plain_writer = builder.exchange(create_output_writer(&plain));
+        let mut encrypted_writer = builder
+            .exchange_with_encoder::<Chained>(create_output_writer(&encrypted), (level, key.into()))
+            .unwrap();
+        let mut pool = builder.build().unwrap();
+
+        let data = b"compressed then encrypted\n".repeat(10_000);
+        plain_writer.write_all(&data).unwrap();
+        encrypted_writer.write_all(&data).unwrap();
+        plain_writer.close().unwrap();
+        encrypted_writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        // Each record is a nonce, an encrypted BGZF block and a tag, the blocks being the same
+        // as in the plain output, whose BSIZE fields give the size of each block minus one
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let compressed = std::fs::read(&plain).unwrap();
+        let bytes = std::fs::read(&encrypted).unwrap();
+        let (mut block_start, mut record_start, mut decrypted) = (0, 0, vec![]);
+        while block_start < compressed.len() {
+            let bsize = &compressed[block_start + 16..block_start + 18];
+            let block_len = u16::from_le_bytes([bsize[0], bsize[1]]) as usize + 1;
+            let record = &bytes[record_start..record_start + NONCE_LEN + block_len + TAG_LEN];
+            let nonce = Nonce::from_slice(&record[..NONCE_LEN]);
+            decrypted.extend(cipher.decrypt(nonce, &record[NONCE_LEN..]).unwrap());
+            block_start += block_len;
+            record_start += record.len();
+        }
+        assert_eq!(record_start, bytes.len());
+        assert_eq!(decrypted, compressed);
+    }
+
+    #[test]
+    #[cfg(feature = "aes_gcm_encoder")]
+    fn test_per_writer_encryption_keys() {
+        use crate::aes::{NONCE_LEN, TAG_LEN};
+        use crate::encryption::EncryptionKey;
+        use aes_gcm::aead::{Aead, KeyInit};
+        use aes_gcm::{Aes256Gcm, Key, Nonce};
+
+        let keys = [[5u8; 32], [6u8; 32]];
+        let dir = tempdir().unwrap();
+        let plain = create_output_file_name("plain.txt.gz", &dir.path());
+        let paths: Vec<_> = (0..2)
+            .map(|i| create_output_file_name(&format!("tenant{}.txt.gz.enc", i), &dir.path()))
+            .collect();
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let ids: Vec<_> = keys
+            .iter()
+            .map(|&key| builder.encryption_key(EncryptionKey::AesGcm(key.into())))
+            .collect();
+        let mut writers = vec![builder.exchange(create_output_writer(&plain))];
+        for (path, &id) in paths.iter().zip(&ids) {
+            let options = ExchangeOptions::new().encryption(id);
+            writers
+                .push(builder.exchange_with_options(create_output_writer(path), options).unwrap());
+        }
+        let other = ExchangeOptions::new().encryption(ids[0]);
+        assert!(writers[2].reconfigure(other).is_err());
+        let mut pool = builder.build().unwrap();
+
+        let data = b"encrypted for one tenant\n".repeat(10_000);
+        for writer in &mut writers {
+            writer.write_all(&data).unwrap();
+        }
+        writers.into_iter().try_for_each(|w| w.close()).unwrap();
+        pool.stop_pool().unwrap();
+
+        // Each record holds one encrypted BGZF block of the plain output, as in
+        // test_exchange_with_encoder_chain, and only decrypts with its writer's key
+        let compressed = std::fs::read(&plain).unwrap();
+        let decrypt = |path: &PathBuf, key: &[u8; 32]| -> Option<Vec<u8>> {
+            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+            let bytes = std::fs::read(path).unwrap();
+            let (mut block_start, mut record_start, mut decrypted) = (0, 0, vec![]);
+            while block_start < compressed.len() {
+                let bsize = &compressed[block_start + 16..block_start + 18];
+                let block_len = u16::from_le_bytes([bsize[0], bsize[1]]) as usize + 1;
+                let record = &bytes[record_start..record_start + NONCE_LEN + block_len + TAG_LEN];
+                let nonce = Nonce::from_slice(&record[..NONCE_LEN]);
+                decrypted.extend(cipher.decrypt(nonce, &record[NONCE_LEN..]).ok()?);
+                block_start += block_len;
+                record_start += record.len();
+            }
+            Some(decrypted)
+        };
+        assert_eq!(decrypt(&paths[0], &keys[0]), Some(compressed.clone()));
+        assert_eq!(decrypt(&paths[1], &keys[1]), Some(compressed.clone()));
+        assert_eq!(decrypt(&paths[0], &keys[1]), None);
+    }
+
+    /// A passthrough compressor whose scratch memory grows with the compression level.
+    struct MemoryHungryCompressor;
+
+    impl Compressor for MemoryHungryCompressor {
+        type Error = io::Error;
+        type CompressionLevel = u8;
+
+        fn capabilities() -> CompressorCapabilities {
+            CompressorCapabilities::new(Self::BLOCK_SIZE).compression_levels(0, 20)
+        }
+
+        fn capabilities_for(level: &Self::CompressionLevel) -> CompressorCapabilities {
+            Self::capabilities().scratch_memory((*level as usize + 1) << 20)
+        }
+
+        fn new(_level: Self::CompressionLevel) -> Self {
+            Self
+        }
+
+        fn default_compression_level() -> Self::CompressionLevel {
+            20
+        }
+
+        fn new_compression_level(level: u8) -> io::Result<Self::CompressionLevel> {
+            Ok(level)
+        }
+
+        fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+            output.extend_from_slice(input);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let build = |level: Option<u8>, budget: usize| {
+            let mut builder = PoolBuilder::<Vec<u8>, MemoryHungryCompressor>::new()
+                .threads(8)
+                .drop_policy(DropPolicy::Discard)
+                .memory_budget(budget);
+            if let Some(level) = level {
+                builder = builder.compression_level(level).unwrap();
+            }
+            let _writer = builder.exchange(vec![]);
+            builder.build()
+        };
+
+        // Four MiB per thread fits twice in ten MiB
+        assert_eq!(build(Some(3), 10 << 20).unwrap().threads(), 2);
+        // The default level doesn't fit at all, so is lowered to level 9 on a single thread
+        assert_eq!(build(None, 10 << 20).unwrap().threads(), 1);
+        // A generous budget leaves the threads alone
+        assert_eq!(build(Some(3), 1 << 30).unwrap().threads(), 8);
+        // Not even the lowest level fits
+        assert!(matches!(build(None, 1 << 19), Err(PoolError::UnsupportedOption(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "checksums")]
+    fn test_exchange_with_checksum() {
+        use crate::checksum::ChecksumAlgorithm;
+        use sha2::Digest;
+
+        let dir = tempdir().unwrap();
+        let md5_path = create_output_file_name("md5.txt.gz", &dir.path());
+        let sidecar = create_output_file_name("md5.txt.gz.md5", &dir.path());
+        let sha_path = create_output_file_name("sha.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let (mut md5_writer, md5) = builder.exchange_with_checksum_sidecar(
+            create_output_writer(&md5_path),
+            ChecksumAlgorithm::Md5,
+            create_output_writer(&sidecar),
+            "md5.txt.gz",
+        );
+        let (mut sha_writer, sha) = builder
+            .exchange_with_checksum(create_output_writer(&sha_path), ChecksumAlgorithm::Sha256);
+        let mut pool = builder.build().unwrap();
+
+        let data = b"checksummed on the pool\n".repeat(20_000);
+        md5_writer.write_all(&data).unwrap();
+        sha_writer.write_all(&data).unwrap();
+        assert_eq!(md5.hex(), None);
+        md5_writer.close().unwrap();
+        sha_writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let hex = |digest: &[u8]| digest.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let expected_md5 = hex(&md5::Md5::digest(&data));
+        assert_eq!(md5.hex(), Some(expected_md5.clone()));
+        assert_eq!(sha.hex(), Some(hex(&sha2::Sha256::digest(&data))));
+        let sidecar = std::fs::read_to_string(&sidecar).unwrap();
+        assert_eq!(sidecar, format!("{}  md5.txt.gz\n", expected_md5));
+    }
+
+    #[test]
+    #[cfg(feature = "checksums")]
+    fn test_exchange_hasher() {
+        use crate::checksum::ChecksumAlgorithm;
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("compressed.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let (mut hash_writer, digest) = builder.exchange_hasher(ChecksumAlgorithm::Blake3).unwrap();
+        let mut pool = builder.build().unwrap();
+
+        let data = b"hashed on the pool\n".repeat(20_000);
+        writer.write_all(&data).unwrap();
+        hash_writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        hash_writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        assert_eq!(digest.hex(), Some(blake3::hash(&data).to_hex().to_string()));
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    #[cfg(feature = "block_checksums")]
+    fn test_block_checksums() {
+        use crate::integrity::BlockChecksum;
+
+        let data = b"checksummed block by block\n".repeat(20_000);
+        for algorithm in BlockChecksum::ALL {
+            let dir = tempdir().unwrap();
+            let path = create_output_file_name("blocks.txt.gz", &dir.path());
+            let mut builder =
+                PoolBuilder::<_, BgzfCompressor>::new().threads(2).block_checksums(algorithm);
+            let mut writer = builder.exchange(create_output_writer(&path));
+            let mut pool = builder.build().unwrap();
+            writer.write_all(&data).unwrap();
+            writer.close().unwrap();
+            pool.stop_pool().unwrap();
+
+            let manifest = pool.block_manifest(0).unwrap();
+            assert_eq!(BlockChecksum::from_name(manifest.algorithm), Some(algorithm));
+            assert!(manifest.blocks.len() > 2);
+            let uncompressed: usize = manifest.blocks.iter().map(|b| b.uncompressed_len).sum();
+            assert_eq!(uncompressed, data.len());
+
+            // The last block holds the EOF marker as well as the final compressed block
+            let bytes = std::fs::read(&path).unwrap();
+            let mut remaining = bytes.as_slice();
+            for block in &manifest.blocks {
+                assert_eq!(block.checksum, algorithm.checksum(&remaining[..block.compressed_len]));
+                remaining = &remaining[block.compressed_len..];
+            }
+            assert!(remaining.is_empty());
+        }
+    }
+
+    /// A writer that writes at most 1000 bytes per call and fails every third call, after
+    /// having written the bytes of the previous calls.
+    struct FlakyWriter {
+        bytes: Arc<Mutex<Vec<u8>>>,
+        calls: usize,
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            if self.calls % 3 == 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "transient failure"));
+            }
+            let n = std::cmp::min(buf.len(), 1000);
+            self.bytes.lock().extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_retry_failed_writes() {
+        let data = b"written exactly once\n".repeat(20_000);
+        for retries in [1, 0] {
+            let bytes = Arc::new(Mutex::new(vec![]));
+            let mut builder =
+                PoolBuilder::<_, BgzfCompressor>::new().threads(2).retry_failed_writes(retries);
+            let mut writer = builder.exchange(FlakyWriter { bytes: bytes.clone(), calls: 0 });
+            let mut pool = builder.build().unwrap();
+            let _ = writer.write_all(&data);
+            let _ = writer.close();
+            let result = pool.stop_pool();
+
+            if retries == 0 {
+                assert!(result.is_err());
+            } else {
+                result.unwrap();
+                assert!(pool.stats().writers[0].write_retries > 0);
+                let mut actual = vec![];
+                Reader::new(bytes.lock().as_slice()).read_to_end(&mut actual).unwrap();
+                assert_eq!(actual, data);
+            }
+        }
+    }
+
+    /// A writer that fails once it holds `capacity` bytes, as if the disk were full.
+    struct FullWriter {
+        bytes: Arc<Mutex<Vec<u8>>>,
+        capacity: usize,
+    }
+
+    impl Write for FullWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut bytes = self.bytes.lock();
+            let n = std::cmp::min(buf.len(), self.capacity.saturating_sub(bytes.len()));
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "no space left on device"));
+            }
+            bytes.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_reopen_failed_writers() {
+        let data = b"resumed after the disk was freed\n".repeat(20_000);
+        let bytes = Arc::new(Mutex::new(vec![]));
+        let reopened = bytes.clone();
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+            .threads(2)
+            .retry_failed_writes(1)
+            .reopen_failed_writers(move |index, offset, _| {
+                assert_eq!(index, 0);
+                reopened.lock().truncate(offset as usize);
+                Ok(FullWriter { bytes: reopened.clone(), capacity: usize::MAX })
+            });
+        let mut writer = builder.exchange(FullWriter { bytes: bytes.clone(), capacity: 1_000 });
+        let mut pool = builder.build().unwrap();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        assert_eq!(pool.stats().writers[0].reopens, 1);
+        let mut actual = vec![];
+        Reader::new(bytes.lock().as_slice()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_exchange_with_pipeline() {
+        use crate::encoder::{Pipeline, PipelineConfig};
+
+        let level = BgzfCompressor::new_compression_level(3).unwrap();
+        let pipeline = PipelineConfig::new().encoder::<BgzfCompressor>(level).map(|block, out| {
+            out.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            out.extend_from_slice(block);
+            Ok(())
+        });
+        let (tx, rx) = std::sync::mpsc::channel::<Vec<u8>>();
+        let mut builder = PoolBuilder::<Box<dyn Write + Send>, BgzfCompressor>::new().threads(2);
+        let callback = callback::CallbackWriter::new(move |block: &[u8]| {
+            tx.send(block.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+        });
+        let mut writer =
+            builder.exchange_with_encoder::<Pipeline>(Box::new(callback), pipeline).unwrap();
+        let mut pool = builder.build().unwrap();
+
+        let data = b"compressed then framed\n".repeat(10_000);
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        // Strip the length prefixes, including that of the EOF block, then decompress
+        let framed: Vec<u8> = rx.iter().flatten().collect();
+        let (mut compressed, mut remaining, mut frames) = (vec![], framed.as_slice(), 0);
+        while !remaining.is_empty() {
+            let len = u32::from_le_bytes(remaining[..4].try_into().unwrap()) as usize;
+            compressed.extend_from_slice(&remaining[4..4 + len]);
+            remaining = &remaining[4 + len..];
+            frames += 1;
+        }
+        assert!(frames > 2);
+        let mut actual = vec![];
+        Reader::new(compressed.as_slice()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_recompress_small_outputs() {
+        let dir = tempdir().unwrap();
+        let small = create_output_file_name("small.txt.gz", &dir.path());
+        let unrecorded = create_output_file_name("unrecorded.txt.gz", &dir.path());
+        let large = create_output_file_name("large.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+            .threads(2)
+            .compression_level(1)
+            .unwrap()
+            .recompress_small_outputs(100_000, 12)
+            .unwrap();
+        let data: Vec<u8> = (0..2_000)
+            .flat_map(|i| format!("record {} {}\n", i, i * i % 997).into_bytes())
+            .collect();
+        let large_data = data.repeat(10);
+        let mut writers = vec![];
+        for (path, data) in [(&small, &data), (&unrecorded, &data), (&large, &large_data)] {
+            let writer = builder.exchange(create_output_writer(path));
+            if path != &unrecorded {
+                builder.set_destination(&writer, path).unwrap();
+            }
+            writers.push((writer, data));
+        }
+        let mut pool = builder.build().unwrap();
+        for (mut writer, data) in writers {
+            writer.write_all(data).unwrap();
+            writer.close().unwrap();
+        }
+        pool.stop_pool().unwrap();
+
+        // Only the small output whose destination is known is re-compressed
+        let len = |path: &PathBuf| std::fs::metadata(path).unwrap().len();
+        assert!(len(&small) < len(&unrecorded));
+        assert!(!dir.path().join("small.txt.gz.recompress").exists());
+        for (path, expected) in [(&small, &data), (&unrecorded, &data), (&large, &large_data)] {
+            let mut actual = vec![];
+            Reader::new(File::open(path).unwrap()).read_to_end(&mut actual).unwrap();
+            assert_eq!(&actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_set_destination() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("shared.txt.gz", &dir.path());
+        let other = create_output_file_name("other.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let first = builder.exchange(create_output_writer(&path));
+        let second = builder.exchange(create_output_writer(&other));
+        builder.set_destination(&first, &path).unwrap();
+        builder.set_destination(&second, &other).unwrap();
+        // The same file by another route, and a file not yet created, are both caught
+        let indirect = dir.path().join(".").join("shared.txt.gz");
+        assert!(matches!(
+            builder.set_destination(&second, indirect),
+            Err(PoolError::DuplicateDestination { writer: 0, .. })
+        ));
+        let pending = create_output_file_name("pending.txt.gz", &dir.path());
+        builder.set_destination(&first, &pending).unwrap();
+        assert!(matches!(
+            builder.set_destination(&second, &pending),
+            Err(PoolError::DuplicateDestination { writer: 0, .. })
+        ));
+        // Re-recording a writer's own destination is allowed, and replaces its previous one
+        builder.set_destination(&first, &path).unwrap();
+        builder.set_destination(&second, &pending).unwrap();
+        #[cfg(unix)]
+        {
+            let link = dir.path().join("linked.txt.gz");
+            std::fs::hard_link(&path, &link).unwrap();
+            assert!(matches!(
+                builder.set_destination(&second, &link),
+                Err(PoolError::DuplicateDestination { writer: 0, .. })
+            ));
+        }
+
+        // Writers of other builders are rejected
+        let strangers: Vec<_> = (0..2)
+            .map(|i| create_output_file_name(&format!("stranger{}.txt.gz", i), &dir.path()))
+            .collect();
+        let mut other_builder = PoolBuilder::<_, BgzfCompressor>::new().threads(1);
+        let skipped = other_builder.exchange(create_output_writer(&strangers[0]));
+        let stranger = other_builder.exchange(create_output_writer(&strangers[1]));
+        assert!(matches!(
+            builder.set_destination(&stranger, &strangers[1]),
+            Err(PoolError::UnknownWriter(1))
+        ));
+        let mut other_pool = other_builder.build().unwrap();
+        skipped.close().unwrap();
+        stranger.close().unwrap();
+        other_pool.stop_pool().unwrap();
+
+        let mut pool = builder.build().unwrap();
+        first.close().unwrap();
+        second.close().unwrap();
+        pool.stop_pool().unwrap();
+    }
+
+    #[test]
+    fn test_writer_ids() {
+        let bytes = Arc::new(Mutex::new(vec![]));
+        let mut builder = PoolBuilder::<Box<dyn Write + Send>, BgzfCompressor>::new().threads(2);
+        let mut plain = builder.exchange(Box::new(vec![]));
+        let mut flaky = builder.exchange(Box::new(FlakyWriter { bytes, calls: 0 }));
+        builder.set_writer_id(&plain, "sample1.R1.fq.gz").unwrap();
+        builder.set_writer_id(&flaky, "sample1.R2.fq.gz").unwrap();
+        assert!(matches!(
+            builder.set_writer_id(&flaky, "sample1.R1.fq.gz"),
+            Err(PoolError::DuplicateWriterId(_))
+        ));
+        // Re-assigning a writer's own ID is allowed
+        builder.set_writer_id(&plain, "sample1.R1.fq.gz").unwrap();
+        assert_eq!(plain.id().as_deref(), Some("sample1.R1.fq.gz"));
+        let mut pool = builder.build().unwrap();
+
+        let data = b"identified\n".repeat(20_000);
+        plain.write_all(&data).unwrap();
+        plain.close().unwrap();
+        let _ = flaky.write_all(&data);
+        let _ = flaky.close();
+        match pool.stop_pool() {
+            Err(PoolError::Writer { id, source }) => {
+                assert_eq!(id, "sample1.R2.fq.gz");
+                assert!(matches!(*source, PoolError::Io(_)));
+            }
+            other => panic!("Expected a writer error, got {:?}", other),
+        }
+
+        assert_eq!(pool.writer_index("sample1.R2.fq.gz"), Some(1));
+        assert_eq!(pool.writer_index("sample2.R1.fq.gz"), None);
+        let stats = pool.stats();
+        assert_eq!(stats.writers[0].id.as_deref(), Some("sample1.R1.fq.gz"));
+        assert_eq!(stats.writers[1].id.as_deref(), Some("sample1.R2.fq.gz"));
+    }
+
+    /// A format of length prefixed blocks, each holding its bytes reversed.
+    struct Reversed;
+
+    impl crate::reader::Decompressor for Reversed {
+        type Error = io::Error;
+
+        fn new() -> Self {
+            Reversed
+        }
+
+        fn read_block<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+            let mut len = [0u8; 4];
+            match reader.read_exact(&mut len) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            }
+            let mut block = vec![0; u32::from_le_bytes(len) as usize];
+            reader.read_exact(&mut block)?;
+            Ok(Some(block))
+        }
+
+        fn decompress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+            output.extend(input.iter().rev());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_exchange_reader() {
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let mut stream = vec![];
+        for chunk in data.chunks(777) {
+            stream.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            stream.extend(chunk.iter().rev());
+        }
+        // An empty block, which is skipped
+        stream.extend_from_slice(&[0, 0, 0, 0]);
+
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(3);
+        let mut reader: crate::reader::PooledReader<_, Reversed> =
+            builder.exchange_reader(stream.as_slice());
+        let mut pool = builder.build().unwrap();
+        let mut actual = vec![];
+        reader.read_to_end(&mut actual).unwrap();
+        pool.stop_pool().unwrap();
+        assert_eq!(actual, data);
+        // The pool's threads are gone, so nothing more can be decompressed
+        let mut late: crate::reader::PooledReader<_, Reversed> =
+            PoolBuilder::<Vec<u8>, BgzfCompressor>::new().exchange_reader(stream.as_slice());
+        assert!(late.read_to_end(&mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_pooled_bgzf_reader() {
+        use crate::bgzf::PooledBgzfReader;
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("read.txt.gz", &dir.path());
+        let data: Vec<u8> = (0..50_000)
+            .flat_map(|i| format!("line {}\n", i * 7919 % 10_007).into_bytes())
+            .collect();
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(4);
+        let mut reader: PooledBgzfReader<_> =
+            builder.exchange_reader(BufReader::new(File::open(&path).unwrap()));
+        let mut pool = builder.build().unwrap();
+        let mut actual = vec![];
+        reader.read_to_end(&mut actual).unwrap();
+        pool.stop_pool().unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_exchange_with_gzi() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("indexed.txt.gz", &dir.path());
+        let gzi = create_output_file_name("indexed.txt.gz.gzi", &dir.path());
+        let data: Vec<u8> =
+            (0..50_000).flat_map(|i| format!("entry {}\n", i * 13 % 997).into_bytes()).collect();
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut writer =
+            builder.exchange_with_gzi(create_output_writer(&path), create_output_writer(&gzi));
+        let mut pool = builder.build().unwrap();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let index = std::fs::read(&gzi).unwrap();
+        let int = |i: usize| u64::from_le_bytes(index[i * 8..(i + 1) * 8].try_into().unwrap());
+        let entries = int(0) as usize;
+        let blocks = (data.len() + BgzfCompressor::BLOCK_SIZE - 1) / BgzfCompressor::BLOCK_SIZE;
+        assert_eq!(entries, blocks);
+        assert_eq!(index.len(), 8 + entries * 16);
+
+        // The last entry is the end of the last block of data, followed by only the EOF block
+        let len = std::fs::metadata(&path).unwrap().len();
+        assert_eq!(int(entries * 2 - 1), len - ::bgzf::BGZF_EOF.len() as u64);
+        assert_eq!(int(entries * 2), data.len() as u64);
+    }
+
+    #[test]
+    fn test_transcoder() {
+        use crate::bgzf::BgzfDecompressor;
+        use crate::transcode::Transcoder;
+
+        let dir = tempdir().unwrap();
+        let input = create_output_file_name("fast.txt.gz", &dir.path());
+        let output = create_output_file_name("small.txt.gz", &dir.path());
+        let data: Vec<u8> =
+            (0..50_000).flat_map(|i| format!("row {}\n", i * 31 % 4_999).into_bytes()).collect();
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().compression_level(1).unwrap();
+        let mut writer = builder.exchange(create_output_writer(&input));
+        let mut pool = builder.build().unwrap();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().compression_level(9).unwrap();
+        let transcoder: Transcoder<_, BgzfDecompressor> =
+            builder.exchange_transcoder(File::open(&input).unwrap(), create_output_writer(&output));
+        let mut pool = builder.build().unwrap();
+        assert_eq!(transcoder.run().unwrap(), data.len() as u64);
+        pool.stop_pool().unwrap();
+
+        let len = |path: &PathBuf| std::fs::metadata(path).unwrap().len();
+        assert!(len(&output) < len(&input));
+        let mut actual = vec![];
+        Reader::new(File::open(&output).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    #[cfg(feature = "gzip_compressor")]
+    fn test_transcode_gzip() {
+        use crate::gzip::{GzipCompressor, GzipDecompressor};
+        use crate::transcode::Transcoder;
+
+        // Named members written by a pool, then members of pseudo-random bytes, which are
+        // stored, and of a few bytes, which use the fixed Huffman codes
+        let dir = tempdir().unwrap();
+        let input = create_output_file_name("pigz.txt.gz", &dir.path());
+        let output = create_output_file_name("bgzf.txt.gz", &dir.path());
+        let mut data: Vec<u8> =
+            (0..50_000).flat_map(|i| format!("row {}\n", i * 31 % 4_999).into_bytes()).collect();
+        let header = GzipHeader::new().file_name("pigz.txt");
+        let mut builder = PoolBuilder::<_, GzipCompressor>::new().gzip_header(header).unwrap();
+        let mut writer = builder.exchange(create_output_writer(&input));
+        let mut pool = builder.build().unwrap();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut seed = 7u32;
+        let noise: Vec<u8> = (0..70_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let mut file = std::fs::OpenOptions::new().append(true).open(&input).unwrap();
+        for member in [&noise[..], &b"tail\n"[..]] {
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+            encoder.write_all(member).unwrap();
+            file.write_all(&encoder.finish().unwrap()).unwrap();
+            data.extend_from_slice(member);
+        }
+        drop(file);
+
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new();
+        let transcoder: Transcoder<_, GzipDecompressor> = builder.exchange_transcoder(
+            BufReader::new(File::open(&input).unwrap()),
+            create_output_writer(&output),
+        );
+        let mut pool = builder.build().unwrap();
+        assert_eq!(transcoder.run().unwrap(), data.len() as u64);
+        pool.stop_pool().unwrap();
+
+        let mut actual = vec![];
+        Reader::new(File::open(&output).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_pool_run() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<_> = (0..2)
+            .map(|i| create_output_file_name(&format!("scoped{}.txt.gz", i), &dir.path()))
+            .collect();
+        let data = b"scoped\n".repeat(20_000);
+
+        let builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let written = Pool::run(builder, |scope| {
+            let mut first = scope.exchange(create_output_writer(&paths[0]))?;
+            let mut second = scope.exchange(create_output_writer(&paths[1]))?;
+            first.write_all(&data)?;
+            // The pool has started, so no more writers can be exchanged
+            assert!(scope.exchange(create_output_writer(&paths[0])).is_err());
+            second.write_all(&data)?;
+            first.close()?;
+            // The second writer is finalized as it is dropped
+            Ok(data.len())
+        })
+        .unwrap();
+        assert_eq!(written, data.len());
+
+        for path in &paths {
+            let mut actual = vec![];
+            Reader::new(File::open(path).unwrap()).read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, data);
+        }
+
+        // Errors from the closure are returned once the pool has been stopped
+        let builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(2);
+        let result: PoolResult<()> = Pool::run(builder, |scope| {
+            scope.exchange(vec![])?.write_all(b"partial")?;
+            Err(PoolError::NoWriters)
+        });
+        assert!(matches!(result, Err(PoolError::NoWriters)));
+    }
+
+    #[test]
+    fn test_parallel_compress_writer() {
+        use crate::parallel::ParallelCompressWriter;
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("parallel.txt.gz", &dir.path());
+        let data = b"one big file\n".repeat(50_000);
+        let mut writer = ParallelCompressWriter::<_, BgzfCompressor>::with_level(
+            create_output_writer(&path),
+            4,
+            6,
+        )
+        .unwrap();
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap();
+
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+        assert!(ParallelCompressWriter::<_, BgzfCompressor>::with_level(vec![], 1, 13).is_err());
+    }
+
+    #[test]
+    fn test_shared_writer_exclusive() {
+        use crate::shared::SharedWriter;
+
+        let shared = SharedWriter::new(vec![]);
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut writer = builder.exchange(shared.clone());
+        let mut pool = builder.build().unwrap();
+
+        let first = b"before\n".repeat(20_000);
+        writer.write_all(&first).unwrap();
+        shared.exclusive(&mut writer).unwrap().write_all(b"RAW").unwrap();
+        writer.write_all(b"after").unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        // The raw bytes follow every block written before the window, and precede the rest
+        let bytes = shared.inner().lock().clone();
+        let mut at = 0;
+        let mut actual = vec![];
+        while actual.len() < first.len() {
+            let len = u16::from_le_bytes([bytes[at + 16], bytes[at + 17]]) as usize + 1;
+            Reader::new(&bytes[at..at + len]).read_to_end(&mut actual).unwrap();
+            at += len;
+        }
+        assert_eq!(actual, first);
+        assert_eq!(&bytes[at..at + 3], b"RAW");
+        let mut rest = vec![];
+        Reader::new(&bytes[at + 3..]).read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"after");
+    }
+
+    #[test]
+    fn test_seek_barrier() {
+        use crate::noop::NoopCompressor;
+        use std::io::Cursor;
+
+        let mut builder = PoolBuilder::<Cursor<Vec<u8>>, NoopCompressor>::new().threads(2);
+        let mut writer = builder.exchange_seekable(Cursor::new(vec![]));
+        let mut unseekable = builder.exchange(Cursor::new(vec![]));
+        let mut pool = builder.build().unwrap();
+
+        // Write a placeholder header and a body, then patch the header with the body's length
+        let body = b"body\n".repeat(30_000);
+        writer.write_all(b"len=??????\n").unwrap();
+        writer.write_all(&body).unwrap();
+        assert_eq!(writer.seek_barrier(SeekFrom::Start(4)).unwrap(), 4);
+        writer.write_all(format!("{:06}", body.len()).as_bytes()).unwrap();
+        let end = writer.seek_barrier(SeekFrom::End(0)).unwrap();
+        assert_eq!(end, 11 + body.len() as u64);
+        writer.write_all(b"end\n").unwrap();
+        writer.close().unwrap();
+        assert!(unseekable.seek_barrier(SeekFrom::Start(0)).is_err());
+        unseekable.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut expected = b"len=150000\n".to_vec();
+        expected.extend_from_slice(&body);
+        expected.extend_from_slice(b"end\n");
+        assert_eq!(pool.quiesce_writer::<Cursor<Vec<u8>>>(0).unwrap().get_ref(), &expected);
+    }
+
+    #[test]
+    fn test_quiesce_writer() {
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(2);
+        let mut writer = builder.exchange(vec![]);
+        let mut pool = builder.build().unwrap();
+
+        let first = b"before\n".repeat(20_000);
+        writer.write_all(&first).unwrap();
+        writer.flush_partial().unwrap();
+        {
+            let mut guard = pool.quiesce_writer::<Vec<u8>>(0).unwrap();
+            assert_eq!(guard.len() as u64, pool.stats().writers[0].compressed_bytes);
+            guard.write_all(b"RAW").unwrap();
+        }
+        assert!(matches!(pool.quiesce_writer::<Vec<u8>>(1), Err(PoolError::UnknownWriter(1))));
+        assert!(matches!(pool.quiesce_writer::<File>(0), Err(PoolError::UnknownWriter(0))));
+        writer.write_all(b"after").unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        // The finished output is still available once the pool has been stopped
+        let bytes = pool.quiesce_writer::<Vec<u8>>(0).unwrap().clone();
+        let mut actual = vec![];
+        let mut at = 0;
+        while actual.len() < first.len() {
+            let len = u16::from_le_bytes([bytes[at + 16], bytes[at + 17]]) as usize + 1;
+            Reader::new(&bytes[at..at + len]).read_to_end(&mut actual).unwrap();
+            at += len;
+        }
+        assert_eq!(actual, first);
+        assert_eq!(&bytes[at..at + 3], b"RAW");
+        let mut rest = vec![];
+        Reader::new(&bytes[at + 3..]).read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"after");
+    }
+
+    #[test]
+    fn test_quiesced_writer_does_not_hold_up_pool_threads() {
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(1);
+        let mut quiesced = builder.exchange(vec![]);
+        let mut other = builder.exchange(vec![]);
+        let mut pool = builder.build().unwrap();
+
+        {
+            let mut guard = pool.quiesce_writer::<Vec<u8>>(0).unwrap();
+            guard.write_all(b"RAW").unwrap();
+            quiesced.write_all(b"held\n").unwrap();
+            quiesced.flush_partial().unwrap();
+            // The only thread writes the other writer's blocks while the quiesced writer's wait
+            other.write_all(&b"other\n".repeat(20_000)).unwrap();
+            other.flush_partial().unwrap();
+            assert!(!pool.quiesce_writer::<Vec<u8>>(1).unwrap().is_empty());
+        }
+        quiesced.close().unwrap();
+        other.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let bytes = pool.quiesce_writer::<Vec<u8>>(0).unwrap().clone();
+        assert_eq!(&bytes[..3], b"RAW");
+        let mut actual = vec![];
+        Reader::new(&bytes[3..]).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, b"held\n");
+    }
+
+    #[test]
+    fn test_detach_and_attach_writer() {
+        use crate::noop::NoopCompressor;
+
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(1);
+        let mut writers: Vec<_> = (0..3).map(|_| builder.exchange(vec![])).collect();
+        builder.set_writer_id(&writers[1], "straggler").unwrap();
+        let mut pool = builder.build().unwrap();
+
+        let data: Vec<Vec<u8>> =
+            (0..3).map(|i| format!("writer {} line\n", i).repeat(20_000).into_bytes()).collect();
+        for (writer, data) in writers.iter_mut().zip(&data) {
+            writer.write_all(&data[..data.len() / 2]).unwrap();
+        }
+        let unattached = writers.pop().unwrap();
+        let moved = writers.pop().unwrap();
+
+        // The straggler carries on in a pool of its own, from where it left off
+        let detached = pool.detach_writer::<Vec<u8>>(moved).unwrap();
+        let mut slow = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(1);
+        let mut moved = slow.attach(detached).unwrap();
+        assert_eq!(moved.id().as_deref(), Some("straggler"));
+        let mut slow_pool = slow.build().unwrap();
+        moved.write_all(&data[1][data[1].len() / 2..]).unwrap();
+        writers[0].write_all(&data[0][data[0].len() / 2..]).unwrap();
+        moved.close().unwrap();
+        writers.pop().unwrap().close().unwrap();
+        slow_pool.stop_pool().unwrap();
+
+        // A writer can only be attached to a pool with the same compressor
+        let detached = pool.detach_writer::<Vec<u8>>(unattached).unwrap();
+        let mut other = PoolBuilder::<Vec<u8>, NoopCompressor>::new();
+        assert!(matches!(other.attach(detached), Err(PoolError::UnsupportedOption(_))));
+        assert!(matches!(pool.quiesce_writer::<Vec<u8>>(1), Err(PoolError::UnknownWriter(1))));
+        pool.stop_pool().unwrap();
+
+        let outputs = [
+            pool.quiesce_writer::<Vec<u8>>(0).unwrap().clone(),
+            slow_pool.quiesce_writer::<Vec<u8>>(0).unwrap().clone(),
+        ];
+        for (output, data) in outputs.iter().zip(&data) {
+            let mut actual = vec![];
+            Reader::new(output.as_slice()).read_to_end(&mut actual).unwrap();
+            assert_eq!(&actual, data);
+        }
+        assert_eq!(pool.stats().writers[1].remaining_blocks(), 0);
+    }
+
+    #[test]
+    fn test_exchange_callback() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("file.txt.gz", &dir.path());
+        let (tx, rx) = std::sync::mpsc::channel::<Vec<u8>>();
+        let mut builder = PoolBuilder::<Box<dyn Write + Send>, BgzfCompressor>::new().threads(2);
+        let mut to_file = builder.exchange(Box::new(create_output_writer(&path)));
+        let mut to_channel = builder.exchange_callback(move |block| {
+            tx.send(block.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+        });
+        let mut pool = builder.build().unwrap();
+
+        let data = b"the same bytes to both\n".repeat(10_000);
+        to_file.write_all(&data).unwrap();
+        to_channel.write_all(&data).unwrap();
+        to_file.close().unwrap();
+        to_channel.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let received: Vec<u8> = rx.iter().flatten().collect();
+        assert_eq!(received, std::fs::read(&path).unwrap());
+        let mut actual = vec![];
+        Reader::new(received.as_slice()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    /// A writer that counts the bytes written through it.
+    struct CountingWriter {
+        inner: Box<dyn Write + Send>,
+        count: Arc<AtomicUsize>,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = self.inner.write(buf)?;
+            self.count.fetch_add(n, Ordering::Relaxed);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn test_map_writers() {
+        let dir = tempdir().unwrap();
+        let prefix = dir.path().to_path_buf();
+        let path = move |i: usize| prefix.join(format!("mapped{}.txt.gz", i));
+        let count = Arc::new(AtomicUsize::new(0));
+        let wrapped = count.clone();
+        let mut builder = PoolBuilder::<Box<dyn Write + Send>, BgzfCompressor>::new()
+            .threads(2)
+            .map_writers(move |inner| Box::new(CountingWriter { inner, count: wrapped.clone() }));
+        let mut writer = builder.exchange(Box::new(create_output_writer(path(0))));
+        let factory_path = path.clone();
+        let mut split = builder
+            .exchange_split_by_records(
+                1,
+                Box::new(move |i| {
+                    File::create(factory_path(i + 1)).map(|f| Box::new(f) as Box<dyn Write + Send>)
+                }),
+            )
+            .unwrap();
+        let mut pool = builder.build().unwrap();
+
+        writer.write_all(b"hello").unwrap();
+        for record in &[b"first\n", b"again\n"] {
+            split.write_all(*record).unwrap();
+            split.end_record().unwrap();
+        }
+        writer.close().unwrap();
+        split.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        // Every output, including those opened by the split writer, was counted
+        let total: u64 = (0..3).map(|i| std::fs::metadata(path(i)).unwrap().len()).sum();
+        assert_eq!(count.load(Ordering::Relaxed) as u64, total);
+    }
+
+    #[test]
+    fn test_handoff_pool_chain() {
+        use crate::handoff::{Handoff, PoolChain};
+        use crate::noop::NoopCompressor;
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("chained.txt.gz", &dir.path());
+        let mut upload = PoolBuilder::<_, NoopCompressor>::new().threads(1).queue_size(2);
+        let uploaded = upload.exchange(create_output_writer(&path));
+        let upload = upload.build().unwrap();
+
+        let handoff = Handoff::new(uploaded);
+        let mut compress = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut writer = compress.exchange(handoff.clone());
+        let compress = compress.build().unwrap();
+        let mut chain =
+            PoolChain::new().stage(compress, vec![handoff.clone()]).stage(upload, vec![]);
+        assert_eq!(chain.pools().count(), 2);
+
+        let data = b"compressed then uploaded\n".repeat(20_000);
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        chain.stop().unwrap();
+
+        // The downstream writer was closed after the upstream pool stopped
+        assert!(handoff.clone().write_all(b"late").is_err());
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    /// A writer that is not `Send`, standing in for a wrapper over an FFI handle.
+    struct LocalWriter {
+        bytes: std::rc::Rc<std::cell::RefCell<Vec<u8>>>,
+    }
+
+    impl Write for LocalWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.bytes.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_exchange_marshaled() {
+        let mut builder = PoolBuilder::<Box<dyn Write + Send>, BgzfCompressor>::new().threads(2);
+        let (mut writer, mut pump) =
+            builder.exchange_marshaled(LocalWriter { bytes: std::rc::Rc::default() });
+        let mut pool = builder.build().unwrap();
+
+        let data = b"written on the owning thread\n".repeat(20_000);
+        for chunk in data.chunks(BgzfCompressor::BLOCK_SIZE) {
+            writer.write_all(chunk).unwrap();
+            assert!(!pump.pump().unwrap());
+        }
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+        let local = pump.finish().unwrap();
+
+        let compressed = local.bytes.borrow().clone();
+        let mut actual = vec![];
+        Reader::new(compressed.as_slice()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_max_in_flight_blocks() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("hot.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<Box<dyn Write + Send>, BgzfCompressor>::new()
+            .threads(2)
+            .max_in_flight_blocks(2);
+        let mut file = create_output_writer(&path);
+        let mut writer = builder.exchange_callback(move |block| {
+            std::thread::sleep(Duration::from_millis(2));
+            file.write_all(block)
+        });
+        let mut pool = builder.build().unwrap();
+
+        let data: Vec<u8> = (0..20 * BgzfCompressor::BLOCK_SIZE).map(|i| (i % 31) as u8).collect();
+        for block in data.chunks(BgzfCompressor::BLOCK_SIZE) {
+            writer.write_all(block).unwrap();
+            assert!(pool.stats().writers[0].remaining_blocks() <= 2);
+        }
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_max_in_flight_bytes() {
+        let bytes = Arc::new(InFlightLimit::new(100));
+        assert!(bytes.acquire(60));
+        let waiter = {
+            let bytes = bytes.clone();
+            std::thread::spawn(move || bytes.acquire(60))
+        };
+        bytes.release(60);
+        assert!(waiter.join().unwrap());
+        bytes.release(60);
+        // A block over the limit is let through once nothing else is in flight
+        assert!(bytes.acquire(150));
+        // Writers waiting for the limit are failed once the pool stops
+        let waiter = {
+            let bytes = bytes.clone();
+            std::thread::spawn(move || bytes.acquire(1))
         };
+        bytes.close();
+        assert!(!waiter.join().unwrap());
+
+        let dir = tempdir().unwrap();
+        let limit = 3 * BgzfCompressor::BLOCK_SIZE;
+        let mut builder = PoolBuilder::<Box<dyn Write + Send>, BgzfCompressor>::new()
+            .threads(2)
+            .max_in_flight_bytes(limit);
+        let paths: Vec<_> = (0..3)
+            .map(|i| create_output_file_name(&format!("slow{}.txt.gz", i), &dir.path()))
+            .collect();
+        let mut writers: Vec<_> = paths
+            .iter()
+            .map(|path| {
+                let mut file = create_output_writer(path);
+                builder.exchange_callback(move |block| {
+                    std::thread::sleep(Duration::from_millis(2));
+                    file.write_all(block)
+                })
+            })
+            .collect();
+        let mut pool = builder.build().unwrap();
+
+        let data: Vec<u8> = (0..10 * BgzfCompressor::BLOCK_SIZE).map(|i| (i % 29) as u8).collect();
+        for block in data.chunks(BgzfCompressor::BLOCK_SIZE) {
+            for writer in &mut writers {
+                writer.write_all(block).unwrap();
+                assert!(pool.stats().remaining_bytes() <= limit as u64);
+            }
+        }
+        writers.into_iter().try_for_each(|w| w.close()).unwrap();
+        pool.stop_pool().unwrap();
+
+        for path in &paths {
+            let mut actual = vec![];
+            Reader::new(File::open(path).unwrap()).read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, data);
+        }
+    }
+
+    #[test]
+    fn test_thread_spawner() {
+        let dir = tempdir().unwrap();
+        let output = create_output_file_name("spawned.txt.gz", &dir.path());
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let spawned = Arc::clone(&names);
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+            .threads(2)
+            .thread_name_prefix("bgzf")
+            .thread_stack_size(4 * 1024 * 1024)
+            .thread_spawner(Arc::new(move |thread: spawn::PoolThread| {
+                assert_eq!(thread.stack_size(), Some(4 * 1024 * 1024));
+                spawned.lock().push(thread.name().unwrap().to_string());
+                thread.spawn()
+            }));
+        let mut writer = builder.exchange(create_output_writer(&output));
+        let mut pool = builder.build().unwrap();
+
+        let data: Vec<u8> = (0..3 * BgzfCompressor::BLOCK_SIZE).map(|i| (i % 31) as u8).collect();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut names = names.lock().clone();
+        names.sort();
+        assert_eq!(names, vec!["bgzf-0", "bgzf-1", "bgzf-main"]);
+        let mut actual = vec![];
+        Reader::new(File::open(&output).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    #[cfg(feature = "snappy_compressor")]
+    fn test_snappy_compressor() {
+        use crate::snappy::SnappyCompressor;
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("test.txt.sz", &dir.path());
+        let mut builder = PoolBuilder::<_, SnappyCompressor>::new().threads(2);
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        let data: Vec<u8> =
+            (0..5 * SnappyCompressor::BLOCK_SIZE / 2).map(|i| (i % 79) as u8).collect();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut actual = vec![];
+        snap::read::FrameDecoder::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+        assert!(PoolBuilder::<File, SnappyCompressor>::new().compression_level(1).is_err());
+
+        let mut builder = PoolBuilder::<Vec<u8>, SnappyCompressor>::new().threads(2);
+        let mut reader: crate::reader::PooledReader<_, crate::snappy::SnappyDecompressor> =
+            builder.exchange_reader(File::open(&path).unwrap());
+        let mut pool = builder.build().unwrap();
+        let mut actual = vec![];
+        reader.read_to_end(&mut actual).unwrap();
+        pool.stop_pool().unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_noop_compressor() {
+        use crate::noop::NoopCompressor;
+
+        let dir = tempdir().unwrap();
+        let paths: Vec<_> = (0..3)
+            .map(|i| create_output_file_name(&format!("test{}.txt", i), &dir.path()))
+            .collect();
+        let mut builder = PoolBuilder::<_, NoopCompressor>::new().threads(2);
+        let mut writers: Vec<_> =
+            paths.iter().map(|p| builder.exchange(create_output_writer(p))).collect();
+        let mut pool = builder.build().unwrap();
+
+        let data: Vec<u8> =
+            (0..5 * NoopCompressor::BLOCK_SIZE / 2).map(|i| (i % 79) as u8).collect();
+        for writer in writers.iter_mut() {
+            writer.write_all(&data).unwrap();
+        }
+        writers.into_iter().try_for_each(|w| w.close()).unwrap();
+        pool.stop_pool().unwrap();
+
+        for path in paths {
+            assert_eq!(std::fs::read(&path).unwrap(), data);
+        }
+        assert!(PoolBuilder::<File, NoopCompressor>::new().compression_level(1).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "indicatif_progress")]
+    fn test_pool_progress() {
+        use crate::progress::PoolProgress;
+        use indicatif::{MultiProgress, ProgressDrawTarget};
+
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(2);
+        let mut first = builder.exchange(vec![]);
+        let mut second = builder.exchange(vec![]);
+        builder.set_writer_id(&second, "second").unwrap();
+        let mut pool = builder.build().unwrap();
+        let mut progress =
+            PoolProgress::new(MultiProgress::with_draw_target(ProgressDrawTarget::hidden()));
+        progress.update(&pool.stats());
+        assert_eq!(progress.total_bar().position(), 0);
+
+        first.write_all(&b"first\n".repeat(20_000)).unwrap();
+        second.write_all(&b"second\n".repeat(10_000)).unwrap();
+        first.close().unwrap();
+        second.close().unwrap();
+        pool.stop_pool_with_progress(Duration::from_millis(1), |stats| progress.update(stats))
+            .unwrap();
+        progress.finish();
+
+        let first = progress.writer_bar(0).unwrap();
+        assert_eq!(first.position(), 120_000);
+        assert_eq!(progress.writer_bar(1).unwrap().prefix(), "second");
+        assert_eq!(progress.total_bar().position(), 190_000);
+        assert!(progress.writer_bar(2).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "thread_priority")]
+    fn test_thread_priority() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("niced.txt.gz", &dir.path());
+        let mut builder =
+            PoolBuilder::<_, BgzfCompressor>::new().threads(2).thread_priority(ThreadPriority::Min);
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        let data = "lower priority\n".repeat(10_000);
+        writer.write_all(data.as_bytes()).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data.as_bytes());
+    }
+
+    #[test]
+    fn test_exchange_with_compressor() {
+        use crate::noop::NoopCompressor;
+
+        let dir = tempdir().unwrap();
+        let bgzf = create_output_file_name("default.txt.gz", &dir.path());
+        let fast = create_output_file_name("fast.txt.gz", &dir.path());
+        let plain = create_output_file_name("plain.txt", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut writers = vec![
+            builder.exchange(create_output_writer(&bgzf)),
+            builder
+                .exchange_with_compressor::<BgzfCompressor>(create_output_writer(&fast), Some(1))
+                .unwrap(),
+            builder
+                .exchange_with_compressor::<NoopCompressor>(create_output_writer(&plain), None)
+                .unwrap(),
+        ];
+        let mut pool = builder.build().unwrap();
+
+        let data = "heterogeneous\n".repeat(20_000);
+        for writer in writers.iter_mut() {
+            writer.write_all(data.as_bytes()).unwrap();
+        }
+        assert_eq!(writers[2].block_size(), NoopCompressor::BLOCK_SIZE);
+        writers.into_iter().try_for_each(|w| w.close()).unwrap();
+        pool.stop_pool().unwrap();
+
+        for path in [&bgzf, &fast] {
+            let mut actual = vec![];
+            Reader::new(File::open(path).unwrap()).read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, data.as_bytes());
+        }
+        assert_eq!(std::fs::read(&plain).unwrap(), data.as_bytes());
+
+        // Only the pool's own compressor type is included in the per-level statistics
+        let levels: Vec<_> = pool.stats().levels.iter().map(|l| l.level).collect();
+        assert_eq!(levels, [None, Some(1)]);
+
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+            .block_size_tuning(BlockSizeTuning::halving(BgzfCompressor::BLOCK_SIZE))
+            .unwrap();
+        let path = create_output_file_name("tuned.txt", &dir.path());
+        assert!(builder
+            .exchange_with_compressor::<NoopCompressor>(create_output_writer(&path), None)
+            .is_err());
+        assert!(builder
+            .exchange_with_compressor::<NoopCompressor>(create_output_writer(&path), Some(3))
+            .is_err());
+    }
+
+    #[test]
+    fn test_exchange_with_level() {
+        let dir = tempdir().unwrap();
+        let temp = create_output_file_name("temp.txt.gz", &dir.path());
+        let last = create_output_file_name("final.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut temp_writer = builder.exchange_with_level(create_output_writer(&temp), 1).unwrap();
+        let mut last_writer = builder.exchange_with_level(create_output_writer(&last), 7).unwrap();
+        assert!(builder.exchange_with_level(create_output_writer(&last), 13).is_err());
+        let mut pool = builder.build().unwrap();
+
+        let data = "per-writer level\n".repeat(10_000);
+        temp_writer.write_all(data.as_bytes()).unwrap();
+        last_writer.write_all(data.as_bytes()).unwrap();
+        temp_writer.close().unwrap();
+        last_writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let stats = pool.stats();
+        assert_eq!(stats.levels.iter().map(|l| l.level).collect::<Vec<_>>(), [Some(1), Some(7)]);
+        assert_eq!(stats.level(Some(1)).unwrap().blocks, stats.writers[0].blocks);
+        assert_eq!(stats.level(Some(7)).unwrap().blocks, stats.writers[1].blocks);
+        for path in [&temp, &last] {
+            let mut actual = vec![];
+            Reader::new(File::open(path).unwrap()).read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, data.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_reconfigure() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("reconfigured.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut writer = builder
+            .exchange_with_options(create_output_writer(&path), ExchangeOptions::new())
+            .unwrap();
+        let mut pool = builder.build().unwrap();
+
+        let data = vec![b'A'; 5 * BgzfCompressor::BLOCK_SIZE / 2];
+        writer.write_all(&data).unwrap();
+        let options = ExchangeOptions::new()
+            .compression_level(1)
+            .coalesce_partial_flushes(true)
+            .flush_each_block(true);
+        assert!(writer.reconfigure(ExchangeOptions::new().compression_level(13)).is_err());
+        writer.reconfigure(options).unwrap();
+        assert_eq!(writer.options(), options);
+
+        // Partial flushes are coalesced, so the buffered half block isn't sent yet
+        writer.write_all(b"coalesced").unwrap();
+        writer.flush_partial().unwrap();
+        assert_eq!(pool.stats().writers[0].blocks, 2);
+
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let stats = pool.stats();
+        assert_eq!(stats.level(None).unwrap().blocks, 2);
+        assert_eq!(stats.level(Some(1)).unwrap().blocks, stats.writers[0].blocks - 2);
+        let mut expected = data.clone();
+        expected.extend_from_slice(b"coalesced");
+        expected.extend_from_slice(&data);
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_omit_eof_marker() {
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(2);
+        let omit = ExchangeOptions::new().omit_eof_marker(true);
+        let mut first = builder.exchange_with_options(vec![], omit).unwrap();
+        let mut second = builder.exchange_with_options(vec![], omit).unwrap();
+        let mut last = builder.exchange(vec![]);
+        let mut pool = builder.build().unwrap();
+
+        let shards = [b"first shard\n".repeat(10_000), b"second\n".repeat(100), b"last\n".to_vec()];
+        for (writer, shard) in [&mut first, &mut second, &mut last].iter_mut().zip(&shards) {
+            writer.write_all(shard).unwrap();
+        }
+        first.close().unwrap();
+        second.close().unwrap();
+        last.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let mut joined = vec![];
+        for index in 0..3 {
+            let shard = pool.quiesce_writer::<Vec<u8>>(index).unwrap().clone();
+            assert_eq!(shard.ends_with(&bgzf_eof()), index == 2);
+            joined.extend(shard);
+        }
+        let mut actual = vec![];
+        Reader::new(joined.as_slice()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, shards.concat());
+
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().compressor_per_writer(true);
+        assert!(builder.exchange_with_options(vec![], omit).is_err());
+    }
+
+    #[test]
+    fn test_exchange_append() {
+        use std::io::Cursor;
+
+        let first = b"first run\n".repeat(20_000);
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(2);
+        let mut writer = builder.exchange(vec![]);
+        let mut pool = builder.build().unwrap();
+        writer.write_all(&first).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+        let existing = pool.quiesce_writer::<Vec<u8>>(0).unwrap().clone();
+        let resumed_at = (existing.len() - bgzf_eof().len()) as u64;
+
+        let second = b"second run\n".repeat(20_000);
+        let mut builder = PoolBuilder::<Cursor<Vec<u8>>, BgzfCompressor>::new()
+            .threads(2)
+            .virtual_offsets(true)
+            .unwrap();
+        let mut writer = builder.exchange_append(Cursor::new(existing)).unwrap();
+        assert!(builder.exchange_append(Cursor::new(b"plain text".to_vec())).is_err());
+        let mut pool = builder.build().unwrap();
+        let offset = writer.virtual_offset().unwrap();
+        writer.write_all(&second).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+        assert_eq!(offset.wait(), Some(resumed_at << 16));
+
+        let appended = pool.quiesce_writer::<Cursor<Vec<u8>>>(0).unwrap().get_ref().clone();
+        let eof = bgzf_eof();
+        assert!(appended.ends_with(&eof));
+        assert_eq!(appended.windows(eof.len()).filter(|w| *w == eof.as_slice()).count(), 1);
+        let mut actual = vec![];
+        Reader::new(appended.as_slice()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, [first, second].concat());
+    }
+
+    /// A passthrough compressor that finishes each stream with a multi-byte trailer.
+    struct TrailerCompressor;
+
+    impl Compressor for TrailerCompressor {
+        type Error = io::Error;
+        type CompressionLevel = ();
+
+        fn new(_level: Self::CompressionLevel) -> Self {
+            Self
+        }
+
+        fn default_compression_level() -> Self::CompressionLevel {}
+
+        fn new_compression_level(_level: u8) -> io::Result<Self::CompressionLevel> {
+            Ok(())
+        }
+
+        fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+            output.extend_from_slice(input);
+            Ok(())
+        }
+
+        fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+            output.extend_from_slice(b"<trailer>");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_compressor_finish() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<_> = (0..2)
+            .map(|i| create_output_file_name(&format!("test{}.txt", i), &dir.path()))
+            .collect();
+        let mut builder = PoolBuilder::<_, TrailerCompressor>::new().threads(2);
+        let mut writers: Vec<_> =
+            paths.iter().map(|p| builder.exchange(create_output_writer(p))).collect();
+        let mut pool = builder.build().unwrap();
+
+        let data = "finished\n".repeat(20_000);
+        writers[0].write_all(data.as_bytes()).unwrap();
+        writers.into_iter().try_for_each(|w| w.close()).unwrap();
+        pool.stop_pool().unwrap();
+
+        assert_eq!(std::fs::read(&paths[0]).unwrap(), format!("{}<trailer>", data).as_bytes());
+        assert_eq!(std::fs::read(&paths[1]).unwrap(), b"<trailer>");
+    }
+
+    /// A passthrough compressor that finishes each stream with the length and CRC32 of the whole
+    /// stream, which it keeps track of across blocks.
+    #[derive(Default)]
+    struct RunningCrcCompressor {
+        stream: Vec<u8>,
+    }
+
+    impl Compressor for RunningCrcCompressor {
+        type Error = io::Error;
+        type CompressionLevel = ();
+
+        const BLOCK_SIZE: usize = 64;
+
+        fn capabilities() -> CompressorCapabilities {
+            CompressorCapabilities::new(Self::BLOCK_SIZE).eof_marker(true).stateful(true)
+        }
+
+        fn new(_level: Self::CompressionLevel) -> Self {
+            Self::default()
+        }
+
+        fn default_compression_level() -> Self::CompressionLevel {}
+
+        fn new_compression_level(_level: u8) -> io::Result<Self::CompressionLevel> {
+            Ok(())
+        }
+
+        fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+            self.stream.extend_from_slice(input);
+            output.extend_from_slice(input);
+            Ok(())
+        }
+
+        fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+            output.extend_from_slice(&(self.stream.len() as u64).to_le_bytes());
+            output.extend_from_slice(&libdeflater::crc32(&self.stream).to_le_bytes());
+            Ok(())
+        }
+    }
+
+    /// A [`RunningCrcCompressor`] that doesn't report that it is stateful.
+    #[derive(Default)]
+    struct UndeclaredRunningCrcCompressor(RunningCrcCompressor);
+
+    impl Compressor for UndeclaredRunningCrcCompressor {
+        type Error = io::Error;
+        type CompressionLevel = ();
+
+        const BLOCK_SIZE: usize = RunningCrcCompressor::BLOCK_SIZE;
+
+        fn new(_level: Self::CompressionLevel) -> Self {
+            Self::default()
+        }
+
+        fn default_compression_level() -> Self::CompressionLevel {}
+
+        fn new_compression_level(_level: u8) -> io::Result<Self::CompressionLevel> {
+            Ok(())
+        }
+
+        fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+            self.0.compress(input, output)
+        }
+
+        fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+            self.0.finish(output)
+        }
+    }
+
+    /// Writes interleaved data to four writers and checks that each has the trailer of a
+    /// [`RunningCrcCompressor`] that saw just its own stream.
+    fn check_per_writer_compressors<C: Compressor>(builder: PoolBuilder<BufWriter<File>, C>) {
+        let dir = tempdir().unwrap();
+        let paths: Vec<_> = (0..4)
+            .map(|i| create_output_file_name(&format!("test{}.txt", i), &dir.path()))
+            .collect();
+        let mut builder = builder.threads(4);
+        assert!(builder.exchange_with_level(create_output_writer(&paths[0]), 1).is_err());
+        let mut writers: Vec<_> =
+            paths.iter().map(|p| builder.exchange(create_output_writer(p))).collect();
+        assert!(writers[0].reconfigure(ExchangeOptions::new().compression_level(1)).is_err());
+        let mut pool = builder.build().unwrap();
+
+        let data: Vec<String> =
+            (0..4).map(|i| (0..2_000).map(|j| format!("{}:{} ", i, j)).collect()).collect();
+        for chunk in 0..100 {
+            for (writer, data) in writers.iter_mut().zip(&data) {
+                let step = data.len() / 100;
+                writer.write_all(&data.as_bytes()[chunk * step..(chunk + 1) * step]).unwrap();
+            }
+        }
+        for (writer, data) in writers.iter_mut().zip(&data) {
+            writer.write_all(&data.as_bytes()[data.len() / 100 * 100..]).unwrap();
+        }
+        writers.into_iter().try_for_each(|w| w.close()).unwrap();
+        pool.stop_pool().unwrap();
+
+        for (path, data) in paths.iter().zip(&data) {
+            let mut expected = data.as_bytes().to_vec();
+            expected.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            expected.extend_from_slice(&libdeflater::crc32(data.as_bytes()).to_le_bytes());
+            assert_eq!(std::fs::read(path).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_stateful_compressor() {
+        check_per_writer_compressors(PoolBuilder::<_, RunningCrcCompressor>::new());
+    }
+
+    #[test]
+    fn test_compressor_per_writer() {
+        check_per_writer_compressors(
+            PoolBuilder::<_, UndeclaredRunningCrcCompressor>::new().compressor_per_writer(true),
+        );
+    }
+
+    #[test]
+    fn test_doctor() {
+        use crate::doctor::Bottleneck;
+
+        let dir = tempdir().unwrap();
+        let mut n = 0;
+        let builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let report = builder
+            .doctor(|| {
+                n += 1;
+                File::create(dir.path().join(format!("scratch{}.gz", n)))
+            })
+            .unwrap();
+
+        // One writer for the IO measurement and one per thread for the trial pool
+        assert_eq!(n, 3);
+        assert_eq!(report.threads, 2);
+        assert!(report.compression_mb_per_sec_per_thread > 0.0);
+        assert!(report.io_mb_per_sec > 0.0);
+        assert!(report.pool_mb_per_sec > 0.0);
+        assert!(!report.suggestions.is_empty());
+        let expected = report.compression_capacity_mb_per_sec().min(report.io_mb_per_sec) * 0.5;
+        assert_eq!(report.bottleneck == Bottleneck::Channel, report.pool_mb_per_sec < expected);
+    }
+
+    #[test]
+    fn test_dyn_compressor() {
+        use crate::dynamic::{CompressorChoice, DynCompressor};
+
+        assert!(CompressorChoice::from_name("bogus", None).is_err());
+        assert!(CompressorChoice::from_name("bgzf", Some(13)).is_err());
+        assert!(PoolBuilder::<Vec<u8>, DynCompressor>::new().compression_level(3).is_err());
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("dyn.txt.gz", &dir.path());
+        let choice = CompressorChoice::from_name("bgzf", Some(3)).unwrap();
+        assert_eq!(choice.level(), Some(3));
+        assert!(choice.capabilities().supports_eof_marker);
+        let mut builder = PoolBuilder::<_, DynCompressor>::new().threads(2).compressor(choice);
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        let data = "chosen at runtime\n".repeat(10_000);
+        writer.write_all(data.as_bytes()).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        assert_eq!(pool.stats().levels.iter().map(|l| l.level).collect::<Vec<_>>(), [Some(3)]);
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data.as_bytes());
+        assert!(std::fs::read(&path).unwrap().ends_with(::bgzf::BGZF_EOF));
+    }
+
+    #[test]
+    fn test_streaming_mode() {
+        let dir = tempdir().unwrap();
+        let flushed = create_output_file_name("flushed.txt.gz", &dir.path());
+        let eager = create_output_file_name("eager.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let long = ExchangeOptions::new().streaming(Duration::from_secs(3600));
+        let mut flushed_writer =
+            builder.exchange_with_options(create_output_writer(&flushed), long).unwrap();
+        let zero = ExchangeOptions::new().streaming(Duration::from_secs(0));
+        let mut eager_writer =
+            builder.exchange_with_options(create_output_writer(&eager), zero).unwrap();
+        let mut pool = builder.build().unwrap();
+
+        // A flushed frame reaches the file without waiting for a full block or the end of stream
+        flushed_writer.write_all(b"first line\n").unwrap();
+        assert_eq!(pool.stats().writers[0].blocks, 0);
+        flushed_writer.flush().unwrap();
+        let start = std::time::Instant::now();
+        while std::fs::metadata(&flushed).unwrap().len() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(10), "Frame was not written");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        flushed_writer.write_all(b"second line\n").unwrap();
+
+        // With no delay allowed, every write is sent as its own frame
+        for i in 0..5 {
+            eager_writer.write_all(format!("line {}\n", i).as_bytes()).unwrap();
+        }
+        flushed_writer.close().unwrap();
+        eager_writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let stats = pool.stats();
+        assert_eq!((stats.writers[0].blocks, stats.writers[0].partial_blocks), (2, 1));
+        assert_eq!((stats.writers[1].blocks, stats.writers[1].partial_blocks), (6, 5));
+        let mut actual = vec![];
+        Reader::new(File::open(&flushed).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, b"first line\nsecond line\n");
+        let mut actual = vec![];
+        Reader::new(File::open(&eager).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, (0..5).map(|i| format!("line {}\n", i)).collect::<String>().as_bytes());
+    }
+
+    #[test]
+    fn test_exchange_with_header() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<_> = (0..2)
+            .map(|i| create_output_file_name(&format!("test{}.txt.gz", i), &dir.path()))
+            .collect();
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let too_long = vec![b'H'; BgzfCompressor::BLOCK_SIZE + 1];
+        assert!(builder.exchange_with_header(create_output_writer(&paths[0]), &too_long).is_err());
+        let mut writers: Vec<_> = paths
+            .iter()
+            .map(|p| builder.exchange_with_header(create_output_writer(p), b"#header\n").unwrap())
+            .collect();
+        let mut pool = builder.build().unwrap();
+
+        let data = "record\n".repeat(20_000);
+        writers[0].write_all(data.as_bytes()).unwrap();
+        writers.into_iter().try_for_each(|w| w.close()).unwrap();
+        pool.stop_pool().unwrap();
+
+        for (path, expected) in paths.iter().zip([format!("#header\n{}", data), "#header\n".into()])
+        {
+            let mut actual = vec![];
+            Reader::new(File::open(path).unwrap()).read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_adaptive_compression() {
+        use crate::adaptive::AdaptiveCompression;
+
+        let builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new();
+        assert!(builder.adaptive_compression(AdaptiveCompression::new(1, 13)).is_err());
+
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("adaptive.txt.gz", &dir.path());
+        let config = AdaptiveCompression::new(1, 9).water_marks(0.1, 0.5).patience(1);
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+            .threads(1)
+            .queue_size(4)
+            .adaptive_compression(config)
+            .unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+
+        // Fill the queue before the pool starts, so that the first blocks are taken from a full
+        // queue and the remainder from an idle one
+        let data = vec![b'A'; 4 * BgzfCompressor::BLOCK_SIZE];
+        writer.write_all(&data).unwrap();
+        let mut pool = builder.build().unwrap();
+        std::thread::sleep(Duration::from_millis(250));
+        assert_eq!(pool.adaptive_compression_level(), Some(9));
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        let levels: Vec<_> = pool.stats().levels.iter().map(|l| l.level).collect();
+        assert!(levels.iter().any(|&l| l < Some(9)), "Level was not lowered: {:?}", levels);
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_autoscale() {
+        use crate::autoscale::{AutoScaling, ThreadScaler};
+
+        let config = AutoScaling::new(1, 8).water_marks(0.1, 0.5).patience(2);
+        let scaler = ThreadScaler::new(&config, 3);
+        let active = AtomicUsize::new(scaler.initial_threads());
+        assert_eq!(active.load(Ordering::Relaxed), 1);
+        assert!(!scaler.observe(4, 4, &active, 3));
+        assert!(scaler.observe(4, 4, &active, 3));
+        assert_eq!(active.load(Ordering::Relaxed), 2);
+        (0..4).for_each(|_| {
+            scaler.observe(3, 4, &active, 3);
+        });
+        // Capped at the number of threads in the pool
+        assert_eq!(active.load(Ordering::Relaxed), 3);
+        (0..2).for_each(|_| {
+            scaler.observe(1, 4, &active, 3);
+        });
+        assert_eq!(active.load(Ordering::Relaxed), 3);
+        (0..10).for_each(|_| {
+            scaler.observe(0, 4, &active, 3);
+        });
+        assert_eq!(active.load(Ordering::Relaxed), 1);
+        // Capped at the limit set by the application, which moves the number back down
+        (0..10).for_each(|_| {
+            scaler.observe(4, 4, &active, 2);
+        });
+        assert_eq!(active.load(Ordering::Relaxed), 2);
+        scaler.observe(2, 4, &active, 1);
+        assert_eq!(active.load(Ordering::Relaxed), 1);
 
-        Ok(pool)
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("autoscale.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+            .threads(4)
+            .queue_size(4)
+            .autoscale(AutoScaling::new(2, 4).patience(1));
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+        assert_eq!(pool.max_active_threads(), 2);
+
+        let data: Vec<u8> = (0..16 * BgzfCompressor::BLOCK_SIZE).map(|i| (i % 37) as u8).collect();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        assert!((2..=4).contains(&pool.max_active_threads()));
+        let mut actual = vec![];
+        Reader::new(File::open(&path).unwrap()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+
+        // A limit set by the application holds however busy the pool
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new()
+            .threads(4)
+            .queue_size(4)
+            .autoscale(AutoScaling::new(2, 4).patience(1));
+        let mut writer = builder.exchange(vec![]);
+        let mut pool = builder.build().unwrap();
+        pool.set_max_active_threads(1);
+        writer.write_all(&data).unwrap();
+        assert_eq!(pool.max_active_threads(), 1);
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+        assert_eq!(pool.max_active_threads(), 1);
     }
-}
 
-impl<W, C> Default for PoolBuilder<W, C>
-where
-    W: Write + Send + 'static,
-    C: Compressor,
-{
-    fn default() -> Self {
-        Self::new()
+    #[test]
+    fn test_empty_pool_policy() {
+        let mut pool = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(4).build().unwrap();
+        assert!(pool.pool_handle.is_none());
+        assert_eq!(pool.threads(), 4);
+        pool.stop_pool().unwrap();
+
+        let pool = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().build().unwrap();
+        drop(pool);
+
+        let result = PoolBuilder::<Vec<u8>, BgzfCompressor>::new()
+            .empty_pool_policy(EmptyPoolPolicy::Error)
+            .build();
+        assert!(matches!(result, Err(PoolError::NoWriters)));
     }
-}
 
-////////////////////////////////////////////////////////////////////////////////
-// The Pool struct and impls
-////////////////////////////////////////////////////////////////////////////////
+    #[test]
+    fn test_completion_handle() {
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(2);
+        let mut writer = builder.exchange(vec![]);
+        let mut pool = builder.build().unwrap();
+        let handle = pool.completion_handle();
+        let receiver = handle.receiver();
+        let supervisor = {
+            let handle = handle.clone();
+            std::thread::spawn(move || handle.wait())
+        };
+        assert!(!handle.is_finished());
+        assert!(handle.wait_timeout(Duration::from_millis(10)).is_none());
 
-/// A [`Pool`] orchestrates two different threadpools, a compressor pool and a writer pool.
-///
-/// The pool is suitable for scenarios where there are many more writers than threads, efficiently
-/// managing resources for M writers to N threads.
-#[derive(Debug)]
-pub struct Pool {
-    /// The join handle for the thread that manages all pool resources and coordination.
-    pool_handle: Option<JoinHandle<PoolResult<()>>>,
-    /// The send end of the channel for communicating with the compressor pool.
-    compressor_tx: Option<Sender<CompressorMessage>>,
-    /// Sentinel channel to tell the pool management thread to shutdown.
-    shutdown_tx: Option<Sender<()>>,
-}
+        writer.write_all(b"supervised\n").unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+        assert!(handle.is_finished());
+        assert!(matches!(handle.try_result(), Some(Ok(()))));
+        assert!(matches!(receiver.recv().unwrap(), Ok(())));
+        assert!(supervisor.join().unwrap().is_ok());
 
-impl Pool {
-    /// The main "run" method for the pool that orchestrates all the pieces.
-    ///
-    /// The [`PooledWriter`]s are sending to the compressor, the compressor compresses them, then forwards the compressed bytes.
-    /// The bytes are forwarded to a queue per writer and the writer threads are iterating over that queue pulling down
-    /// all values in the queue at once and writing till the queue is empty.
-    ///
-    /// # Arguments
-    /// - `num_threads` - The number of threads to use.
-    /// - `compression_level` - The compression level to use for the [`Compressor`] pool.
-    /// - `compressor_rx ` - The receiving end of the channel for communicating with the compressor pool.
-    /// - `writer_rxs ` - The receive halves of the channels for the [`PooledWriter`]s to enqueue the one-shot channels.
-    /// - `writers` - The writers that were exchanged for [`PooledWriter`]s.
-    /// - `shutdown_rx` - Sentinel channel to tell the pool management thread to shutdown.
-    #[allow(clippy::unnecessary_wraps, clippy::needless_collect, clippy::needless_pass_by_value)]
-    fn pool_main<W, C>(
-        num_threads: usize,
-        compression_level: C::CompressionLevel,
-        compressor_rx: Receiver<CompressorMessage>,
-        writer_rxs: Vec<Receiver<Receiver<WriterMessage>>>, // must be pass by value to allow for easy sharing between threads
-        writers: Vec<W>,
-        shutdown_rx: Receiver<()>,
-    ) -> PoolResult<()>
-    where
-        W: Write + Send + 'static,
-        C: Compressor,
-    {
-        // Add locks to the writers
-        let writers: Arc<Vec<_>> =
-            Arc::new(writers.into_iter().map(|w| Arc::new(Mutex::new(w))).collect());
+        // Errors from the pool thread are seen by both the owner and the handle
+        let mut builder = PoolBuilder::<Box<dyn Write + Send>, BgzfCompressor>::new().threads(2);
+        let mut writer = builder.exchange_callback(|_| {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "downstream went away"))
+        });
+        let mut pool = builder.build().unwrap();
+        let handle = pool.completion_handle();
+        let _ = writer.write_all(b"unwritable\n");
+        let _ = writer.close();
+        assert!(pool.stop_pool().is_err());
+        match handle.wait() {
+            Err(PoolError::Io(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+                assert_eq!(e.to_string(), "downstream went away");
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert!(matches!(handle.receiver().recv().unwrap(), Err(PoolError::Io(_))));
 
-        // Generate one more channel for queuing up information about when a writer has data
-        // available to be written
-        let (write_available_tx, write_available_rx): (Sender<usize>, Receiver<usize>) =
-            flume::unbounded();
+        // A pool without writers has nothing to do, so is finished immediately
+        let pool = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().build().unwrap();
+        assert!(matches!(pool.completion_handle().wait(), Ok(())));
+    }
 
-        let thread_handles: Vec<JoinHandle<PoolResult<()>>> = (0..num_threads)
-            .map(|thread_idx| {
-                let compressor_rx = compressor_rx.clone();
-                let mut compressor = C::new(compression_level.clone());
-                let writer_rxs = writer_rxs.clone();
-                let writers = writers.clone();
-                let shutdown_rx = shutdown_rx.clone();
-                let sleep_delay = Duration::from_millis(25);
-                let write_available_tx = write_available_tx.clone();
-                let write_available_rx = write_available_rx.clone();
-
-                std::thread::spawn(move || {
-                    loop {
-                        let mut did_something = false;
-
-                        // Try to process one compression message
-                        if let Ok(message) = compressor_rx.try_recv() {
-                            // Compress the buffer in the message
-                            let chunk = &message.buffer;
-                            // Compress will correctly resize the compressed vec.
-                            let mut compressed = Vec::new();
-                            compressor
-                                .compress(chunk, &mut compressed, message.is_last)
-                                .map_err(|e| PoolError::CompressionError(e.to_string()))?;
-                            message
-                                .oneshot
-                                .send(WriterMessage { buffer: compressed })
-                                .map_err(|_e| PoolError::ChannelSend);
-                            write_available_tx.send(message.writer_index);
-                            did_something = true;
-                        }
+    #[test]
+    fn test_exchange_dry_run() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("real.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut real = builder.exchange(create_output_writer(&path));
+        let mut dry_run = builder.exchange_dry_run();
+        let mut pool = builder.build().unwrap();
 
-                        // Then try to process one write message
-                        if let Ok(writer_index) = write_available_rx.try_recv() {
-                            let mut writer = writers[writer_index].lock();
-                            let writer_rx = &writer_rxs[writer_index];
-                            let one_shot_rx = writer_rx.recv()?;
-                            let write_message = one_shot_rx.recv()?;
-                            writer.write_all(&write_message.buffer)?;
-                            did_something = true;
-                        }
+        let data = b"accounted for, then discarded\n".repeat(10_000);
+        real.write_all(&data).unwrap();
+        dry_run.write_all(&data).unwrap();
+        real.close().unwrap();
+        dry_run.close().unwrap();
+        pool.stop_pool().unwrap();
 
-                        // If we didn't do anything either sleep for a few ms to avoid busy-waiting
-                        // or if shutdown is requested and all the channels are empty, terminate.
-                        if !did_something {
-                            if shutdown_rx.is_disconnected()
-                                && write_available_rx.is_empty()
-                                && compressor_rx.is_empty()
-                                && writer_rxs.iter().all(|w| w.is_empty())
-                            {
-                                break;
-                            } else {
-                                std::thread::sleep(sleep_delay);
-                            }
-                        }
-                    }
+        // The dry run is compressed and counted exactly as the real output is
+        let stats = pool.stats();
+        let (real, dry_run) = (&stats.writers[0], &stats.writers[1]);
+        assert_eq!(dry_run.compressed_bytes, std::fs::metadata(&path).unwrap().len());
+        assert_eq!(dry_run.compressed_bytes, real.compressed_bytes);
+        assert_eq!(dry_run.blocks_written, real.blocks_written);
+        assert_eq!(dry_run.uncompressed_bytes_written, data.len() as u64);
+    }
 
-                    Ok(())
-                })
-            })
-            .collect();
+    /// A compressor that panics on every block.
+    struct PanickingCompressor;
 
-        // Close writer handles
-        thread_handles.into_iter().try_for_each(|handle| match handle.join() {
-            Ok(result) => result,
-            Err(e) => std::panic::resume_unwind(e),
-        });
+    impl Compressor for PanickingCompressor {
+        type Error = io::Error;
+        type CompressionLevel = ();
 
-        // Flush each writer
-        writers.iter().try_for_each(|w| w.lock().flush())?;
+        fn new(_level: Self::CompressionLevel) -> Self {
+            Self
+        }
 
-        Ok(())
-    }
+        fn default_compression_level() -> Self::CompressionLevel {}
 
-    /// Shutdown all pool resources and close all channels.
-    ///
-    /// Ideally the [`PooledWriter`]s should all have been flushed first, that is up to the user. Any
-    /// further attempts to send to the [`Pool`] will return an error.
-    pub fn stop_pool(&mut self) -> Result<(), PoolError> {
-        let compressor_queue = self.compressor_tx.take().unwrap();
-        while !compressor_queue.is_empty() {
-            // Wait for compression to finish before dropping the sender
+        fn new_compression_level(_level: u8) -> Result<Self::CompressionLevel, Self::Error> {
+            Ok(())
         }
-        drop(compressor_queue);
 
-        // Shutdown called to force writers to start checking their receivers for disconnection / empty
-        drop(self.shutdown_tx.take());
+        fn compress(&mut self, _input: &[u8], _output: &mut Vec<u8>) -> io::Result<()> {
+            panic!("compressor bug")
+        }
+    }
+
+    #[test]
+    fn test_panic_policy() {
+        for policy in [PanicPolicy::ResumeUnwind, PanicPolicy::ConvertToError] {
+            let mut builder =
+                PoolBuilder::<Vec<u8>, PanickingCompressor>::new().threads(1).panic_policy(policy);
+            let mut writer = builder.exchange(vec![]);
+            let mut pool = builder.build().unwrap();
+            let handle = pool.completion_handle();
+            let _ = writer.write_all(b"never compressed\n");
+            let _ = writer.close();
 
-        // Wait on the pool thread to finish and pull any errors from it
-        match self.pool_handle.take().unwrap().join() {
-            Ok(result) => result,
-            Err(e) => std::panic::resume_unwind(e),
+            let result =
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| pool.stop_pool()));
+            match policy {
+                PanicPolicy::ResumeUnwind => assert!(result.is_err()),
+                _ => match result {
+                    Ok(Err(PoolError::Panicked(msg))) => assert_eq!(msg, "compressor bug"),
+                    other => panic!("Unexpected result {:?}", other),
+                },
+            }
+            assert!(matches!(handle.wait(), Err(PoolError::Panicked(_))));
         }
     }
-}
 
-impl Drop for Pool {
-    fn drop(&mut self) {
-        // Check if `stop_pool` has already been called. If it hasn't, call it.
-        if self.compressor_tx.is_some() && self.pool_handle.is_some() {
-            self.stop_pool().unwrap();
+    /// Encodes blocks by XOR-ing every byte with a key, and finishes streams with the key.
+    struct XorEncoder(u8);
+
+    impl crate::encoder::Encoder for XorEncoder {
+        type Error = io::Error;
+        type Config = u8;
+
+        const BLOCK_SIZE: usize = 1024;
+
+        fn new(key: Self::Config) -> Self {
+            Self(key)
+        }
+
+        fn encode(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+            output.extend(input.iter().map(|b| b ^ self.0));
+            Ok(())
+        }
+
+        fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+            output.push(self.0);
+            Ok(())
         }
     }
-}
 
-////////////////////////////////////////////////////////////////////////////////
-// Tests
-////////////////////////////////////////////////////////////////////////////////
+    #[test]
+    fn test_encoder() {
+        use crate::encoder::Encoding;
 
-#[cfg(test)]
-mod test {
-    use std::{
-        assert_eq, format,
-        fs::File,
-        io::{BufReader, BufWriter},
-        path::{Path, PathBuf},
-        vec,
-    };
+        type Builder = PoolBuilder<Box<dyn Write + Send>, Encoding<XorEncoder>>;
+        let mut builder = Builder::new().threads(2).encoder(42);
+        let (tx, rx) = std::sync::mpsc::channel::<Vec<u8>>();
+        let mut writer = builder.exchange_callback(move |block| {
+            tx.send(block.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+        });
+        let mut pool = builder.build().unwrap();
+        assert_eq!(pool.block_size(), 1024);
 
-    use crate::bgzf::BgzfCompressor;
+        let data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
 
-    use super::*;
-    use ::bgzf::Reader;
-    use proptest::prelude::*;
-    use tempfile::tempdir;
+        let mut expected: Vec<u8> = data.iter().map(|b| b ^ 42).collect();
+        expected.push(42);
+        assert_eq!(rx.iter().flatten().collect::<Vec<u8>>(), expected);
 
-    fn create_output_writer<P: AsRef<Path>>(path: P) -> BufWriter<File> {
-        BufWriter::new(File::create(path).unwrap())
+        // Without a configuration every block fails to encode
+        let mut builder = Builder::new();
+        let mut writer = builder.exchange(Box::new(vec![]));
+        let mut pool = builder.build().unwrap();
+        let _ = writer.write_all(b"unencoded");
+        let _ = writer.close();
+        assert!(matches!(pool.stop_pool(), Err(PoolError::CompressionError(_))));
+
+        // Encoders may not be configured with numeric compression levels
+        assert!(Builder::new().compression_level(1).is_err());
     }
 
-    fn create_output_file_name(name: impl AsRef<Path>, dir: impl AsRef<Path>) -> PathBuf {
-        let path = dir.as_ref().to_path_buf();
-        path.join(name)
+    #[test]
+    fn test_encoder_config_dropped_once_writer_finishes() {
+        /// Encodes blocks by XOR-ing every byte with a shared key.
+        struct SharedKeyEncoder(u8);
+
+        impl crate::encoder::Encoder for SharedKeyEncoder {
+            type Error = io::Error;
+            type Config = Arc<u8>;
+
+            fn new(key: Self::Config) -> Self {
+                Self(*key)
+            }
+
+            fn encode(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+                output.extend(input.iter().map(|b| b ^ self.0));
+                Ok(())
+            }
+        }
+
+        let key = Arc::new(42u8);
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(2);
+        let mut writer =
+            builder.exchange_with_encoder::<SharedKeyEncoder>(vec![], key.clone()).unwrap();
+        let mut pool = builder.build().unwrap();
+        writer.write_all(b"secret").unwrap();
+        assert_eq!(Arc::strong_count(&key), 2);
+
+        // The pool's copy of the key is dropped once the writer's last block is written
+        writer.close().unwrap();
+        let output = pool.quiesce_writer::<Vec<u8>>(0).unwrap().clone();
+        assert_eq!(output, b"secret".iter().map(|b| b ^ 42).collect::<Vec<u8>>());
+        assert_eq!(Arc::strong_count(&key), 1);
+        pool.stop_pool().unwrap();
     }
 
     #[test]
-    fn test_simple() {
-        let dir = tempdir().unwrap();
-        let output_names: Vec<PathBuf> = (0..20)
-            .into_iter()
-            .map(|i| create_output_file_name(format!("test.{}.txt.gz", i), &dir.path()))
-            .collect();
+    fn test_work_quantum() {
+        let quanta = [
+            WorkQuantum::new(1, 8),
+            WorkQuantum::new(4, 1),
+            WorkQuantum::new(16, 16).time_slice(Duration::from_micros(50)),
+        ];
+        for quantum in quanta {
+            let dir = tempdir().unwrap();
+            let paths: Vec<_> = (0..4)
+                .map(|i| create_output_file_name(&format!("quantum{}.txt.gz", i), &dir.path()))
+                .collect();
+            let mut builder =
+                PoolBuilder::<_, BgzfCompressor>::new().threads(3).work_quantum(quantum);
+            let mut writers: Vec<_> =
+                paths.iter().map(|p| builder.exchange(create_output_writer(p))).collect();
+            let mut pool = builder.build().unwrap();
 
-        let output_writers: Vec<BufWriter<File>> =
-            output_names.iter().map(create_output_writer).collect();
-        let mut builder =
-            PoolBuilder::<_, BgzfCompressor>::new().threads(8).compression_level(2).unwrap();
-        let mut pooled_writers: Vec<PooledWriter> =
-            output_writers.into_iter().map(|w| builder.exchange(w)).collect();
+            let data: Vec<u8> =
+                (0..3 * BgzfCompressor::BLOCK_SIZE).map(|i| (i % 7) as u8).collect();
+            writers.iter_mut().for_each(|w| w.write_all(&data).unwrap());
+            writers.into_iter().try_for_each(|w| w.close()).unwrap();
+            pool.stop_pool().unwrap();
+
+            for path in &paths {
+                let mut actual = vec![];
+                Reader::new(File::open(path).unwrap()).read_to_end(&mut actual).unwrap();
+                assert_eq!(actual, data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_work_weights() {
+        use WorkKind::{Compress, Decompress, Write};
+
+        let weights = WorkWeights::default();
+        assert_eq!(weights.order(0, 0, 0), [Write, Compress, Decompress]);
+        assert_eq!(weights.order(5, 0, 2), [Compress, Write, Decompress]);
+        assert_eq!(weights.order(1, 3, 2), [Decompress, Write, Compress]);
+        assert_eq!(WorkWeights::new(1, 4).order(5, 0, 2), [Write, Compress, Decompress]);
+
+        for weights in [WorkWeights::new(1, 8), WorkWeights::new(8, 1)] {
+            let mut builder =
+                PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(3).work_weights(weights);
+            let mut writers: Vec<_> = (0..4).map(|_| builder.exchange(vec![])).collect();
+            let mut pool = builder.build().unwrap();
+
+            let data: Vec<u8> =
+                (0..5 * BgzfCompressor::BLOCK_SIZE).map(|i| (i % 11) as u8).collect();
+            writers.iter_mut().for_each(|w| w.write_all(&data).unwrap());
+            writers.into_iter().try_for_each(|w| w.close()).unwrap();
+            pool.stop_pool().unwrap();
+
+            for index in 0..4 {
+                let compressed = pool.quiesce_writer::<Vec<u8>>(index).unwrap().clone();
+                let mut actual = vec![];
+                Reader::new(compressed.as_slice()).read_to_end(&mut actual).unwrap();
+                assert_eq!(actual, data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_buffer_recycling() {
+        let buffers = BufferRecycler::new(1);
+        let mut first = buffers.take(16);
+        first.extend_from_slice(b"stale");
+        let allocation = first.as_ptr();
+        buffers.recycle(first);
+        buffers.recycle(BytesMut::with_capacity(16));
+        let reused = buffers.take(16);
+        assert_eq!(reused.as_ptr(), allocation);
+        assert!(reused.is_empty());
+        // The second buffer was dropped as the recycler was full
+        assert_ne!(buffers.take(16).as_ptr(), allocation);
+
+        let compressed = BufferRecycler::<Vec<u8>>::new(1);
+        let mut first = compressed.take(0);
+        first.extend_from_slice(&[1; 100]);
+        let allocation = first.as_ptr();
+        compressed.recycle(first);
+        let reused = compressed.take(0);
+        assert_eq!(reused.as_ptr(), allocation);
+        assert!(reused.is_empty() && reused.capacity() >= 100);
+
+        // Recycled buffers are reused across writers, and aren't recycled while they're teed
+        let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(2);
+        let mut writers = vec![builder.exchange(vec![]), builder.exchange(vec![])];
+        writers.push(builder.exchange_tee_uncompressed(vec![], vec![]));
         let mut pool = builder.build().unwrap();
 
-        for (i, writer) in pooled_writers.iter_mut().enumerate() {
-            writer.write_all(format!("This is writer {}.", i).as_bytes()).unwrap();
+        let data: Vec<u8> = (0..20 * BgzfCompressor::BLOCK_SIZE).map(|i| (i % 13) as u8).collect();
+        for chunk in data.chunks(1000) {
+            writers.iter_mut().for_each(|w| w.write_all(chunk).unwrap());
         }
-        pooled_writers.into_iter().try_for_each(|mut w| w.flush()).unwrap();
-        pool.stop_pool();
+        writers.into_iter().try_for_each(|w| w.close()).unwrap();
+        pool.stop_pool().unwrap();
 
-        for (i, path) in output_names.iter().enumerate() {
-            let mut reader = Reader::new(BufReader::new(File::open(path).unwrap()));
+        for index in 0..3 {
+            let compressed = pool.quiesce_writer::<Vec<u8>>(index).unwrap().clone();
             let mut actual = vec![];
-            reader.read_to_end(&mut actual).unwrap();
-            assert_eq!(actual, format!("This is writer {}.", i).as_bytes());
+            Reader::new(compressed.as_slice()).read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, data);
         }
     }
 
-    proptest! {
-        // This test takes around 20 minutes on a 32 core machine to run but is very comprehensive.
-        // Run with `cargo test -- --ignored`
-        #[ignore]
-        #[test]
-        fn test_complete(
-            input_size in 1..=BUFSIZE * 4,
-            buf_size in 1..=BUFSIZE,
-            num_output_files in 1..2*num_cpus::get(),
-            threads in 1..=2+num_cpus::get(),
-            comp_level in 1..=8_u8,
-            write_size in 1..=2*BUFSIZE,
-        ) {
-            let dir = tempdir().unwrap();
-            let output_names: Vec<PathBuf> = (0..num_output_files)
-                .into_iter()
-                .map(|i| create_output_file_name(format!("test.{}.txt.gz", i), &dir.path()))
-                .collect();
-            let output_writers: Vec<_> = output_names.iter().map(create_output_writer).collect();
+    #[test]
+    fn test_reorder_buffer() {
+        let message = |sequence: u64| WriterMessage {
+            buffer: vec![sequence as u8],
+            sequence,
+            raw: None,
+            compressed_at: Duration::default(),
+            is_last: false,
+            uncompressed_len: 0,
+            flush: false,
+            block_number: sequence,
+            checksum: None,
+            control: None,
+        };
+        let mut reorder = ReorderBuffer::default();
+        reorder.insert(message(2));
+        reorder.insert(message(1));
+        assert!(reorder.pop_next().is_none());
 
-            let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
-                .threads(threads)
-                .compression_level(comp_level)?;
+        reorder.insert(message(0));
+        let written: Vec<_> = std::iter::from_fn(|| reorder.pop_next()).collect();
+        assert_eq!(written.iter().map(|m| m.sequence).collect::<Vec<_>>(), [0, 1, 2]);
+        reorder.insert(message(4));
+        assert!(reorder.pop_next().is_none());
+        reorder.insert(message(3));
+        assert_eq!(reorder.pop_next().unwrap().sequence, 3);
+        assert_eq!(reorder.pop_next().unwrap().sequence, 4);
+    }
 
-            let mut pooled_writers: Vec<_> = output_writers.into_iter().map(|w| builder.exchange(w)).collect();
-            let mut pool = builder.build()?;
+    #[test]
+    fn test_batch_small_blocks() {
+        let mut builder =
+            PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(2).batch_small_blocks(1024);
+        let mut writer = builder.exchange(vec![]);
+        let mut other = builder.exchange(vec![]);
 
-            let inputs: Vec<Vec<u8>> = (0..num_output_files).map(|_| {
-                (0..input_size).map(|_| rand::random::<u8>()).collect()
-            }).collect();
+        // The blocks are all sent before the pool's threads start, so the small ones are batched
+        let mut expected = vec![];
+        for i in 0..10 {
+            let line = format!("line {}\n", i);
+            writer.write_all(line.as_bytes()).unwrap();
+            writer.flush_partial().unwrap();
+            expected.extend_from_slice(line.as_bytes());
+        }
+        let large = vec![b'x'; 2048];
+        other.write_all(&large).unwrap();
+        other.flush_partial().unwrap();
+        writer.close().unwrap();
+        other.close().unwrap();
+        let mut pool = builder.build().unwrap();
+        pool.stop_pool().unwrap();
 
-            let chunks = (input_size as f64 / write_size as f64).ceil() as usize;
+        // The final empty blocks are small too
+        let stats = pool.stats();
+        assert_eq!(stats.writers[0].batched_blocks, 11);
+        assert_eq!(stats.writers[1].batched_blocks, 0);
+        assert_eq!(stats.writers[0].blocks_written, 11);
+        for (index, expected) in [(0, expected), (1, large)] {
+            let compressed = pool.quiesce_writer::<Vec<u8>>(index).unwrap().clone();
+            let mut actual = vec![];
+            Reader::new(compressed.as_slice()).read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, expected);
+        }
+    }
 
-            // write a chunk to each writer (could randomly select the writers?)
-            for i in (0..chunks) {
-                for (j, writer) in pooled_writers.iter_mut().enumerate() {
-                    let input = &inputs[j];
-                    let bytes = &input[write_size * i..std::cmp::min(write_size * (i + 1), input.len())];
-                    writer.write_all(bytes).unwrap()
-                }
+    #[test]
+    fn test_remaining_in_block() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("packed.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new().threads(2);
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+        assert_eq!(pool.block_size(), BgzfCompressor::BLOCK_SIZE);
+        assert_eq!(writer.remaining_in_block(), BgzfCompressor::BLOCK_SIZE);
+
+        // Pack 1000 byte records so that no record spans two blocks
+        let record = vec![b'R'; 1000];
+        for _ in 0..200 {
+            if writer.remaining_in_block() < record.len() {
+                writer.flush_partial().unwrap();
             }
+            writer.write_all(&record).unwrap();
+        }
+        assert_eq!(writer.remaining_in_block(), writer.block_size() - (200 % 65) * 1000);
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
 
-            pooled_writers.into_iter().try_for_each(|mut w| w.flush()).unwrap();
-            pool.stop_pool();
+        let stats = pool.stats();
+        assert_eq!(stats.writers[0].partial_blocks, 3);
+        assert_eq!(stats.writers[0].uncompressed_bytes, 200_000);
+    }
 
-            for (i, path) in output_names.iter().enumerate() {
-                let mut reader = Reader::new(BufReader::new(File::open(path).unwrap()));
-                let mut actual = vec![];
-                reader.read_to_end(&mut actual).unwrap();
-                assert_eq!(actual, inputs[i]);
-            }
+    #[test]
+    #[cfg(feature = "deflate_compressor")]
+    fn test_deflate_compressor() {
+        use crate::deflate::DeflateCompressor;
 
+        let (tx, rx) = std::sync::mpsc::channel::<Vec<u8>>();
+        let mut builder = PoolBuilder::<Box<dyn Write + Send>, DeflateCompressor>::new().threads(2);
+        let mut writer = builder.exchange_callback(move |block| {
+            tx.send(block.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+        });
+        let mut pool = builder.build().unwrap();
+
+        let data: Vec<u8> =
+            (0..5 * DeflateCompressor::BLOCK_SIZE / 2).map(|i| (i % 73) as u8).collect();
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        // Each raw deflate block arrives in a separate call and decodes on its own
+        let mut actual = vec![];
+        for block in rx.iter() {
+            flate2::read::DeflateDecoder::new(block.as_slice()).read_to_end(&mut actual).unwrap();
         }
+        assert_eq!(actual, data);
+        assert!(!DeflateCompressor::capabilities().supports_eof_marker);
+    }
+
+    #[test]
+    fn test_bgzf_store_mode() {
+        let dir = tempdir().unwrap();
+        let path = create_output_file_name("stored.txt.gz", &dir.path());
+        let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+            .threads(2)
+            .compression_level(0)
+            .unwrap()
+            .verify_blocks(true)
+            .unwrap();
+        let mut writer = builder.exchange(create_output_writer(&path));
+        let mut pool = builder.build().unwrap();
+
+        let data = b"stored, not deflated\n".repeat(20_000);
+        writer.write_all(&data).unwrap();
+        writer.close().unwrap();
+        pool.stop_pool().unwrap();
+
+        // Each full block holds its bytes as is, plus the BGZF and stored block overheads
+        let blocks = (data.len() + BgzfCompressor::BLOCK_SIZE - 1) / BgzfCompressor::BLOCK_SIZE;
+        let written = std::fs::read(&path).unwrap();
+        assert_eq!(written.len(), data.len() + blocks * (18 + 5 + 8) + 28);
+        let mut actual = vec![];
+        Reader::new(written.as_slice()).read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+
+        // A stored block must still fit within the 64 KiB BGZF limit
+        let level = BgzfCompressor::new_compression_level(0).unwrap();
+        let mut compressor = BgzfCompressor::new(level);
+        assert!(compressor.compress(&vec![0; 65_505], &mut vec![]).is_ok());
+        assert!(compressor.compress(&vec![0; 65_506], &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_compression_level_validated_against_capabilities() {
+        let caps = BgzfCompressor::capabilities();
+        assert!(caps.supports_eof_marker);
+        assert_eq!(caps.max_block_size, BgzfCompressor::BLOCK_SIZE);
+
+        let result = PoolBuilder::<BufWriter<File>, BgzfCompressor>::new().compression_level(13);
+        assert!(matches!(
+            result,
+            Err(PoolError::InvalidCompressionLevel { level: 13, min: 0, max: 12 })
+        ));
+        assert!(caps.check_block_size(caps.max_block_size + 1).is_err());
+        assert!(caps.check_block_size(1024).is_ok());
     }
 }
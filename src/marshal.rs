@@ -0,0 +1,121 @@
+//! A marshaled mode for writers that cannot be moved to the pool's threads, e.g. wrappers over
+//! FFI handles that are not [`Send`].  The pool's threads hand each compressed block back over
+//! a channel, and the thread that owns the writer drives the actual writes with a [`Pump`].
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::channel::{self, Receiver, Sender};
+
+/// A message from the pool's threads to the [`Pump`].
+enum Message {
+    Write(Vec<u8>),
+    Flush,
+}
+
+/// The first error hit by the [`Pump`], as its kind and message, since [`io::Error`] is not
+/// [`Clone`].
+type SharedError = Arc<Mutex<Option<(io::ErrorKind, String)>>>;
+
+/// Creates the two halves of a marshaled writer: a [`MarshaledWriter`] to exchange with a pool,
+/// and the [`Pump`] that writes its blocks to `writer` on the current thread.
+///
+/// The channel between them is unbounded so that a producer that also drives the pump from
+/// the same thread cannot deadlock with the pool, so the pump should be called regularly to
+/// keep the blocks held in memory to a minimum.
+pub fn marshal<W: Write>(writer: W) -> (MarshaledWriter, Pump<W>) {
+    let (tx, rx) = channel::unbounded();
+    let error = SharedError::default();
+    (MarshaledWriter { tx, error: error.clone() }, Pump { writer, rx, error })
+}
+
+/// The [`Write`] half of a marshaled writer, exchanged with a pool in place of the writer
+/// itself.  Each buffer written to it is queued for the [`Pump`].  Writes fail once the pump
+/// has failed to write to the underlying writer, or has been dropped.
+pub struct MarshaledWriter {
+    tx: Sender<Message>,
+    error: SharedError,
+}
+
+impl MarshaledWriter {
+    /// Queues `message` for the pump, unless the pump has failed or been dropped.
+    fn send(&self, message: Message) -> io::Result<()> {
+        if let Some((kind, msg)) = self.error.lock().as_ref() {
+            return Err(io::Error::new(*kind, msg.clone()));
+        }
+        self.tx
+            .send(message)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "marshaled writer pump dropped"))
+    }
+}
+
+impl Write for MarshaledWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(Message::Write(buf.to_vec()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send(Message::Flush)
+    }
+}
+
+impl fmt::Debug for MarshaledWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MarshaledWriter").finish()
+    }
+}
+
+/// The half of a marshaled writer that owns the underlying writer and stays on its thread,
+/// writing the blocks delivered by the pool whenever it is pumped.
+pub struct Pump<W: Write> {
+    writer: W,
+    rx: Receiver<Message>,
+    error: SharedError,
+}
+
+impl<W: Write> Pump<W> {
+    /// Writes every block delivered so far, without waiting for more.  Returns true once the
+    /// pool has finished with the writer, i.e. the [`MarshaledWriter`] has been dropped, and
+    /// all of its blocks have been written.
+    pub fn pump(&mut self) -> io::Result<bool> {
+        while let Ok(message) = self.rx.try_recv() {
+            self.handle(message)?;
+        }
+        Ok(self.rx.is_disconnected() && self.rx.is_empty())
+    }
+
+    /// Writes blocks as they are delivered until the pool has finished with the writer, e.g.
+    /// after [`Pool::stop_pool`](crate::Pool::stop_pool), then returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        while let Ok(message) = self.rx.recv() {
+            self.handle(message)?;
+        }
+        Ok(self.writer)
+    }
+
+    /// A reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Writes or flushes the underlying writer, recording any error for the pool's side.
+    fn handle(&mut self, message: Message) -> io::Result<()> {
+        let result = match message {
+            Message::Write(bytes) => self.writer.write_all(&bytes),
+            Message::Flush => self.writer.flush(),
+        };
+        if let Err(e) = &result {
+            self.error.lock().get_or_insert_with(|| (e.kind(), e.to_string()));
+        }
+        result
+    }
+}
+
+impl<W: Write> fmt::Debug for Pump<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pump").field("queued", &self.rx.len()).finish()
+    }
+}
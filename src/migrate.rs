@@ -0,0 +1,274 @@
+//! Moving a writer to another pool while it is being written, e.g. to move an output whose
+//! storage has turned out to be slow to a dedicated pool, so that it no longer holds up the
+//! other writers of its pool.
+//!
+//! [`Pool::detach_writer`] takes a [`PooledWriter`] out of its pool, along with its underlying
+//! writer and the blocks it had sent that were not yet written, without finishing its stream.
+//! [`PoolBuilder::attach`] then exchanges it with the builder of another pool, which writes
+//! those blocks before any sent afterwards.  The underlying writer is neither closed nor
+//! reopened, so the output is one continuous stream.
+//!
+//! ```rust
+//! use std::io::Write;
+//! use pooled_writer::{bgzf::BgzfCompressor, PoolBuilder};
+//!
+//! let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new();
+//! let mut writer = builder.exchange(vec![]);
+//! let mut pool = builder.build()?;
+//! writer.write_all(b"written by the first pool\n")?;
+//!
+//! let detached = pool.detach_writer::<Vec<u8>>(writer)?;
+//! let mut builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new().threads(1);
+//! let mut writer = builder.attach(detached)?;
+//! let mut slow_pool = builder.build()?;
+//! writer.write_all(b"and the rest by the second\n")?;
+//! writer.close()?;
+//! slow_pool.stop_pool()?;
+//! pool.stop_pool()?;
+//! # Ok::<(), pooled_writer::PoolError>(())
+//! ```
+use std::any::TypeId;
+use std::fmt;
+use std::io::{self, SeekFrom, Write};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::channel;
+use crate::{
+    Compressor, ExchangeOptions, Pool, PoolBuilder, PoolError, PoolResult, PooledWriter, Sink,
+    WriterMessage,
+};
+
+/// A writer detached from its pool by [`Pool::detach_writer`], holding its underlying writer
+/// and the blocks it had sent that were not yet written, to be attached to another pool with
+/// [`PoolBuilder::attach`].  If it is dropped instead, the underlying writer is dropped with its
+/// stream unfinished.
+pub struct DetachedWriter<W> {
+    /// The underlying writer.
+    writer: W,
+    /// The blocks that were compressed but not yet written, in order.
+    blocks: Vec<WriterMessage>,
+    /// The per-writer settings of the writer.
+    options: ExchangeOptions,
+    /// The stable ID given to the writer, if any.
+    id: Option<String>,
+    /// Seeks the writer, if it is seekable.
+    seek: Option<fn(&mut W, SeekFrom) -> io::Result<u64>>,
+    /// The number of compressed bytes written to the output so far.
+    output_offset: u64,
+    /// True if any blocks of the stream were sent before the writer was detached.
+    started: bool,
+    /// The type of the compressor of the pool the writer was detached from.
+    compressor_type: TypeId,
+}
+
+impl<W> DetachedWriter<W> {
+    /// The number of blocks sent by the writer that had not been written when it was detached,
+    /// and that are written first once it is attached to another pool.
+    pub fn pending_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+}
+
+impl<W> fmt::Debug for DetachedWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DetachedWriter")
+            .field("pending_blocks", &self.blocks.len())
+            .field("options", &self.options)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+/// The error for a writer that cannot be detached, described by `writer`.
+fn not_detachable(writer: &str) -> PoolError {
+    PoolError::UnsupportedOption(format!("{} cannot be detached from its pool", writer))
+}
+
+impl Pool {
+    /// Detaches `writer` from the pool without finishing its stream, returning its underlying
+    /// writer, of type `W`, with the blocks it had sent that were not yet written.  Any bytes
+    /// still buffered by the writer are first sent as a partial block.  The pool's other writers
+    /// carry on as before.
+    ///
+    /// Only writers that write to a single underlying writer with the pool's own compressor can
+    /// be detached, i.e. not those whose compressor is stateful, those exchanged with their own
+    /// compressor or encoder, tee and split writers, writers with observers, or writers whose
+    /// virtual offsets are tracked.  Returns [`PoolError::UnknownWriter`] if the writer is not
+    /// from this pool or its writer is not a `W`, and [`PoolError::ChannelSend`] if the pool
+    /// stops before the writer's blocks are compressed.  A writer that can't be detached is
+    /// dropped, applying its [`DropPolicy`](crate::DropPolicy).
+    pub fn detach_writer<W: Write + Send + 'static>(
+        &self,
+        mut pooled: PooledWriter,
+    ) -> PoolResult<DetachedWriter<W>> {
+        let index = pooled.writer_index;
+        let state = match self.writer_states.get(index) {
+            Some(state) if Arc::ptr_eq(state, &pooled.shared) => state,
+            _ => return Err(PoolError::UnknownWriter(index)),
+        };
+        let sink = self.sinks[index]
+            .downcast_ref::<Mutex<Sink<W>>>()
+            .ok_or(PoolError::UnknownWriter(index))?;
+        if pooled.finalized {
+            return Err(state.label_error(PoolError::WriterFinalized(index)));
+        }
+        if state.stream.is_some() {
+            return Err(not_detachable("a writer with a stateful compressor"));
+        }
+        if state.compressor.is_some() {
+            return Err(not_detachable("a writer with its own compressor"));
+        }
+        if state.offsets.is_some() {
+            return Err(not_detachable("a writer whose virtual offsets are tracked"));
+        }
+        {
+            let sink = sink.lock();
+            let single = sink.writer.is_some()
+                && sink.tee.is_none()
+                && sink.rotation.is_none()
+                && sink.observer.is_none()
+                && sink.block_observer.is_none();
+            if !single {
+                return Err(not_detachable("a writer with more than one output or an observer"));
+            }
+        }
+
+        if !pooled.buffer.is_empty() {
+            pooled.send_block(false)?;
+        }
+        // The stream is carried on elsewhere, so is not finished when the writer is dropped
+        pooled.finalized = true;
+
+        // Stop the pool's threads writing the writer's blocks, waiting for any being written,
+        // then wait until every block it sent has been compressed
+        {
+            let _sink = sink.lock();
+            state.detached.store(true, Ordering::Relaxed);
+        }
+        let mut reorder = state.reorder.lock();
+        while reorder.compressed() < pooled.messages_sent {
+            if state.stopped.load(Ordering::Relaxed) {
+                return Err(PoolError::ChannelSend);
+            }
+            state.written.wait(&mut reorder);
+        }
+        drop(reorder);
+
+        let mut sink = sink.lock();
+        let mut blocks: Vec<WriterMessage> =
+            state.reorder.lock().slots.drain(..).flatten().collect();
+        // A block that was partly written is carried over from where it left off
+        if let Some(block) = blocks.first_mut() {
+            if block.block_number == sink.progress.next_block {
+                block.buffer.drain(..sink.progress.written);
+            }
+        }
+        // The carried blocks no longer count against the pool's limits
+        for block in &mut blocks {
+            block.raw = None;
+            if let Some(blocks) = &state.in_flight {
+                blocks.release(1);
+            }
+            if let Some(bytes) = &state.in_flight_bytes {
+                bytes.release(block.uncompressed_len);
+            }
+        }
+        let carried_len = blocks.iter().map(|b| b.uncompressed_len).sum();
+        state.counters.record_detached(blocks.len(), carried_len);
+        sink.recompress = None;
+
+        Ok(DetachedWriter {
+            writer: sink.writer.take().expect("Checked above"),
+            blocks,
+            options: pooled.options,
+            id: state.id.lock().as_ref().map(|id| id.to_string()),
+            seek: sink.seek,
+            output_offset: sink.output_offset,
+            started: pooled.blocks_sent > 0,
+            compressor_type: self.compressor_type,
+        })
+    }
+}
+
+impl<W, C> PoolBuilder<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    /// Exchanges a writer detached from another pool with [`Pool::detach_writer`] for a
+    /// [`PooledWriter`] that carries on its stream.  The blocks that were not yet written when
+    /// it was detached are written first, and count against this pool's limits on blocks and
+    /// bytes in flight.  The writer keeps its per-writer settings and ID, while the settings of
+    /// this builder, e.g. its block size, apply to the blocks sent from here on.  Its output is
+    /// not re-compressed once finished, see [`PoolBuilder::recompress_small_outputs`].
+    ///
+    /// Returns an error, before the writer is exchanged, if the pool it was detached from used
+    /// a different compressor, if virtual offsets are tracked, or if its settings or ID can't
+    /// be used with this builder.
+    pub fn attach(&mut self, detached: DetachedWriter<W>) -> PoolResult<PooledWriter> {
+        if detached.compressor_type != TypeId::of::<C>() {
+            return Err(PoolError::UnsupportedOption(
+                "the writer was detached from a pool with a different compressor".to_string(),
+            ));
+        }
+        if self.virtual_offsets {
+            return Err(PoolError::UnsupportedOption(
+                "virtual offsets cannot be tracked for an attached writer".to_string(),
+            ));
+        }
+        self.check_options(&detached.options)?;
+        if let Some(id) = &detached.id {
+            if self.writer_states.iter().any(|s| s.id.lock().as_deref() == Some(id.as_str())) {
+                return Err(PoolError::DuplicateWriterId(id.clone()));
+            }
+        }
+
+        let mut pooled = self.exchange_with_options(detached.writer, detached.options)?;
+        if let Some(id) = &detached.id {
+            *pooled.shared.id.lock() = Some(Arc::from(id.as_str()));
+        }
+        let sink = self.writers.last_mut().expect("Unreachable");
+        sink.seek = detached.seek;
+        sink.output_offset = detached.output_offset;
+        sink.recompress = None;
+        pooled.seekable = detached.seek.is_some();
+        if detached.started {
+            pooled.small_output = None;
+        }
+
+        // The carried blocks take slots in the writer's queue like any others, so the queue is
+        // made large enough to hold them as well
+        let carried = detached.blocks.len();
+        let (tx, rx) = channel::bounded(self.queue_size.expect("Unreachable") + carried);
+        for _ in 0..carried {
+            let _ = tx.send(());
+        }
+        *self.writer_txs.last_mut().expect("Unreachable") = tx.clone();
+        *self.writer_rxs.last_mut().expect("Unreachable") = rx;
+        pooled.writer_tx = tx;
+
+        let shared = pooled.shared.clone();
+        let mut reorder = shared.reorder.lock();
+        for (number, mut block) in detached.blocks.into_iter().enumerate() {
+            block.sequence = number as u64;
+            block.block_number = number as u64;
+            block.compressed_at = self.clock.now();
+            block.checksum = shared.block_checksums.as_ref().map(|c| (c.checksum)(&block.buffer));
+            shared.counters.record_block(block.uncompressed_len, false);
+            if let Some(blocks) = &shared.in_flight {
+                blocks.take(1);
+            }
+            if let Some(bytes) = &shared.in_flight_bytes {
+                bytes.take(block.uncompressed_len);
+            }
+            reorder.insert(block);
+        }
+        pooled.blocks_sent = carried as u64;
+        pooled.messages_sent = carried as u64;
+        drop(reorder);
+        Ok(pooled)
+    }
+}
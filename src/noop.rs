@@ -0,0 +1,54 @@
+///! An implementation of [`Compressor`] that passes data through uncompressed.
+use std::io;
+
+use crate::{check_round_trip, Compressor, CompressorCapabilities};
+
+/// A passthrough compressor that copies each block to the output unchanged.
+///
+/// This allows the pool to be used to fan out uncompressed writes across many files, so callers
+/// can keep a single code path whether or not compression is enabled.  Only level 0 is accepted
+/// and there is no EOF marker.
+pub struct NoopCompressor;
+
+impl Compressor for NoopCompressor {
+    type Error = io::Error;
+    type CompressionLevel = ();
+
+    const BLOCK_SIZE: usize = 64 * 1024;
+
+    fn capabilities() -> CompressorCapabilities {
+        CompressorCapabilities::new(Self::BLOCK_SIZE)
+            .eof_marker(false)
+            .dictionaries(false)
+            .extra_subfields(false)
+            .compression_levels(0, 0)
+            .deterministic(true)
+            .verification(true)
+    }
+
+    fn new(_compression_level: Self::CompressionLevel) -> Self {
+        Self
+    }
+
+    fn default_compression_level() -> Self::CompressionLevel {}
+
+    fn new_compression_level(compression_level: u8) -> Result<Self::CompressionLevel, Self::Error> {
+        if compression_level == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the noop compressor has no compression levels, got {}", compression_level),
+            ))
+        }
+    }
+
+    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        output.extend_from_slice(input);
+        Ok(())
+    }
+
+    fn verify(&mut self, input: &[u8], compressed: &[u8]) -> io::Result<()> {
+        check_round_trip(input, compressed)
+    }
+}
@@ -0,0 +1,194 @@
+//! Resolution of BGZF-style virtual offsets for positions within a [`PooledWriter`]'s stream.
+//!
+//! A virtual offset combines the offset of a compressed block within the output with the offset
+//! of a position within the uncompressed bytes of that block: `block_start << 16 | within_block`.
+//! Indexers such as those for BAM (BAI) files need the virtual offset before and after each
+//! record, but the compressed offset of a block is only known once every preceding block has been
+//! compressed and written.  When virtual offset tracking is enabled via
+//! [`PoolBuilder::virtual_offsets`](crate::PoolBuilder::virtual_offsets), the caller marks record
+//! boundaries with [`PooledWriter::virtual_offset`](crate::PooledWriter::virtual_offset) and
+//! receives a [`PendingVirtualOffset`] that resolves once the containing block has been written.
+//!
+//! [`PooledWriter`]: crate::PooledWriter
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+
+#[derive(Debug, Default)]
+struct OffsetsState {
+    /// The compressed offset at which each block written from `first` onwards starts.
+    starts: VecDeque<u64>,
+    /// The index of the block whose start is at the front of `starts`.  The starts of earlier
+    /// blocks are dropped once no [`PendingVirtualOffset`] can resolve against them.
+    first: u64,
+    /// The number of [`PendingVirtualOffset`]s alive for each block, by block index.
+    pending: BTreeMap<u64, usize>,
+    /// The total number of compressed bytes written so far.
+    total: u64,
+    /// True once the pool has stopped writing, after which no more blocks will be written.
+    closed: bool,
+}
+
+impl OffsetsState {
+    /// The start offset of `block` if it has been written.
+    fn start(&self, block: u64) -> Option<u64> {
+        let index = block.checked_sub(self.first)?;
+        self.starts.get(index as usize).copied()
+    }
+
+    /// Drops the starts of the blocks before the oldest block with a [`PendingVirtualOffset`],
+    /// or of every block written if there are none.  New offsets are only ever taken for blocks
+    /// that have not been written yet, so those starts are never needed again.
+    fn prune(&mut self) {
+        let oldest = self.pending.keys().next().copied().unwrap_or(u64::MAX);
+        while self.first < oldest && !self.starts.is_empty() {
+            self.starts.pop_front();
+            self.first += 1;
+        }
+    }
+}
+
+/// Records the compressed start offset of each block of a writer as it is written.
+#[derive(Debug, Default)]
+pub(crate) struct BlockOffsets {
+    state: Mutex<OffsetsState>,
+    written: Condvar,
+}
+
+impl BlockOffsets {
+    /// Records that the next block, of `compressed_len` bytes, has been written.
+    pub(crate) fn record_block(&self, compressed_len: usize) {
+        let mut state = self.state.lock();
+        let start = state.total;
+        state.starts.push_back(start);
+        state.total += compressed_len as u64;
+        self.written.notify_all();
+        state.prune();
+    }
+
+    /// Starts the offsets of the blocks at `offset` rather than zero, for an output that already
+    /// holds `offset` compressed bytes.  Must be called before any block is recorded.
+    pub(crate) fn start_at(&self, offset: u64) {
+        self.state.lock().total = offset;
+    }
+
+    /// Marks that no more blocks will be written, waking any waiters.
+    pub(crate) fn close(&self) {
+        self.state.lock().closed = true;
+        self.written.notify_all();
+    }
+
+    /// The start offset of `block` if it has been written.
+    fn block_start(&self, block: u64) -> Option<u64> {
+        self.state.lock().start(block)
+    }
+
+    /// The number of block starts held, i.e. not yet pruned.
+    pub(crate) fn retained(&self) -> usize {
+        self.state.lock().starts.len()
+    }
+
+    /// Notes that a [`PendingVirtualOffset`] for `block` has been created, so that the block's
+    /// start is kept until it is dropped.
+    fn hold(&self, block: u64) {
+        *self.state.lock().pending.entry(block).or_insert(0) += 1;
+    }
+
+    /// Notes that a [`PendingVirtualOffset`] for `block` has been dropped.
+    fn release(&self, block: u64) {
+        let mut state = self.state.lock();
+        if let Some(count) = state.pending.get_mut(&block) {
+            *count -= 1;
+            if *count == 0 {
+                state.pending.remove(&block);
+                state.prune();
+            }
+        }
+    }
+}
+
+/// A virtual offset that will be resolved once its block has been compressed and written.
+#[derive(Debug)]
+pub struct PendingVirtualOffset {
+    /// The index of the block within the writer's stream.
+    block: u64,
+    /// The offset within the uncompressed bytes of the block.
+    within_block: u16,
+    /// The offsets of the blocks as they are written.
+    offsets: Arc<BlockOffsets>,
+}
+
+impl PendingVirtualOffset {
+    pub(crate) fn new(block: u64, within_block: u16, offsets: Arc<BlockOffsets>) -> Self {
+        offsets.hold(block);
+        Self { block, within_block, offsets }
+    }
+
+    /// The index of the block, within the writer's stream, that contains this position.
+    pub fn block(&self) -> u64 {
+        self.block
+    }
+
+    /// The offset of this position within the uncompressed bytes of its block.
+    pub fn within_block(&self) -> u16 {
+        self.within_block
+    }
+
+    /// Returns the virtual offset if the containing block has already been written.
+    pub fn try_resolve(&self) -> Option<u64> {
+        self.offsets.block_start(self.block).map(|start| self.combine(start))
+    }
+
+    /// Blocks until the containing block has been written and returns the virtual offset, or
+    /// `None` if the pool stopped without writing the block.
+    ///
+    /// Note that the block is only sent for compression once it is full or the writer is
+    /// flushed, so this should not be called from the producing thread before the writer has
+    /// been flushed with [`PooledWriter::flush_partial`](crate::PooledWriter::flush_partial) or
+    /// closed.
+    pub fn wait(&self) -> Option<u64> {
+        let mut state = self.offsets.state.lock();
+        loop {
+            if let Some(start) = state.start(self.block) {
+                return Some(self.combine(start));
+            } else if state.closed {
+                return None;
+            }
+            self.offsets.written.wait(&mut state);
+        }
+    }
+
+    /// As [`PendingVirtualOffset::wait`] but gives up and returns `None` after `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<u64> {
+        let deadline = std::time::Instant::now() + timeout;
+        let mut state = self.offsets.state.lock();
+        loop {
+            if let Some(start) = state.start(self.block) {
+                return Some(self.combine(start));
+            }
+            let now = std::time::Instant::now();
+            if state.closed || now >= deadline {
+                return None;
+            }
+            self.offsets.written.wait_for(&mut state, deadline - now);
+        }
+    }
+
+    fn combine(&self, block_start: u64) -> u64 {
+        (block_start << 16) | u64::from(self.within_block)
+    }
+}
+
+impl Clone for PendingVirtualOffset {
+    fn clone(&self) -> Self {
+        Self::new(self.block, self.within_block, self.offsets.clone())
+    }
+}
+
+impl Drop for PendingVirtualOffset {
+    fn drop(&mut self) {
+        self.offsets.release(self.block);
+    }
+}
@@ -0,0 +1,102 @@
+//! A drop-in [`Write`] that compresses a single output with several threads, for the common case
+//! of one large file, e.g. as a replacement for `pigz`, without the multi-writer pool API.
+//!
+//! ```rust,no_run
+//! use std::io::Write;
+//! use pooled_writer::{bgzf::BgzfCompressor, parallel::ParallelCompressWriter};
+//!
+//! let file = std::fs::File::create("out.txt.gz")?;
+//! let mut writer = ParallelCompressWriter::<_, BgzfCompressor>::new(file, 8)?;
+//! writer.write_all(b"hello")?;
+//! writer.finish()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::fmt;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+
+use crate::{Compressor, Pool, PoolBuilder, PoolResult, PooledWriter};
+
+/// A [`Write`] that compresses everything written to it with the compressor `C` on a pool of
+/// its own threads, writing the compressed blocks to `W` in order.
+///
+/// The output is finished, e.g. the BGZF EOF block appended, by [`ParallelCompressWriter::finish`],
+/// which reports any error.  If the writer is dropped without being finished it is finished
+/// then, and any error is ignored.
+pub struct ParallelCompressWriter<W, C> {
+    writer: Option<PooledWriter>,
+    pool: Option<Pool>,
+    types: PhantomData<fn() -> (W, C)>,
+}
+
+impl<W, C> ParallelCompressWriter<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    /// Creates a writer that compresses to `writer` on `threads` threads at the compressor's
+    /// default compression level.
+    pub fn new(writer: W, threads: usize) -> PoolResult<Self> {
+        Self::from_builder(PoolBuilder::<W, C>::new().threads(threads), writer)
+    }
+
+    /// Creates a writer that compresses to `writer` on `threads` threads at the given
+    /// compression level.
+    ///
+    /// Returns an error if the level is not valid for the compressor.
+    pub fn with_level(writer: W, threads: usize, level: u8) -> PoolResult<Self> {
+        Self::from_builder(
+            PoolBuilder::<W, C>::new().threads(threads).compression_level(level)?,
+            writer,
+        )
+    }
+
+    /// Exchanges `writer` with `builder` and starts the pool.
+    fn from_builder(mut builder: PoolBuilder<W, C>, writer: W) -> PoolResult<Self> {
+        let writer = builder.exchange(writer);
+        let pool = builder.build()?;
+        Ok(Self { writer: Some(writer), pool: Some(pool), types: PhantomData })
+    }
+}
+
+impl<W, C> ParallelCompressWriter<W, C> {
+    /// Finishes the output, waiting for every block to be compressed and written, and stops the
+    /// threads.
+    pub fn finish(mut self) -> PoolResult<()> {
+        self.finish_output()
+    }
+
+    /// Finishes the output if that has not already been done.
+    fn finish_output(&mut self) -> PoolResult<()> {
+        let closed = self.writer.take().map_or(Ok(()), PooledWriter::close);
+        let stopped = self.pool.take().map_or(Ok(()), |mut pool| pool.stop_pool());
+        closed.map_err(Into::into).and(stopped)
+    }
+
+    /// The writer, which is only taken once the output is finished.
+    fn writer(&mut self) -> &mut PooledWriter {
+        self.writer.as_mut().expect("Unreachable")
+    }
+}
+
+impl<W, C> Write for ParallelCompressWriter<W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+impl<W, C> Drop for ParallelCompressWriter<W, C> {
+    fn drop(&mut self) {
+        let _ = self.finish_output();
+    }
+}
+
+impl<W, C> fmt::Debug for ParallelCompressWriter<W, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelCompressWriter").field("pool", &self.pool).finish()
+    }
+}
@@ -0,0 +1,93 @@
+//! Progress bars for a pool, drawn with [`indicatif`] from the pool's statistics: one bar per
+//! writer showing the uncompressed bytes written against those sent, and one for the whole pool
+//! showing the overall throughput.
+//!
+//! The bars are updated from a [`PoolStats`] snapshot, so they can be driven from any loop that
+//! polls [`Pool::stats`](crate::Pool::stats), or while the pool is stopped:
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use pooled_writer::{bgzf::BgzfCompressor, progress::PoolProgress, PoolBuilder};
+//!
+//! let mut builder = PoolBuilder::<_, BgzfCompressor>::new();
+//! let writer = builder.exchange(std::fs::File::create("out.txt.gz")?);
+//! let mut pool = builder.build()?;
+//! let mut progress = PoolProgress::new(indicatif::MultiProgress::new());
+//! // ... write to and close the writer ...
+//! # writer.close()?;
+//! pool.stop_pool_with_progress(Duration::from_millis(100), |stats| progress.update(stats))?;
+//! progress.finish();
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+use crate::stats::PoolStats;
+
+/// The template for the bar of each writer.
+const WRITER_TEMPLATE: &str = "{prefix:>16} [{bar:30}] {bytes}/{total_bytes} {msg}";
+
+/// The template for the bar of the whole pool.
+const TOTAL_TEMPLATE: &str =
+    "{prefix:>16} [{bar:30}] {bytes}/{total_bytes} {binary_bytes_per_sec} {elapsed_precise}";
+
+/// A set of [`indicatif`] progress bars tracking the writers of a pool.  Bars are added for
+/// writers as they first appear in the statistics, labelled with the writer's ID if it has one.
+#[derive(Debug)]
+pub struct PoolProgress {
+    multi: MultiProgress,
+    total: ProgressBar,
+    writers: Vec<ProgressBar>,
+}
+
+impl PoolProgress {
+    /// Creates the bars within `multi`, which may also hold the application's own bars.
+    pub fn new(multi: MultiProgress) -> Self {
+        let total = multi.add(ProgressBar::new(0).with_style(style(TOTAL_TEMPLATE)));
+        total.set_prefix("total");
+        Self { multi, total, writers: vec![] }
+    }
+
+    /// Updates the bars from a snapshot of the pool's statistics.
+    pub fn update(&mut self, stats: &PoolStats) {
+        for writer in &stats.writers[self.writers.len().min(stats.writers.len())..] {
+            let bar = ProgressBar::new(0).with_style(style(WRITER_TEMPLATE));
+            bar.set_prefix(match &writer.id {
+                Some(id) => id.clone(),
+                None => format!("writer {}", writer.writer_index),
+            });
+            self.writers.push(self.multi.insert_before(&self.total, bar));
+        }
+
+        for (bar, writer) in self.writers.iter().zip(&stats.writers) {
+            bar.set_length(writer.uncompressed_bytes);
+            bar.set_position(writer.uncompressed_bytes_written);
+            if writer.compressed_bytes > 0 {
+                bar.set_message(format!("ratio {:.2}", writer.compression_ratio()));
+            }
+        }
+
+        self.total.set_length(stats.uncompressed_bytes());
+        self.total.set_position(stats.writers.iter().map(|w| w.uncompressed_bytes_written).sum());
+    }
+
+    /// Finishes every bar, leaving them drawn with their final values.
+    pub fn finish(&self) {
+        self.writers.iter().for_each(ProgressBar::finish);
+        self.total.finish();
+    }
+
+    /// The bar for the writer at `writer_index`, once it has appeared in the statistics.
+    pub fn writer_bar(&self, writer_index: usize) -> Option<&ProgressBar> {
+        self.writers.get(writer_index)
+    }
+
+    /// The bar for the whole pool.
+    pub fn total_bar(&self) -> &ProgressBar {
+        &self.total
+    }
+}
+
+/// The style for a bar with the given template, which is known to be valid.
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template).expect("Unreachable.").progress_chars("=> ")
+}
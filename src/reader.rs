@@ -0,0 +1,206 @@
+//! Parallel decompression, the reading counterpart of a [`PooledWriter`](crate::PooledWriter).
+//!
+//! A reader exchanged with [`PoolBuilder::exchange_reader`](crate::PoolBuilder::exchange_reader)
+//! becomes a [`PooledReader`], which splits the compressed stream into blocks on the thread
+//! that reads from it, and has the blocks decompressed by the pool's threads alongside any
+//! compression.  Up to a fixed number of blocks are prefetched ahead of the reader, and the
+//! decompressed bytes are always returned in order.
+//!
+//! Each format that can be read this way implements [`Decompressor`] alongside its
+//! [`Compressor`](crate::Compressor), e.g. [`BgzfDecompressor`](crate::bgzf::BgzfDecompressor).
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::marker::PhantomData;
+
+use crate::channel::{self, DoorbellSender, Receiver, Sender};
+
+/// A unit of work for the pool's threads other than compressing a block.
+pub(crate) type Task = Box<dyn FnOnce() + Send>;
+
+/// A format whose streams are made of independently compressed blocks, and so can be
+/// decompressed in parallel.
+pub trait Decompressor: Sized + 'static {
+    /// The error returned when a block cannot be decompressed.
+    type Error: Error + Send + Sync + 'static;
+
+    /// Creates a new decompressor.  A decompressor is created for each block, on the pool thread
+    /// that decompresses it, so this should be cheap.
+    fn new() -> Self;
+
+    /// Reads the next whole compressed block from `reader`, returning `None` at the end of the
+    /// stream.  This is called on the thread that reads from the [`PooledReader`].
+    fn read_block<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>>;
+
+    /// Decompresses a block read by [`Decompressor::read_block`], appending the decompressed
+    /// bytes to `output`.
+    fn decompress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error>;
+}
+
+/// A [`Read`] implementation whose blocks are decompressed by the threads of a
+/// [`Pool`](crate::Pool).
+///
+/// Blocks are only decompressed while the pool is running, so the reader should be read to the
+/// end before the pool is stopped.  Reads fail once the pool has been stopped.
+pub struct PooledReader<R: Read, D: Decompressor> {
+    /// The compressed stream.
+    reader: R,
+    /// The channel on which blocks are sent to the pool's threads to be decompressed.
+    tasks: DoorbellSender<Task>,
+    /// The channel on which the pool's threads send back each decompressed block with its
+    /// sequence number.  It holds as many blocks as may be prefetched, so sends never block.
+    results_tx: Sender<(u64, io::Result<Vec<u8>>)>,
+    results_rx: Receiver<(u64, io::Result<Vec<u8>>)>,
+    /// The sequence number of the next block to be read.
+    next: u64,
+    /// The blocks from the next onwards, by sequence number, or `None` where a block is still
+    /// being decompressed.
+    pending: VecDeque<Option<io::Result<Vec<u8>>>>,
+    /// The maximum number of blocks to decompress ahead of the reader.
+    prefetch: usize,
+    /// The decompressed block currently being read.
+    block: Vec<u8>,
+    /// How much of the current block has been read.
+    offset: usize,
+    /// True once the end of the compressed stream has been reached.
+    eof: bool,
+    decompressor: PhantomData<fn() -> D>,
+}
+
+impl<R: Read, D: Decompressor> PooledReader<R, D> {
+    /// Creates a reader that decompresses up to `prefetch` blocks ahead on the pool's threads.
+    pub(crate) fn new(reader: R, tasks: DoorbellSender<Task>, prefetch: usize) -> Self {
+        let prefetch = std::cmp::max(prefetch, 1);
+        let (results_tx, results_rx) = channel::bounded(prefetch);
+        Self {
+            reader,
+            tasks,
+            results_tx,
+            results_rx,
+            next: 0,
+            pending: VecDeque::new(),
+            prefetch,
+            block: vec![],
+            offset: 0,
+            eof: false,
+            decompressor: PhantomData,
+        }
+    }
+
+    /// Reads blocks from the compressed stream and sends them to be decompressed until the
+    /// prefetch limit or the end of the stream is reached.
+    fn prefetch(&mut self) -> io::Result<()> {
+        while !self.eof && self.pending.len() < self.prefetch {
+            let input = match D::read_block(&mut self.reader)? {
+                Some(input) => input,
+                None => {
+                    self.eof = true;
+                    break;
+                }
+            };
+            let sequence = self.next + self.pending.len() as u64;
+            let mut reply = Reply { sequence, tx: Some(self.results_tx.clone()) };
+            let task: Task = Box::new(move || {
+                let mut output = vec![];
+                let result = D::new()
+                    .decompress(&input, &mut output)
+                    .map(|_| output)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+                reply.send(result);
+            });
+            // A task the pool can't take is dropped, and so still replies with an error
+            let sent = self.tasks.send(task);
+            self.pending.push_back(None);
+            sent.map_err(|_| pool_stopped())?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the next block to be decompressed and removes it, or returns `None` if no
+    /// blocks are pending.
+    fn next_block(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        while self.pending[0].is_none() {
+            let (sequence, result) = self.results_rx.recv().map_err(|_| pool_stopped())?;
+            self.pending[(sequence - self.next) as usize] = Some(result);
+        }
+        self.next += 1;
+        self.pending.pop_front().flatten().transpose()
+    }
+
+    /// A reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+}
+
+impl<R: Read, D: Decompressor> Read for PooledReader<R, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let block = self.fill_buf()?;
+        let n = std::cmp::min(buf.len(), block.len());
+        buf[..n].copy_from_slice(&block[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+/// Reading through [`BufRead`] gives access to each decompressed block without copying it.
+impl<R: Read, D: Decompressor> BufRead for PooledReader<R, D> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Move on to the next non-empty block once the current one has been read
+        while self.offset == self.block.len() {
+            self.prefetch()?;
+            match self.next_block()? {
+                Some(block) => {
+                    self.block = block;
+                    self.offset = 0;
+                }
+                None => break,
+            }
+        }
+        Ok(&self.block[self.offset..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.offset = std::cmp::min(self.offset + amt, self.block.len());
+    }
+}
+
+impl<R: Read, D: Decompressor> fmt::Debug for PooledReader<R, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledReader")
+            .field("pending", &self.pending.len())
+            .field("prefetch", &self.prefetch)
+            .field("eof", &self.eof)
+            .finish()
+    }
+}
+
+/// Sends the result of decompressing a block back to its [`PooledReader`], or an error if the
+/// task is dropped unrun, e.g. once the pool has stopped, so that the reader never waits for it.
+struct Reply {
+    sequence: u64,
+    tx: Option<Sender<(u64, io::Result<Vec<u8>>)>>,
+}
+
+impl Reply {
+    fn send(&mut self, result: io::Result<Vec<u8>>) {
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send((self.sequence, result));
+        }
+    }
+}
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        self.send(Err(pool_stopped()));
+    }
+}
+
+/// The error returned when the pool has stopped before a block could be decompressed.
+fn pool_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "pool stopped before the block was decompressed")
+}
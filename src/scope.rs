@@ -0,0 +1,248 @@
+//! Structured use of a pool whose lifetime is tied to a closure, see [`Pool::run`].  The pool is
+//! always stopped, and its errors returned, when the closure returns, so that there is no
+//! ordering of `close()` and `stop_pool()` calls to get right.
+//!
+//! ```rust,no_run
+//! use std::io::Write;
+//! use pooled_writer::{bgzf::BgzfCompressor, Pool, PoolBuilder};
+//!
+//! let builder = PoolBuilder::<_, BgzfCompressor>::new().threads(8);
+//! Pool::run(builder, |scope| {
+//!     let mut first = scope.exchange(std::fs::File::create("first.txt.gz")?)?;
+//!     let mut second = scope.exchange(std::fs::File::create("second.txt.gz")?)?;
+//!     first.write_all(b"hello")?;
+//!     second.write_all(b"world")?;
+//!     Ok(())
+//! })?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! The writers borrow the scope, so they can't outlive the closure to be used once the pool has
+//! been stopped:
+//!
+//! ```rust,compile_fail
+//! use pooled_writer::{bgzf::BgzfCompressor, Pool, PoolBuilder};
+//!
+//! let builder = PoolBuilder::<Vec<u8>, BgzfCompressor>::new();
+//! let writer = Pool::run(builder, |scope| scope.exchange(vec![]))?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::fmt;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::{
+    Compressor, ExchangeOptions, ExtraSubfield, Pool, PoolBuilder, PoolError, PoolResult,
+    PooledWriter,
+};
+
+/// The state of the pool of a [`PoolScope`].
+enum ScopeState<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    /// Writers are still being exchanged.
+    Building(PoolBuilder<W, C>),
+    /// The pool has been built and is running.
+    Running(Pool),
+    /// The pool has been stopped, or failed to start.
+    Stopped,
+}
+
+/// Exchanges writers with the pool of [`Pool::run`].  The pool starts once any of its writers
+/// sends its first block, or when [`PoolScope::start`] is called, after which no more writers
+/// may be exchanged.
+pub struct PoolScope<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    state: Arc<Mutex<ScopeState<W, C>>>,
+}
+
+impl<W, C> PoolScope<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    /// Exchanges a writer for a [`ScopedWriter`] as with [`PoolBuilder::exchange`].
+    ///
+    /// Returns an error if the pool has already started.
+    pub fn exchange(&self, writer: W) -> PoolResult<ScopedWriter<'_>> {
+        let mut state = self.state.lock();
+        let builder = match &mut *state {
+            ScopeState::Building(builder) => builder,
+            _ => {
+                return Err(PoolError::UnsupportedOption(
+                    "writers cannot be exchanged once the pool has started".to_string(),
+                ))
+            }
+        };
+        let mut pooled = builder.exchange(writer);
+        let scope = self.state.clone();
+        pooled.start = Some(StartHook(Arc::new(move || start(&scope))));
+        Ok(ScopedWriter { writer: pooled, scope: PhantomData })
+    }
+
+    /// Starts the pool if it has not started yet, e.g. before handing writers to scoped
+    /// threads.
+    ///
+    /// Returns an error if the pool cannot be built, or has already been stopped.
+    pub fn start(&self) -> PoolResult<()> {
+        start(&self.state)
+    }
+
+    /// Stops the pool if it was started.
+    fn stop(&self) -> PoolResult<()> {
+        let state = std::mem::replace(&mut *self.state.lock(), ScopeState::Stopped);
+        match state {
+            ScopeState::Running(mut pool) => pool.stop_pool(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<W, C> fmt::Debug for PoolScope<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match &*self.state.lock() {
+            ScopeState::Building(_) => "building",
+            ScopeState::Running(_) => "running",
+            ScopeState::Stopped => "stopped",
+        };
+        f.debug_struct("PoolScope").field("state", &state).finish()
+    }
+}
+
+/// A [`PooledWriter`] exchanged with the pool of [`Pool::run`], which borrows the
+/// [`PoolScope`] so that it is always closed or dropped before the pool is stopped.  It derefs
+/// to the [`PooledWriter`] for its accessors, and has the pooled writer's methods that change
+/// it.
+#[derive(Debug)]
+pub struct ScopedWriter<'scope> {
+    writer: PooledWriter,
+    scope: PhantomData<&'scope ()>,
+}
+
+impl ScopedWriter<'_> {
+    /// See [`PooledWriter::reconfigure`].
+    pub fn reconfigure(&mut self, options: ExchangeOptions) -> PoolResult<()> {
+        self.writer.reconfigure(options)
+    }
+
+    /// See [`PooledWriter::add_block_subfield`].
+    pub fn add_block_subfield(&mut self, subfield: ExtraSubfield) -> PoolResult<()> {
+        self.writer.add_block_subfield(subfield)
+    }
+
+    /// See [`PooledWriter::end_record`].
+    pub fn end_record(&mut self) -> io::Result<()> {
+        self.writer.end_record()
+    }
+
+    /// See [`PooledWriter::flush_partial`].
+    pub fn flush_partial(&mut self) -> io::Result<()> {
+        self.writer.flush_partial()
+    }
+
+    /// See [`PooledWriter::quiesce`].
+    pub fn quiesce(&mut self) -> io::Result<()> {
+        self.writer.quiesce()
+    }
+
+    /// See [`PooledWriter::finalize`].
+    pub fn finalize(self) -> io::Result<()> {
+        self.writer.finalize()
+    }
+
+    /// See [`PooledWriter::close`].
+    pub fn close(self) -> io::Result<()> {
+        self.writer.close()
+    }
+}
+
+impl Deref for ScopedWriter<'_> {
+    type Target = PooledWriter;
+
+    fn deref(&self) -> &PooledWriter {
+        &self.writer
+    }
+}
+
+impl Write for ScopedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Builds and starts the pool, if it has not been started already.
+fn start<W, C>(state: &Mutex<ScopeState<W, C>>) -> PoolResult<()>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    let mut state = state.lock();
+    match std::mem::replace(&mut *state, ScopeState::Stopped) {
+        ScopeState::Building(builder) => {
+            *state = ScopeState::Running(builder.build()?);
+            Ok(())
+        }
+        running @ ScopeState::Running(_) => {
+            *state = running;
+            Ok(())
+        }
+        ScopeState::Stopped => Err(PoolError::ChannelSend),
+    }
+}
+
+/// Starts the pool of the [`PoolScope`] that a writer was exchanged with, before the writer
+/// sends its first block.
+#[derive(Clone)]
+pub(crate) struct StartHook(Arc<dyn Fn() -> PoolResult<()> + Send + Sync>);
+
+impl StartHook {
+    pub(crate) fn call(&self) -> PoolResult<()> {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for StartHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StartHook").finish()
+    }
+}
+
+impl Pool {
+    /// Builds a pool from `builder` and calls `f` with a [`PoolScope`] through which writers are
+    /// exchanged with it, then stops the pool once `f` returns, so that the pool is always
+    /// stopped and its errors returned.  The pool starts once any writer sends its first block,
+    /// or when [`PoolScope::start`] is called.  The [`ScopedWriter`]s borrow the scope, so any
+    /// still open when `f` returns have been dropped before the pool is stopped, and so handled
+    /// according to the pool's [`DropPolicy`], which by default finalizes them.
+    ///
+    /// Returns the pool's error if it fails, otherwise the result of `f`.
+    ///
+    /// [`DropPolicy`]: crate::DropPolicy
+    pub fn run<W, C, T, F>(builder: PoolBuilder<W, C>, f: F) -> PoolResult<T>
+    where
+        W: Write + Send + 'static,
+        C: Compressor,
+        F: FnOnce(&PoolScope<W, C>) -> PoolResult<T>,
+    {
+        let scope = PoolScope { state: Arc::new(Mutex::new(ScopeState::Building(builder))) };
+        let result = f(&scope);
+        scope.stop().and(result)
+    }
+}
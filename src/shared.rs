@@ -0,0 +1,123 @@
+//! Writers that are shared between a pool and the application, for applications that need to
+//! write directly to the underlying writer now and then, e.g. an uncompressed region of a file
+//! between compressed ones.
+//!
+//! A [`SharedWriter`] wraps the underlying writer in an `Arc<Mutex<W>>` and is exchanged with a
+//! pool in place of it.  The application gets an exclusive window on the underlying writer with
+//! [`SharedWriter::exclusive`], which first waits for the pool to write every block sent by the
+//! [`PooledWriter`] so far.  Bytes written in the window therefore follow everything written to
+//! the pooled writer before it, and precede everything written after it.
+//!
+//! ```rust,no_run
+//! use std::io::Write;
+//! use pooled_writer::{bgzf::BgzfCompressor, shared::SharedWriter, PoolBuilder};
+//!
+//! let shared = SharedWriter::new(std::fs::File::create("out.bin")?);
+//! let mut builder = PoolBuilder::<_, BgzfCompressor>::new();
+//! let mut writer = builder.exchange(shared.clone());
+//! let mut pool = builder.build()?;
+//!
+//! writer.write_all(b"compressed")?;
+//! shared.exclusive(&mut writer)?.write_all(b"uncompressed")?;
+//! writer.write_all(b"compressed again")?;
+//! writer.close()?;
+//! pool.stop_pool()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use parking_lot::{Mutex, MutexGuard};
+
+use crate::PooledWriter;
+
+/// A writer shared between a pool, which writes the compressed blocks to it, and the
+/// application.  Clones share the same underlying writer.
+#[derive(Debug, Default)]
+pub struct SharedWriter<W> {
+    inner: Arc<Mutex<W>>,
+}
+
+impl<W> Clone for SharedWriter<W> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<W: Write> SharedWriter<W> {
+    /// Wraps `writer` so that it can be shared.
+    pub fn new(writer: W) -> Self {
+        Self { inner: Arc::new(Mutex::new(writer)) }
+    }
+
+    /// Waits until the pool has written every block sent by `pooled`, which must have been
+    /// exchanged for this writer, then returns exclusive access to the underlying writer.
+    /// Nothing more can be written to `pooled` until the guard is dropped.  Any bytes buffered
+    /// by `pooled` are sent as a partial block first.
+    ///
+    /// Returns an error if the pool stops before the blocks are written.
+    pub fn exclusive<'a>(&'a self, pooled: &'a mut PooledWriter) -> io::Result<Exclusive<'a, W>> {
+        pooled.quiesce()?;
+        Ok(Exclusive { guard: self.inner.lock(), _pooled: pooled })
+    }
+
+    /// The shared underlying writer, e.g. to inspect it once the pool has been stopped.  Writing
+    /// to it other than through [`SharedWriter::exclusive`] may interleave with the pool's
+    /// writes.
+    pub fn inner(&self) -> &Arc<Mutex<W>> {
+        &self.inner
+    }
+}
+
+impl<W: Write> Write for SharedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.lock().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.inner.lock().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.lock().flush()
+    }
+}
+
+/// Exclusive access to the underlying writer of a [`SharedWriter`], during which the pooled
+/// writer it was exchanged for cannot be written to.
+pub struct Exclusive<'a, W> {
+    guard: MutexGuard<'a, W>,
+    _pooled: &'a mut PooledWriter,
+}
+
+impl<W> Deref for Exclusive<'_, W> {
+    type Target = W;
+
+    fn deref(&self) -> &W {
+        &self.guard
+    }
+}
+
+impl<W> DerefMut for Exclusive<'_, W> {
+    fn deref_mut(&mut self) -> &mut W {
+        &mut self.guard
+    }
+}
+
+impl<W: Write> Write for Exclusive<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.guard.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.guard.flush()
+    }
+}
+
+impl<W> fmt::Debug for Exclusive<'_, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Exclusive").finish()
+    }
+}
@@ -0,0 +1,94 @@
+///! An implementation of [`Compressor`] for the Snappy framing format.
+use std::io::{self, Read, Write};
+
+use snap::read::FrameDecoder;
+use snap::write::FrameEncoder;
+
+use crate::reader::Decompressor;
+use crate::{Compressor, CompressorCapabilities};
+
+/// The stream identifier chunk that starts every Snappy framed stream.
+const STREAM_IDENTIFIER: &[u8] = b"\xff\x06\x00\x00sNaPpY";
+
+/// A Snappy compressor that encodes each block with the Snappy framing format.
+///
+/// Each block starts with a stream identifier, which the framing format allows to be repeated,
+/// so the output of a pooled writer is a valid Snappy framed stream, e.g. for Hadoop or Spark.
+/// Snappy has no compression levels, so only level 0 is accepted.  There is no EOF marker.
+pub struct SnappyCompressor;
+
+impl Compressor for SnappyCompressor {
+    type Error = io::Error;
+    type CompressionLevel = ();
+
+    /// The maximum amount of uncompressed data in a single chunk of the framing format.
+    const BLOCK_SIZE: usize = 64 * 1024;
+
+    fn capabilities() -> CompressorCapabilities {
+        CompressorCapabilities::new(Self::BLOCK_SIZE)
+            .eof_marker(false)
+            .dictionaries(false)
+            .extra_subfields(false)
+            .compression_levels(0, 0)
+            .deterministic(true)
+    }
+
+    fn new(_compression_level: Self::CompressionLevel) -> Self {
+        Self
+    }
+
+    fn default_compression_level() -> Self::CompressionLevel {}
+
+    fn new_compression_level(compression_level: u8) -> Result<Self::CompressionLevel, Self::Error> {
+        if compression_level == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("snappy has no compression levels, got {}", compression_level),
+            ))
+        }
+    }
+
+    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        let mut encoder = FrameEncoder::new(output);
+        encoder.write_all(input)?;
+        encoder.flush()
+    }
+}
+
+/// A Snappy decompressor that decompresses each chunk of the framing format independently.
+///
+/// Each block is a single chunk, so stream identifiers and padding decompress to nothing.
+pub struct SnappyDecompressor;
+
+impl Decompressor for SnappyDecompressor {
+    type Error = io::Error;
+
+    fn new() -> Self {
+        Self
+    }
+
+    fn read_block<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+        let mut header = [0u8; 4];
+        let mut read = 0;
+        while read < header.len() {
+            match reader.read(&mut header[read..])? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                n => read += n,
+            }
+        }
+        let len = u32::from_le_bytes([header[1], header[2], header[3], 0]) as usize;
+        let mut chunk = header.to_vec();
+        chunk.resize(header.len() + len, 0);
+        reader.read_exact(&mut chunk[header.len()..])?;
+        Ok(Some(chunk))
+    }
+
+    fn decompress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        // The decoder requires each stream to start with an identifier
+        FrameDecoder::new(STREAM_IDENTIFIER.chain(input)).read_to_end(output)?;
+        Ok(())
+    }
+}
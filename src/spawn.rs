@@ -0,0 +1,119 @@
+//! Spawning of a pool's threads, which may be named and given a stack size, or handed to the
+//! application to spawn itself, e.g. so that they show meaningful names in profilers or are
+//! tracked along with the application's own threads.
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//! use pooled_writer::{bgzf::BgzfCompressor, spawn::PoolThread, PoolBuilder};
+//!
+//! let mut builder = PoolBuilder::<_, BgzfCompressor>::new()
+//!     .thread_name_prefix("bam-writer")
+//!     .thread_spawner(Arc::new(|thread: PoolThread| {
+//!         eprintln!("starting {}", thread.name().unwrap_or("pool thread"));
+//!         thread.spawn()
+//!     }));
+//! let writer = builder.exchange(std::fs::File::create("out.txt.gz")?);
+//! let mut pool = builder.build()?;
+//! # writer.close()?;
+//! # pool.stop_pool()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use crate::PoolResult;
+
+/// Spawns a [`PoolThread`], returning the handle through which the pool joins it, see
+/// [`PoolBuilder::thread_spawner`](crate::PoolBuilder::thread_spawner).
+pub type ThreadSpawner =
+    Arc<dyn Fn(PoolThread) -> io::Result<JoinHandle<PoolResult<()>>> + Send + Sync>;
+
+/// One of a pool's threads, to be spawned by a [`ThreadSpawner`].
+pub struct PoolThread {
+    name: Option<String>,
+    stack_size: Option<usize>,
+    main: Box<dyn FnOnce() -> PoolResult<()> + Send>,
+}
+
+impl PoolThread {
+    /// The name of the thread, if a name prefix was set with
+    /// [`PoolBuilder::thread_name_prefix`](crate::PoolBuilder::thread_name_prefix): the prefix
+    /// followed by `-main` for the thread that manages the pool, or by the index of the thread
+    /// for the others.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The stack size of the thread, if one was set with
+    /// [`PoolBuilder::thread_stack_size`](crate::PoolBuilder::thread_stack_size).
+    pub fn stack_size(&self) -> Option<usize> {
+        self.stack_size
+    }
+
+    /// Does the thread's work, returning once the pool has stopped.  Must be called on the newly
+    /// spawned thread.
+    pub fn run(self) -> PoolResult<()> {
+        (self.main)()
+    }
+
+    /// Spawns the thread with [`std::thread::Builder`], with its name and stack size if set,
+    /// which is how the pool spawns its threads unless given a [`ThreadSpawner`].
+    pub fn spawn(self) -> io::Result<JoinHandle<PoolResult<()>>> {
+        let mut builder = std::thread::Builder::new();
+        if let Some(name) = &self.name {
+            builder = builder.name(name.clone());
+        }
+        if let Some(stack_size) = self.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        builder.spawn(move || self.run())
+    }
+}
+
+impl fmt::Debug for PoolThread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolThread")
+            .field("name", &self.name)
+            .field("stack_size", &self.stack_size)
+            .finish()
+    }
+}
+
+/// How a pool spawns its threads.
+#[derive(Clone, Default)]
+pub(crate) struct Spawner {
+    pub(crate) name_prefix: Option<String>,
+    pub(crate) stack_size: Option<usize>,
+    pub(crate) spawner: Option<ThreadSpawner>,
+}
+
+impl Spawner {
+    /// Spawns a thread running `main`, named with the name prefix followed by `name` if a prefix
+    /// is set.
+    pub(crate) fn spawn<F>(&self, name: &str, main: F) -> io::Result<JoinHandle<PoolResult<()>>>
+    where
+        F: FnOnce() -> PoolResult<()> + Send + 'static,
+    {
+        let thread = PoolThread {
+            name: self.name_prefix.as_ref().map(|prefix| format!("{}-{}", prefix, name)),
+            stack_size: self.stack_size,
+            main: Box::new(main),
+        };
+        match &self.spawner {
+            Some(spawner) => spawner(thread),
+            None => thread.spawn(),
+        }
+    }
+}
+
+impl fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spawner")
+            .field("name_prefix", &self.name_prefix)
+            .field("stack_size", &self.stack_size)
+            .field("custom", &self.spawner.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,406 @@
+//! Statistics gathered about the data passing through a [`Pool`](crate::Pool).
+//!
+//! Counters are updated by the [`PooledWriter`](crate::PooledWriter)s and the pool's threads as
+//! data flows through the pool, and a point-in-time snapshot may be taken at any time, including
+//! after the pool has been stopped, via [`Pool::stats`](crate::Pool::stats).
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+/// The number of buckets in a [`LatencyHistogram`], one per power of two nanoseconds.
+const LATENCY_BUCKETS: usize = 64;
+
+/// A lock-free histogram of latencies with buckets of exponentially increasing width, i.e.
+/// bucket `i` holds latencies in `[2^i, 2^(i+1))` nanoseconds.  Percentiles are therefore
+/// accurate to within a factor of two, which is ample for spotting bottlenecks.
+#[derive(Debug)]
+pub(crate) struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: [(); LATENCY_BUCKETS].map(|_| AtomicU64::new(0)),
+            total_nanos: AtomicU64::new(0),
+            max_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Records a single latency.
+    pub(crate) fn record(&self, latency: Duration) {
+        let nanos = std::cmp::min(latency.as_nanos(), u128::from(u64::MAX)) as u64;
+        let bucket = (63 - std::cmp::max(nanos, 1).leading_zeros()) as usize;
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Summarizes the histogram.
+    pub(crate) fn summary(&self) -> LatencySummary {
+        let counts: Vec<u64> = self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+        let count: u64 = counts.iter().sum();
+        let max = Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed));
+        let percentile = |p: f64| {
+            if count == 0 {
+                return Duration::default();
+            }
+            let rank = std::cmp::max((p * count as f64).ceil() as u64, 1);
+            let mut seen = 0;
+            for (bucket, &n) in counts.iter().enumerate() {
+                seen += n;
+                if seen >= rank {
+                    // Report the upper bound of the bucket, capped at the largest latency seen
+                    let upper = 1u64.checked_shl(bucket as u32 + 1).unwrap_or(u64::MAX);
+                    return std::cmp::min(Duration::from_nanos(upper), max);
+                }
+            }
+            max
+        };
+
+        LatencySummary {
+            count,
+            mean: if count == 0 {
+                Duration::default()
+            } else {
+                Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed) / count)
+            },
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            max,
+        }
+    }
+}
+
+/// A summary of a distribution of latencies.  Percentiles are approximate, see
+/// [`WriterStats::reorder_wait`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencySummary {
+    /// The number of latencies recorded.
+    pub count: u64,
+    /// The mean latency.
+    pub mean: Duration,
+    /// The approximate median latency.
+    pub p50: Duration,
+    /// The approximate 90th percentile latency.
+    pub p90: Duration,
+    /// The approximate 99th percentile latency.
+    pub p99: Duration,
+    /// The maximum latency.
+    pub max: Duration,
+}
+
+/// The live counters for a single writer, shared between the [`PooledWriter`](crate::PooledWriter),
+/// the pool threads and the [`Pool`](crate::Pool).
+#[derive(Debug, Default)]
+pub(crate) struct WriterCounters {
+    blocks: AtomicU64,
+    partial_blocks: AtomicU64,
+    uncompressed_bytes: AtomicU64,
+    compressed_bytes: AtomicU64,
+    blocks_written: AtomicU64,
+    uncompressed_bytes_written: AtomicU64,
+    requeued_blocks: AtomicU64,
+    batched_blocks: AtomicU64,
+    write_retries: AtomicU64,
+    reopens: AtomicU64,
+    block_size: AtomicU64,
+    reorder_wait: LatencyHistogram,
+}
+
+impl WriterCounters {
+    /// Records that a block of `len` uncompressed bytes was sent for compression.  A block is
+    /// `partial` if it was sent before it filled up, other than as the final block of the stream.
+    pub(crate) fn record_block(&self, len: usize, partial: bool) {
+        self.blocks.fetch_add(1, Ordering::Relaxed);
+        self.uncompressed_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        if partial {
+            self.partial_blocks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records that a block of `len` compressed bytes, holding `uncompressed_len` uncompressed
+    /// bytes, was written to the underlying writer.
+    pub(crate) fn record_write(&self, len: usize, uncompressed_len: usize) {
+        self.compressed_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        self.blocks_written.fetch_add(1, Ordering::Relaxed);
+        self.uncompressed_bytes_written.fetch_add(uncompressed_len as u64, Ordering::Relaxed);
+    }
+
+    /// Records that `blocks` blocks holding `len` uncompressed bytes, sent but not yet written,
+    /// were carried over to another pool when the writer was detached, so that they are no
+    /// longer counted as sent by this one.
+    pub(crate) fn record_detached(&self, blocks: usize, len: usize) {
+        self.blocks.fetch_sub(blocks as u64, Ordering::Relaxed);
+        self.uncompressed_bytes.fetch_sub(len as u64, Ordering::Relaxed);
+    }
+
+    /// The number of blocks sent for compression so far.
+    pub(crate) fn blocks_sent(&self) -> u64 {
+        self.blocks.load(Ordering::Relaxed)
+    }
+
+    /// The number of blocks written to the underlying writer so far.
+    pub(crate) fn blocks_written(&self) -> u64 {
+        self.blocks_written.load(Ordering::Relaxed)
+    }
+
+    /// Records that a block failed to compress and was re-queued.
+    pub(crate) fn record_requeue(&self) {
+        self.requeued_blocks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that `blocks` blocks were compressed together as a batch.
+    pub(crate) fn record_batch(&self, blocks: usize) {
+        self.batched_blocks.fetch_add(blocks as u64, Ordering::Relaxed);
+    }
+
+    /// Records that writing a block failed and was retried.
+    pub(crate) fn record_write_retry(&self) {
+        self.write_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that the underlying writer was replaced after writing to it failed.
+    pub(crate) fn record_reopen(&self) {
+        self.reopens.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records how long a compressed block waited for its turn to be written.
+    pub(crate) fn record_reorder_wait(&self, wait: Duration) {
+        self.reorder_wait.record(wait);
+    }
+
+    /// Records the block size currently used by the writer.
+    pub(crate) fn set_block_size(&self, block_size: usize) {
+        self.block_size.store(block_size as u64, Ordering::Relaxed);
+    }
+
+    /// Takes a snapshot of the counters.
+    pub(crate) fn snapshot(&self, writer_index: usize, id: Option<String>) -> WriterStats {
+        WriterStats {
+            writer_index,
+            id,
+            blocks: self.blocks.load(Ordering::Relaxed),
+            partial_blocks: self.partial_blocks.load(Ordering::Relaxed),
+            uncompressed_bytes: self.uncompressed_bytes.load(Ordering::Relaxed),
+            compressed_bytes: self.compressed_bytes.load(Ordering::Relaxed),
+            blocks_written: self.blocks_written.load(Ordering::Relaxed),
+            uncompressed_bytes_written: self.uncompressed_bytes_written.load(Ordering::Relaxed),
+            requeued_blocks: self.requeued_blocks.load(Ordering::Relaxed),
+            batched_blocks: self.batched_blocks.load(Ordering::Relaxed),
+            write_retries: self.write_retries.load(Ordering::Relaxed),
+            reopens: self.reopens.load(Ordering::Relaxed),
+            block_size: self.block_size.load(Ordering::Relaxed) as usize,
+            reorder_wait: self.reorder_wait.summary(),
+        }
+    }
+}
+
+/// A snapshot of the statistics for a single writer.
+#[derive(Debug, Clone, Default)]
+pub struct WriterStats {
+    /// The index of the writer within the pool, in the order writers were exchanged.
+    pub writer_index: usize,
+    /// The stable ID given to the writer, if any, see
+    /// [`PoolBuilder::set_writer_id`](crate::PoolBuilder::set_writer_id).
+    pub id: Option<String>,
+    /// The number of blocks sent for compression.
+    pub blocks: u64,
+    /// The number of blocks that were sent before they were full because the writer was flushed.
+    ///
+    /// A high proportion of partial blocks degrades the compression ratio and indicates that the
+    /// caller should flush less often or batch its writes.
+    pub partial_blocks: u64,
+    /// The number of uncompressed bytes sent for compression.
+    pub uncompressed_bytes: u64,
+    /// The number of compressed bytes written to the underlying writer.
+    pub compressed_bytes: u64,
+    /// The number of blocks written to the underlying writer.
+    pub blocks_written: u64,
+    /// The number of uncompressed bytes in the blocks written to the underlying writer.
+    pub uncompressed_bytes_written: u64,
+    /// The number of blocks that failed to compress and were re-queued, see
+    /// [`PoolBuilder::requeue_failed_blocks`](crate::PoolBuilder::requeue_failed_blocks).
+    pub requeued_blocks: u64,
+    /// The number of blocks compressed in batches of more than one, see
+    /// [`PoolBuilder::batch_small_blocks`](crate::PoolBuilder::batch_small_blocks).
+    pub batched_blocks: u64,
+    /// The number of times writing a block to the underlying writer failed and was retried, see
+    /// [`PoolBuilder::retry_failed_writes`](crate::PoolBuilder::retry_failed_writes).
+    pub write_retries: u64,
+    /// The number of times the underlying writer was replaced after writing to it failed, see
+    /// [`PoolBuilder::reopen_failed_writers`](crate::PoolBuilder::reopen_failed_writers).
+    pub reopens: u64,
+    /// The block size currently used by the writer.
+    pub block_size: usize,
+    /// How long compressed blocks waited between being compressed and being picked up to be
+    /// written, e.g. because earlier blocks for the same writer were still being compressed.
+    ///
+    /// Consistently long waits indicate that the ordered delivery of blocks, rather than
+    /// compression or IO, is the bottleneck.  Percentiles are accurate to within a factor of two.
+    pub reorder_wait: LatencySummary,
+}
+
+impl WriterStats {
+    /// The fraction of all blocks that were partial blocks, or 0 if no blocks were sent.
+    pub fn partial_block_fraction(&self) -> f64 {
+        if self.blocks == 0 {
+            0.0
+        } else {
+            self.partial_blocks as f64 / self.blocks as f64
+        }
+    }
+
+    /// The ratio of uncompressed to compressed bytes, or 0 if nothing has been written yet.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            0.0
+        } else {
+            self.uncompressed_bytes as f64 / self.compressed_bytes as f64
+        }
+    }
+
+    /// The number of blocks sent for compression that have not yet been written.
+    pub fn remaining_blocks(&self) -> u64 {
+        self.blocks.saturating_sub(self.blocks_written)
+    }
+
+    /// The number of uncompressed bytes sent for compression that have not yet been written.
+    pub fn remaining_bytes(&self) -> u64 {
+        self.uncompressed_bytes.saturating_sub(self.uncompressed_bytes_written)
+    }
+}
+
+/// The live counters for each compression level used by a pool, shared between the pool threads
+/// and the [`Pool`](crate::Pool).
+#[derive(Debug, Default)]
+pub(crate) struct LevelCounters {
+    levels: Mutex<Vec<LevelStats>>,
+}
+
+impl LevelCounters {
+    /// Records that a block of `uncompressed_len` bytes was compressed at `level` to
+    /// `compressed_len` bytes, taking `elapsed`.
+    pub(crate) fn record(
+        &self,
+        level: Option<u8>,
+        uncompressed_len: usize,
+        compressed_len: usize,
+        elapsed: Duration,
+    ) {
+        let mut levels = self.levels.lock();
+        let index = match levels.iter().position(|l| l.level == level) {
+            Some(index) => index,
+            None => {
+                levels.push(LevelStats { level, ..LevelStats::default() });
+                levels.len() - 1
+            }
+        };
+        let stats = &mut levels[index];
+        stats.blocks += 1;
+        stats.uncompressed_bytes += uncompressed_len as u64;
+        stats.compressed_bytes += compressed_len as u64;
+        stats.compression_time += elapsed;
+    }
+
+    /// Takes a snapshot of the counters, ordered by level.
+    pub(crate) fn snapshot(&self) -> Vec<LevelStats> {
+        let mut levels = self.levels.lock().clone();
+        levels.sort_by_key(|l| l.level);
+        levels
+    }
+}
+
+/// A snapshot of the statistics for the blocks compressed at a single compression level, across
+/// all writers, e.g. to verify whether a choice of levels pays off on real data.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LevelStats {
+    /// The compression level, or `None` for the compressor's default level when no level was set
+    /// with [`PoolBuilder::compression_level`](crate::PoolBuilder::compression_level).
+    pub level: Option<u8>,
+    /// The number of blocks compressed at this level.
+    pub blocks: u64,
+    /// The number of uncompressed bytes compressed at this level.
+    pub uncompressed_bytes: u64,
+    /// The number of compressed bytes produced at this level.
+    pub compressed_bytes: u64,
+    /// The total time spent compressing at this level, summed across threads.
+    pub compression_time: Duration,
+}
+
+impl LevelStats {
+    /// The ratio of uncompressed to compressed bytes, or 0 if nothing was compressed.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            0.0
+        } else {
+            self.uncompressed_bytes as f64 / self.compressed_bytes as f64
+        }
+    }
+
+    /// The compression throughput of a single thread in MB/s of uncompressed data, or 0 if
+    /// nothing was compressed.
+    pub fn throughput_mb_per_sec(&self) -> f64 {
+        let secs = self.compression_time.as_secs_f64();
+        if secs == 0.0 {
+            0.0
+        } else {
+            self.uncompressed_bytes as f64 / 1_000_000.0 / secs
+        }
+    }
+}
+
+/// A snapshot of the statistics for all writers in a pool.
+#[derive(Debug, Clone, Default)]
+pub struct PoolStats {
+    /// The statistics for each writer, in the order writers were exchanged.
+    pub writers: Vec<WriterStats>,
+    /// The statistics for each compression level used, ordered by level.  Blocks written
+    /// uncompressed, e.g. by [`SmallOutputPolicy::Uncompressed`](crate::SmallOutputPolicy), are
+    /// not included.
+    pub levels: Vec<LevelStats>,
+}
+
+impl PoolStats {
+    /// The total number of blocks sent for compression across all writers.
+    pub fn blocks(&self) -> u64 {
+        self.writers.iter().map(|w| w.blocks).sum()
+    }
+
+    /// The total number of partial blocks across all writers.
+    pub fn partial_blocks(&self) -> u64 {
+        self.writers.iter().map(|w| w.partial_blocks).sum()
+    }
+
+    /// The total number of uncompressed bytes sent for compression across all writers.
+    pub fn uncompressed_bytes(&self) -> u64 {
+        self.writers.iter().map(|w| w.uncompressed_bytes).sum()
+    }
+
+    /// The total number of compressed bytes written across all writers.
+    pub fn compressed_bytes(&self) -> u64 {
+        self.writers.iter().map(|w| w.compressed_bytes).sum()
+    }
+
+    /// The total number of blocks that have not yet been written across all writers, e.g. to
+    /// report progress while the pool is stopping.
+    pub fn remaining_blocks(&self) -> u64 {
+        self.writers.iter().map(WriterStats::remaining_blocks).sum()
+    }
+
+    /// The total number of uncompressed bytes that have not yet been written across all writers.
+    pub fn remaining_bytes(&self) -> u64 {
+        self.writers.iter().map(WriterStats::remaining_bytes).sum()
+    }
+
+    /// The statistics for the given compression level, if any blocks were compressed at it.
+    pub fn level(&self, level: Option<u8>) -> Option<&LevelStats> {
+        self.levels.iter().find(|l| l.level == level)
+    }
+}
@@ -0,0 +1,89 @@
+//! Re-compressing a stream from one format, or level, to another, e.g. a BGZF file written at a
+//! low level to one at a high level, or a multi-member gzip file to BGZF with the
+//! [`GzipDecompressor`](crate::gzip::GzipDecompressor), with both the decompression and the
+//! compression spread over the threads of one pool.
+//!
+//! Memory is bounded on both sides: the input is decompressed a limited number of blocks ahead
+//! of the output, and the output is held back by the pool's queues.  Each decompressed block is
+//! handed to the output without being copied into an intermediate buffer.
+//!
+//! ```rust,no_run
+//! use pooled_writer::bgzf::{BgzfCompressor, BgzfDecompressor};
+//! use pooled_writer::PoolBuilder;
+//! use pooled_writer::transcode::Transcoder;
+//!
+//! let mut builder = PoolBuilder::<_, BgzfCompressor>::new().compression_level(9)?;
+//! let input = std::fs::File::open("in.txt.gz")?;
+//! let output = std::fs::File::create("out.txt.gz")?;
+//! let transcoder: Transcoder<_, BgzfDecompressor> = builder.exchange_transcoder(input, output);
+//! let mut pool = builder.build()?;
+//! transcoder.run()?;
+//! pool.stop_pool()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+
+use crate::reader::{Decompressor, PooledReader};
+use crate::{Compressor, PoolBuilder, PooledWriter};
+
+/// Copies the decompressed bytes of a [`PooledReader`] to a [`PooledWriter`] of the same pool.
+pub struct Transcoder<R: Read, D: Decompressor> {
+    reader: PooledReader<R, D>,
+    writer: PooledWriter,
+}
+
+impl<R: Read, D: Decompressor> Transcoder<R, D> {
+    /// Creates a transcoder from a reader and writer, which may be exchanged with the same pool
+    /// or with different pools.
+    pub fn new(reader: PooledReader<R, D>, writer: PooledWriter) -> Self {
+        Self { reader, writer }
+    }
+
+    /// Copies the whole input to the output and finalizes the output, returning the number of
+    /// uncompressed bytes copied.  This must be called after the pool is built and before it is
+    /// stopped.
+    pub fn run(mut self) -> io::Result<u64> {
+        let mut copied = 0;
+        loop {
+            let block = self.reader.fill_buf()?;
+            if block.is_empty() {
+                break;
+            }
+            self.writer.write_all(block)?;
+            let len = block.len();
+            self.reader.consume(len);
+            copied += len as u64;
+        }
+        self.writer.close()?;
+        Ok(copied)
+    }
+}
+
+impl<R: Read, D: Decompressor> fmt::Debug for Transcoder<R, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transcoder")
+            .field("reader", &self.reader)
+            .field("writer", &self.writer)
+            .finish()
+    }
+}
+
+impl<W, C> PoolBuilder<W, C>
+where
+    W: Write + Send + 'static,
+    C: Compressor,
+{
+    /// Exchanges a reader of a stream compressed in the format `D` and a writer for a
+    /// [`Transcoder`] that re-compresses the stream with the pool's compressor, with both
+    /// sides using the pool's threads.
+    pub fn exchange_transcoder<R: Read, D: Decompressor>(
+        &mut self,
+        input: R,
+        output: W,
+    ) -> Transcoder<R, D> {
+        let reader = self.exchange_reader(input);
+        let writer = self.exchange(output);
+        Transcoder::new(reader, writer)
+    }
+}
@@ -0,0 +1,184 @@
+//! Experimental self-tuning of the block size used by each [`PooledWriter`](crate::PooledWriter).
+//!
+//! When enabled via [`PoolBuilder::block_size_tuning`](crate::PoolBuilder::block_size_tuning),
+//! each writer cycles through a set of candidate block sizes during a warm-up window, the pool
+//! threads measure the compression ratio and throughput achieved for each, and the writer then
+//! locks in the best candidate for the remainder of the stream.
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+/// Configuration for block size tuning.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockSizeTuning {
+    /// The candidate block sizes to try.
+    pub candidates: Vec<usize>,
+    /// The number of full blocks to measure for each candidate before choosing.
+    pub trials_per_size: usize,
+    /// Candidates whose compression ratio is within this many percent of the best ratio are
+    /// considered equivalent, and the fastest of them is chosen.
+    pub ratio_tolerance_percent: f64,
+}
+
+impl BlockSizeTuning {
+    /// The default number of trial blocks for each candidate.
+    pub const DEFAULT_TRIALS_PER_SIZE: usize = 4;
+
+    /// The default ratio tolerance in percent.
+    pub const DEFAULT_RATIO_TOLERANCE_PERCENT: f64 = 1.0;
+
+    /// Creates a new tuning configuration trying each of `candidates`.
+    pub fn new(candidates: Vec<usize>) -> Self {
+        Self {
+            candidates,
+            trials_per_size: Self::DEFAULT_TRIALS_PER_SIZE,
+            ratio_tolerance_percent: Self::DEFAULT_RATIO_TOLERANCE_PERCENT,
+        }
+    }
+
+    /// Creates a configuration trying `max_block_size` and its halves and quarters.
+    pub fn halving(max_block_size: usize) -> Self {
+        Self::new(vec![max_block_size, max_block_size / 2, max_block_size / 4])
+    }
+
+    /// Sets the number of trial blocks measured for each candidate.
+    pub fn trials_per_size(mut self, trials: usize) -> Self {
+        assert!(trials > 0, "Must measure at least one block per candidate.");
+        self.trials_per_size = trials;
+        self
+    }
+
+    /// Sets the ratio tolerance in percent.
+    pub fn ratio_tolerance_percent(mut self, tolerance: f64) -> Self {
+        self.ratio_tolerance_percent = tolerance;
+        self
+    }
+}
+
+/// The measurements for one candidate block size.
+#[derive(Debug, Clone)]
+struct Candidate {
+    block_size: usize,
+    issued: usize,
+    measured: usize,
+    uncompressed_bytes: u64,
+    compressed_bytes: u64,
+    elapsed: Duration,
+}
+
+impl Candidate {
+    fn ratio(&self) -> f64 {
+        self.uncompressed_bytes as f64 / std::cmp::max(self.compressed_bytes, 1) as f64
+    }
+
+    fn throughput(&self) -> f64 {
+        self.uncompressed_bytes as f64 / std::cmp::max(self.elapsed.as_nanos(), 1) as f64
+    }
+}
+
+#[derive(Debug)]
+struct TunerState {
+    candidates: Vec<Candidate>,
+    next: usize,
+    chosen: Option<usize>,
+}
+
+/// The per-writer tuner, shared between the [`PooledWriter`](crate::PooledWriter) that picks the
+/// size of each block and the pool threads that measure the compressed blocks.
+#[derive(Debug)]
+pub(crate) struct BlockSizeTuner {
+    trials_per_size: usize,
+    ratio_tolerance: f64,
+    state: Mutex<TunerState>,
+}
+
+impl BlockSizeTuner {
+    pub(crate) fn new(config: &BlockSizeTuning) -> Self {
+        let candidates = config
+            .candidates
+            .iter()
+            .map(|&block_size| Candidate {
+                block_size,
+                issued: 0,
+                measured: 0,
+                uncompressed_bytes: 0,
+                compressed_bytes: 0,
+                elapsed: Duration::default(),
+            })
+            .collect();
+
+        Self {
+            trials_per_size: config.trials_per_size,
+            ratio_tolerance: config.ratio_tolerance_percent / 100.0,
+            state: Mutex::new(TunerState { candidates, next: 0, chosen: None }),
+        }
+    }
+
+    /// The block size that was locked in, if tuning has completed.
+    pub(crate) fn chosen_block_size(&self) -> Option<usize> {
+        self.state.lock().chosen
+    }
+
+    /// Returns the size to use for the next block.  During the warm-up window candidates are
+    /// issued round-robin until each has had enough trial blocks; once all trials have been
+    /// measured the best candidate is locked in.
+    pub(crate) fn next_block_size(&self) -> usize {
+        let mut state = self.state.lock();
+        if let Some(chosen) = state.chosen {
+            return chosen;
+        }
+
+        if state.candidates.iter().all(|c| c.measured >= self.trials_per_size) {
+            let chosen = self.choose(&state.candidates);
+            state.chosen = Some(chosen);
+            return chosen;
+        }
+
+        let n = state.candidates.len();
+        for offset in 0..n {
+            let idx = (state.next + offset) % n;
+            if state.candidates[idx].issued < self.trials_per_size {
+                state.next = (idx + 1) % n;
+                state.candidates[idx].issued += 1;
+                return state.candidates[idx].block_size;
+            }
+        }
+
+        // All trials issued but not yet measured: use the largest candidate while waiting.
+        state.candidates.iter().map(|c| c.block_size).max().expect("No tuning candidates")
+    }
+
+    /// Returns a trial for `block_size` that will never be measured, e.g. because the block was
+    /// flushed before it was full.
+    pub(crate) fn abandon(&self, block_size: usize) {
+        let mut state = self.state.lock();
+        if let Some(c) = state.candidates.iter_mut().find(|c| c.block_size == block_size) {
+            c.issued = c.issued.saturating_sub(1);
+        }
+    }
+
+    /// Records the compression of one full block of `uncompressed` bytes.
+    pub(crate) fn record(&self, uncompressed: usize, compressed: usize, elapsed: Duration) {
+        let mut state = self.state.lock();
+        if state.chosen.is_some() {
+            return;
+        }
+        if let Some(c) = state.candidates.iter_mut().find(|c| c.block_size == uncompressed) {
+            c.measured += 1;
+            c.uncompressed_bytes += uncompressed as u64;
+            c.compressed_bytes += compressed as u64;
+            c.elapsed += elapsed;
+        }
+    }
+
+    /// Picks the fastest candidate among those whose ratio is within tolerance of the best.
+    fn choose(&self, candidates: &[Candidate]) -> usize {
+        let best_ratio = candidates.iter().map(Candidate::ratio).fold(0.0, f64::max);
+        candidates
+            .iter()
+            .filter(|c| c.ratio() >= best_ratio * (1.0 - self.ratio_tolerance))
+            .max_by(|a, b| a.throughput().partial_cmp(&b.throughput()).expect("NaN throughput"))
+            .map(|c| c.block_size)
+            .expect("No tuning candidates")
+    }
+}
@@ -0,0 +1,84 @@
+///! An implementation of [`Compressor`] for the xz (LZMA2) format.
+use thiserror::Error;
+use xz2::stream::{Action, Check, Status, Stream};
+
+use crate::{Compressor, CompressorCapabilities};
+
+/// The minimum supported xz compression level (preset).
+const MIN_LEVEL: u8 = 0;
+
+/// The maximum supported xz compression level (preset).
+const MAX_LEVEL: u8 = 9;
+
+/// The approximate memory, in MiB, used by the encoder at each preset, per the `xz` manual.
+const PRESET_MEMORY_MIB: [usize; 10] = [3, 9, 17, 32, 48, 94, 94, 186, 370, 674];
+
+/// The errors that may be returned by the [`XzCompressor`].
+#[derive(Error, Debug)]
+pub enum XzError {
+    #[error("Invalid xz compression level {0}, must be in 0..=9")]
+    InvalidCompressionLevel(u8),
+    #[error(transparent)]
+    Lzma(#[from] xz2::stream::Error),
+}
+
+/// An xz compressor that compresses each block as an independent xz stream.
+///
+/// A concatenation of xz streams is itself a valid xz file, so the output of a pooled writer
+/// can be read by any `xz` decoder.  There is no EOF marker.  Since xz favours ratio over speed
+/// the blocks are larger than for the other compressors, which increases the memory held in the
+/// pool's queues accordingly.
+pub struct XzCompressor {
+    preset: u32,
+}
+
+impl Compressor for XzCompressor {
+    type Error = XzError;
+    type CompressionLevel = u32;
+
+    const BLOCK_SIZE: usize = 1024 * 1024;
+
+    fn capabilities() -> CompressorCapabilities {
+        CompressorCapabilities::new(Self::BLOCK_SIZE)
+            .eof_marker(false)
+            .dictionaries(false)
+            .extra_subfields(false)
+            .compression_levels(MIN_LEVEL, MAX_LEVEL)
+            .deterministic(true)
+    }
+
+    fn capabilities_for(compression_level: &Self::CompressionLevel) -> CompressorCapabilities {
+        let mib = PRESET_MEMORY_MIB.get(*compression_level as usize).copied().unwrap_or(0);
+        Self::capabilities().scratch_memory(mib << 20)
+    }
+
+    fn new(compression_level: Self::CompressionLevel) -> Self {
+        Self { preset: compression_level }
+    }
+
+    fn default_compression_level() -> Self::CompressionLevel {
+        6
+    }
+
+    fn new_compression_level(compression_level: u8) -> Result<Self::CompressionLevel, Self::Error> {
+        if (MIN_LEVEL..=MAX_LEVEL).contains(&compression_level) {
+            Ok(u32::from(compression_level))
+        } else {
+            Err(XzError::InvalidCompressionLevel(compression_level))
+        }
+    }
+
+    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        let mut stream = Stream::new_easy_encoder(self.preset, Check::Crc64)?;
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(std::cmp::max(input.len() / 2, 4096));
+            }
+            let consumed = stream.total_in() as usize;
+            if stream.process_vec(&input[consumed..], output, Action::Finish)? == Status::StreamEnd
+            {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+///! An implementation of [`Compressor`] for the Zstandard (`zstd`) format.
+use std::io;
+
+use crate::{Compressor, CompressorCapabilities};
+
+/// The minimum supported zstd compression level.
+const MIN_LEVEL: u8 = 1;
+
+/// The maximum supported zstd compression level.
+const MAX_LEVEL: u8 = 22;
+
+/// A zstd compressor that compresses each block as an independent zstd frame.
+///
+/// A sequence of zstd frames is itself a valid zstd stream, so the output of a pooled writer can
+/// be read by any zstd decoder.  There is no EOF marker.  A pre-trained dictionary may be supplied
+/// via [`PoolBuilder::dictionary`](crate::PoolBuilder::dictionary), in which case the output must
+/// be decoded with the same dictionary.
+pub struct ZstdCompressor {
+    inner: zstd::bulk::Compressor<'static>,
+}
+
+impl Compressor for ZstdCompressor {
+    type Error = io::Error;
+    type CompressionLevel = i32;
+
+    const BLOCK_SIZE: usize = 128 * 1024;
+
+    fn capabilities() -> CompressorCapabilities {
+        CompressorCapabilities::new(Self::BLOCK_SIZE)
+            .eof_marker(false)
+            .dictionaries(true)
+            .extra_subfields(false)
+            .compression_levels(MIN_LEVEL, MAX_LEVEL)
+            .deterministic(true)
+    }
+
+    fn new(compression_level: Self::CompressionLevel) -> Self {
+        Self {
+            inner: zstd::bulk::Compressor::new(compression_level)
+                .expect("Failed to create zstd compressor"),
+        }
+    }
+
+    fn new_with_dictionary(compression_level: Self::CompressionLevel, dictionary: &[u8]) -> Self {
+        Self {
+            inner: zstd::bulk::Compressor::with_dictionary(compression_level, dictionary)
+                .expect("Failed to create zstd compressor with dictionary"),
+        }
+    }
+
+    fn default_compression_level() -> Self::CompressionLevel {
+        3
+    }
+
+    fn new_compression_level(compression_level: u8) -> Result<Self::CompressionLevel, Self::Error> {
+        if (MIN_LEVEL..=MAX_LEVEL).contains(&compression_level) {
+            Ok(i32::from(compression_level))
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "zstd compression level {} is not in {}..={}",
+                    compression_level, MIN_LEVEL, MAX_LEVEL
+                ),
+            ))
+        }
+    }
+
+    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        output.extend_from_slice(&self.inner.compress(input)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+//! Round-trips the output of pools through the reference command line tools, e.g. `bgzip` and
+//! `zstd`, to catch framing incompatibilities that round-trips through Rust decoders may miss.
+//!
+//! These tests only run if the `POOLED_WRITER_CLI_TESTS` environment variable is set, and each
+//! is skipped with a message if its tool is not on the `PATH`.
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    process::Command,
+};
+
+use pooled_writer::{Compressor, PoolBuilder};
+use tempfile::tempdir;
+
+/// Returns a command for the tool `name`, or `None` if the CLI tests are disabled or the tool
+/// can't be run.
+fn tool(name: &str) -> Option<Command> {
+    if std::env::var_os("POOLED_WRITER_CLI_TESTS").is_none() {
+        return None;
+    }
+    match Command::new(name).arg("--version").output() {
+        Ok(output) if output.status.success() => Some(Command::new(name)),
+        _ => {
+            eprintln!("Skipping test, {} is not available", name);
+            None
+        }
+    }
+}
+
+/// Several blocks worth of compressible but varied text.
+fn test_data() -> Vec<u8> {
+    (0..200_000).flat_map(|i| format!("line {}\t{}\n", i, i * 7919 % 1013).into_bytes()).collect()
+}
+
+/// Writes `data` to `path` through a pool using the compressor `C`.
+fn compress<C: Compressor>(data: &[u8], path: &Path) {
+    let mut builder = PoolBuilder::<_, C>::new().threads(4);
+    let mut writer = builder.exchange(BufWriter::new(File::create(path).unwrap()));
+    let mut pool = builder.build().unwrap();
+    writer.write_all(data).unwrap();
+    writer.close().unwrap();
+    pool.stop_pool().unwrap();
+}
+
+/// Runs `command` on `path`, asserting that it succeeds, and returns its standard output.
+fn run(mut command: Command, path: &Path) -> Vec<u8> {
+    let output = command.arg(path).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+/// Compresses test data with `C`, and checks that it is decompressed by `command`.
+fn check_round_trip<C: Compressor>(command: Option<Command>, extension: &str) {
+    if let Some(command) = command {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(format!("test.{}", extension));
+        let data = test_data();
+        compress::<C>(&data, &path);
+        assert_eq!(run(command, &path), data);
+    }
+}
+
+/// Returns a command for the tool `name` with the arguments `args`, as with [`tool`].
+fn tool_with_args(name: &str, args: &[&str]) -> Option<Command> {
+    let mut command = tool(name)?;
+    command.args(args);
+    Some(command)
+}
+
+#[test]
+#[cfg(feature = "bgzf_compressor")]
+fn test_bgzip() {
+    use pooled_writer::bgzf::BgzfCompressor;
+
+    check_round_trip::<BgzfCompressor>(tool_with_args("bgzip", &["-dc"]), "gz");
+
+    // An empty stream is just the EOF block, which bgzip also checks when testing integrity
+    if let Some(command) = tool_with_args("bgzip", &["-t"]) {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("empty.gz");
+        compress::<BgzfCompressor>(&[], &path);
+        run(command, &path);
+    }
+}
+
+#[test]
+#[cfg(feature = "bgzf_compressor")]
+fn test_gunzip_bgzf() {
+    use pooled_writer::bgzf::BgzfCompressor;
+
+    check_round_trip::<BgzfCompressor>(tool_with_args("gzip", &["-dc"]), "gz");
+}
+
+#[test]
+#[cfg(feature = "gzip_compressor")]
+fn test_gunzip() {
+    use pooled_writer::gzip::GzipCompressor;
+
+    check_round_trip::<GzipCompressor>(tool_with_args("gzip", &["-dc"]), "gz");
+}
+
+#[test]
+#[cfg(feature = "zstd_compressor")]
+fn test_zstd() {
+    use pooled_writer::zstd::ZstdCompressor;
+
+    check_round_trip::<ZstdCompressor>(tool_with_args("zstd", &["-dcq"]), "zst");
+}
+
+#[test]
+#[cfg(feature = "xz_compressor")]
+fn test_xz() {
+    use pooled_writer::xz::XzCompressor;
+
+    check_round_trip::<XzCompressor>(tool_with_args("xz", &["-dc"]), "xz");
+}